@@ -0,0 +1,41 @@
+// Time building the NFA of long patterns, one element at a time. Run with cargo bench. Doubling
+// the length should about double the time, since concat and union only touch the states they
+// join and move the smaller table into the larger.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use regex_rs::parse::{parse, try_lex};
+
+// a name and the pattern of length n to time
+type Case = (&'static str, fn(usize) -> String);
+
+// the best of a few runs, in the spirit of a benchmark harness without one
+fn time(pattern: &str) -> (Duration, usize) {
+    let toks = try_lex(pattern).unwrap();
+    let mut best = Duration::MAX;
+    let mut states = 0;
+    for _ in 0..5 {
+        let toks = toks.clone();
+        let start = Instant::now();
+        let nfa = black_box(parse(toks).unwrap());
+        best = best.min(start.elapsed());
+        states = nfa.state_count();
+    }
+    (best, states)
+}
+
+fn main() {
+    let cases: [Case; 4] = [
+        ("literals", |n| "ab".repeat(n / 2)),
+        ("alternatives", |n| vec!["ab"; n / 2].join("|")),
+        ("plus", |n| "a+".repeat(n)),
+        ("range", |n| format!("(ab){{{n}}}")),
+    ];
+    for (name, pattern) in cases {
+        for n in [1000, 2000, 4000] {
+            let (elapsed, states) = time(&pattern(n));
+            println!("{name:>12} n = {n:>4}: {elapsed:>10.2?} ({states} states)");
+        }
+    }
+}
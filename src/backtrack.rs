@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+
+use crate::decision_tree::{DecisionTree, Outcome};
+use crate::prog::{Inst, Program};
+
+// a successful search: capture slots and, if requested, every value each group took
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Found {
+    pub slots: Vec<Option<usize>>,
+    pub history: Vec<(usize, usize, usize)>, // (group, start, end) in the order groups closed
+}
+
+#[derive(Debug)]
+enum Job {
+    Explore(usize, usize, usize), // (instruction, byte offset, branch of the decision tree)
+    RestoreSlot(usize, Option<usize>),
+    RestoreCounter(usize, u64),
+    TruncateHistory(usize),
+}
+
+// Buffers a search needs, kept between searches so a batch of them doesn't allocate each time
+#[derive(Debug, Default)]
+pub struct Scratch {
+    visited: HashSet<(usize, usize, Vec<u64>)>,
+    stack: Vec<Job>,
+    steps: usize,
+    // the state of the path being explored, which is the match once explore returns true
+    slots: Vec<Option<usize>>,
+    counters: Vec<u64>,
+    history: Vec<(usize, usize, usize)>,
+    // the decision tree being recorded, if the search is traced
+    tree: Option<DecisionTree>,
+}
+
+impl Scratch {
+    // how many (instruction, offset) pairs the last search explored, a measure of its work
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+}
+
+// Leftmost-first search with memoization: a (instruction, offset) pair that was already explored
+// can't lead to a match the second time either, so the search is O(instructions * input length).
+// With counters the pair also includes their values. If end is set, only matches ending at that
+// offset count, e.g. input.len() for a full match.
+pub fn search(
+    prog: &Program,
+    input: &str,
+    from: usize,
+    anchored: bool,
+    end: Option<usize>,
+    record: bool,
+) -> Option<Found> {
+    search_with(
+        &mut Scratch::default(),
+        prog,
+        input,
+        from,
+        anchored,
+        end,
+        record,
+    )
+}
+
+// search, with the buffers taken from scratch
+pub fn search_with(
+    scratch: &mut Scratch,
+    prog: &Program,
+    input: &str,
+    from: usize,
+    anchored: bool,
+    end: Option<usize>,
+    record: bool,
+) -> Option<Found> {
+    explore(scratch, prog, input, from, anchored, end, record).then(|| Found {
+        slots: scratch.slots.clone(),
+        history: std::mem::take(&mut scratch.history),
+    })
+}
+
+// search, recording its decision tree as it goes (see DecisionTree)
+pub fn search_traced(
+    prog: &Program,
+    input: &str,
+    from: usize,
+    anchored: bool,
+    end: Option<usize>,
+) -> (Option<Found>, DecisionTree) {
+    let mut scratch = Scratch {
+        tree: Some(DecisionTree::default()),
+        ..Scratch::default()
+    };
+    let found = search_with(&mut scratch, prog, input, from, anchored, end, false);
+    (found, scratch.tree.unwrap_or_default())
+}
+
+// The bounds of the leftmost-first match at or after from. Unlike search_with it doesn't build
+// a Found, so once scratch's buffers have grown to fit, a search doesn't allocate.
+pub fn match_bounds(
+    scratch: &mut Scratch,
+    prog: &Program,
+    input: &str,
+    from: usize,
+) -> Option<(usize, usize)> {
+    if !explore(scratch, prog, input, from, false, None, false) {
+        return None;
+    }
+    Some((scratch.slots[0]?, scratch.slots[1]?))
+}
+
+// the search itself, leaving the match in scratch.slots and scratch.history
+fn explore(
+    scratch: &mut Scratch,
+    prog: &Program,
+    input: &str,
+    from: usize,
+    anchored: bool,
+    end: Option<usize>,
+    record: bool,
+) -> bool {
+    let Scratch {
+        visited,
+        stack,
+        steps,
+        slots,
+        counters,
+        history,
+        tree,
+    } = scratch;
+    visited.clear();
+    stack.clear();
+    *steps = 0;
+
+    let starts = input[from..]
+        .char_indices()
+        .map(|(i, _)| from + i)
+        .chain([input.len()])
+        .take(if anchored { 1 } else { usize::MAX });
+
+    let mut candidate = from;
+    for start in starts {
+        // jump to the next occurrence of the text every match starts with
+        if let Some(prefix) = &prog.prefix
+            && !anchored
+        {
+            if start < candidate {
+                continue;
+            }
+            match input[start..].find(prefix.as_str()) {
+                Some(i) => candidate = start + i,
+                None => break,
+            }
+            if start < candidate {
+                continue;
+            }
+        }
+
+        // positions that can't begin a match are skipped without exploring them
+        if let Some(first) = &prog.first_chars
+            && !anchored
+            && !input[start..]
+                .chars()
+                .next()
+                .is_some_and(|c| first.contains((prog.char_map)(c)))
+        {
+            continue;
+        }
+
+        slots.clear();
+        slots.resize(prog.slots, None);
+        counters.clear();
+        counters.resize(prog.counters, 0);
+        history.clear();
+        let root = tree.as_mut().map_or(0, |t| t.root());
+        stack.push(Job::Explore(0, start, root));
+
+        while let Some(job) = stack.pop() {
+            let (mut pc, mut pos, mut branch) = match job {
+                Job::Explore(pc, pos, branch) => (pc, pos, branch),
+                Job::RestoreSlot(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+                Job::RestoreCounter(counter, old) => {
+                    counters[counter] = old;
+                    continue;
+                }
+                Job::TruncateHistory(len) => {
+                    history.truncate(len);
+                    continue;
+                }
+            };
+
+            if let Some(tree) = tree {
+                tree.enter(branch);
+            }
+
+            loop {
+                if !visited.insert((pc, pos, counters.clone())) {
+                    if let Some(tree) = tree {
+                        tree.end(branch, Outcome::Memoized);
+                    }
+                    break;
+                }
+                *steps += 1;
+                if let Some(tree) = tree {
+                    tree.step(branch, pc, pos);
+                }
+
+                // offsets still advance by the length of the original character
+                let next = input[pos..].chars().next().map(|n| (n, n.len_utf8()));
+                match &prog.insts[pc] {
+                    Inst::Char(c) => match next {
+                        Some((n, len)) if (prog.char_map)(n) == *c => {
+                            pc += 1;
+                            pos += len;
+                        }
+                        _ => break,
+                    },
+                    Inst::Any => match next {
+                        Some((_, len)) => {
+                            pc += 1;
+                            pos += len;
+                        }
+                        None => break,
+                    },
+                    Inst::Class(chars) => match next {
+                        Some((n, len)) if chars.binary_search(&(prog.char_map)(n)).is_ok() => {
+                            pc += 1;
+                            pos += len;
+                        }
+                        _ => break,
+                    },
+                    Inst::Pred(id) => match next {
+                        Some((n, len)) if id.test((prog.char_map)(n)) => {
+                            pc += 1;
+                            pos += len;
+                        }
+                        _ => break,
+                    },
+                    Inst::NotClass(chars) => match next {
+                        Some((n, len)) if chars.binary_search(&(prog.char_map)(n)).is_err() => {
+                            pc += 1;
+                            pos += len;
+                        }
+                        _ => break,
+                    },
+                    Inst::Split(a, b) => {
+                        let (first, second) = tree.as_mut().map_or((0, 0), |t| t.split(branch));
+                        stack.push(Job::Explore(*b, pos, second));
+                        branch = first;
+                        pc = *a;
+                    }
+                    Inst::Jmp(t) => pc = *t,
+                    Inst::Save(slot) => {
+                        stack.push(Job::RestoreSlot(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+
+                        // closing a group (other than group 0) completes one of its iterations
+                        if record
+                            && slot % 2 == 1
+                            && *slot > 1
+                            && let Some(start) = slots[slot - 1]
+                        {
+                            stack.push(Job::TruncateHistory(history.len()));
+                            history.push((slot / 2, start, pos));
+                        }
+                        pc += 1;
+                    }
+                    Inst::CounterInit(counter) => {
+                        stack.push(Job::RestoreCounter(*counter, counters[*counter]));
+                        counters[*counter] = 0;
+                        pc += 1;
+                    }
+                    Inst::CounterLoop {
+                        counter,
+                        min,
+                        max,
+                        exit,
+                        lazy,
+                    } => {
+                        let count = counters[*counter];
+                        let may_exit = count >= *min;
+                        let may_loop = max.is_none_or(|max| count < max);
+                        // without an upper bound, the count only matters until it reaches min
+                        let next = if max.is_some() {
+                            count + 1
+                        } else {
+                            (count + 1).min(*min)
+                        };
+
+                        match (may_exit, may_loop) {
+                            (false, false) => break,
+                            (true, false) => pc = *exit,
+                            // leave now, and loop once more with the next count if that fails
+                            (true, true) if *lazy => {
+                                let (first, second) =
+                                    tree.as_mut().map_or((0, 0), |t| t.split(branch));
+                                stack.push(Job::RestoreCounter(*counter, count));
+                                stack.push(Job::Explore(pc + 1, pos, second));
+                                branch = first;
+                                stack.push(Job::RestoreCounter(*counter, next));
+                                pc = *exit;
+                            }
+                            _ => {
+                                if may_exit {
+                                    let (first, second) =
+                                        tree.as_mut().map_or((0, 0), |t| t.split(branch));
+                                    stack.push(Job::Explore(*exit, pos, second));
+                                    branch = first;
+                                }
+                                stack.push(Job::RestoreCounter(*counter, count));
+                                counters[*counter] = next;
+                                pc += 1;
+                            }
+                        }
+                    }
+                    Inst::StartAnchor if pos == 0 => pc += 1,
+                    Inst::EndAnchor if pos == input.len() => pc += 1,
+                    Inst::StartAnchor | Inst::EndAnchor => break,
+                    Inst::WordBoundary(word) | Inst::NotWordBoundary(word) => {
+                        let is_word = |c: Option<char>| {
+                            c.is_some_and(|c| word.binary_search(&(prog.char_map)(c)).is_ok())
+                        };
+                        let boundary = is_word(input[..pos].chars().next_back())
+                            != is_word(next.map(|(n, _)| n));
+                        if boundary != matches!(prog.insts[pc], Inst::WordBoundary(_)) {
+                            break;
+                        }
+                        pc += 1;
+                    }
+                    Inst::Match => {
+                        if end.is_some_and(|end| pos != end) {
+                            break;
+                        }
+                        if let Some(tree) = tree {
+                            tree.end(branch, Outcome::Matched);
+                        }
+                        stack.clear();
+                        return true;
+                    }
+                    Inst::Fail => break,
+                }
+            }
+        }
+    }
+
+    false
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::ops::{Index, Range};
+use std::sync::Arc;
+
+use crate::backtrack::Found;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'h> {
+    haystack: &'h str,
+    start: usize,
+    end: usize,
+}
+
+impl<'h> Match<'h> {
+    pub fn new(haystack: &'h str, start: usize, end: usize) -> Self {
+        Self {
+            haystack,
+            start,
+            end,
+        }
+    }
+
+    // byte offset of the start of the match
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    // byte offset just past the end of the match
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    // Char index of the start of the match, e.g. for a column number. Unlike the byte offsets
+    // these take time linear in the offset.
+    pub fn char_start(&self) -> usize {
+        self.haystack[..self.start].chars().count()
+    }
+
+    // char index just past the end of the match
+    pub fn char_end(&self) -> usize {
+        self.char_start() + self.as_str().chars().count()
+    }
+
+    pub fn char_range(&self) -> Range<usize> {
+        self.char_start()..self.char_end()
+    }
+
+    pub fn as_str(&self) -> &'h str {
+        &self.haystack[self.range()]
+    }
+
+    pub(crate) fn haystack(&self) -> &'h str {
+        self.haystack
+    }
+}
+
+// What a group reports when it matched more than once, e.g. (a|b)+ against "ab"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaptureMode {
+    // only the final iteration is kept, group 1 is "b"
+    #[default]
+    LastIteration,
+    // every iteration is kept as well, group 1 also reports "a" then "b" via iterations()
+    AllIterations,
+}
+
+#[derive(Debug, Clone)]
+pub struct Captures<'h> {
+    haystack: &'h str,
+    slots: Vec<Option<usize>>,
+    names: Arc<HashMap<String, usize>>,
+    // (start, end) of every iteration of every group, if recorded
+    iterations: Option<Vec<Vec<(usize, usize)>>>,
+}
+
+impl<'h> Captures<'h> {
+    pub(crate) fn new(
+        haystack: &'h str,
+        found: Found,
+        names: Arc<HashMap<String, usize>>,
+        mode: CaptureMode,
+    ) -> Self {
+        let iterations = match mode {
+            CaptureMode::LastIteration => None,
+            CaptureMode::AllIterations => {
+                let mut iterations = vec![Vec::new(); found.slots.len() / 2];
+                for (group, start, end) in found.history {
+                    iterations[group].push((start, end));
+                }
+                Some(iterations)
+            }
+        };
+
+        Self {
+            haystack,
+            slots: found.slots,
+            names,
+            iterations,
+        }
+    }
+
+    // the number of groups, including group 0
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    // what group i matched in its last iteration, None if it didn't participate
+    pub fn get(&self, i: usize) -> Option<Match<'h>> {
+        match (self.slots.get(i * 2)?, self.slots.get(i * 2 + 1)?) {
+            (Some(start), Some(end)) => Some(Match::new(self.haystack, *start, *end)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self, name: &str) -> Option<Match<'h>> {
+        self.get(*self.names.get(name)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h>>> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    // The whole match and the text of groups 1 through N, so `let (_, [k, v]) = caps.extract()`
+    // works for a pattern with two groups. A group that didn't participate gives "".
+    // Panics if the pattern doesn't have exactly N groups.
+    pub fn extract<const N: usize>(&self) -> (&'h str, [&'h str; N]) {
+        assert_eq!(
+            self.len() - 1,
+            N,
+            "expected {N} groups but the pattern has {}",
+            self.len() - 1
+        );
+
+        let text = |i: usize| self.get(i).map_or("", |m| m.as_str());
+        (text(0), std::array::from_fn(|i| text(i + 1)))
+    }
+
+    // Every iteration of group i in order. Without CaptureMode::AllIterations this is just the
+    // last iteration. Group 0 only ever has one iteration.
+    pub fn iterations(&self, i: usize) -> Vec<Match<'h>> {
+        match &self.iterations {
+            Some(iterations) if i > 0 => iterations
+                .get(i)
+                .map(|spans| {
+                    spans
+                        .iter()
+                        .map(|(start, end)| Match::new(self.haystack, *start, *end))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => self.get(i).into_iter().collect(),
+        }
+    }
+
+    // Append replacement to dst with $n and ${n} replaced by what group n matched, ${name} by
+    // what the named group matched and $$ by $. Groups that don't exist or didn't participate
+    // give "", and a $ that starts none of these is kept as it is.
+    pub fn expand(&self, replacement: &str, dst: &mut String) {
+        let mut rest = replacement;
+        while let Some(at) = rest.find('$') {
+            dst.push_str(&rest[..at]);
+            rest = &rest[at + 1..];
+
+            if let Some(after) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = after;
+                continue;
+            }
+
+            let (group, after) = match rest.strip_prefix('{').and_then(|r| r.split_once('}')) {
+                Some((group, after)) => (group, after),
+                None => {
+                    let digits = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    rest.split_at(digits)
+                }
+            };
+            if group.is_empty() {
+                dst.push('$');
+                continue;
+            }
+
+            let m = match group.parse::<usize>() {
+                Ok(i) => self.get(i),
+                Err(_) => self.name(group),
+            };
+            dst.push_str(m.map_or("", |m| m.as_str()));
+            rest = after;
+        }
+        dst.push_str(rest);
+    }
+}
+
+impl<'h> Index<usize> for Captures<'h> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.get(i)
+            .unwrap_or_else(|| panic!("Group {i} did not match!"))
+            .as_str()
+    }
+}
+
+impl<'h> Index<&str> for Captures<'h> {
+    type Output = str;
+
+    fn index(&self, name: &str) -> &str {
+        self.name(name)
+            .unwrap_or_else(|| panic!("Group {name} did not match!"))
+            .as_str()
+    }
+}
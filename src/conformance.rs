@@ -0,0 +1,265 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::error::ErrorKind;
+use crate::meta::Engine;
+use crate::RegexBuilder;
+
+// A pattern, a haystack, and the leftmost-first match expected in it (byte offsets), if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub pattern: &'static str,
+    pub haystack: &'static str,
+    pub expect: Option<Range<usize>>,
+}
+
+const fn case(pattern: &'static str, haystack: &'static str, expect: Option<Range<usize>>) -> Case {
+    Case {
+        pattern,
+        haystack,
+        expect,
+    }
+}
+
+// The bundled corpus. Most cases are the POSIX-style vectors of Henry Spencer's test suite
+// (as in Fowler's basic.dat) that leftmost-first and leftmost-longest matching agree on. The
+// cases after them pin down where this engine differs from POSIX, and its other syntax.
+pub const CORPUS: &[Case] = &[
+    case("abc", "abc", Some(0..3)),
+    case("abc", "xbc", None),
+    case("abc", "axc", None),
+    case("abc", "abx", None),
+    case("abc", "xabcy", Some(1..4)),
+    case("abc", "ababc", Some(2..5)),
+    case("ab*c", "abc", Some(0..3)),
+    case("ab*bc", "abc", Some(0..3)),
+    case("ab*bc", "abbbbc", Some(0..6)),
+    case("ab+bc", "abbc", Some(0..4)),
+    case("ab+bc", "abc", None),
+    case("ab+bc", "abq", None),
+    case("ab+bc", "abbbbc", Some(0..6)),
+    case("ab?bc", "abbc", Some(0..4)),
+    case("ab?bc", "abc", Some(0..3)),
+    case("ab?bc", "abbbbc", None),
+    case("ab?c", "abc", Some(0..3)),
+    case("a.c", "abc", Some(0..3)),
+    case("a.c", "axc", Some(0..3)),
+    case("a.*c", "axyzc", Some(0..5)),
+    case("a.*c", "axyzd", None),
+    case("a[bc]d", "abc", None),
+    case("a[bc]d", "abd", Some(0..3)),
+    case("a[b-d]e", "abd", None),
+    case("a[b-d]e", "ace", Some(0..3)),
+    case("a[b-d]", "aac", Some(1..3)),
+    case("a|b|c|d|e", "e", Some(0..1)),
+    case("(a|b|c|d|e)f", "ef", Some(0..2)),
+    case("abcd*efg", "abcdefg", Some(0..7)),
+    case("ab*", "xabyabbbz", Some(1..3)),
+    case("ab*", "xayabbbz", Some(1..2)),
+    case("(ab|cd)e", "abcde", Some(2..5)),
+    case("(a|b)c*d", "abcd", Some(1..4)),
+    case("(ab|ab*)bc", "abc", Some(0..3)),
+    case("a([bc]*)c*", "abc", Some(0..3)),
+    case("a([bc]*)(c*d)", "abcd", Some(0..4)),
+    case("a([bc]+)(c*d)", "abcd", Some(0..4)),
+    case("a([bc]*)(c+d)", "abcd", Some(0..4)),
+    case("a[bcd]*dcdcde", "adcdcde", Some(0..7)),
+    case("a[bcd]+dcdcde", "adcdcde", None),
+    case("(ab|a)b*c", "abc", Some(0..3)),
+    case("((a)(b)c)(d)", "abcd", Some(0..4)),
+    case("[a-zA-Z_][a-zA-Z0-9_]*", "alpha", Some(0..5)),
+    case("(bc+d|ef*g.|h?i(j|k))", "effgz", Some(0..5)),
+    case("(bc+d|ef*g.|h?i(j|k))", "ij", Some(0..2)),
+    case("(bc+d|ef*g.|h?i(j|k))", "effg", None),
+    case("(bc+d|ef*g.|h?i(j|k))", "bcdd", Some(0..3)),
+    case("(bc+d|ef*g.|h?i(j|k))", "reffgz", Some(1..6)),
+    case("((((((((((a))))))))))", "a", Some(0..1)),
+    case("(((((((((a)))))))))", "a", Some(0..1)),
+    case("multiple words of text", "uh-uh", None),
+    case("multiple words", "multiple words, yeah", Some(0..14)),
+    case("(.*)c(.*)", "abcde", Some(0..5)),
+    case("a(bc)d", "abcd", Some(0..4)),
+    case("a[b-d]*e", "abde", Some(0..4)),
+    case("(a+|b)*", "ab", Some(0..2)),
+    case("(a+|b){0,}", "ab", Some(0..2)),
+    case("(a+|b)+", "ab", Some(0..2)),
+    case("(a+|b){1,}", "ab", Some(0..2)),
+    case("(a+|b)?", "ab", Some(0..1)),
+    case("(a+|b){0,1}", "ab", Some(0..1)),
+    case("a*", "", Some(0..0)),
+    case("(a*)*", "-", Some(0..0)),
+    case("(a*)+", "-", Some(0..0)),
+    case("(a*|b)*", "-", Some(0..0)),
+    case("a{2}", "a", None),
+    case("a{2}", "baab", Some(1..3)),
+    case("^abc$", "abc", Some(0..3)),
+    case("^abc$", "abcc", None),
+    case("^abc", "abcc", Some(0..3)),
+    case("^abc$", "aabc", None),
+    case("abc$", "aabc", Some(1..4)),
+    case("^", "abc", Some(0..0)),
+    case("$", "abc", Some(3..3)),
+    case("^(ab|cd)e", "abcde", None),
+    case("(^a|b)$", "ab", Some(1..2)),
+    case("a[^bc]d", "aed", Some(0..3)),
+    case("a[^bc]d", "abd", None),
+    case("a[^-b]c", "adc", Some(0..3)),
+    case("a[^-b]c", "a-c", None),
+    // leftmost-first: the first alternative that matches wins, where POSIX takes the longest
+    case("a|ab", "ab", Some(0..1)),
+    case("(a|ab)(c|bcd)", "abcd", Some(0..4)),
+    case("a{2,3}", "aaaa", Some(0..3)),
+    // lazy quantifiers repeat as few times as the rest of the pattern allows
+    case("a+?", "aaa", Some(0..1)),
+    case("a*?b", "aab", Some(0..3)),
+    case("a??b", "ab", Some(0..2)),
+    case("a{2,}?", "aaaa", Some(0..2)),
+    case("<.+?>", "<a><b>", Some(0..3)),
+    // shorthands, counted repetition, backreferences and case folding
+    case("x{1,150}", "xxx", Some(0..3)),
+    case("\\d+", "ab123c", Some(2..5)),
+    case("\\w+\\s\\w+", "hi there", Some(0..8)),
+    case("x{3,5}", "xxxxxxx", Some(0..5)),
+    case("(a)\\1", "xaa", Some(1..3)),
+    // a backreference matches the group's pattern again, not the text it matched
+    case("(a|b)\\1", "ab", Some(0..2)),
+    case("(?i:abc)", "xAbC", Some(1..4)),
+    case("(?i:k)", "\u{212A}", Some(0..3)),
+    // offsets are in bytes
+    case("é+", "café", Some(3..5)),
+    case(".b", "éb", Some(0..3)),
+];
+
+// how an engine's answer for a case differed from what was expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub case: Case,
+    pub engine: Engine,
+    pub what: Finding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    // the pattern didn't compile
+    Error(String),
+    // find returned this instead
+    Found(Option<Range<usize>>),
+    // is_match said whether there is a match, and disagreed with expect
+    IsMatch(bool),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub checked: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+fn show(range: &Option<Range<usize>>) -> String {
+    match range {
+        Some(r) => format!("{}..{}", r.start, r.end),
+        None => "no match".to_string(),
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "checked {} cases, {} divergences",
+            self.checked,
+            self.divergences.len()
+        )?;
+
+        for d in &self.divergences {
+            let Case {
+                pattern,
+                haystack,
+                expect,
+            } = &d.case;
+            let found = match &d.what {
+                Finding::Error(e) => format!("error: {e}"),
+                Finding::Found(found) => show(found),
+                Finding::IsMatch(true) => "is_match true".to_string(),
+                Finding::IsMatch(false) => "is_match false".to_string(),
+            };
+            writeln!(
+                f,
+                "  {pattern:?} on {haystack:?} ({:?}): expected {}, found {found}",
+                d.engine,
+                show(expect)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// run the bundled corpus, see run_cases
+pub fn run() -> Report {
+    run_cases(CORPUS)
+}
+
+// Check every case with each engine: find has to return the expected match and is_match has to
+// agree with it
+pub fn run_cases(cases: &[Case]) -> Report {
+    let mut report = Report::default();
+
+    for case in cases {
+        report.checked += 1;
+        for engine in [Engine::Dfa, Engine::LazyDfa, Engine::Backtrack] {
+            let what = match RegexBuilder::new(case.pattern).engine(engine).build() {
+                // patterns the DFA can't match alone, like large repeats, only go to the backtracker
+                Err(e) if matches!(e.kind, ErrorKind::Disabled(_)) => continue,
+                Err(e) => Some(Finding::Error(e.to_string())),
+                Ok(regex) => {
+                    let found = regex.find(case.haystack).map(|m| m.range());
+                    let is_match = regex.is_match(case.haystack);
+                    if found != case.expect {
+                        Some(Finding::Found(found))
+                    } else if is_match != case.expect.is_some() {
+                        Some(Finding::IsMatch(is_match))
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            if let Some(what) = what {
+                report.divergences.push(Divergence {
+                    case: case.clone(),
+                    engine,
+                    what,
+                });
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus() {
+        let report = run();
+        assert!(report.is_ok(), "{report}");
+        assert_eq!(report.checked, CORPUS.len());
+
+        let wrong = [case("a|ab", "ab", Some(0..2)), case("a**b", "ab", None)];
+        let report = run_cases(&wrong);
+        assert_eq!(report.divergences.len(), 6);
+        assert!(matches!(report.divergences[5].what, Finding::Error(_)));
+        assert_eq!(report.divergences[0].what, Finding::Found(Some(0..1)));
+        let text = report.to_string();
+        assert!(text.starts_with("checked 2 cases, 6 divergences\n"));
+        assert!(text.contains("  \"a|ab\" on \"ab\" (Dfa): expected 0..2, found 0..1\n"));
+    }
+}
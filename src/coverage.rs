@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::error::RegexError;
+use crate::nfa::{Edge, Nfa, AT_END, AT_START};
+use crate::parse::{parse, try_lex, Span};
+use crate::transition_table::{NfaState, Transition};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub pattern: String,
+    // number of inputs that exercised each pattern region
+    pub hits: BTreeMap<Span, usize>,
+    // whether each input matched the pattern at all
+    pub matched: Vec<bool>,
+}
+
+impl CoverageReport {
+    pub fn covered(&self) -> Vec<Span> {
+        self.hits
+            .iter()
+            .filter(|(_, n)| **n > 0)
+            .map(|(span, _)| *span)
+            .collect()
+    }
+
+    pub fn uncovered(&self) -> Vec<Span> {
+        self.hits
+            .iter()
+            .filter(|(_, n)| **n == 0)
+            .map(|(span, _)| *span)
+            .collect()
+    }
+
+    // the pattern text of each region that no input exercised
+    pub fn uncovered_text(&self) -> Vec<&str> {
+        self.uncovered()
+            .into_iter()
+            .map(|span| &self.pattern[span.start..span.end])
+            .collect()
+    }
+}
+
+// all consuming edges out of the given states that accept c, with the state each is out of
+fn steps<'a>(nfa: &'a Nfa, states: &BTreeSet<NfaState>, c: char) -> Vec<(NfaState, &'a Edge)> {
+    let mut ret = Vec::new();
+
+    for state in states {
+        for edge in nfa.edges(*state) {
+            if edge.transition.accepts(c) {
+                ret.push((*state, edge));
+            }
+        }
+    }
+
+    ret
+}
+
+// the spans of every edge lying on an accepting path for input, or None if input doesn't match
+fn exercised(nfa: &Nfa, input: &str) -> Option<BTreeSet<Span>> {
+    let chars: Vec<char> = input.chars().collect();
+
+    // forward pass: states reachable after each prefix
+    let mut reachable = vec![nfa.closure(vec![NfaState::START], &AT_START)];
+    for c in &chars {
+        let ends = steps(nfa, reachable.last().unwrap(), *c)
+            .into_iter()
+            .map(|(_, edge)| edge.end)
+            .collect();
+        reachable.push(nfa.epsilon_closure(ends));
+    }
+
+    let last = reachable.last().unwrap().iter().copied().collect();
+    if !nfa.closure(last, &AT_END).contains(&NfaState::ACCEPTING) {
+        return None;
+    }
+
+    // backward pass: keep only the edges that can still reach the accepting state
+    let mut spans = BTreeSet::new();
+    let mut live = BTreeSet::from([NfaState::ACCEPTING]);
+
+    for (i, c) in chars.iter().enumerate().rev() {
+        let mut prev_live = BTreeSet::new();
+
+        // the end anchor can only be crossed after the last character
+        let through: &[Transition] = if i + 1 == chars.len() {
+            &AT_END
+        } else {
+            &[Transition::Epsilon]
+        };
+        for (start, edge) in steps(nfa, &reachable[i], *c) {
+            if nfa.closure(vec![edge.end], through).is_disjoint(&live) {
+                continue;
+            }
+
+            prev_live.insert(start);
+            spans.extend(edge.span);
+        }
+
+        live = prev_live;
+    }
+
+    Some(spans)
+}
+
+pub fn coverage(pattern: &str, inputs: &[&str]) -> Result<CoverageReport, RegexError> {
+    let nfa = parse(try_lex(pattern)?)?;
+
+    let mut hits: BTreeMap<Span, usize> = nfa.spans().values().map(|span| (*span, 0)).collect();
+    let mut matched = Vec::new();
+
+    for input in inputs {
+        match exercised(&nfa, input) {
+            Some(spans) => {
+                for span in spans {
+                    *hits.entry(span).or_default() += 1;
+                }
+                matched.push(true);
+            }
+            None => matched.push(false),
+        }
+    }
+
+    Ok(CoverageReport {
+        pattern: pattern.to_string(),
+        hits,
+        matched,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uncovered_alternative() {
+        let report = coverage("ab|cd|e+", &["ab", "eee", "xyz"]).unwrap();
+
+        assert_eq!(report.matched, vec![true, true, false]);
+        assert_eq!(report.uncovered_text(), vec!["c", "d"]);
+        assert_eq!(report.hits.get(&Span::new(6, 7)), Some(&1));
+    }
+
+    #[test]
+    fn test_optional_branch() {
+        let report = coverage("a(bc)?[0-9]", &["a1", "a2"]).unwrap();
+
+        assert_eq!(report.uncovered_text(), vec!["b", "c"]);
+        assert_eq!(report.covered(), vec![Span::new(0, 1), Span::new(6, 11)]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Write;
+
+use crate::dot::quote;
+use crate::prog::Program;
+
+// How a branch of a backtracking search ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    // a choice point (a Split, or a counted loop that can leave or go on) where the search went
+    // on with the first child and saved the second to backtrack to
+    Split,
+    // an instruction didn't match the input there
+    Failed,
+    // the search had already been at this instruction and offset, and memoization cut it short
+    Memoized,
+    Matched,
+    // saved to backtrack to, but a match was found first
+    Unexplored,
+}
+
+// A run of steps of the search between two decisions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub parent: Option<usize>,
+    // the preferred alternative first
+    pub children: Vec<usize>,
+    // the (instruction, byte offset) pairs explored, in order
+    pub steps: Vec<(usize, usize)>,
+    pub outcome: Outcome,
+}
+
+// The decision tree of one backtracking search: every choice point it came to, which alternative
+// it tried first, where each one failed and what it backtracked to. There is a root for each
+// offset the search tried to start a match at. order is the timeline, the branches in the order
+// the search explored them, so stepping through it replays the search, and each branch after
+// the first of its root is a backtrack. From Regex::backtrack_tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecisionTree {
+    pub branches: Vec<Branch>,
+    pub order: Vec<usize>,
+}
+
+impl DecisionTree {
+    // a root for the next offset a match can start at, explored later
+    pub(crate) fn root(&mut self) -> usize {
+        self.add(None)
+    }
+
+    fn add(&mut self, parent: Option<usize>) -> usize {
+        self.branches.push(Branch {
+            parent,
+            children: Vec::new(),
+            steps: Vec::new(),
+            outcome: Outcome::Unexplored,
+        });
+        let id = self.branches.len() - 1;
+        if let Some(parent) = parent {
+            self.branches[parent].children.push(id);
+        }
+        id
+    }
+
+    // the search moves on to branch, which fails unless it ends otherwise
+    pub(crate) fn enter(&mut self, branch: usize) {
+        self.branches[branch].outcome = Outcome::Failed;
+        self.order.push(branch);
+    }
+
+    // branch is a choice point: the search enters its first child and saves the second
+    pub(crate) fn split(&mut self, branch: usize) -> (usize, usize) {
+        self.branches[branch].outcome = Outcome::Split;
+        let (first, second) = (self.add(Some(branch)), self.add(Some(branch)));
+        self.enter(first);
+        (first, second)
+    }
+
+    pub(crate) fn step(&mut self, branch: usize, pc: usize, pos: usize) {
+        self.branches[branch].steps.push((pc, pos));
+    }
+
+    pub(crate) fn end(&mut self, branch: usize, outcome: Outcome) {
+        self.branches[branch].outcome = outcome;
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.branches.len()).filter(|&b| self.branches[b].parent.is_none())
+    }
+
+    // the branch that found the match, if any
+    pub fn matched(&self) -> Option<usize> {
+        (0..self.branches.len()).find(|&b| self.branches[b].outcome == Outcome::Matched)
+    }
+
+    // branch and its ancestors, from its root down
+    pub fn path(&self, branch: usize) -> Vec<usize> {
+        let mut path = vec![branch];
+        while let Some(parent) = self.branches[*path.last().unwrap()].parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    fn depth(&self, branch: usize) -> usize {
+        self.path(branch).len() - 1
+    }
+
+    // every (instruction, offset) pair explored, which is the work the search did
+    pub fn steps(&self) -> usize {
+        self.branches.iter().map(|b| b.steps.len()).sum()
+    }
+
+    // how many times the search went back to a choice point to try its other alternative
+    pub fn backtracks(&self) -> usize {
+        let first_children = self.branches.iter().filter_map(|b| b.children.first());
+        let resumed = self.order.len() - first_children.count();
+        resumed - self.roots().filter(|&r| self.order.contains(&r)).count()
+    }
+
+    // a branch's steps as "pc@offset .. pc@offset"
+    fn span(&self, branch: usize) -> String {
+        let steps = &self.branches[branch].steps;
+        match (steps.first(), steps.last()) {
+            (Some(first), Some(last)) if first != last => {
+                format!("{}@{} .. {}@{}", first.0, first.1, last.0, last.1)
+            }
+            (Some(first), _) => format!("{}@{}", first.0, first.1),
+            _ => "no steps".to_string(),
+        }
+    }
+
+    // The timeline, a line per branch in the order the search explored them, indented by depth:
+    // the steps taken before it, its instructions and offsets, how many steps it took and how it
+    // ended. prog gives the instruction the branch ended on.
+    pub fn timeline(&self, prog: &Program) -> String {
+        let mut out = String::new();
+        let mut step = 0;
+        for &b in &self.order {
+            let branch = &self.branches[b];
+            let last = branch.steps.last().map(|(pc, _)| &prog.insts[*pc]);
+            let from = match branch.parent {
+                None => "start".to_string(),
+                Some(p) if self.branches[p].children[0] == b => format!("from #{p}"),
+                Some(p) => format!("back to #{p}"),
+            };
+            let _ = writeln!(
+                out,
+                "{step:>5} {}#{b} ({from}) {}: {} steps, {:?}{}",
+                "  ".repeat(self.depth(b)),
+                self.span(b),
+                branch.steps.len(),
+                branch.outcome,
+                last.map_or(String::new(), |inst| format!(" at {inst:?}")),
+            );
+            step += branch.steps.len();
+        }
+        out
+    }
+
+    // The tree in DOT: a node per branch with its steps, green if it matched, red if it failed
+    // and gray if memoization cut it short or it was never explored. Edges are labelled with the
+    // alternative they took (1 preferred, 2 backtracked to) and nodes with when they were entered.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        for (b, branch) in self.branches.iter().enumerate() {
+            let color = match branch.outcome {
+                Outcome::Matched => "green",
+                Outcome::Failed => "red",
+                Outcome::Memoized | Outcome::Unexplored => "gray",
+                Outcome::Split => "black",
+            };
+            let when = self
+                .order
+                .iter()
+                .position(|o| *o == b)
+                .map_or("never".to_string(), |i| format!("t{i}"));
+            let label = format!("#{b} {when}\n{}\n{:?}", self.span(b), branch.outcome);
+            let _ = writeln!(
+                out,
+                "b{b} [label = \"{}\", color = {color}];",
+                quote(&label)
+            );
+            for (i, child) in branch.children.iter().enumerate() {
+                let _ = writeln!(out, "b{b} -> b{child} [label = \"{}\"];", i + 1);
+            }
+        }
+
+        format!("digraph backtrack {{\nnode [shape = box];\n{out}}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Regex;
+
+    use super::*;
+
+    #[test]
+    fn test_decision_tree() {
+        let regex = Regex::new("(a|ab)c").unwrap();
+        let tree = regex.backtrack_tree("abc");
+        let matched = tree.matched().unwrap();
+        // a then c fails, so the search backtracks to try ab
+        assert_eq!(tree.backtracks(), 1);
+        let path = tree.path(matched);
+        assert_eq!(path[0], 0);
+        let split = path[path.len() - 2];
+        let [first, second] = tree.branches[split].children[..] else {
+            panic!("a split has two children");
+        };
+        assert_eq!(second, matched);
+        assert_eq!(tree.branches[first].outcome, Outcome::Failed);
+        assert_eq!(tree.order.last(), Some(&matched));
+
+        let timeline = tree.timeline(regex.prog());
+        assert_eq!(timeline.lines().count(), tree.order.len());
+        assert!(timeline.contains(&format!("#{matched} (back to #{split})")));
+        let dot = tree.to_dot();
+        assert!(dot.contains(&format!("b{split} -> b{second} [label = \"2\"]")));
+        assert!(dot.contains("color = green"));
+
+        // without a match, each way of going on fails
+        let tree = regex.backtrack_tree("ab");
+        assert_eq!(tree.matched(), None);
+        assert_eq!(tree.roots().count(), 1);
+        assert_eq!(tree.backtracks(), 1);
+    }
+}
@@ -0,0 +1,592 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::captures::Match;
+use crate::dfa::Dfa;
+use crate::id::{PredicateId, StateId};
+use crate::predicate;
+use crate::transition_table::Transition;
+use crate::CharMap;
+
+// no edge
+const DEAD: u32 = u32::MAX;
+
+// A Dfa compiled for matching: states are u32 indices and the edges one flat Vec with a row per
+// state and a column per class of characters, so following an edge is a single array lookup.
+// Characters every state treats alike share a class, e.g. all the characters without a literal
+// edge anywhere, which keeps the rows short. ASCII characters find their class in an array,
+// others by a binary search over the non-ASCII literals (and by their predicate class if the
+// pattern has \p{name}).
+#[derive(Debug, Clone)]
+pub struct DenseDfa {
+    table: Vec<u32>,
+    classes: usize,
+    ascii: [u32; 128],
+    // the class of every non-ASCII literal, sorted
+    literals: Vec<(char, u32)>,
+    // the class of each predicate mask with edges of its own, sorted
+    pred_classes: Vec<(u64, u32)>,
+    predicates: Vec<PredicateId>,
+    start: u32,
+    unanchored_start: Option<u32>,
+    inner_start: u32,
+    accepting: Vec<bool>,
+    accepting_at_end: Vec<bool>,
+    char_map: CharMap,
+}
+
+impl DenseDfa {
+    pub fn new(dfa: &Dfa) -> Self {
+        let edges: Vec<HashMap<Transition, StateId>> = dfa
+            .states()
+            .map(|s| dfa.transitions(s).into_iter().collect())
+            .collect();
+        let mut literals = BTreeSet::new();
+        let mut masks = BTreeSet::new();
+        for (transition, _) in edges.iter().flatten() {
+            match transition {
+                Transition::Literal(c) => literals.insert(*c),
+                Transition::PredClass(mask) => masks.insert(*mask),
+                _ => false,
+            };
+        }
+
+        // where each state goes on a character with the given edges, in lookup order
+        let column = |lookup: &[Transition]| -> Vec<u32> {
+            edges
+                .iter()
+                .map(|row| {
+                    let target = lookup.iter().find_map(|t| row.get(t));
+                    target.map_or(DEAD, |s| s.as_usize() as u32)
+                })
+                .collect()
+        };
+        let mut columns: Vec<Vec<u32>> = vec![column(&[Transition::Wildcard])];
+        let mut class_of = |column: Vec<u32>| -> u32 {
+            let class = columns
+                .iter()
+                .position(|c| *c == column)
+                .unwrap_or_else(|| {
+                    columns.push(column);
+                    columns.len() - 1
+                });
+            class as u32
+        };
+        let char_class = |c: char, class_of: &mut dyn FnMut(Vec<u32>) -> u32| {
+            let mut lookup = vec![Transition::Literal(c)];
+            lookup.extend(dfa.pred_class(c));
+            lookup.push(Transition::Wildcard);
+            class_of(column(&lookup))
+        };
+
+        let mut ascii = [0; 128];
+        for (i, class) in ascii.iter_mut().enumerate() {
+            *class = char_class(char::from(i as u8), &mut class_of);
+        }
+        let literals: Vec<(char, u32)> = literals
+            .into_iter()
+            .filter(|c| !c.is_ascii())
+            .map(|c| (c, char_class(c, &mut class_of)))
+            .collect();
+        let pred_classes: Vec<(u64, u32)> = masks
+            .into_iter()
+            .map(|mask| {
+                let lookup = [Transition::PredClass(mask), Transition::Wildcard];
+                (mask, class_of(column(&lookup)))
+            })
+            .collect();
+
+        let classes = columns.len();
+        let mut table = vec![DEAD; edges.len() * classes];
+        for (class, column) in columns.iter().enumerate() {
+            for (state, target) in column.iter().enumerate() {
+                table[state * classes + class] = *target;
+            }
+        }
+
+        Self {
+            table,
+            classes,
+            ascii,
+            literals,
+            pred_classes,
+            start: dfa.start().as_usize() as u32,
+            unanchored_start: dfa.unanchored_start().map(|s| s.as_usize() as u32),
+            inner_start: dfa.inner_start().as_usize() as u32,
+            accepting: dfa.states().map(|s| dfa.is_accepting(s)).collect(),
+            accepting_at_end: dfa.states().map(|s| dfa.accepts_at_end(s)).collect(),
+            char_map: dfa.char_map(),
+            predicates: dfa.predicates().to_vec(),
+        }
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.accepting.len()
+    }
+
+    // the number of columns of the table, one per class of characters all states treat alike
+    pub fn class_count(&self) -> usize {
+        self.classes
+    }
+
+    // the size of the table in bytes
+    pub fn table_bytes(&self) -> usize {
+        self.table.len() * size_of::<u32>()
+    }
+
+    // the column of the (mapped) character c
+    fn class(&self, c: char) -> u32 {
+        if c.is_ascii() {
+            return self.ascii[c as usize];
+        }
+        if let Ok(i) = self.literals.binary_search_by_key(&c, |(l, _)| *l) {
+            return self.literals[i].1;
+        }
+        if self.pred_classes.is_empty() {
+            return 0;
+        }
+        let mask = predicate::mask(&self.predicates, c);
+        self.pred_classes
+            .binary_search_by_key(&mask, |(m, _)| *m)
+            .map_or(0, |i| self.pred_classes[i].1)
+    }
+
+    pub fn start(&self) -> StateId {
+        StateId::new(self.start as usize)
+    }
+
+    // the state reached by reading c in state, if any, as Dfa::next_state
+    pub fn next_state(&self, state: StateId, c: char) -> Option<StateId> {
+        self.next_id(state, c)
+    }
+
+    pub fn is_accepting(&self, state: StateId) -> bool {
+        self.accepting[state.as_usize()]
+    }
+
+    // whether the DFA accepts the whole of input, like Dfa::simulate without the reasons
+    pub fn is_match(&self, input: &str) -> bool {
+        Rows::is_match(self, input)
+    }
+
+    // Whether input contains a match anywhere, like Dfa::contains_match
+    pub fn contains_match(&self, input: &str) -> bool {
+        Rows::contains_match(self, input)
+    }
+
+    // the length of the longest accepted prefix of input, like Dfa::longest_prefix
+    pub fn longest_prefix(&self, input: &str) -> Option<usize> {
+        self.longest_prefix_from(self.start, input)
+    }
+
+    // the leftmost-longest match anywhere in haystack, like Dfa::find
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        Rows::find(self, haystack)
+    }
+
+    // The table in the layout DenseDfaRef::from_bytes reads, to be written to a file or embedded
+    // with include_bytes!. The char map is a function and isn't saved, so the loaded DFA matches
+    // characters as they are. Predicates are only registered in this process, so a DFA with
+    // \p{name} edges can't be saved.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FormatError> {
+        if !self.predicates.is_empty() {
+            return Err(FormatError::Predicates);
+        }
+        let states = self.state_count();
+        let header = [
+            VERSION,
+            self.classes as u32,
+            states as u32,
+            self.start,
+            self.unanchored_start.unwrap_or(DEAD),
+            self.inner_start,
+            self.literals.len() as u32,
+        ];
+        let flags =
+            (0..states).map(|s| self.accepting[s] as u32 | (self.accepting_at_end[s] as u32) << 1);
+        let literals = self
+            .literals
+            .iter()
+            .flat_map(|&(c, class)| [c as u32, class]);
+        let words: Vec<u32> = header
+            .into_iter()
+            .chain(self.ascii)
+            .chain(literals)
+            .chain(self.table.iter().copied())
+            .chain(flags)
+            .collect();
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(words.iter().flat_map(|w| w.to_le_bytes()));
+        Ok(bytes)
+    }
+}
+
+// the first four bytes of DenseDfa::to_bytes
+const MAGIC: &[u8; 4] = b"RXDF";
+const VERSION: u32 = 1;
+// the words before the ASCII classes: version, classes, states, the three starts and the number
+// of literals
+const HEADER: usize = 7;
+
+// Why bytes aren't a saved DenseDfa
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    // the DFA has \p{name} edges (only from to_bytes)
+    Predicates,
+    // the bytes don't start with the format's magic and a version this crate reads
+    NotADfa,
+    // the length doesn't match the sizes in the header
+    Length { expected: usize, found: usize },
+    // a state, class or character out of range, at this byte offset
+    Corrupt { offset: usize },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Predicates => write!(f, "a DFA with predicates can't be saved"),
+            Self::NotADfa => write!(f, "not a saved DFA, or saved by another version"),
+            Self::Length { expected, found } => {
+                write!(f, "expected {expected} bytes, found {found}")
+            }
+            Self::Corrupt { offset } => write!(f, "corrupt DFA at byte {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+// A DenseDfa read straight out of the bytes DenseDfa::to_bytes wrote, e.g. a file mapped into
+// memory or a table embedded with include_bytes!. Loading checks the bytes once and builds
+// nothing, so it takes no time beyond that check, and matching reads the table where it lies.
+#[derive(Debug, Clone, Copy)]
+pub struct DenseDfaRef<'a> {
+    bytes: &'a [u8],
+    classes: usize,
+    states: usize,
+    start: u32,
+    unanchored_start: Option<u32>,
+    inner_start: u32,
+    literals: usize,
+}
+
+// the little-endian u32 at word i of bytes, after the magic
+fn word(bytes: &[u8], i: usize) -> u32 {
+    let at = MAGIC.len() + i * 4;
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+impl<'a> DenseDfaRef<'a> {
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, FormatError> {
+        let header_len = MAGIC.len() + (HEADER + 128) * 4;
+        if bytes.len() < header_len || &bytes[..4] != MAGIC || word(bytes, 0) != VERSION {
+            return Err(FormatError::NotADfa);
+        }
+        let [classes, states, literals] = [1, 2, 6].map(|i| word(bytes, i) as usize);
+        let dfa = Self {
+            bytes,
+            classes,
+            states,
+            start: word(bytes, 3),
+            unanchored_start: Some(word(bytes, 4)).filter(|&s| s != DEAD),
+            inner_start: word(bytes, 5),
+            literals,
+        };
+
+        let expected = classes
+            .checked_mul(states)
+            .and_then(|cells| cells.checked_add(literals.checked_mul(2)? + states))
+            .and_then(|words| words.checked_mul(4)?.checked_add(header_len));
+        if expected != Some(bytes.len()) {
+            return Err(FormatError::Length {
+                expected: expected.unwrap_or(usize::MAX),
+                found: bytes.len(),
+            });
+        }
+
+        // every word that is read as a state or class has to be one
+        let corrupt = |i: usize| FormatError::Corrupt {
+            offset: MAGIC.len() + i * 4,
+        };
+        let state = |i: usize, dead: bool| {
+            let s = word(bytes, i);
+            if (s as usize) < states || dead && s == DEAD {
+                Ok(())
+            } else {
+                Err(corrupt(i))
+            }
+        };
+        state(3, false)?;
+        state(4, true)?;
+        state(5, false)?;
+        let class = |i: usize| match word(bytes, i) as usize {
+            c if c < classes => Ok(()),
+            _ => Err(corrupt(i)),
+        };
+        for i in HEADER..HEADER + 128 {
+            class(i)?;
+        }
+        let mut last = '\u{7f}';
+        for i in (0..literals).map(|l| dfa.literal_word(l)) {
+            match char::from_u32(word(bytes, i)) {
+                Some(c) if c > last => last = c,
+                _ => return Err(corrupt(i)),
+            }
+            class(i + 1)?;
+        }
+        for i in (0..states * classes).map(|cell| dfa.table_word() + cell) {
+            state(i, true)?;
+        }
+
+        Ok(dfa)
+    }
+
+    fn literal_word(&self, l: usize) -> usize {
+        HEADER + 128 + l * 2
+    }
+
+    fn table_word(&self) -> usize {
+        self.literal_word(self.literals)
+    }
+
+    fn flags(&self, state: u32) -> u32 {
+        word(
+            self.bytes,
+            self.table_word() + self.states * self.classes + state as usize,
+        )
+    }
+
+    fn class(&self, c: char) -> u32 {
+        if c.is_ascii() {
+            return word(self.bytes, HEADER + c as usize);
+        }
+        let (mut low, mut high) = (0, self.literals);
+        while low < high {
+            let mid = (low + high) / 2;
+            let at = self.literal_word(mid);
+            match word(self.bytes, at).cmp(&(c as u32)) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return word(self.bytes, at + 1),
+            }
+        }
+        0
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.classes
+    }
+
+    pub fn start(&self) -> StateId {
+        StateId::new(self.start as usize)
+    }
+
+    pub fn next_state(&self, state: StateId, c: char) -> Option<StateId> {
+        self.next_id(state, c)
+    }
+
+    pub fn is_accepting(&self, state: StateId) -> bool {
+        self.accepting(state.as_usize() as u32)
+    }
+
+    pub fn is_match(&self, input: &str) -> bool {
+        Rows::is_match(self, input)
+    }
+
+    pub fn contains_match(&self, input: &str) -> bool {
+        Rows::contains_match(self, input)
+    }
+
+    pub fn longest_prefix(&self, input: &str) -> Option<usize> {
+        self.longest_prefix_from(self.start, input)
+    }
+
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        Rows::find(self, haystack)
+    }
+}
+
+// What matching needs from a dense table, so DenseDfa and DenseDfaRef share one implementation
+trait Rows {
+    fn next(&self, state: u32, c: char) -> u32;
+    fn accepting(&self, state: u32) -> bool;
+    fn accepting_at_end(&self, state: u32) -> bool;
+    // the anchored, unanchored and inner start
+    fn starts(&self) -> (u32, Option<u32>, u32);
+
+    fn next_id(&self, state: StateId, c: char) -> Option<StateId> {
+        match self.next(state.as_usize() as u32, c) {
+            DEAD => None,
+            next => Some(StateId::new(next as usize)),
+        }
+    }
+
+    fn is_match(&self, input: &str) -> bool {
+        let mut state = self.starts().0;
+        for c in input.chars() {
+            state = self.next(state, c);
+            if state == DEAD {
+                return false;
+            }
+        }
+        self.accepting_at_end(state)
+    }
+
+    fn contains_match(&self, input: &str) -> bool {
+        let (start, unanchored, _) = self.starts();
+        let mut state = unanchored.unwrap_or(start);
+        for c in input.chars() {
+            if self.accepting(state) {
+                return true;
+            }
+            state = self.next(state, c);
+            if state == DEAD {
+                return false;
+            }
+        }
+        self.accepting_at_end(state)
+    }
+
+    fn longest_prefix_from(&self, mut state: u32, input: &str) -> Option<usize> {
+        let mut longest = None;
+        for (i, c) in input.char_indices() {
+            if self.accepting(state) {
+                longest = Some(i);
+            }
+            state = self.next(state, c);
+            if state == DEAD {
+                return longest;
+            }
+        }
+        if self.accepting_at_end(state) {
+            longest = Some(input.len());
+        }
+        longest
+    }
+
+    fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        let (start, unanchored, inner) = self.starts();
+        if unanchored.is_some() && !self.contains_match(haystack) {
+            return None;
+        }
+        haystack
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([haystack.len()])
+            .find_map(|i| {
+                let from = if i == 0 { start } else { inner };
+                let len = self.longest_prefix_from(from, &haystack[i..])?;
+                Some(Match::new(haystack, i, i + len))
+            })
+    }
+}
+
+impl Rows for DenseDfa {
+    fn next(&self, state: u32, c: char) -> u32 {
+        let class = self.class((self.char_map)(c));
+        self.table[state as usize * self.classes + class as usize]
+    }
+
+    fn accepting(&self, state: u32) -> bool {
+        self.accepting[state as usize]
+    }
+
+    fn accepting_at_end(&self, state: u32) -> bool {
+        self.accepting_at_end[state as usize]
+    }
+
+    fn starts(&self) -> (u32, Option<u32>, u32) {
+        (self.start, self.unanchored_start, self.inner_start)
+    }
+}
+
+impl Rows for DenseDfaRef<'_> {
+    fn next(&self, state: u32, c: char) -> u32 {
+        let cell = state as usize * self.classes + self.class(c) as usize;
+        word(self.bytes, self.table_word() + cell)
+    }
+
+    fn accepting(&self, state: u32) -> bool {
+        self.flags(state) & 1 != 0
+    }
+
+    fn accepting_at_end(&self, state: u32) -> bool {
+        self.flags(state) & 2 != 0
+    }
+
+    fn starts(&self) -> (u32, Option<u32>, u32) {
+        (self.start, self.unanchored_start, self.inner_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_regex, Regex};
+
+    #[test]
+    fn test_dense_dfa() {
+        let dfa = compile_regex("[a-z]+[0-9]?é|x$");
+        let dense = DenseDfa::new(&dfa);
+        assert_eq!(dense.state_count(), dfa.state_count());
+        // [a-z] minus x, x, [0-9], é and everything else
+        assert_eq!(dense.class_count(), 5);
+        for input in ["ab1é", "abé", "x", "xé", "ab12é", "", "é", "Aé", "ab1"] {
+            let expected = dfa.simulate(input.to_string()).is_ok();
+            assert_eq!(dense.is_match(input), expected, "{input:?}");
+            assert_eq!(dense.longest_prefix(input), dfa.longest_prefix(input));
+        }
+
+        // searches start where Dfa's do
+        let regex = Regex::new("^ab|b+$|c").unwrap();
+        let dense = DenseDfa::new(regex.dfa());
+        for h in ["xab", "abb", "xbb", "cab", "", "zz"] {
+            let expected = regex.dfa().find(h).map(|m| m.range());
+            assert_eq!(dense.find(h).map(|m| m.range()), expected, "{h:?}");
+            assert_eq!(dense.contains_match(h), regex.dfa().contains_match(h));
+        }
+    }
+
+    #[test]
+    fn test_dfa_bytes() {
+        let regex = Regex::new("^ab|b+$|cé?").unwrap();
+        let bytes = regex.dfa().to_bytes().unwrap();
+        let loaded = Dfa::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.state_count(), regex.dfa().state_count());
+        for h in ["xab", "abb", "xbb", "cé", "xcéé", "", "zz", "b"] {
+            let expected = regex.dfa().find(h).map(|m| m.range());
+            assert_eq!(loaded.find(h).map(|m| m.range()), expected, "{h:?}");
+            assert_eq!(
+                loaded.is_match(h),
+                regex.dfa().simulate(h.to_string()).is_ok()
+            );
+        }
+
+        // damaged bytes are rejected rather than read out of bounds
+        assert_eq!(Dfa::from_bytes(b"RXDF").unwrap_err(), FormatError::NotADfa);
+        let short = &bytes[..bytes.len() - 4];
+        assert!(matches!(
+            Dfa::from_bytes(short),
+            Err(FormatError::Length { .. })
+        ));
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 4 * (loaded.state_count() + 1);
+        corrupt[last..last + 4].copy_from_slice(&7777u32.to_le_bytes());
+        assert!(matches!(
+            Dfa::from_bytes(&corrupt),
+            Err(FormatError::Corrupt { .. })
+        ));
+
+        let pred = crate::RegexBuilder::new("\\p{bytes_test}")
+            .predicate("bytes_test", |c| c == 'q')
+            .build()
+            .unwrap();
+        assert_eq!(pred.dfa().to_bytes(), Err(FormatError::Predicates));
+    }
+}
@@ -1,22 +1,334 @@
 #![allow(non_snake_case)]
 
-use std::collections::{BTreeSet, HashMap};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, VecDeque};
+use std::fmt;
 
-use crate::nfa::Nfa;
-use crate::transition_table::{NfaState, StateContainer, Transition, TransitionTable};
+use crate::captures::Match;
+use crate::dense::{DenseDfa, DenseDfaRef, FormatError};
+use crate::dot::{dfa_entries, edge_labels, quote, state_names, DotOptions};
+use crate::generate::Rng;
+use crate::id::{PatternId, PredicateId, StateId};
+use crate::iter::{DfaMatches, LanguageIter};
+use crate::meta::InvalidUtf8;
+use crate::nfa::{Nfa, AT_END, AT_START};
+use crate::predicate;
+use crate::transition_table::{
+    Index, NfaState, StateContainer, TableBackend, Transition, TransitionTable,
+};
+use crate::CharMap;
 
+// Why a string was rejected. Offsets are in bytes and expected lists the edges that could still
+// lead to a match from where the simulation stopped.
 #[derive(Debug, PartialEq)]
 pub enum SimError {
-    #[allow(dead_code)] // only accessed via Debug
-    NoMatch(char),
-    EndOfString,
-    NoTransitions,
-    Premature,
+    // the character at offset can't be matched here
+    NoMatch {
+        found: char,
+        offset: usize,
+        expected: Vec<Transition>,
+    },
+    // the input ended while more was needed
+    EndOfString {
+        expected: Vec<Transition>,
+    },
+    // no continuation of the input from offset on can match anymore
+    DeadState {
+        offset: usize,
+    },
+    // everything before offset matched but the input continues
+    Premature {
+        offset: usize,
+    },
+    // the bytes at offset aren't valid UTF-8, see simulate_bytes
+    InvalidUtf8 {
+        offset: usize,
+    },
 }
 
+fn describe(expected: &[Transition]) -> String {
+    let labels: Vec<_> = expected
+        .iter()
+        .map(|t| match t {
+            Transition::Wildcard => "any character".to_string(),
+            Transition::EndAnchor => "the end of the input".to_string(),
+            t => t.dot_label(),
+        })
+        .collect();
+
+    labels.join(" or ")
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatch {
+                found,
+                offset,
+                expected,
+            } => write!(
+                f,
+                "unexpected '{found}' at {offset}, expected {}",
+                describe(expected)
+            ),
+            Self::EndOfString { expected } => {
+                write!(f, "input ended early, expected {}", describe(expected))
+            }
+            Self::DeadState { offset } => write!(f, "nothing can match from {offset} on"),
+            Self::Premature { offset } => {
+                write!(f, "matched up to {offset} but the input continues")
+            }
+            Self::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 at {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for SimError {}
+
+// How Dfa::simulate_bytes reads bytes as characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteMode {
+    // UTF-8 text, with invalid sequences rejected or read as U+FFFD by the policy
+    Utf8(InvalidUtf8),
+    // Every byte is one character, the one with the byte's value (U+0000 to U+00FF), so binary
+    // data can be matched byte by byte: \xff matches the byte 0xff and . any byte.
+    #[default]
+    Raw,
+}
+
+// A string from a batch that wasn't treated as expected, with why it was rejected (None if it
+// was expected to be rejected but was accepted)
+#[derive(Debug, PartialEq)]
+pub struct BatchFailure {
+    pub index: usize,
+    pub input: String,
+    pub error: Option<SimError>,
+}
+
+// the outcome of checking a batch of strings, see Dfa::accepts_all_of
+#[derive(Debug, PartialEq)]
+pub struct BatchReport {
+    pub checked: usize,
+    pub failures: Vec<BatchFailure>,
+}
+
+impl BatchReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.passed() {
+            return write!(f, "all {} strings passed", self.checked);
+        }
+
+        writeln!(
+            f,
+            "{} of {} strings failed:",
+            self.failures.len(),
+            self.checked
+        )?;
+        for failure in &self.failures {
+            match &failure.error {
+                Some(e) => writeln!(f, "  [{}] {:?}: {e}", failure.index, failure.input)?,
+                None => writeln!(f, "  [{}] {:?}: accepted", failure.index, failure.input)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// States of an unminimized DFA that minimization merged into one state of the minimized DFA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    pub into: StateId,
+    // sorted, in the unminimized DFA's numbering
+    pub from: Vec<StateId>,
+}
+
+// An edge of the unminimized DFA whose target was merged, so it now leads to the merged state.
+// from and to are in the unminimized DFA's numbering, now in the minimized one's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub from: StateId,
+    pub transition: Transition,
+    pub to: StateId,
+    pub now: StateId,
+}
+
+// what minimization changed, see Dfa::merge_diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeDiff {
+    pub states_before: usize,
+    pub states_after: usize,
+    pub merges: Vec<Merge>,
+    pub redirects: Vec<Redirect>,
+}
+
+impl fmt::Display for MergeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} states before minimization, {} after",
+            self.states_before, self.states_after
+        )?;
+        for merge in &self.merges {
+            let from: Vec<_> = merge.from.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "merged {} into {}", from.join(", "), merge.into)?;
+        }
+        for r in &self.redirects {
+            writeln!(
+                f,
+                "redirected {} -{}-> {}, now to {}",
+                r.from,
+                r.transition.dot_label(),
+                r.to,
+                r.now
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// One character read while tracing a simulation, with the state it led to (None if there was no
+// edge for it)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceStep {
+    pub offset: usize,
+    pub found: char,
+    pub from: StateId,
+    pub to: Option<StateId>,
+}
+
+// the path a full-match simulation took through the DFA, see Dfa::trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub steps: Vec<TraceStep>,
+    // the state after the whole input, or None if it got stuck
+    pub end: Option<StateId>,
+    pub accepted: bool,
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            let label = Transition::Literal(step.found).dot_label();
+            match step.to {
+                Some(to) => writeln!(f, "  {}: {} -{label}-> {to}", step.offset, step.from)?,
+                None => writeln!(f, "  {}: {} -{label}-> no edge", step.offset, step.from)?,
+            }
+        }
+
+        match self.end {
+            Some(end) if self.accepted => write!(f, "  ends in {end} (accepting)"),
+            Some(end) => write!(f, "  ends in {end}"),
+            None => write!(f, "  stuck"),
+        }
+    }
+}
+
+// What an edge is taken on, see Dfa::adjacency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SymbolRange {
+    // the characters from the first to the second, inclusive
+    Chars(char, char),
+    // the end of the input, for $
+    End,
+}
+
+// the characters right after and right before c, skipping the surrogate gap
+fn next_char(c: char) -> Option<char> {
+    (c as u32 + 1..=char::MAX as u32).find_map(char::from_u32)
+}
+
+fn prev_char(c: char) -> Option<char> {
+    (0..c as u32).rev().find_map(char::from_u32)
+}
+
+// add start..=end to the edges, extending the last range if it leads to the same state
+// a state of each of two DFAs, None being the dead state
+pub(crate) type Pair = (Option<StateId>, Option<StateId>);
+
+// The characters that can tell the states of dfas apart: every literal of any of them, and for
+// each combination of their predicates some other character has, the first such character (just
+// one character if none of them has predicates), by its mask over all their predicates, which
+// come second
+fn symbols(dfas: &[&Dfa]) -> (BTreeSet<char>, Vec<PredicateId>, BTreeMap<u64, char>) {
+    let literals: BTreeSet<char> = dfas
+        .iter()
+        .flat_map(|dfa| dfa.states().flat_map(|s| dfa.transitions(s)))
+        .filter_map(|(t, _)| match t {
+            Transition::Literal(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+    let predicates: Vec<PredicateId> = dfas
+        .iter()
+        .flat_map(|dfa| dfa.predicates.iter().copied())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut others: BTreeMap<u64, char> = BTreeMap::new();
+    let likely = ('a'..='z').chain('0'..='9').chain(char::MIN..=char::MAX);
+    for c in likely.filter(|c| !literals.contains(c)) {
+        others.entry(predicate::mask(&predicates, c)).or_insert(c);
+        if predicates.is_empty() {
+            break;
+        }
+    }
+
+    (literals, predicates, others)
+}
+
+fn push_range(edges: &mut Vec<(SymbolRange, StateId)>, start: char, end: char, to: StateId) {
+    if let Some((SymbolRange::Chars(_, last), prev)) = edges.last_mut()
+        && *prev == to
+        && next_char(*last) == Some(start)
+    {
+        *last = end;
+        return;
+    }
+    edges.push((SymbolRange::Chars(start, end), to));
+}
+
+// a state's node in Dfa::to_petgraph
+#[cfg(feature = "petgraph")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfaNode {
+    pub id: StateId,
+    pub accepting: bool,
+}
+
+// A set of characters, where any means every character (from a wildcard edge)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CharSet {
+    pub chars: BTreeSet<char>,
+    pub any: bool,
+}
+
+impl CharSet {
+    // the set of every character
+    pub fn everything() -> Self {
+        Self {
+            any: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.any || self.chars.contains(&c)
+    }
+}
+
+// A set of NFA states. It is accepting exactly when the set contains the accepting NFA state, so
+// it can only be built from such a set (see From) and the fields stay private.
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Default)]
 pub struct DfaState {
-    pub internal: BTreeSet<NfaState>,
+    internal: BTreeSet<NfaState>,
     accepting: bool,
 }
 
@@ -24,7 +336,7 @@ impl From<BTreeSet<NfaState>> for DfaState {
     fn from(value: BTreeSet<NfaState>) -> Self {
         Self {
             internal: value.clone(),
-            accepting: value.contains(&NfaState::Accepting),
+            accepting: value.contains(&NfaState::ACCEPTING),
         }
     }
 }
@@ -51,33 +363,55 @@ impl StateContainer<DfaState> for DfaState {
             self.insert_state(new);
         }
     }
+
+    fn first_state(&self) -> Option<&DfaState> {
+        Some(self)
+    }
+
+    fn states(&self) -> Vec<DfaState> {
+        vec![self.clone()]
+    }
 }
 
 impl DfaState {
+    // the NFA states this state stands for
+    pub fn nfa_states(&self) -> &BTreeSet<NfaState> {
+        &self.internal
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        self.accepting
+    }
+
     fn merge(&mut self, other: DfaState) {
         self.internal = self.internal.union(&other.internal).cloned().collect();
         self.accepting |= other.accepting;
     }
 
-    fn to_dot_node_ref(&self) -> String {
-        self.internal.iter().fold(String::new(), |mut acc, s| {
-            acc.push_str(s.dot_node().as_str());
-            acc
-        })
-    }
-
-    fn to_dot_node_label(&self) -> String {
-        let r = self.to_dot_node_ref();
+    // the underlying NFA states, i.e. {s1, s2}
+    // the NFA states by their names in names, see Dfa::state_names
+    fn set_label(&self, names: &BTreeMap<NfaState, String>) -> String {
         let mut label = String::from('{');
 
         for state in &self.internal {
             if label.len() != 1 {
                 label.push_str(", ");
             }
-            label.push_str(state.dot_node().as_str());
+            label.push_str(&names[state]);
         }
 
         label.push('}');
+        label
+    }
+
+    // style holds extra attributes, each starting with "; "
+    fn to_dot_node_label(
+        &self,
+        id: StateId,
+        style: &str,
+        names: &BTreeMap<NfaState, String>,
+    ) -> String {
+        let label = self.set_label(names);
 
         let shape = if self.accepting {
             "doublecircle"
@@ -85,28 +419,134 @@ impl DfaState {
             "circle"
         };
 
-        format!("{r} [label = \"{label}\"; shape = {shape}];\n")
+        format!("{id} [label = \"{id} {label}\"; shape = {shape}{style}];\n")
     }
 }
 
-#[derive(Debug)]
+// States grouped into blocks, each block sorted and the blocks in order of their first state
+pub type Partition = Vec<Vec<StateId>>;
+
+// How Dfa::minimize_with finds the equivalent states. Both give the same minimal DFA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Minimizer {
+    // Hopcroft's algorithm, splitting one block at a time by the smaller half of a split, in
+    // O(n log n) time
+    #[default]
+    Hopcroft,
+    // Moore's algorithm, splitting every block at once by where its states' edges lead, round
+    // after round until nothing changes, in O(n^2) time. Its steps are easier to follow.
+    Moore,
+}
+
+#[derive(Debug, Clone)]
 pub struct Dfa {
     transitions: HashMap<DfaState, HashMap<Transition, DfaState>>,
-    states: BTreeSet<DfaState>,
     start_state: DfaState,
+    // where a search for a match anywhere in the input starts, if the DFA was built for that
+    unanchored_start: Option<DfaState>,
+    // where a match that doesn't begin at the start of the input begins, which is the start state
+    // without what ^ leads to. Only built with an unanchored start, for patterns with ^.
+    inner_start: Option<DfaState>,
+    // applied to every input character before looking up its edge
+    char_map: CharMap,
+    // the predicates of the NFA's \p{name} edges, which PredClass edges combine
+    predicates: Vec<PredicateId>,
+    // the state behind each StateId, refreshed whenever the states change (see renumber)
+    order: Vec<DfaState>,
+    ids: HashMap<DfaState, StateId>,
+    // for each state minimization merged, the subset-construction states that went into it
+    merged: HashMap<DfaState, Vec<BTreeSet<NfaState>>>,
+    // for a DFA of several patterns, the NFA state each pattern's matches pass through (see
+    // Nfa::mark_accepting), by PatternId
+    patterns: Vec<NfaState>,
+    // the edges again by StateId, which is what matching follows, kept in the chosen backend
+    backend: TableBackend,
+    table: Box<dyn TransitionTable<StateId>>,
 }
 
 impl Dfa {
     pub fn from_nfa(nfa: Nfa) -> Self {
-        let start_state = DfaState::from(nfa.epsilon_closure(vec![NfaState::Start]));
+        Self::from_nfa_starts(nfa, None)
+    }
+
+    // One automaton with both an anchored start and an unanchored one (see
+    // Nfa::add_unanchored_start). The two share every state reachable from both, so a single
+    // compiled DFA answers anchored and unanchored queries.
+    pub fn from_nfa_with_unanchored(mut nfa: Nfa) -> Self {
+        let search = nfa.add_unanchored_start();
+        Self::from_nfa_starts(nfa, Some(search))
+    }
+
+    // from_nfa_with_unanchored, giving up with None once subset construction has made more than
+    // max_states states, instead of spending seconds on a pattern like (a|b)*a(a|b){14} whose
+    // DFA has tens of thousands (see LazyDfa for matching those)
+    pub fn try_from_nfa_with_unanchored(mut nfa: Nfa, max_states: usize) -> Option<Self> {
+        let search = nfa.add_unanchored_start();
+        Self::subset_construction(nfa, Some(search), max_states)
+    }
+
+    // One automaton for several patterns, which also tells which of them each accepting state
+    // matches (see accepting_patterns), e.g. for a lexer. Minimization keeps states of different
+    // patterns apart.
+    pub fn from_patterns(nfas: Vec<Nfa>) -> Self {
+        let mut union = Nfa::never();
+        let mut patterns = Vec::new();
+        for mut nfa in nfas {
+            let marker = nfa.mark_accepting();
+            patterns.push(union.union(&mut nfa)[marker.index()]);
+        }
+
+        let mut dfa = Self::from_nfa_starts(union, None);
+        dfa.patterns = patterns;
+
+        dfa
+    }
+
+    fn from_nfa_starts(nfa: Nfa, search: Option<NfaState>) -> Self {
+        Self::subset_construction(nfa, search, usize::MAX).expect("no limit on states")
+    }
+
+    fn subset_construction(nfa: Nfa, search: Option<NfaState>, max_states: usize) -> Option<Self> {
+        // ^ holds before the first character, and only then
+        let start_state = DfaState::from(nfa.closure(vec![NfaState::START], &AT_START));
+        let unanchored_start = search.map(|s| DfaState::from(nfa.closure(vec![s], &AT_START)));
+        let inner_start = search
+            .map(|_| DfaState::from(nfa.epsilon_closure(vec![NfaState::START])))
+            .filter(|inner| *inner != start_state);
         let mut transitions: HashMap<DfaState, HashMap<Transition, DfaState>> = HashMap::new();
         let mut states = BTreeSet::from([start_state.clone()]);
+        states.extend(unanchored_start.clone());
+        states.extend(inner_start.clone());
+
+        // the predicates on the NFA's edges and the combinations of them some character has,
+        // found by testing every character once
+        let predicates: Vec<PredicateId> = nfa
+            .states()
+            .flat_map(|state| nfa.edges(state))
+            .filter_map(|edge| match edge.transition {
+                Transition::Pred(id) => Some(id),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let classes: BTreeSet<u64> = if predicates.is_empty() {
+            BTreeSet::new()
+        } else {
+            (char::MIN..=char::MAX)
+                .map(|c| predicate::mask(&predicates, c))
+                .filter(|mask| *mask != 0)
+                .collect()
+        };
 
         let mut seen = BTreeSet::new();
-        let mut unmarked = BTreeSet::from([start_state.clone()]);
+        let mut unmarked = states.clone();
 
         while let Some(state) = unmarked.pop_first() {
             seen.insert(state.clone());
+            if seen.len() > max_states {
+                return None;
+            }
 
             if !states.contains(&state) {
                 states.insert(state.clone());
@@ -114,24 +554,38 @@ impl Dfa {
 
             // find all transitions out of the state set
             let mut possible: HashMap<Transition, Vec<NfaState>> = HashMap::new();
+            let mut excepts = Vec::new();
+            let mut preds = Vec::new();
 
             for internal in &state.internal {
-                if !nfa.transitions.contains_key(internal) {
-                    continue;
-                }
-
-                for (transition, ends) in nfa.transitions.get(internal).unwrap() {
-                    if *transition == Transition::Epsilon {
-                        continue;
+                for edge in nfa.edges(*internal) {
+                    let end = edge.end;
+                    match edge.transition {
+                        transition @ (Transition::Literal(_) | Transition::Wildcard) => {
+                            possible.entry(transition).or_default().push(end)
+                        }
+                        Transition::Except(chars) => {
+                            // the excluded characters need edges of their own, even if those lead
+                            // nowhere, so that they don't take the wildcard edge
+                            for c in chars.iter() {
+                                possible.entry(Transition::Literal(*c)).or_default();
+                            }
+                            possible.entry(Transition::Wildcard).or_default();
+                            excepts.push((chars, end));
+                        }
+                        Transition::Pred(id) => preds.push((id, end)),
+                        _ => {}
                     }
-
-                    possible
-                        .entry(*transition)
-                        .or_default()
-                        .extend(ends.clone());
                 }
             }
 
+            // a character without a literal edge takes the edge of the combination of predicates
+            // that hold for it, for each combination one of the predicates here is part of
+            let here = preds.iter().fold(0, |mask, (id, _)| mask | id.bit());
+            for class in classes.iter().filter(|class| *class & here != 0) {
+                possible.entry(Transition::PredClass(*class)).or_default();
+            }
+
             // If there is a wildcard transition, add its end states to every other transition
             // this allows for expressions such as a.?b
             if possible.contains_key(&Transition::Wildcard) {
@@ -143,6 +597,56 @@ impl Dfa {
                 }
             }
 
+            // and a negated class's end states to every transition for a character outside it
+            for (chars, except_end) in excepts {
+                for (trans, ends) in possible.iter_mut() {
+                    let outside = match trans {
+                        Transition::Literal(c) => chars.binary_search(c).is_err(),
+                        _ => true,
+                    };
+                    if outside {
+                        ends.push(except_end);
+                    }
+                }
+            }
+
+            // and a predicate's end states to every transition for a character it holds for
+            for (id, pred_end) in preds {
+                for (trans, ends) in possible.iter_mut() {
+                    let holds = match trans {
+                        Transition::Literal(c) => id.test(*c),
+                        Transition::PredClass(class) => class & id.bit() != 0,
+                        _ => false,
+                    };
+                    if holds {
+                        ends.push(pred_end);
+                    }
+                }
+            }
+
+            // $ holds once the input has ended, so its edge is only followed then, and only if it
+            // leads to a match. Nothing is read after it, so it leads to the states reachable
+            // through end anchors that don't read anything either.
+            let crossed = nfa.closure(state.internal.iter().copied().collect(), &AT_END);
+            let at_end: BTreeSet<_> = crossed
+                .iter()
+                .copied()
+                .filter(|s| nfa.edges(*s).iter().all(|edge| !edge.transition.consumes()))
+                .collect();
+            if crossed != state.internal
+                && at_end.contains(&NfaState::ACCEPTING)
+                && at_end != state.internal
+            {
+                let end = DfaState::from(at_end);
+                if !seen.contains(&end) {
+                    unmarked.insert(end.clone());
+                }
+                transitions
+                    .entry(state.clone())
+                    .or_default()
+                    .insert(Transition::EndAnchor, end);
+            }
+
             // loop through each transition
             for (trans, ends) in possible {
                 let closure = DfaState::from(nfa.epsilon_closure(ends));
@@ -166,102 +670,60 @@ impl Dfa {
             }
         }
 
-        Self {
+        let mut dfa = Self {
             transitions,
-            states,
             start_state,
-        }
+            unanchored_start,
+            inner_start,
+            char_map: std::convert::identity,
+            predicates,
+            order: Vec::new(),
+            ids: HashMap::new(),
+            merged: HashMap::new(),
+            patterns: Vec::new(),
+            backend: TableBackend::default(),
+            table: TableBackend::default().table::<StateId, StateId>(),
+        };
+        dfa.renumber();
+
+        Some(dfa)
     }
 
+    // Merge equivalent states, see refine. ^ was resolved into the start states and $ into edges
+    // of their own by subset construction, so equivalence covers them. The anchored and
+    // unanchored starts each follow their block, and only end up as one state if they accept
+    // the same strings.
     pub fn minimize(&mut self) {
-        // hopcroft's algorithm as described in (Hopcroft 1971) and (Xu 2009)
-
-        // create inverse transition table
-        // $ \delta^{-1}(s,a) = \{t|\delta(t, a) = s\} $
-        let mut inv_delta: HashMap<DfaState, HashMap<Transition, BTreeSet<DfaState>>> =
-            HashMap::new();
-
-        for (start, map) in &self.transitions {
-            for (trans, end) in map {
-                inv_delta.add_transition(end.clone(), *trans, start.clone());
-            }
-        }
-
-        // start with partitions of accepting and non-accepting states
-        let accepting = self
-            .states
-            .iter()
-            .filter(|s| s.accepting)
-            .cloned()
-            .collect::<BTreeSet<_>>();
+        self.minimize_with(Minimizer::Hopcroft);
+    }
 
-        let nonaccepting = self
-            .states
-            .iter()
-            .filter(|s| !s.accepting)
-            .cloned()
-            .collect::<BTreeSet<_>>();
-
-        let mut W = BTreeSet::from([accepting, nonaccepting]);
-        let mut P = W.clone();
-
-        while let Some(S) = W.pop_first() {
-            // $$ I_a \leftarrow \delta^{-1}(S, a)$$
-            let mut end_states = HashMap::new();
-            for state in S {
-                if !inv_delta.contains_key(&state) {
-                    continue;
-                }
-                for (in_trans, ends) in inv_delta.get(&state).unwrap() {
-                    if !end_states.contains_key(in_trans) {
-                        end_states.insert(*in_trans, BTreeSet::new());
-                    }
-                    end_states
-                        .get_mut(in_trans)
-                        .unwrap()
-                        .extend(ends.iter().cloned());
-                }
-            }
-
-            for (_, end_states) in end_states {
-                let P_clone = P.clone();
-                let old_P_iter = P_clone.iter();
-                for R in old_P_iter {
-                    let mut intersection = R.intersection(&end_states).cloned().peekable();
-                    if intersection.peek().is_some() && !R.is_subset(&end_states) {
-                        let R1 = intersection.collect();
-                        let R2: BTreeSet<DfaState> = R.difference(&R1).cloned().collect();
-
-                        P.remove(R);
-                        P.insert(R1.clone());
-                        P.insert(R2.clone());
-
-                        if W.contains(R) {
-                            W.remove(R);
-                            W.insert(R1);
-                            W.insert(R2);
-                        } else if R1.len() <= R2.len() {
-                            W.insert(R1);
-                        } else {
-                            W.insert(R2);
-                        }
-                    }
-                }
-            }
-        }
+    // minimize, finding the equivalent states with the given algorithm
+    pub fn minimize_with(&mut self, minimizer: Minimizer) {
+        let blocks = self.refine(minimizer, None);
 
         // map the old DFA states to their new, minimized equivalents
         let mut changes: HashMap<DfaState, DfaState> = HashMap::new();
-        for p in P {
-            if p.len() > 1 {
+        for block in blocks {
+            if block.len() > 1 {
+                let p: Vec<_> = block
+                    .iter()
+                    .map(|id| self.order[id.as_usize()].clone())
+                    .collect();
                 let mut new_state = DfaState {
                     internal: BTreeSet::new(),
                     accepting: false,
                 };
+                let mut sources = Vec::new();
                 for state in &p {
                     new_state.internal.extend(state.internal.clone());
                     new_state.accepting |= state.accepting;
+                    match self.merged.remove(state) {
+                        Some(merged) => sources.extend(merged),
+                        None => sources.push(state.internal.clone()),
+                    }
                 }
+                sources.sort();
+                self.merged.insert(new_state.clone(), sources);
 
                 for state in p {
                     changes.insert(state, new_state.clone());
@@ -272,93 +734,1698 @@ impl Dfa {
         if changes.contains_key(&self.start_state) {
             self.start_state = changes.get(&self.start_state).unwrap().clone();
         }
-
-        for (old, new) in changes {
-            self.transitions.rename(old, new);
+        if let Some(new) = self.unanchored_start.as_ref().and_then(|s| changes.get(s)) {
+            self.unanchored_start = Some(new.clone());
         }
+        if let Some(new) = self.inner_start.as_ref().and_then(|s| changes.get(s)) {
+            self.inner_start = Some(new.clone());
+        }
+
+        // equivalent states have equivalent rows, so it doesn't matter which of them is kept
+        let rename = |s: DfaState| changes.get(&s).cloned().unwrap_or(s);
+        self.transitions = std::mem::take(&mut self.transitions)
+            .into_iter()
+            .map(|(start, row)| {
+                let row = row.into_iter().map(|(t, end)| (t, rename(end))).collect();
+                (rename(start), row)
+            })
+            .collect();
+        self.renumber();
     }
 
-    pub fn to_dot(&self, label: &str) -> String {
-        let mut edges = String::new();
-        let mut nodes = HashMap::new();
+    // Every step Hopcroft's algorithm takes while minimizing the DFA, as the partition of its
+    // states into blocks that might still be equivalent: first accepting and non-accepting states,
+    // then one partition per split. In the last one each block is a state of the minimal DFA, so
+    // on a DFA that is already minimal every block ends up with a single state.
+    pub fn minimization_steps(&self) -> Vec<Partition> {
+        self.minimization_steps_with(Minimizer::Hopcroft)
+    }
 
-        for (start, map) in &self.transitions {
-            for (transition, end) in map {
-                let start_ref = start.to_dot_node_ref();
-                let end_ref = end.to_dot_node_ref();
-                if !nodes.contains_key(&start_ref) {
-                    nodes.insert(start_ref.clone(), start.to_dot_node_label());
-                }
-                if !nodes.contains_key(&end_ref) {
-                    nodes.insert(end_ref.clone(), end.to_dot_node_label());
-                }
-                edges.push_str(
-                    format!(
-                        "{} -> {} [label = \"{}\"];\n",
-                        start_ref,
-                        end_ref,
-                        transition.dot_label()
-                    )
-                    .as_str(),
-                );
+    // minimization_steps for the given algorithm. Moore's algorithm takes one step per round.
+    pub fn minimization_steps_with(&self, minimizer: Minimizer) -> Vec<Partition> {
+        let mut steps = Vec::new();
+        self.refine(minimizer, Some(&mut steps));
+        steps
+    }
+
+    // The reachable states grouped into equivalence classes, recording each step if steps is
+    // given. This is Hopcroft's algorithm as described in (Hopcroft 1971) and (Xu 2009), which
+    // needs every state to have an edge for every symbol. So the symbols are the literals and
+    // predicate classes on any edge plus the wildcard, standing for all other characters, a state
+    // without a literal's edge follows the edge for its predicate class or else its wildcard edge
+    // instead, and a missing edge leads to an extra dead state. With Minimizer::Moore the
+    // blocks are refined by Moore's algorithm instead, on the same symbols.
+    fn refine(
+        &self,
+        minimizer: Minimizer,
+        mut steps: Option<&mut Vec<Partition>>,
+    ) -> Vec<Vec<StateId>> {
+        let n = self.order.len();
+        let dead = n;
+
+        let mut symbols: Vec<Transition> = self
+            .order
+            .iter()
+            .flat_map(|s| {
+                self.transitions
+                    .get(s)
+                    .into_iter()
+                    .flat_map(|row| row.keys())
+            })
+            .copied()
+            .chain([Transition::Wildcard])
+            .collect();
+        symbols.sort();
+        symbols.dedup();
+
+        // $ \delta^{-1}(s,a) = \{t|\delta(t, a) = s\} $, by symbol and then by s
+        let mut inv_delta = vec![vec![Vec::new(); n + 1]; symbols.len()];
+        for (a, symbol) in symbols.iter().enumerate() {
+            inv_delta[a][dead].push(dead);
+            for (t, state) in self.order.iter().enumerate() {
+                let row = self.transitions.get(state);
+                // the wildcard stands in for characters, not for the end of the input
+                let end = row
+                    .and_then(|row| match symbol {
+                        Transition::EndAnchor => row.get(symbol),
+                        Transition::Literal(c) => row
+                            .get(symbol)
+                            .or_else(|| row.get(&self.pred_class(*c)?))
+                            .or_else(|| row.get(&Transition::Wildcard)),
+                        _ => row.get(symbol).or_else(|| row.get(&Transition::Wildcard)),
+                    })
+                    .map_or(dead, |end| self.ids[end].as_usize());
+                inv_delta[a][end].push(t);
             }
         }
 
-        let node_str = nodes.values().fold(String::new(), |mut acc, n| {
-            acc.push_str(n.as_str());
-            acc
-        });
+        // start with blocks of accepting and non-accepting states, with the accepting ones also
+        // split by the patterns they match
+        let mut by_patterns: BTreeMap<Vec<PatternId>, Vec<usize>> = BTreeMap::new();
+        for t in 0..=n {
+            let patterns = if t < n {
+                self.accepting_patterns(StateId::new(t))
+            } else {
+                Vec::new()
+            };
+            by_patterns.entry(patterns).or_default().push(t);
+        }
+        let mut blocks: Vec<Vec<usize>> = by_patterns.into_values().collect();
+        let mut block_of = vec![0; n + 1];
+        for (b, block) in blocks.iter().enumerate() {
+            for &t in block {
+                block_of[t] = b;
+            }
+        }
 
-        format!("digraph dfa {{\ngraph [label=\"{label}\"];\n{node_str}\n{edges}}}")
-    }
+        let record = |blocks: &[Vec<usize>], steps: &mut Option<&mut Vec<Partition>>| {
+            let Some(steps) = steps else { return };
+            let partition = Self::partition(blocks, dead);
+            if steps.last() != Some(&partition) {
+                steps.push(partition);
+            }
+        };
+        record(&blocks, &mut steps);
 
-    pub fn simulate(&self, input: String) -> Result<(), SimError> {
-        let mut curr_state = &self.start_state;
+        if minimizer == Minimizer::Moore {
+            Self::moore(&inv_delta, &mut blocks, &mut |b| record(b, &mut steps));
+            return Self::partition(&blocks, dead);
+        }
 
-        let mut char_iter = input.chars().peekable();
-        let mut accepted = false;
+        // splitting by every block but one is as good as splitting by all of them, see below
+        let largest = (0..blocks.len()).max_by_key(|&b| blocks[b].len());
+        let mut W: Vec<usize> = (0..blocks.len()).filter(|&b| Some(b) != largest).collect();
+        let mut in_W: Vec<bool> = (0..blocks.len()).map(|b| Some(b) != largest).collect();
 
-        while !accepted {
-            if let Some(map) = self.transitions.get(curr_state) {
-                if char_iter.peek().is_some() {
-                    let c = *char_iter.peek().unwrap();
-                    let possible_edges = [
-                        Transition::Literal(c),
-                        Transition::Wildcard,
-                        Transition::Epsilon,
-                    ];
+        let mut marked = vec![false; n + 1];
+        while let Some(S) = W.pop() {
+            in_W[S] = false;
+            let splitter = blocks[S].clone();
 
-                    let transition = possible_edges.iter().find(|&edge| map.get(edge).is_some());
+            for inverse in &inv_delta {
+                // $$ I_a \leftarrow \delta^{-1}(S, a)$$, gathered by the block each state is in
+                let mut touched: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+                for &s in &splitter {
+                    for &t in &inverse[s] {
+                        if !marked[t] {
+                            marked[t] = true;
+                            touched.entry(block_of[t]).or_default().push(t);
+                        }
+                    }
+                }
 
-                    if let Some(transition) = transition {
-                        if *transition != Transition::Epsilon {
-                            let _ = char_iter.next();
+                for (R, R1) in touched {
+                    if R1.len() == blocks[R].len() {
+                        for &t in &R1 {
+                            marked[t] = false;
                         }
+                        continue;
+                    }
 
-                        curr_state = map.get(transition).unwrap();
-                    } else if curr_state.accepting {
-                        accepted = true;
+                    // R keeps the states outside I_a and R1 becomes a new block
+                    blocks[R].retain(|t| !marked[*t]);
+                    for &t in &R1 {
+                        marked[t] = false;
+                    }
+                    let R2 = blocks.len();
+                    for &t in &R1 {
+                        block_of[t] = R2;
+                    }
+                    let smaller = if R1.len() <= blocks[R].len() { R2 } else { R };
+                    blocks.push(R1);
+
+                    // A block in W has to be replaced by both halves. Otherwise splitting by
+                    // the smaller half is enough, as splitting by R already happened, and
+                    // splitting by R and one half equals splitting by both halves.
+                    if in_W[R] {
+                        in_W.push(true);
+                        W.push(R2);
                     } else {
-                        return Err(SimError::NoMatch(c));
+                        in_W.push(smaller == R2);
+                        in_W[R] = smaller == R;
+                        W.push(smaller);
                     }
-                } else if let Some(new_state) = map.get(&Transition::Epsilon) {
-                    curr_state = new_state;
-                } else if curr_state.accepting {
-                    accepted = true;
-                } else {
-                    return Err(SimError::EndOfString);
+
+                    record(&blocks, &mut steps);
+                }
+            }
+        }
+
+        blocks
+            .iter()
+            .map(|block| {
+                block
+                    .iter()
+                    .filter(|&&t| t != dead)
+                    .map(|&t| StateId::new(t))
+                    .collect()
+            })
+            .filter(|block: &Vec<_>| !block.is_empty())
+            .collect()
+    }
+
+    // Moore's algorithm: in each round, states stay in one block only if they were in one block
+    // and their edges lead to the same blocks for every symbol, until a round splits nothing
+    fn moore(
+        inv_delta: &[Vec<Vec<usize>>],
+        blocks: &mut Vec<Vec<usize>>,
+        record: &mut dyn FnMut(&[Vec<usize>]),
+    ) {
+        let n = inv_delta.first().map_or(0, |inverse| inverse.len());
+        let mut delta = vec![vec![0; n]; inv_delta.len()];
+        for (a, inverse) in inv_delta.iter().enumerate() {
+            for (s, sources) in inverse.iter().enumerate() {
+                for &t in sources {
+                    delta[a][t] = s;
+                }
+            }
+        }
+
+        let mut block_of = vec![0; n];
+        loop {
+            for (b, block) in blocks.iter().enumerate() {
+                for &t in block {
+                    block_of[t] = b;
+                }
+            }
+            let mut by_signature: BTreeMap<Vec<usize>, Vec<usize>> = BTreeMap::new();
+            for t in 0..n {
+                let mut signature = vec![block_of[t]];
+                signature.extend(delta.iter().map(|targets| block_of[targets[t]]));
+                by_signature.entry(signature).or_default().push(t);
+            }
+            if by_signature.len() == blocks.len() {
+                return;
+            }
+            *blocks = by_signature.into_values().collect();
+            record(blocks);
+        }
+    }
+
+    // blocks of state indices in terms of state ids, leaving out the dead state
+    fn partition(blocks: &[Vec<usize>], dead: usize) -> Partition {
+        let mut partition: Partition = blocks
+            .iter()
+            .map(|block| {
+                let mut ids: Vec<_> = block
+                    .iter()
+                    .filter(|&&t| t != dead)
+                    .map(|&t| StateId::new(t))
+                    .collect();
+                ids.sort();
+                ids
+            })
+            .filter(|ids| !ids.is_empty())
+            .collect();
+        partition.sort();
+
+        partition
+    }
+
+    // number the reachable states breadth-first from the start state(s), see StateId
+    fn renumber(&mut self) {
+        let mut order = vec![self.start_state.clone()];
+        order.extend(self.unanchored_start.clone());
+        order.extend(self.inner_start.clone());
+        order.dedup();
+        let mut ids: HashMap<DfaState, StateId> = order
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), StateId::new(i)))
+            .collect();
+
+        let mut next = 0;
+        while next < order.len() {
+            let mut edges: Vec<_> = self
+                .transitions
+                .get(&order[next])
+                .into_iter()
+                .flatten()
+                .collect();
+            edges.sort();
+
+            for (_, end) in edges {
+                if !ids.contains_key(end) {
+                    ids.insert(end.clone(), StateId::new(order.len()));
+                    order.push(end.clone());
                 }
-            } else if curr_state.accepting {
-                accepted = true;
+            }
+            next += 1;
+        }
+
+        self.order = order;
+        self.ids = ids;
+        self.build_table();
+    }
+
+    // Keep the edges by StateId in backend from now on, e.g. TableBackend::BTree for a
+    // deterministic layout or TableBackend::Dense to save memory
+    pub fn set_backend(&mut self, backend: TableBackend) {
+        self.backend = backend;
+        self.build_table();
+    }
+
+    pub fn backend(&self) -> TableBackend {
+        self.backend
+    }
+
+    fn build_table(&mut self) {
+        let mut table = self.backend.table::<StateId, StateId>();
+        for (id, state) in self.order.iter().enumerate() {
+            for (transition, end) in self.transitions.get(state).into_iter().flatten() {
+                table.add_transition(StateId::new(id), *transition, self.ids[end]);
+            }
+        }
+        self.table = table;
+    }
+
+    pub fn start(&self) -> StateId {
+        self.ids[&self.start_state]
+    }
+
+    pub fn unanchored_start(&self) -> Option<StateId> {
+        self.unanchored_start.as_ref().map(|s| self.ids[s])
+    }
+
+    // where a match that doesn't begin at offset 0 begins, see inner_start
+    pub(crate) fn inner_start(&self) -> StateId {
+        self.inner_start
+            .as_ref()
+            .map_or(self.start(), |s| self.ids[s])
+    }
+
+    // every reachable state, in StateId order
+    pub fn states(&self) -> impl Iterator<Item = StateId> {
+        (0..self.order.len()).map(StateId::new)
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_accepting(&self, state: StateId) -> bool {
+        self.order[state.as_usize()].accepting
+    }
+
+    pub fn accept_states(&self) -> Vec<StateId> {
+        self.states().filter(|s| self.is_accepting(*s)).collect()
+    }
+
+    // Which patterns of a DFA built by from_patterns match when state is reached, in order. For
+    // a DFA of a single pattern that is pattern 0 in every accepting state.
+    pub fn accepting_patterns(&self, state: StateId) -> Vec<PatternId> {
+        let state = self.state(state);
+        if self.patterns.is_empty() {
+            return if state.accepting {
+                vec![PatternId::new(0)]
             } else {
-                return Err(SimError::NoTransitions);
+                Vec::new()
+            };
+        }
+
+        (0..self.patterns.len())
+            .filter(|i| state.internal.contains(&self.patterns[*i]))
+            .map(PatternId::new)
+            .collect()
+    }
+
+    // the set of NFA states behind a state
+    pub fn state(&self, state: StateId) -> &DfaState {
+        &self.order[state.as_usize()]
+    }
+
+    // The states of the unminimized DFA that minimization merged into state, each as the NFA
+    // states subset construction built it from, sorted. A state that wasn't merged with any
+    // other (or any state before minimize) is just its own NFA states.
+    pub fn merged_states(&self, state: StateId) -> Vec<&BTreeSet<NfaState>> {
+        let state = self.state(state);
+        match self.merged.get(state) {
+            Some(merged) => merged.iter().collect(),
+            None => vec![state.nfa_states()],
+        }
+    }
+
+    // Which states of raw minimization merged into which of this DFA's, and the edges of raw that
+    // now lead to a merged state. None if this DFA isn't raw minimized.
+    pub fn merge_diff(&self, raw: &Dfa) -> Option<MergeDiff> {
+        let mut class = HashMap::new();
+        for id in self.states() {
+            for source in self.merged_states(id) {
+                class.insert(source, id);
             }
         }
+        let class_of = |id: StateId| class.get(raw.state(id).nfa_states()).copied();
+
+        let mut merges: BTreeMap<StateId, Vec<StateId>> = BTreeMap::new();
+        for id in raw.states() {
+            merges.entry(class_of(id)?).or_default().push(id);
+        }
 
-        if char_iter.peek().is_none() {
-            return Ok(());
+        let mut redirects = Vec::new();
+        for from in raw.states() {
+            for (transition, to) in raw.transitions(from) {
+                let now = class_of(to)?;
+                if self.table.target(&class_of(from)?, &transition) != Some(now) {
+                    return None;
+                }
+                if merges[&now].len() > 1 {
+                    redirects.push(Redirect {
+                        from,
+                        transition,
+                        to,
+                        now,
+                    });
+                }
+            }
+        }
+
+        Some(MergeDiff {
+            states_before: raw.state_count(),
+            states_after: self.state_count(),
+            merges: merges
+                .iter()
+                .filter(|(_, from)| from.len() > 1)
+                .map(|(into, from)| Merge {
+                    into: *into,
+                    from: from.clone(),
+                })
+                .collect(),
+            redirects,
+        })
+    }
+
+    // The states built from nfa_state, e.g. to see where the pattern position behind an NFA
+    // state (see Nfa::spans) ended up. Two positions in the same state are reached by the same
+    // inputs. There can be several, as subset construction adds an NFA state to every set of
+    // states it is reachable together with.
+    pub fn states_with(&self, nfa_state: NfaState) -> Vec<StateId> {
+        self.states()
+            .filter(|id| self.state(*id).nfa_states().contains(&nfa_state))
+            .collect()
+    }
+
+    // the edges out of state, sorted by transition
+    pub fn transitions(&self, state: StateId) -> Vec<(Transition, StateId)> {
+        self.table.edges(&state)
+    }
+
+    // the state reached by reading c in state, if any
+    pub fn next_state(&self, state: StateId, c: char) -> Option<StateId> {
+        self.step_mapped(state, (self.char_map)(c))
+    }
+
+    // next_state for a character the char map has already been applied to
+    fn step_mapped(&self, state: StateId, c: char) -> Option<StateId> {
+        self.table
+            .target(&state, &Transition::Literal(c))
+            .or_else(|| self.table.target(&state, &self.pred_class(c)?))
+            .or_else(|| self.table.target(&state, &Transition::Wildcard))
+    }
+
+    // the predicate class of a (mapped) character, if any of the DFA's predicates hold for it
+    pub(crate) fn pred_class(&self, c: char) -> Option<Transition> {
+        if self.predicates.is_empty() {
+            return None;
+        }
+        match predicate::mask(&self.predicates, c) {
+            0 => None,
+            mask => Some(Transition::PredClass(mask)),
+        }
+    }
+
+    // A character that takes transition from state to end, for spelling out a walk through the
+    // DFA. None for $ edges.
+    pub(crate) fn edge_char(
+        &self,
+        state: StateId,
+        transition: Transition,
+        end: StateId,
+    ) -> Option<char> {
+        match transition {
+            Transition::Literal(c) => Some(c),
+            Transition::EndAnchor => None,
+            // any character without a literal edge of its own takes the wildcard, or its
+            // predicate class, which may only have characters far into the alphabet
+            _ => {
+                let takes = |c: &char| self.next_state(state, *c) == Some(end);
+                let likely = ('a'..='z')
+                    .chain('0'..='9')
+                    .chain(char::MAX..=char::MAX)
+                    .find(takes);
+                match transition {
+                    Transition::PredClass(_) => {
+                        likely.or_else(|| (char::MIN..=char::MAX).find(takes))
+                    }
+                    _ => likely,
+                }
+            }
+        }
+    }
+
+    // The DFA as a petgraph Graph, to run graph algorithms (strongly connected components,
+    // dominators, path counts, ...) on it. Node i is state di, and each edge carries its transition.
+    #[cfg(feature = "petgraph")]
+    pub fn to_petgraph(&self) -> petgraph::Graph<DfaNode, Transition> {
+        use petgraph::graph::NodeIndex;
+
+        let mut graph = petgraph::Graph::with_capacity(self.state_count(), 0);
+        for id in self.states() {
+            graph.add_node(DfaNode {
+                id,
+                accepting: self.is_accepting(id),
+            });
+        }
+        for id in self.states() {
+            for (transition, to) in self.transitions(id) {
+                let (from, to) = (NodeIndex::new(id.as_usize()), NodeIndex::new(to.as_usize()));
+                graph.add_edge(from, to, transition);
+            }
+        }
+
+        graph
+    }
+
+    // Every state with its edges as ranges of characters in order, for drawing the automaton
+    // without going through DOT. The wildcard and predicate classes are spelled out as the ranges
+    // between the literal edges, so each state's ranges don't overlap, and $ edges come last.
+    pub fn adjacency(&self) -> Vec<(StateId, Vec<(SymbolRange, StateId)>)> {
+        self.states()
+            .map(|id| {
+                let mut literals = BTreeMap::new();
+                let mut classes = HashMap::new();
+                let (mut other, mut end) = (None, None);
+                for (transition, to) in self.transitions(id) {
+                    match transition {
+                        Transition::Literal(c) => {
+                            literals.insert(c, to);
+                        }
+                        Transition::PredClass(_) => {
+                            classes.insert(transition, to);
+                        }
+                        Transition::Wildcard => other = Some(to),
+                        Transition::EndAnchor => end = Some(to),
+                        // a DFA has no others
+                        _ => {}
+                    }
+                }
+
+                // the characters from..=to, which have no literal edges, one by one if they
+                // have to be sorted into predicate classes
+                let fill = |edges: &mut Vec<_>, from: char, to: char| {
+                    if classes.is_empty() {
+                        if let Some(other) = other {
+                            push_range(edges, from, to, other);
+                        }
+                        return;
+                    }
+                    for c in from..=to {
+                        let target = self
+                            .pred_class(c)
+                            .and_then(|class| classes.get(&class).copied())
+                            .or(other);
+                        if let Some(target) = target {
+                            push_range(edges, c, c, target);
+                        }
+                    }
+                };
+
+                let mut edges = Vec::new();
+                // the first character no edge has been listed for yet
+                let mut next = Some('\0');
+                for (c, to) in literals {
+                    if let Some(from) = next
+                        && from < c
+                    {
+                        fill(&mut edges, from, prev_char(c).unwrap());
+                    }
+                    push_range(&mut edges, c, c, to);
+                    next = next_char(c);
+                }
+                if let Some(from) = next {
+                    fill(&mut edges, from, char::MAX);
+                }
+                edges.extend(end.map(|to| (SymbolRange::End, to)));
+
+                (id, edges)
+            })
+            .collect()
+    }
+
+    pub fn to_dot(&self, label: &str) -> String {
+        self.to_dot_with(label, DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, label: &str, options: DotOptions) -> String {
+        self.render_dot(label, options, None, None)
+    }
+
+    // the DOT graph with the states and edges trace passed through drawn in red
+    pub fn to_dot_trace(&self, label: &str, trace: &Trace) -> String {
+        self.render_dot(label, DotOptions::default(), Some(trace), None)
+    }
+
+    // the DOT graph with the states filled in one color per block of partition, e.g. to show a
+    // step of minimization_steps
+    pub fn to_dot_partition(&self, label: &str, partition: &Partition) -> String {
+        self.render_dot(label, DotOptions::default(), None, Some(partition))
+    }
+
+    fn render_dot(
+        &self,
+        label: &str,
+        options: DotOptions,
+        trace: Option<&Trace>,
+        partition: Option<&Partition>,
+    ) -> String {
+        let mut visited = BTreeSet::from([self.start()]);
+        let mut used = BTreeSet::new();
+        for step in trace.iter().flat_map(|t| &t.steps) {
+            if let Some(to) = step.to {
+                visited.insert(to);
+                used.insert((step.from, to));
+            }
+        }
+        let red = |highlight| {
+            if highlight {
+                "; color = red; penwidth = 2"
+            } else {
+                ""
+            }
+        };
+
+        // the colors of the set312 scheme, reused if there are more blocks
+        let mut fills = HashMap::new();
+        for (i, block) in partition.into_iter().flatten().enumerate() {
+            for id in block {
+                fills.insert(
+                    *id,
+                    format!("; style = filled; fillcolor = \"/set312/{}\"", i % 12 + 1),
+                );
+            }
+        }
+
+        let names = self.state_names();
+        let mut nodes = dfa_entries(self, "");
+        let mut edges = String::new();
+
+        for id in self.states() {
+            let highlight = trace.is_some() && visited.contains(&id);
+            let mut style = fills.remove(&id).unwrap_or_default();
+            if highlight {
+                style.push_str("; color = red");
+            }
+            nodes.push_str(&self.order[id.as_usize()].to_dot_node_label(id, &style, &names));
+
+            let mut by_end: BTreeMap<StateId, Vec<Transition>> = BTreeMap::new();
+            for (transition, end) in self.transitions(id) {
+                by_end.entry(end).or_default().push(transition);
+            }
+            for (end, transitions) in by_end {
+                let color = red(used.contains(&(id, end)));
+                for label in edge_labels(&transitions, options) {
+                    edges.push_str(&format!(
+                        "{id} -> {end} [label = \"{}\"{color}];\n",
+                        quote(&label)
+                    ));
+                }
+            }
+        }
+
+        let label = quote(label);
+        format!("digraph dfa {{\ngraph [label=\"{label}\"];\n{nodes}\n{edges}}}")
+    }
+
+    // A DFA with every literal edge relabelled by f, e.g. char::to_ascii_lowercase to make an
+    // existing automaton lowercase or |c| if c.is_ascii_digit() { '0' } else { c } to read
+    // every digit as 0, without compiling the pattern again. Edges that end up with the same
+    // label are merged by subset construction over sets of this DFA's states, so the result is
+    // deterministic again. Wildcard and predicate edges stand for characters that aren't spelled
+    // out, so they keep their labels (as do $ edges) and their characters stay as they are. The
+    // result isn't minimized, and its states stand for fresh NFA states since no NFA was built
+    // for it.
+    pub fn map_symbols(&self, f: impl Fn(char) -> char) -> Dfa {
+        // a fresh NFA state for each state that isn't dead, numbered past START and ACCEPTING
+        let fresh: Vec<Option<NfaState>> = self
+            .order
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                (!s.internal.is_empty() || s.accepting).then(|| NfaState::from_index(i + 2))
+            })
+            .collect();
+        let to_state = |set: &BTreeSet<StateId>| {
+            let mut internal: BTreeSet<NfaState> =
+                set.iter().filter_map(|s| fresh[s.as_usize()]).collect();
+            if set.iter().any(|s| self.is_accepting(*s)) {
+                internal.insert(NfaState::ACCEPTING);
+            }
+            DfaState::from(internal)
+        };
+
+        let rows: Vec<HashMap<Transition, StateId>> = self
+            .states()
+            .map(|s| self.transitions(s).into_iter().collect())
+            .collect();
+        // where state goes on transition after relabelling, in lookup order like next_state
+        let targets = |state: StateId, transition: Transition| -> Vec<StateId> {
+            let row = &rows[state.as_usize()];
+            let fallback = |t: Option<Transition>| {
+                t.and_then(|t| row.get(&t))
+                    .or_else(|| row.get(&Transition::Wildcard))
+                    .copied()
+            };
+            match transition {
+                Transition::Literal(x) => {
+                    let mut ends: Vec<StateId> = row
+                        .iter()
+                        .filter(|(t, _)| matches!(t, Transition::Literal(c) if f(*c) == x))
+                        .map(|(_, end)| *end)
+                        .collect();
+                    // x itself took the predicate or wildcard edge, which keeps it as it is
+                    if !row.contains_key(&transition) {
+                        ends.extend(fallback(self.pred_class(x)));
+                    }
+                    ends
+                }
+                Transition::PredClass(_) => fallback(Some(transition)).into_iter().collect(),
+                _ => row.get(&transition).copied().into_iter().collect(),
+            }
+        };
+
+        let start = BTreeSet::from([self.start()]);
+        let unanchored = self.unanchored_start().map(|s| BTreeSet::from([s]));
+        let inner = self
+            .inner_start
+            .as_ref()
+            .map(|_| BTreeSet::from([self.inner_start()]));
+        let mut transitions: HashMap<DfaState, HashMap<Transition, DfaState>> = HashMap::new();
+        let mut seen = BTreeSet::new();
+        let mut unmarked: Vec<BTreeSet<StateId>> = [Some(start.clone()), unanchored.clone()]
+            .into_iter()
+            .chain([inner.clone()])
+            .flatten()
+            .collect();
+
+        while let Some(set) = unmarked.pop() {
+            if !seen.insert(set.clone()) {
+                continue;
+            }
+            let mut symbols = BTreeSet::new();
+            for state in &set {
+                for transition in rows[state.as_usize()].keys() {
+                    symbols.insert(match transition {
+                        Transition::Literal(c) => Transition::Literal(f(*c)),
+                        t => *t,
+                    });
+                }
+            }
+
+            let row = transitions.entry(to_state(&set)).or_default();
+            for symbol in symbols {
+                let next: BTreeSet<StateId> =
+                    set.iter().flat_map(|s| targets(*s, symbol)).collect();
+                // an edge to nowhere still keeps its characters off the wildcard edge
+                row.insert(symbol, to_state(&next));
+                unmarked.push(next);
+            }
+        }
+
+        let mut dfa = Self {
+            transitions,
+            start_state: to_state(&start),
+            unanchored_start: unanchored.as_ref().map(to_state),
+            inner_start: inner.as_ref().map(to_state),
+            char_map: self.char_map,
+            predicates: self.predicates.clone(),
+            order: Vec::new(),
+            ids: HashMap::new(),
+            merged: HashMap::new(),
+            patterns: Vec::new(),
+            backend: self.backend,
+            table: self.backend.table::<StateId, StateId>(),
+        };
+        dfa.renumber();
+
+        dfa
+    }
+
+    // Map every input character before it is matched, e.g. with char::to_ascii_lowercase. The
+    // pattern's own characters should have been mapped the same way (see parse::map_chars).
+    pub fn set_char_map(&mut self, char_map: CharMap) {
+        self.char_map = char_map;
+    }
+
+    // The DFA in a compact binary layout (see DenseDfa::to_bytes), to save a compiled pattern
+    // and load it with Dfa::from_bytes without compiling it again
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FormatError> {
+        DenseDfa::new(self).to_bytes()
+    }
+
+    // A DFA saved with to_bytes, matched in place without copying or building anything. It is a
+    // DenseDfaRef rather than a Dfa, which is made of the NFA states behind each state.
+    pub fn from_bytes(bytes: &[u8]) -> Result<DenseDfaRef<'_>, FormatError> {
+        DenseDfaRef::from_bytes(bytes)
+    }
+
+    // The NFA states behind the DFA's states renumbered s0, s1, ... in order, so that labels
+    // don't depend on how many states were allocated before and the same DFA is always drawn
+    // the same way. States are numbered in the order the NFA was built, so these mostly agree
+    // with the NFA's own drawing.
+    fn state_names(&self) -> BTreeMap<NfaState, String> {
+        let states = self.order.iter().flat_map(|s| s.internal.iter().copied());
+        state_names(states.collect())
+    }
+
+    pub(crate) fn char_map(&self) -> CharMap {
+        self.char_map
+    }
+
+    pub(crate) fn predicates(&self) -> &[PredicateId] {
+        &self.predicates
+    }
+
+    // follow the edge for c out of state, preferring a literal edge over the wildcard
+    fn step(&self, state: &DfaState, c: char) -> Option<&DfaState> {
+        let map = self.transitions.get(state)?;
+        let c = (self.char_map)(c);
+        map.get(&Transition::Literal(c))
+            .or_else(|| map.get(&self.pred_class(c)?))
+            .or_else(|| map.get(&Transition::Wildcard))
+    }
+
+    // the state reached by following the end anchor edge out of state, if it has one
+    pub fn end_state(&self, state: StateId) -> Option<StateId> {
+        self.table.target(&state, &Transition::EndAnchor)
+    }
+
+    // Whether an input ending in state is accepted: state is accepting, or $ leads to an
+    // accepting state
+    pub fn accepts_at_end(&self, state: StateId) -> bool {
+        self.is_accepting(state) || self.end_state(state).is_some_and(|s| self.is_accepting(s))
+    }
+
+    fn state_accepts_at_end(&self, state: &DfaState) -> bool {
+        self.accepts_at_end(self.ids[state])
+    }
+
+    // Whether any prefix of input is accepted, stopping at the first accepting state instead of
+    // reading the rest of the input
+    pub fn matches_prefix(&self, input: &str) -> bool {
+        self.matches_prefix_from(self.start(), input)
+    }
+
+    // Whether input contains a match anywhere, from the unanchored start. Without one (see
+    // from_nfa_with_unanchored) this is the same as matches_prefix.
+    pub fn contains_match(&self, input: &str) -> bool {
+        let start = self.unanchored_start().unwrap_or(self.start());
+        self.matches_prefix_from(start, input)
+    }
+
+    fn matches_prefix_from(&self, start: StateId, input: &str) -> bool {
+        let mut curr_state = start;
+
+        for c in input.chars() {
+            if self.is_accepting(curr_state) {
+                return true;
+            }
+
+            match self.next_state(curr_state, c) {
+                Some(next) => curr_state = next,
+                None => return false,
+            }
+        }
+
+        self.accepts_at_end(curr_state)
+    }
+
+    // the length of the longest accepted prefix of input
+    pub fn longest_prefix(&self, input: &str) -> Option<usize> {
+        self.longest_prefix_from(self.start(), input)
+    }
+
+    fn longest_prefix_from(&self, start: StateId, input: &str) -> Option<usize> {
+        let mut curr_state = start;
+        let mut longest = None;
+
+        for (i, c) in input.char_indices() {
+            if self.is_accepting(curr_state) {
+                longest = Some(i);
+            }
+            match self.next_state(curr_state, c) {
+                Some(next) => curr_state = next,
+                None => return longest,
+            }
+        }
+
+        if self.accepts_at_end(curr_state) {
+            longest = Some(input.len());
+        }
+        longest
+    }
+
+    // The leftmost-longest match anywhere in haystack: of the matches starting at the first
+    // position any match starts at, the longest. Every position is tried in turn, so this can
+    // take time quadratic in the haystack's length, although a DFA with an unanchored start reads
+    // the haystack once first to rule out haystacks without a match. Only there does ^ hold at
+    // the start of the haystack alone, without one every position counts as the start of the
+    // input. Regex::find gives leftmost-first matches instead.
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.find_at(haystack, 0)
+    }
+
+    // find for a match starting at or after byte offset start, which is moved up to the next
+    // character boundary. ^ still only holds at offset 0.
+    pub fn find_at<'h>(&self, haystack: &'h str, start: usize) -> Option<Match<'h>> {
+        let start = (start..=haystack.len()).find(|i| haystack.is_char_boundary(*i))?;
+        if self.unanchored_start.is_some() && !self.contains_match(&haystack[start..]) {
+            return None;
+        }
+
+        let inner = self.inner_start();
+        haystack[start..]
+            .char_indices()
+            .map(|(i, _)| start + i)
+            .chain([haystack.len()])
+            .find_map(|i| {
+                let from = if i == 0 { self.start() } else { inner };
+                let len = self.longest_prefix_from(from, &haystack[i..])?;
+                Some(Match::new(haystack, i, i + len))
+            })
+    }
+
+    // Successive non-overlapping leftmost-longest matches, with the same rules for empty matches
+    // as Regex::find_iter
+    pub fn find_iter<'d, 'h>(&'d self, haystack: &'h str) -> DfaMatches<'d, 'h> {
+        DfaMatches::new(self, haystack)
+    }
+
+    // The length of the longest accepted suffix of input, read backwards from the end. On a DFA
+    // for the reversed language (see Nfa::reverse) this is the longest match ending there.
+    pub fn longest_suffix(&self, input: &str) -> Option<usize> {
+        let mut curr_state = self.start();
+        let mut longest = None;
+
+        for (i, c) in input.char_indices().rev() {
+            if self.is_accepting(curr_state) {
+                longest = Some(input.len() - i - c.len_utf8());
+            }
+            match self.next_state(curr_state, c) {
+                Some(next) => curr_state = next,
+                None => return longest,
+            }
+        }
+
+        if self.accepts_at_end(curr_state) {
+            longest = Some(input.len());
+        }
+        longest
+    }
+
+    // the states from which an accepting state can still be reached
+    fn live_states(&self) -> BTreeSet<&DfaState> {
+        let mut inverse: HashMap<&DfaState, Vec<&DfaState>> = HashMap::new();
+        let mut live = BTreeSet::new();
+        let mut stack = Vec::new();
+
+        for (start, map) in &self.transitions {
+            for end in map.values() {
+                inverse.entry(end).or_default().push(start);
+            }
+        }
+        for state in &self.order {
+            if state.accepting {
+                stack.push(state);
+            }
+        }
+
+        while let Some(state) = stack.pop() {
+            if live.insert(state) {
+                stack.extend(inverse.get(state).into_iter().flatten());
+            }
+        }
+
+        live
+    }
+
+    // the edges out of state that can still lead to a match
+    fn expected(&self, state: &DfaState, live: &BTreeSet<&DfaState>) -> Vec<Transition> {
+        let mut expected: Vec<_> = self
+            .transitions
+            .get(state)
+            .into_iter()
+            .flatten()
+            .filter(|(_, end)| live.contains(end))
+            .map(|(t, _)| *t)
+            .collect();
+        expected.sort();
+
+        expected
+    }
+
+    // A shortest string exactly one of self and other accepts in full (as test_string decides),
+    // or None if they accept the same strings. Both are run side by side on every character
+    // that can make a difference: the literals of either, and one character for each
+    // combination of predicates (or just one if neither has any) standing for all the others.
+    pub fn distinguishing_string(&self, other: &Dfa) -> Option<String> {
+        let (literals, _, others) = symbols(&[self, other]);
+        let chars: Vec<char> = literals.into_iter().chain(others.into_values()).collect();
+
+        // None is the dead state, which takes any input and never accepts
+        type Pair = (Option<StateId>, Option<StateId>);
+        let step = |dfa: &Dfa, state: Option<StateId>, c| dfa.next_state(state?, c);
+        let accepts =
+            |dfa: &Dfa, state: Option<StateId>| state.is_some_and(|s| dfa.accepts_at_end(s));
+
+        let start: Pair = (Some(self.start()), Some(other.start()));
+        let mut parents: HashMap<Pair, (Pair, char)> = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        let mut seen = BTreeSet::from([start]);
+        while let Some(pair) = queue.pop_front() {
+            if accepts(self, pair.0) != accepts(other, pair.1) {
+                let mut path = Vec::new();
+                let mut at = pair;
+                while let Some((parent, c)) = parents.get(&at) {
+                    path.push(*c);
+                    at = *parent;
+                }
+                return Some(path.into_iter().rev().collect());
+            }
+
+            for c in &chars {
+                let next = (step(self, pair.0, *c), step(other, pair.1, *c));
+                if next != (None, None) && seen.insert(next) {
+                    parents.insert(next, (pair, *c));
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Whether self and other accept the same strings in full, e.g. to check that a refactored
+    // pattern still means what the original did. This is Hopcroft and Karp's algorithm: the
+    // start states are merged, and so, in a union-find, are the states each merged pair leads to
+    // on every character that can make a difference (as in distinguishing_string), which fails
+    // as soon as an accepting state is merged with a rejecting one. It takes near-linear time in
+    // the number of states and stops at the first difference, without building either DFA's
+    // product or minimizing them. distinguishing_string gives a string they differ on.
+    pub fn is_equivalent(&self, other: &Dfa) -> bool {
+        let (literals, _, others) = symbols(&[self, other]);
+        let chars: Vec<char> = literals.into_iter().chain(others.into_values()).collect();
+
+        // self's states, then other's, then the dead state both share
+        let offset = self.state_count();
+        let dead = offset + other.state_count();
+        let side = |i: usize| -> (&Dfa, StateId) {
+            if i < offset {
+                (self, StateId::new(i))
+            } else {
+                (other, StateId::new(i - offset))
+            }
+        };
+        let step = |i: usize, c: char| {
+            if i == dead {
+                return dead;
+            }
+            let (dfa, state) = side(i);
+            dfa.next_state(state, c).map_or(dead, |s| {
+                if i < offset {
+                    s.as_usize()
+                } else {
+                    offset + s.as_usize()
+                }
+            })
+        };
+        let accepts = |i: usize| {
+            let (dfa, state) = side(i);
+            i != dead && dfa.accepts_at_end(state)
+        };
+
+        let mut parent: Vec<usize> = (0..=dead).collect();
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+
+        let start = (self.start().as_usize(), offset + other.start().as_usize());
+        parent[start.0] = start.1;
+        let mut pending = vec![start];
+        while let Some((p, q)) = pending.pop() {
+            if accepts(p) != accepts(q) {
+                return false;
+            }
+            for c in &chars {
+                let (p, q) = (step(p, *c), step(q, *c));
+                let (root_p, root_q) = (find(&mut parent, p), find(&mut parent, q));
+                if root_p != root_q {
+                    parent[root_p] = root_q;
+                    pending.push((p, q));
+                }
+            }
+        }
+
+        true
+    }
+
+    // Whether other accepts every string self accepts in full, e.g. that a narrowed firewall
+    // rule only lets through what the old one did. If not, the error is a shortest string self
+    // accepts and other doesn't, found as the shortest string telling self apart from its
+    // intersection with other.
+    pub fn is_subset_of(&self, other: &Dfa) -> Result<(), String> {
+        match self.distinguishing_string(&self.intersect(other)) {
+            Some(witness) => Err(witness),
+            None => Ok(()),
+        }
+    }
+
+    // The product construction: a DFA accepting exactly the strings both self and other accept,
+    // e.g. to check whether two validation patterns can both match one input (see
+    // accept_states). Its states are the pairs of states the two can be in together. Both should
+    // use the same char map, which the result keeps. It has no unanchored start, so like any such
+    // DFA a search treats every position as the start of the input. The result isn't minimized.
+    pub fn intersect(&self, other: &Dfa) -> Dfa {
+        self.product(other, |a, b| a && b).0
+    }
+
+    // A DFA accepting the strings either self or other accepts, by the product construction like
+    // intersect. It has an unanchored start if both do.
+    pub fn union(&self, other: &Dfa) -> Dfa {
+        self.product(other, |a, b| a || b).0
+    }
+
+    // A DFA accepting the strings exactly one of self and other accepts, by the product
+    // construction like intersect. Its accepting states are where the two disagree.
+    pub fn symmetric_difference(&self, other: &Dfa) -> Dfa {
+        self.product(other, |a, b| a != b).0
+    }
+
+    // The product of self and other, whose pairs of states accept where accept holds for the
+    // two, and the pair behind each of its states (both None for the dead state and the $ sink)
+    pub(crate) fn product(&self, other: &Dfa, accept: fn(bool, bool) -> bool) -> (Dfa, Vec<Pair>) {
+        let (literals, predicates, others) = symbols(&[self, other]);
+        let union = accept(true, false) && accept(false, true) && !accept(false, false);
+
+        // None is the dead state of either side. Once one side is dead, so is the pair if it
+        // can't accept whatever the other side does, as in an intersection.
+        let dead: Pair = (None, None);
+        let left_dead = !accept(false, true) && !accept(false, false);
+        let right_dead = !accept(true, false) && !accept(false, false);
+        let step = |pair: Pair, c: char| -> Pair {
+            let next = (
+                pair.0.and_then(|s| self.step_mapped(s, c)),
+                pair.1.and_then(|s| other.step_mapped(s, c)),
+            );
+            match next {
+                (None, _) if left_dead => dead,
+                (_, None) if right_dead => dead,
+                next => next,
+            }
+        };
+        let holds = |pair: Pair, test: fn(&Dfa, StateId) -> bool| {
+            accept(
+                pair.0.is_some_and(|s| test(self, s)),
+                pair.1.is_some_and(|s| test(other, s)),
+            )
+        };
+        let mut states: HashMap<Pair, DfaState> = HashMap::from([(dead, DfaState::default())]);
+        // each pair stands for a fresh NFA state, numbered after end_sink's
+        let mut to_state = |pair: Pair| -> DfaState {
+            let fresh = NfaState::from_index(states.len() + 2);
+            let state = states.entry(pair).or_insert_with(|| {
+                let mut internal = BTreeSet::from([fresh]);
+                if holds(pair, Dfa::is_accepting) {
+                    internal.insert(NfaState::ACCEPTING);
+                }
+                DfaState::from(internal)
+            });
+            state.clone()
+        };
+        // where $ leads from a pair that only accepts once the input has ended
+        let end_sink = DfaState::from(BTreeSet::from([
+            NfaState::from_index(2),
+            NfaState::ACCEPTING,
+        ]));
+
+        let start: Pair = (Some(self.start()), Some(other.start()));
+        let unanchored = match (self.unanchored_start(), other.unanchored_start()) {
+            (Some(a), Some(b)) if union => Some((Some(a), Some(b))),
+            _ => None,
+        };
+        let inner = unanchored
+            .filter(|_| self.inner_start.is_some() || other.inner_start.is_some())
+            .map(|_| (Some(self.inner_start()), Some(other.inner_start())));
+        let mut transitions: HashMap<DfaState, HashMap<Transition, DfaState>> = HashMap::new();
+        let mut seen = BTreeSet::from([dead]);
+        let mut unmarked: Vec<Pair> = [Some(start), unanchored, inner]
+            .into_iter()
+            .flatten()
+            .collect();
+
+        while let Some(pair) = unmarked.pop() {
+            if !seen.insert(pair) {
+                continue;
+            }
+            let mut row = HashMap::new();
+
+            // the characters without a literal edge go by their predicate mask, 0 being the
+            // wildcard, and only need an edge of their own where they don't go the wildcard's way
+            let classes: BTreeMap<u64, Pair> = others
+                .iter()
+                .map(|(mask, c)| (*mask, step(pair, *c)))
+                .collect();
+            let wildcard = classes.get(&0).copied().unwrap_or(dead);
+            for (mask, next) in &classes {
+                let transition = match mask {
+                    0 if *next == dead => continue,
+                    0 => Transition::Wildcard,
+                    _ if *next == wildcard => continue,
+                    mask => Transition::PredClass(*mask),
+                };
+                row.insert(transition, to_state(*next));
+                unmarked.push(*next);
+            }
+            for c in &literals {
+                let next = step(pair, *c);
+                let mask = predicate::mask(&predicates, *c);
+                if next != classes.get(&mask).copied().unwrap_or(wildcard) {
+                    row.insert(Transition::Literal(*c), to_state(next));
+                    unmarked.push(next);
+                }
+            }
+
+            if holds(pair, Dfa::accepts_at_end) && !holds(pair, Dfa::is_accepting) {
+                row.insert(Transition::EndAnchor, end_sink.clone());
+            }
+            transitions.insert(to_state(pair), row);
+        }
+
+        let mut dfa = Self {
+            transitions,
+            start_state: to_state(start),
+            unanchored_start: unanchored.map(&mut to_state),
+            inner_start: inner.map(&mut to_state),
+            char_map: self.char_map,
+            predicates,
+            order: Vec::new(),
+            ids: HashMap::new(),
+            merged: HashMap::new(),
+            patterns: Vec::new(),
+            backend: self.backend,
+            table: self.backend.table::<StateId, StateId>(),
+        };
+        dfa.renumber();
+
+        let mut pairs = vec![dead; dfa.state_count()];
+        for (pair, state) in states {
+            if let Some(id) = dfa.ids.get(&state) {
+                pairs[id.as_usize()] = pair;
+            }
+        }
+
+        (dfa, pairs)
+    }
+
+    // Check every input at once, reporting each one that is rejected and why
+    pub fn accepts_all_of(&self, inputs: &[&str]) -> BatchReport {
+        let failures = inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, input)| {
+                let error = self.simulate(input.to_string()).err()?;
+                Some(BatchFailure {
+                    index,
+                    input: input.to_string(),
+                    error: Some(error),
+                })
+            })
+            .collect();
+
+        BatchReport {
+            checked: inputs.len(),
+            failures,
+        }
+    }
+
+    // check that every input is rejected, reporting each one that is accepted
+    pub fn rejects_all_of(&self, inputs: &[&str]) -> BatchReport {
+        let failures = inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| self.simulate(input.to_string()).is_ok())
+            .map(|(index, input)| BatchFailure {
+                index,
+                input: input.to_string(),
+                error: None,
+            })
+            .collect();
+
+        BatchReport {
+            checked: inputs.len(),
+            failures,
+        }
+    }
+
+    // the characters a non-empty match can start with
+    pub fn first_chars(&self) -> CharSet {
+        let live = self.live_states();
+        let mut first = CharSet::default();
+
+        for transition in self.expected(&self.start_state, &live) {
+            match transition {
+                Transition::Literal(c) => {
+                    first.chars.insert(c);
+                }
+                Transition::EndAnchor => {}
+                _ => first.any = true,
+            }
+        }
+
+        first
+    }
+
+    // the characters a non-empty match can end with: those on an edge into a state that accepts,
+    // out of a state reachable from the start state
+    pub fn last_chars(&self) -> CharSet {
+        let mut last = CharSet::default();
+        let mut seen = BTreeSet::from([&self.start_state]);
+        let mut stack = vec![&self.start_state];
+
+        while let Some(state) = stack.pop() {
+            for (transition, end) in self.transitions.get(state).into_iter().flatten() {
+                if seen.insert(end) {
+                    stack.push(end);
+                }
+                if !self.state_accepts_at_end(end) {
+                    continue;
+                }
+                match transition {
+                    Transition::Literal(c) => {
+                        last.chars.insert(*c);
+                    }
+                    Transition::EndAnchor => {}
+                    _ => last.any = true,
+                }
+            }
+        }
+
+        last
+    }
+
+    // The longest string every accepted string starts with, found by following the start state's
+    // edges for as long as there is only one way to go. Empty if the language is.
+    pub fn longest_common_prefix(&self) -> String {
+        let live = self.live_states();
+        let mut prefix = String::new();
+        let mut curr = &self.start_state;
+        let mut seen = BTreeSet::new();
+
+        while !curr.accepting && seen.insert(curr) {
+            match self.expected(curr, &live)[..] {
+                [Transition::Literal(c)] => {
+                    prefix.push(c);
+                    curr = &self.transitions[curr][&Transition::Literal(c)];
+                }
+                _ => break,
+            }
+        }
+
+        prefix
+    }
+
+    // The fewest and most bytes an accepted string takes in UTF-8, with no most if there are
+    // accepted strings of any length. None if nothing is accepted. A wildcard edge counts as 1 to
+    // 4 bytes.
+    pub fn byte_len_bounds(&self) -> Option<(usize, Option<usize>)> {
+        self.len_bounds(|transition| match transition {
+            Transition::Literal(c) => (c.len_utf8(), c.len_utf8()),
+            Transition::EndAnchor => (0, 0),
+            _ => (1, 4),
+        })
+    }
+
+    // byte_len_bounds in characters
+    pub fn char_len_bounds(&self) -> Option<(usize, Option<usize>)> {
+        self.len_bounds(|transition| match transition {
+            Transition::EndAnchor => (0, 0),
+            _ => (1, 1),
+        })
+    }
+
+    // the bounds on accepted lengths when each edge adds between the two lengths of weight
+    fn len_bounds(
+        &self,
+        weight: impl Fn(Transition) -> (usize, usize),
+    ) -> Option<(usize, Option<usize>)> {
+        // the states on a path from the start to an accepting state, leaving out the unanchored
+        // start's, which are only reachable from there
+        let mut live: BTreeSet<StateId> = BTreeSet::new();
+        let mut stack = vec![self.start()];
+        while let Some(id) = stack.pop() {
+            if live.insert(id) {
+                stack.extend(self.transitions(id).into_iter().map(|(_, end)| end));
+            }
+        }
+        let accepting: BTreeSet<StateId> =
+            self.live_states().iter().map(|s| self.ids[*s]).collect();
+        live.retain(|id| accepting.contains(id));
+        if !live.contains(&self.start()) {
+            return None;
+        }
+        let edges = |id: StateId| {
+            self.transitions(id)
+                .into_iter()
+                .filter(|(_, end)| live.contains(end))
+                .map(|(transition, end)| {
+                    let (min, max) = weight(transition);
+                    (min, max, end)
+                })
+        };
+
+        // shortest: Dijkstra from the start to the nearest accepting state
+        let mut shortest = None;
+        let mut done = BTreeSet::new();
+        let mut queue = BinaryHeap::from([Reverse((0, self.start()))]);
+        while let Some(Reverse((len, id))) = queue.pop() {
+            if !done.insert(id) {
+                continue;
+            }
+            if self.is_accepting(id) {
+                shortest = Some(len);
+                break;
+            }
+            for (min, _, end) in edges(id) {
+                queue.push(Reverse((len + min, end)));
+            }
+        }
+
+        // longest: over the live states in topological order, unless they have a cycle
+        let mut incoming: HashMap<StateId, usize> = live.iter().map(|id| (*id, 0)).collect();
+        for id in &live {
+            for (_, _, end) in edges(*id) {
+                *incoming.get_mut(&end).unwrap() += 1;
+            }
+        }
+        let mut order = Vec::new();
+        let mut ready: Vec<_> = live
+            .iter()
+            .filter(|id| incoming[*id] == 0)
+            .copied()
+            .collect();
+        while let Some(id) = ready.pop() {
+            order.push(id);
+            for (_, _, end) in edges(id) {
+                let n = incoming.get_mut(&end).unwrap();
+                *n -= 1;
+                if *n == 0 {
+                    ready.push(end);
+                }
+            }
+        }
+        let longest = (order.len() == live.len()).then(|| {
+            let mut longest: HashMap<StateId, usize> = HashMap::new();
+            for id in order.into_iter().rev() {
+                let here = edges(id)
+                    .map(|(_, max, end)| max + longest[&end])
+                    .max()
+                    .unwrap_or(0);
+                longest.insert(id, here);
+            }
+            longest[&self.start()]
+        });
+
+        Some((shortest?, longest))
+    }
+
+    // A random accepted string of about len characters and the path it takes, e.g. for worked
+    // examples with to_dot_trace. The walk picks live edges at random for len steps and then takes
+    // the shortest way to an accepting state. None if nothing is accepted.
+    pub fn random_walk_trace(&self, rng: &mut Rng, len: usize) -> Option<(String, Trace)> {
+        // the number of characters from each state to the nearest accepting state
+        let mut distance: HashMap<StateId, usize> = HashMap::new();
+        let mut frontier: Vec<_> = self.states().filter(|s| self.is_accepting(*s)).collect();
+        let mut level = 0;
+        while !frontier.is_empty() {
+            for s in &frontier {
+                distance.insert(*s, level);
+            }
+            frontier = self
+                .states()
+                .filter(|s| !distance.contains_key(s))
+                .filter(|s| {
+                    self.transitions(*s)
+                        .iter()
+                        .any(|(_, end)| frontier.contains(end))
+                })
+                .collect();
+            level += 1;
+        }
+        distance.get(&self.start())?;
+
+        let mut input = String::new();
+        let mut curr = self.start();
+        let mut steps = 0;
+        while steps < len || !self.accepts_at_end(curr) {
+            let live: Vec<_> = self
+                .transitions(curr)
+                .into_iter()
+                .filter(|(transition, _)| *transition != Transition::EndAnchor)
+                .filter(|(_, end)| match distance.get(end) {
+                    Some(d) if steps >= len => *d < distance[&curr],
+                    Some(_) => true,
+                    None => false,
+                })
+                .collect();
+            if live.is_empty() {
+                break;
+            }
+
+            let (transition, end) = *rng.pick(&live);
+            let c = self.edge_char(curr, transition, end)?;
+            input.push(c);
+            curr = end;
+            steps += 1;
+        }
+
+        let trace = self.trace(&input);
+        Some((input, trace))
+    }
+
+    // A shortest string the DFA accepts in full, e.g. to show what a pattern matches or to seed a
+    // test. Found breadth-first from the start state, taking edges in order, so the same DFA
+    // always gives the same string. None if nothing is accepted.
+    pub fn shortest_match_example(&self) -> Option<String> {
+        let mut parents: HashMap<StateId, (StateId, char)> = HashMap::new();
+        let mut queue = VecDeque::from([self.start()]);
+        let mut seen = BTreeSet::from([self.start()]);
+        while let Some(state) = queue.pop_front() {
+            if self.accepts_at_end(state) {
+                let mut path = Vec::new();
+                let mut at = state;
+                while let Some((parent, c)) = parents.get(&at) {
+                    path.push(*c);
+                    at = *parent;
+                }
+                return Some(path.into_iter().rev().collect());
+            }
+
+            for (transition, end) in self.transitions(state) {
+                if seen.contains(&end) {
+                    continue;
+                }
+                if let Some(c) = self.edge_char(state, transition, end) {
+                    seen.insert(end);
+                    parents.insert(end, (state, c));
+                    queue.push_back(end);
+                }
+            }
+        }
+
+        None
+    }
+
+    // The strings the DFA accepts in full in shortlex order, shortest first and alphabetically
+    // among strings of the same length, at most cap of them, e.g. to see what a puzzling pattern
+    // really matches or to build an exhaustive corpus of small test inputs. Ends early for a
+    // finite language.
+    pub fn language_iter(&self, cap: usize) -> LanguageIter {
+        LanguageIter::new(self, cap)
+    }
+
+    // whether the empty string is accepted
+    pub fn accepts_empty(&self) -> bool {
+        self.accepts_at_end(self.start())
+    }
+
+    // every step of matching the whole of input, for showing why it was accepted or rejected
+    pub fn trace(&self, input: &str) -> Trace {
+        let mut steps = Vec::new();
+        let mut curr = Some(self.start());
+
+        for (offset, found) in input.char_indices() {
+            let Some(from) = curr else { break };
+            curr = self.next_state(from, found);
+            steps.push(TraceStep {
+                offset,
+                found,
+                from,
+                to: curr,
+            });
+        }
+
+        Trace {
+            steps,
+            end: curr,
+            accepted: curr.is_some_and(|s| self.accepts_at_end(s)),
+        }
+    }
+
+    pub fn simulate(&self, input: String) -> Result<(), SimError> {
+        self.simulate_chars(input.char_indices(), input.len())
+    }
+
+    // simulate on bytes, e.g. binary data or text that may not be valid UTF-8, read as mode
+    // says. Offsets in errors are in the bytes.
+    pub fn simulate_bytes(&self, input: &[u8], mode: ByteMode) -> Result<(), SimError> {
+        let chars: Vec<(usize, char)> = match mode {
+            ByteMode::Raw => input.iter().map(|b| char::from(*b)).enumerate().collect(),
+            ByteMode::Utf8(policy) => {
+                let mut chars = Vec::new();
+                let mut at = 0;
+                for chunk in input.utf8_chunks() {
+                    chars.extend(chunk.valid().char_indices().map(|(i, c)| (at + i, c)));
+                    at += chunk.valid().len();
+                    if chunk.invalid().is_empty() {
+                        continue;
+                    }
+                    if policy == InvalidUtf8::Reject {
+                        return Err(SimError::InvalidUtf8 { offset: at });
+                    }
+                    chars.push((at, char::REPLACEMENT_CHARACTER));
+                    at += chunk.invalid().len();
+                }
+                chars
+            }
+        };
+
+        self.simulate_chars(chars.into_iter(), input.len())
+    }
+
+    // simulate on the characters of an input of len bytes, each with its offset
+    fn simulate_chars(
+        &self,
+        chars: impl Iterator<Item = (usize, char)>,
+        len: usize,
+    ) -> Result<(), SimError> {
+        let live = self.live_states();
+        let mut curr_state = &self.start_state;
+
+        for (offset, c) in chars {
+            if !live.contains(curr_state) {
+                return Err(SimError::DeadState { offset });
+            }
+
+            match self.step(curr_state, c) {
+                Some(next) => curr_state = next,
+                None if self.state_accepts_at_end(curr_state) => {
+                    return Err(SimError::Premature { offset })
+                }
+                None => {
+                    return Err(SimError::NoMatch {
+                        found: c,
+                        offset,
+                        expected: self.expected(curr_state, &live),
+                    })
+                }
+            }
+        }
+
+        if !live.contains(curr_state) {
+            Err(SimError::DeadState { offset: len })
+        } else if self.state_accepts_at_end(curr_state) {
+            Ok(())
+        } else {
+            Err(SimError::EndOfString {
+                expected: self.expected(curr_state, &live),
+            })
+        }
+    }
+}
+
+impl fmt::Display for Dfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "DFA with {} states", self.state_count())?;
+        let names = self.state_names();
+        for id in self.states() {
+            let mut flags = Vec::new();
+            if id == self.start() {
+                flags.push("start");
+            }
+            if Some(id) == self.unanchored_start() {
+                flags.push("unanchored start");
+            }
+            if self.is_accepting(id) {
+                flags.push("accepting");
+            }
+            let flags = if flags.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", flags.join(", "))
+            };
+            writeln!(
+                f,
+                "  {id}{flags} {}",
+                self.order[id.as_usize()].set_label(&names)
+            )?;
+
+            for (transition, end) in self.transitions(id) {
+                writeln!(f, "    {} -> {end}", transition.dot_label())?;
+            }
         }
 
-        Err(SimError::Premature)
+        Ok(())
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::dfa::{Dfa, Partition};
+use crate::error::RegexError;
+use crate::id::StateId;
+use crate::try_compile_regex;
+
+// How two patterns differ, from diff
+#[derive(Debug)]
+pub struct PatternDiff {
+    // a shortest string only the old pattern matches in full, if there is one
+    pub only_old: Option<String>,
+    // a shortest string only the new pattern matches in full, if there is one
+    pub only_new: Option<String>,
+    // the product of the two DFAs, accepting the strings exactly one of them matches
+    pub product: Dfa,
+    // the product states where only the old pattern accepts, and where only the new one does
+    pub diverging: Partition,
+}
+
+impl PatternDiff {
+    pub fn is_equivalent(&self) -> bool {
+        self.only_old.is_none() && self.only_new.is_none()
+    }
+
+    // The product in DOT, with the states where only the old pattern accepts filled in teal and
+    // those where only the new one does in yellow
+    pub fn to_dot(&self) -> String {
+        let label = "old vs new: teal accepts only old, yellow only new";
+        self.product.to_dot_partition(label, &self.diverging)
+    }
+}
+
+impl fmt::Display for PatternDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_equivalent() {
+            return writeln!(f, "equivalent");
+        }
+        match &self.only_old {
+            Some(witness) => writeln!(f, "only old matches: {witness:?}")?,
+            None => writeln!(f, "new matches everything old does")?,
+        }
+        match &self.only_new {
+            Some(witness) => writeln!(f, "only new matches: {witness:?}"),
+            None => writeln!(f, "old matches everything new does"),
+        }
+    }
+}
+
+// Compare two versions of a pattern, e.g. before and after a change under review: whether they
+// match the same strings and if not, a shortest string for each direction they differ in, along
+// with their product automaton. Matching is in full, as test_string decides.
+pub fn diff(old: &str, new: &str) -> Result<PatternDiff, RegexError> {
+    let old = try_compile_regex(old)?;
+    let new = try_compile_regex(new)?;
+
+    let (product, pairs) = old.product(&new, |a, b| a != b);
+    let accepts = |dfa: &Dfa, state: Option<StateId>| state.is_some_and(|s| dfa.accepts_at_end(s));
+    let mut diverging = vec![Vec::new(), Vec::new()];
+    for (id, (a, b)) in product.states().zip(pairs) {
+        match (accepts(&old, a), accepts(&new, b)) {
+            (true, false) => diverging[0].push(id),
+            (false, true) => diverging[1].push(id),
+            _ => {}
+        }
+    }
+
+    Ok(PatternDiff {
+        only_old: old.is_subset_of(&new).err(),
+        only_new: new.is_subset_of(&old).err(),
+        product,
+        diverging,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let d = diff("a{2,3}b?", "a{2,4}").unwrap();
+        assert!(!d.is_equivalent());
+        assert_eq!(d.only_old.as_deref(), Some("aab"));
+        assert_eq!(d.only_new.as_deref(), Some("aaaa"));
+        assert_eq!(
+            d.to_string(),
+            "only old matches: \"aab\"\nonly new matches: \"aaaa\"\n"
+        );
+        assert!(d.diverging.iter().all(|block| !block.is_empty()));
+        let dot = d.to_dot();
+        assert!(dot.contains("fillcolor = \"/set312/1\""));
+        assert!(dot.contains("fillcolor = \"/set312/2\""));
+
+        let d = diff("(a|b)+", "[ab][ab]*").unwrap();
+        assert!(d.is_equivalent());
+        assert!(d.product.accept_states().is_empty());
+        assert_eq!(diff("a+", "a*").unwrap().only_new.as_deref(), Some(""));
+        assert!(diff("a", "(").is_err());
+    }
+}
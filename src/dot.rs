@@ -0,0 +1,262 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::dfa::Dfa;
+use crate::nfa::Nfa;
+use crate::transition_table::{NfaState, Transition};
+use crate::Compilation;
+
+// How characters that can't be shown as themselves (control characters, invisible Unicode) are
+// written in labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LabelEscape {
+    // \t, \n, \u{200b}
+    #[default]
+    Mnemonic,
+    // 0x09, 0x0A, 0x200B
+    Hex,
+}
+
+// Options for rendering automata as Graphviz DOT, see Dfa::to_dot_with and Nfa::to_dot_with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DotOptions {
+    pub escape: LabelEscape,
+    // draw the characters leading from one state to another as a single edge labeled with a
+    // class like [a-z0-9], rather than one edge per character
+    pub group_classes: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        Self {
+            escape: LabelEscape::default(),
+            group_classes: true,
+        }
+    }
+}
+
+// c as it is shown in a label, with quotes and backslashes escaped either way
+pub fn escape_char(c: char, escape: LabelEscape) -> String {
+    let mnemonic = c.escape_debug().to_string();
+    let printable = !c.is_control() && !mnemonic.starts_with("\\u");
+
+    match escape {
+        LabelEscape::Hex if !printable => format!("0x{:02X}", c as u32),
+        _ => mnemonic,
+    }
+}
+
+// chars as a compact class, e.g. [a-dx] for a, b, c, d and x
+pub fn class_label(chars: &BTreeSet<char>, escape: LabelEscape) -> String {
+    let mut runs: Vec<(char, char)> = Vec::new();
+    for &c in chars {
+        match runs.last_mut() {
+            Some((_, end)) if *end as u32 + 1 == c as u32 => *end = c,
+            _ => runs.push((c, c)),
+        }
+    }
+
+    let show = |c: char| match c {
+        ']' | '-' | '^' => format!("\\{c}"),
+        c => escape_char(c, escape),
+    };
+    let mut label = String::from('[');
+    for (start, end) in runs {
+        label.push_str(&show(start));
+        match end as u32 - start as u32 {
+            0 => {}
+            1 => label.push_str(&show(end)),
+            _ => {
+                label.push('-');
+                label.push_str(&show(end));
+            }
+        }
+    }
+    label.push(']');
+
+    label
+}
+
+// The labels for the edges between one pair of states. Unless grouping is off, two or more
+// literals share a single class label.
+pub(crate) fn edge_labels(transitions: &[Transition], options: DotOptions) -> Vec<String> {
+    let literals: BTreeSet<char> = transitions
+        .iter()
+        .filter_map(|t| match t {
+            Transition::Literal(c) => Some(*c),
+            _ => None,
+        })
+        .collect();
+
+    if !options.group_classes || literals.len() < 2 {
+        return transitions
+            .iter()
+            .map(|t| t.label(options.escape))
+            .collect();
+    }
+
+    let rest = transitions
+        .iter()
+        .filter(|t| !matches!(t, Transition::Literal(_)))
+        .map(|t| t.label(options.escape));
+    std::iter::once(class_label(&literals, options.escape))
+        .chain(rest)
+        .collect()
+}
+
+// s escaped for use inside a quoted DOT string, where \ and " are special
+pub(crate) fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// NFA states renumbered s0, s1, ... in order, so the labels in every cluster of the pipeline
+// agree and stay short however many states were allocated before
+pub(crate) fn nfa_names(nfa: &Nfa) -> BTreeMap<NfaState, String> {
+    state_names(nfa.states().collect())
+}
+
+// states renumbered s0, s1, ... in order, with start and accepting keeping their names, so the
+// same automaton is drawn the same way every time
+pub(crate) fn state_names(states: BTreeSet<NfaState>) -> BTreeMap<NfaState, String> {
+    let mut n = 0;
+    states
+        .into_iter()
+        .map(|state| {
+            let name = match state {
+                NfaState::START | NfaState::ACCEPTING => state.dot_node(),
+                _ => {
+                    n += 1;
+                    format!("s{}", n - 1)
+                }
+            };
+            (state, name)
+        })
+        .collect()
+}
+
+fn nfa_cluster(nfa: &Nfa, names: &BTreeMap<NfaState, String>, options: DotOptions) -> String {
+    let mut out = String::from("subgraph cluster_nfa {\nlabel = \"NFA\";\n");
+    for (state, name) in names {
+        let shape = if *state == NfaState::ACCEPTING {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        out.push_str(&format!(
+            "nfa_{name} [label = \"{name}\"; shape = {shape}];\n"
+        ));
+    }
+
+    for start in nfa.states() {
+        let mut by_end: BTreeMap<NfaState, Vec<Transition>> = BTreeMap::new();
+        for edge in nfa.edges(start) {
+            by_end.entry(edge.end).or_default().push(edge.transition);
+        }
+
+        for (end, mut transitions) in by_end {
+            transitions.sort();
+            for label in edge_labels(&transitions, options) {
+                out.push_str(&format!(
+                    "nfa_{} -> nfa_{} [label = \"{}\"];\n",
+                    names[&start],
+                    names[&end],
+                    quote(&label)
+                ));
+            }
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+// Arrows into the DFA's start states from invisible nodes, a dashed one for the unanchored start
+// so that the two stay apart in the drawing even where minimization merged them. prefix is put
+// in front of every node name.
+pub(crate) fn dfa_entries(dfa: &Dfa, prefix: &str) -> String {
+    let mut out = format!(
+        "{prefix}start [shape = point];\n{prefix}start -> {prefix}{};\n",
+        dfa.start()
+    );
+    if let Some(search) = dfa.unanchored_start() {
+        out.push_str(&format!(
+            "{prefix}search [shape = point];\n{prefix}search -> {prefix}{search} [label = \
+             \"unanchored\"; style = dashed];\n"
+        ));
+    }
+    out
+}
+
+fn dfa_cluster(
+    dfa: &Dfa,
+    prefix: &str,
+    label: &str,
+    names: &BTreeMap<NfaState, String>,
+    options: DotOptions,
+) -> String {
+    let mut out = format!(
+        "subgraph cluster_{prefix} {{\nlabel = \"{}\";\n",
+        quote(label)
+    );
+    for id in dfa.states() {
+        let states: Vec<_> = dfa
+            .state(id)
+            .nfa_states()
+            .iter()
+            .map(|s| names.get(s).map_or_else(|| s.dot_node(), |n| n.clone()))
+            .collect();
+        let shape = if dfa.is_accepting(id) {
+            "doublecircle"
+        } else {
+            "circle"
+        };
+        out.push_str(&format!(
+            "{prefix}_{id} [label = \"{id} {{{}}}\"; shape = {shape}];\n",
+            states.join(", ")
+        ));
+    }
+
+    out.push_str(&dfa_entries(dfa, &format!("{prefix}_")));
+
+    for id in dfa.states() {
+        let mut by_end: BTreeMap<_, Vec<Transition>> = BTreeMap::new();
+        for (transition, end) in dfa.transitions(id) {
+            by_end.entry(end).or_default().push(transition);
+        }
+        for (end, transitions) in by_end {
+            for label in edge_labels(&transitions, options) {
+                out.push_str(&format!(
+                    "{prefix}_{id} -> {prefix}_{end} [label = \"{}\"];\n",
+                    quote(&label)
+                ));
+            }
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+// The NFA, the subset construction and the minimized DFA side by side in one graph, each in its
+// own cluster. NFA states are numbered the same way in all three.
+pub(crate) fn render_pipeline(compilation: &Compilation, options: DotOptions) -> String {
+    let names = nfa_names(&compilation.nfa);
+    let clusters = [
+        nfa_cluster(&compilation.nfa, &names, options),
+        dfa_cluster(
+            &compilation.raw_dfa,
+            "raw",
+            "Unminimized DFA",
+            &names,
+            options,
+        ),
+        dfa_cluster(
+            &compilation.dfa,
+            "min",
+            "DFA minimized with Hopcroft's algorithm",
+            &names,
+            options,
+        ),
+    ];
+
+    format!("digraph pipeline {{\n{}}}", clusters.concat())
+}
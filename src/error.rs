@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::parse::Span;
+
+// a piece of syntax that a SyntaxConfig can turn off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Construct {
+    BackReference,
+    UnboundedRepeat, // *, + and {n,}
+    LargeRepeat,     // a bound over the builder's max_expanded_repeat
+    WordBoundary,    // \b and \B
+}
+
+impl fmt::Display for Construct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BackReference => write!(f, "backreferences"),
+            Self::UnboundedRepeat => write!(f, "unbounded repetitions"),
+            Self::LargeRepeat => write!(f, "repetitions over the expansion limit"),
+            Self::WordBoundary => write!(f, "word boundaries"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UndefinedGroup(u64), // \n where the pattern has fewer than n groups
+    UnclosedGroup,
+    UnopenedGroup, // ) without a matching (
+    UnclosedBracket,
+    EmptyClass,                     // []
+    ReversedClassRange(char, char), // [z-a]
+    ShorthandInRange,               // [\d-z] or [a-\w]
+    UnknownPosixClass(String),      // [[:foo:]]
+    InvalidRepetition,              // anything other than {n}, {n,} or {n,m}
+    InvalidRange(u64, u64),         // {n,m} with n > m
+    NothingToRepeat,                // a modifier at the start of an alternative or after another
+    TrailingBackslash,
+    UnknownEscape(char),
+    InvalidHexEscape,
+    UnsupportedGroupSyntax, // (? not followed by <name> or P<name>
+    UnclosedGroupName,
+    EmptyGroupName,
+    Disabled(Construct),       // turned off by the builder's SyntaxConfig
+    UnknownPredicate(String),  // \p{name} with nothing registered under name
+    UnclosedPredicateName,     // \p{name without the closing brace
+    PredicateConflict(String), // a different predicate is already registered under the name
+    TooManyPredicates,         // more than predicate::MAX_PREDICATES
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UndefinedGroup(n) => write!(f, "backreference to undefined group {n}"),
+            Self::UnclosedGroup => write!(f, "unclosed group"),
+            Self::UnopenedGroup => write!(f, "unopened group"),
+            Self::UnclosedBracket => write!(f, "unclosed character class"),
+            Self::EmptyClass => write!(f, "empty character class"),
+            Self::ReversedClassRange(a, b) => write!(f, "character range {a}-{b} is reversed"),
+            Self::ShorthandInRange => write!(f, "a class shorthand can't be a range endpoint"),
+            Self::UnknownPosixClass(name) => write!(f, "unknown POSIX class [:{name}:]"),
+            Self::InvalidRepetition => write!(f, "invalid repetition"),
+            Self::InvalidRange(n, m) => write!(f, "repetition range {{{n},{m}}} is reversed"),
+            Self::NothingToRepeat => write!(f, "nothing to repeat"),
+            Self::TrailingBackslash => write!(f, "trailing backslash"),
+            Self::UnknownEscape(c) => write!(f, "unknown escape \\{c}"),
+            Self::InvalidHexEscape => write!(f, "invalid hex escape"),
+            Self::UnsupportedGroupSyntax => write!(f, "unsupported group syntax"),
+            Self::UnclosedGroupName => write!(f, "unclosed group name"),
+            Self::EmptyGroupName => write!(f, "empty group name"),
+            Self::Disabled(c) => write!(f, "{c} are disabled"),
+            Self::UnknownPredicate(name) => write!(f, "unknown predicate \\p{{{name}}}"),
+            Self::UnclosedPredicateName => write!(f, "unclosed predicate name"),
+            Self::PredicateConflict(name) => {
+                write!(f, "another predicate is already registered as {name:?}")
+            }
+            Self::TooManyPredicates => write!(f, "too many predicates"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    pub kind: ErrorKind,
+    pub span: Span,
+}
+
+impl RegexError {
+    pub fn new(kind: ErrorKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}..{}", self.kind, self.span.start, self.span.end)
+    }
+}
+
+impl std::error::Error for RegexError {}
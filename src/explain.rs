@@ -0,0 +1,172 @@
+use std::fmt;
+
+use crate::error::RegexError;
+use crate::parse::{try_lex, ParseElement, Span, Token};
+
+// What one element of a pattern does, in English. Groups are followed by the elements inside them
+// at one more depth, so a hover can show the innermost span under the cursor and a listing can
+// indent by depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub span: Span,
+    pub depth: usize,
+    // the element as written in the pattern
+    pub source: String,
+    pub description: String,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}`{}`: {}",
+            "  ".repeat(self.depth),
+            self.source,
+            self.description
+        )
+    }
+}
+
+// chars as a list of ranges, e.g. 0-9, A-Z, _
+fn describe_chars(chars: &[char]) -> String {
+    let mut chars = chars.to_vec();
+    chars.sort();
+    chars.dedup();
+
+    let mut ranges: Vec<(char, char)> = Vec::new();
+    for c in chars {
+        match ranges.last_mut() {
+            Some((_, end)) if (*end as u32) + 1 == c as u32 => *end = c,
+            _ => ranges.push((c, c)),
+        }
+    }
+
+    let show = |c: char| c.escape_debug().to_string();
+    ranges
+        .iter()
+        .map(|&(start, end)| match end as u32 - start as u32 {
+            0 => show(start),
+            1 => format!("{}, {}", show(start), show(end)),
+            _ => format!("{}-{}", show(start), show(end)),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe(elem: &ParseElement, source: &str, group: usize) -> String {
+    match elem {
+        ParseElement::Literal(c) => format!("the character {c:?}"),
+        ParseElement::Wildcard => "any character".to_string(),
+        ParseElement::Star => "repeat the previous element zero or more times".to_string(),
+        ParseElement::Plus => "repeat the previous element one or more times".to_string(),
+        ParseElement::Question => "optionally match the previous element".to_string(),
+        ParseElement::Range(min, max) if min == max => {
+            format!("repeat the previous element exactly {min} times")
+        }
+        ParseElement::Range(min, max) => {
+            format!("repeat the previous element {min} to {max} times")
+        }
+        ParseElement::OpenRange(min) => {
+            format!("repeat the previous element {min} or more times")
+        }
+        ParseElement::Lazy(modifier) => match **modifier {
+            ParseElement::Question => {
+                "optionally match the previous element, preferring to skip it".to_string()
+            }
+            _ => format!(
+                "{}, as few times as possible",
+                describe(modifier, source, group)
+            ),
+        },
+        ParseElement::Union => "or: match either what comes before or what comes after".to_string(),
+        ParseElement::Group(_, Some(name)) => format!("capture group {group} named {name:?}"),
+        ParseElement::Group(_, None) => format!("capture group {group}"),
+        ParseElement::CaseInsensitive(_) => "match the contents regardless of case".to_string(),
+        ParseElement::Bracket(chars) => match source {
+            "\\d" => "a digit (0-9)".to_string(),
+            "\\w" => "a word character (0-9, A-Z, _, a-z)".to_string(),
+            "\\s" => "a space or a tab".to_string(),
+            _ => format!("one character out of {}", describe_chars(chars)),
+        },
+        ParseElement::NegatedBracket(chars) => {
+            format!("one character other than {}", describe_chars(chars))
+        }
+        ParseElement::Predicate(id) | ParseElement::BoundedPredicate(id, _) => {
+            format!("one character the predicate {:?} holds for", id.name())
+        }
+        ParseElement::BackReference(n) => {
+            format!("whatever the pattern of group {n} matches, once more")
+        }
+        ParseElement::StartAnchor => "the start of the input".to_string(),
+        ParseElement::EndAnchor => "the end of the input".to_string(),
+        ParseElement::WordBoundary(_) => {
+            "a word boundary, between a word character and anything else".to_string()
+        }
+        ParseElement::NotWordBoundary(_) => "a position that isn't a word boundary".to_string(),
+    }
+}
+
+// explain every element of the pattern, in pattern order
+pub fn explain(pattern: &str) -> Result<Vec<Explanation>, RegexError> {
+    fn walk(
+        pattern: &str,
+        toks: &[Token],
+        depth: usize,
+        groups: &mut usize,
+        out: &mut Vec<Explanation>,
+    ) {
+        for tok in toks {
+            if let ParseElement::Group(_, _) = tok.elem {
+                *groups += 1;
+            }
+            let source = &pattern[tok.span.start..tok.span.end];
+            out.push(Explanation {
+                span: tok.span,
+                depth,
+                source: source.to_string(),
+                description: describe(&tok.elem, source, *groups),
+            });
+
+            if let ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) = &tok.elem {
+                walk(pattern, grp, depth + 1, groups, out);
+            }
+        }
+    }
+
+    let toks = try_lex(pattern)?;
+    let mut ret = Vec::new();
+    walk(pattern, &toks, 0, &mut 0, &mut ret);
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain() {
+        let lines: Vec<_> = explain("(?<y>\\d{3,5})|[a-cx]+\\.(?i:k)\\1")
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "`(?<y>\\d{3,5})`: capture group 1 named \"y\"",
+                "  `\\d`: a digit (0-9)",
+                "  `{3,5}`: repeat the previous element 3 to 5 times",
+                "`|`: or: match either what comes before or what comes after",
+                "`[a-cx]`: one character out of a-c, x",
+                "`+`: repeat the previous element one or more times",
+                "`\\.`: the character '.'",
+                "`(?i:k)`: match the contents regardless of case",
+                "  `k`: the character 'k'",
+                "`\\1`: whatever the pattern of group 1 matches, once more",
+            ]
+        );
+
+        let e = explain("ab(").unwrap_err();
+        assert_eq!(e.span, Span::new(2, 3));
+    }
+}
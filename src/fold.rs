@@ -0,0 +1,59 @@
+// Simple (one character to one character) Unicode case folding, for (?i:...) scopes. Two
+// characters match each other case-insensitively when they fold to the same character.
+
+// characters whose folding the lower(upper(c)) rule below gets wrong: the dotless i has no
+// folding outside Turkish locales, although its uppercase is I
+const NO_FOLD: &[char] = &['ı'];
+
+// characters that fold to a character whose upper and lower case forms don't lead back to them
+const EXTRA: &[(char, char)] = &[
+    ('k', '\u{212A}'), // Kelvin sign
+    ('s', 'ſ'),        // long s
+    ('å', '\u{212B}'), // Angstrom sign
+    ('σ', 'ς'),        // final sigma
+    ('ß', 'ẞ'),        // capital sharp s
+];
+
+fn single(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+// The representative of c's case class. Characters whose case mapping is more than one
+// character (İ lowercases to i plus a combining dot) fold to themselves.
+pub fn fold(c: char) -> char {
+    if NO_FOLD.contains(&c) {
+        return c;
+    }
+
+    let upper = single(c.to_uppercase()).unwrap_or(c);
+    single(upper.to_lowercase()).unwrap_or(c)
+}
+
+// every character that matches c case-insensitively, sorted and including c
+pub fn variants(c: char) -> Vec<char> {
+    let f = fold(c);
+    let mut candidates = vec![c, f];
+    candidates.extend(single(f.to_uppercase()));
+    candidates.extend(single(f.to_lowercase()));
+    for &(folded, extra) in EXTRA {
+        if folded == f {
+            candidates.push(extra);
+        }
+    }
+
+    candidates.retain(|x| fold(*x) == f);
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+}
+
+// the variants of every character in chars, sorted
+pub fn fold_all(chars: &[char]) -> Vec<char> {
+    let mut folded: Vec<_> = chars.iter().flat_map(|c| variants(*c)).collect();
+    folded.sort();
+    folded.dedup();
+
+    folded
+}
@@ -0,0 +1,467 @@
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use crate::dfa::SimError;
+use crate::error::RegexError;
+use crate::parse::{try_lex, ParseElement, Token};
+use crate::transition_table::Transition;
+use crate::{compile, test_string};
+
+// A small deterministic generator (splitmix64), so that a failing case can be reproduced from
+// its seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // a number in 0..n, n must not be 0
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // true one time in n
+    pub fn one_in(&mut self, n: usize) -> bool {
+        self.below(n) == 0
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+// Which syntax the generated patterns may use and how big they get
+#[derive(Debug, Clone)]
+pub struct GenConfig {
+    pub alphabet: Vec<char>,
+    pub max_depth: usize, // how deeply groups may nest
+    pub max_atoms: usize, // per alternative
+    pub max_alternatives: usize,
+    pub max_repeat: u64, // the largest bound in {n,m}, and how often * and + repeat
+    pub wildcards: bool,
+    pub classes: bool,
+    pub groups: bool,
+    pub quantifiers: bool,
+    pub ranges: bool,
+    pub backreferences: bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> Self {
+        Self {
+            alphabet: vec!['a', 'b', 'c', 'd'],
+            max_depth: 2,
+            max_atoms: 4,
+            max_alternatives: 3,
+            max_repeat: 3,
+            wildcards: true,
+            classes: true,
+            groups: true,
+            quantifiers: true,
+            ranges: true,
+            backreferences: false,
+        }
+    }
+}
+
+// a group that has closed, with the groups its backreferences could see
+struct Closed {
+    toks: Vec<Token>,
+    scope: Vec<Option<Rc<Closed>>>,
+}
+
+// number and close every group in toks, in the order the parser does
+fn close_groups(toks: &[Token], groups: &mut Vec<Option<Rc<Closed>>>) {
+    for tok in toks {
+        match &tok.elem {
+            ParseElement::Group(grp, _) => {
+                let mut scope = groups.clone();
+                scope.push(None);
+
+                let index = groups.len();
+                groups.push(None);
+                close_groups(grp, groups);
+                groups[index] = Some(Rc::new(Closed {
+                    toks: grp.clone(),
+                    scope,
+                }));
+            }
+            ParseElement::CaseInsensitive(grp) => close_groups(grp, groups),
+            _ => {}
+        }
+    }
+}
+
+// Random valid patterns, and strings that they do and don't match
+pub struct Generator {
+    pub config: GenConfig,
+    pub rng: Rng,
+    // while sampling, where the first $ was passed, which has to be the end of the string
+    end_anchor: Option<usize>,
+}
+
+fn push_literal(out: &mut String, c: char) {
+    if "\\.*+?{}|^$()[]-".contains(c) {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+impl Generator {
+    pub fn new(config: GenConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: Rng::new(seed),
+            end_anchor: None,
+        }
+    }
+
+    pub fn pattern(&mut self) -> String {
+        let mut out = String::new();
+        let mut closed = 0;
+        self.alternation(&mut out, self.config.max_depth, &mut closed);
+        out
+    }
+
+    fn alternation(&mut self, out: &mut String, depth: usize, closed: &mut u64) {
+        let alternatives = 1 + self.rng.below(self.config.max_alternatives);
+
+        for i in 0..alternatives {
+            if i > 0 {
+                out.push('|');
+            }
+
+            for _ in 0..1 + self.rng.below(self.config.max_atoms) {
+                self.atom(out, depth, closed);
+                if self.config.quantifiers && self.rng.one_in(3) {
+                    self.quantifier(out);
+                }
+            }
+        }
+    }
+
+    fn atom(&mut self, out: &mut String, depth: usize, closed: &mut u64) {
+        let c = *self.rng.pick(&self.config.alphabet);
+
+        match self.rng.below(8) {
+            0 if self.config.wildcards => out.push('.'),
+            1 if self.config.classes => {
+                out.push('[');
+                for _ in 0..1 + self.rng.below(3) {
+                    let c = *self.rng.pick(&self.config.alphabet);
+                    push_literal(out, c);
+                }
+                out.push(']');
+            }
+            2 | 3 if self.config.groups && depth > 0 => {
+                out.push('(');
+                self.alternation(out, depth - 1, closed);
+                out.push(')');
+                *closed += 1;
+            }
+            4 if self.config.backreferences && (1..10).contains(closed) => {
+                let n = 1 + self.rng.below(*closed as usize);
+                out.push_str(&format!("\\{n}"));
+            }
+            _ => push_literal(out, c),
+        }
+    }
+
+    fn quantifier(&mut self, out: &mut String) {
+        let max = self.config.max_repeat.max(1);
+
+        match self.rng.below(if self.config.ranges { 6 } else { 3 }) {
+            0 => out.push('*'),
+            1 => out.push('+'),
+            2 => out.push('?'),
+            3 => out.push_str(&format!("{{{}}}", self.rng.below(max as usize + 1))),
+            4 => out.push_str(&format!("{{{},}}", self.rng.below(max as usize + 1))),
+            _ => {
+                let n = self.rng.below(max as usize + 1) as u64;
+                let m = n + self.rng.below(max as usize) as u64;
+                out.push_str(&format!("{{{n},{m}}}"));
+            }
+        }
+    }
+
+    // A random string in the pattern's language, or None if the pattern doesn't parse or the
+    // path taken can't match (e.g. a reference to a group that hasn't closed)
+    pub fn matching(&mut self, pattern: &str) -> Option<String> {
+        let toks = try_lex(pattern).ok()?;
+        let mut out = String::new();
+        self.end_anchor = None;
+
+        let ok = self.sample(&toks, &mut out, &mut Vec::new());
+        let ended = self.end_anchor.take().is_none_or(|end| end == out.len());
+        (ok && ended).then_some(out)
+    }
+
+    // groups holds every group opened so far by number, and is None until the group is closed
+    fn sample(
+        &mut self,
+        toks: &[Token],
+        out: &mut String,
+        groups: &mut Vec<Option<Rc<Closed>>>,
+    ) -> bool {
+        let alternatives: Vec<&[Token]> = toks
+            .split(|t| matches!(t.elem, ParseElement::Union))
+            .collect();
+        let chosen = self.rng.below(alternatives.len());
+
+        // groups in the alternatives before the chosen one still take their numbers
+        for alternative in &alternatives[..chosen] {
+            close_groups(alternative, groups);
+        }
+        let alternative = alternatives[chosen];
+
+        let mut iter = alternative.iter().peekable();
+        while let Some(tok) = iter.next() {
+            let max = self.config.max_repeat;
+            let (lower, upper) = match iter.peek().map(|t| t.elem.greedy()) {
+                Some(ParseElement::Star) => (0, max),
+                Some(ParseElement::Plus) => (1, max.max(1)),
+                Some(ParseElement::Question) => (0, 1),
+                Some(ParseElement::Range(n, m)) => (*n, *m),
+                Some(ParseElement::OpenRange(n)) => (*n, n + max),
+                _ => (1, 1),
+            };
+            if iter.peek().is_some_and(|t| t.elem.is_modifier()) {
+                let _ = iter.next();
+            }
+
+            // what a group's contents see: the groups closed before it opened
+            let mut scope = groups.clone();
+            scope.push(None);
+
+            let count = lower + self.rng.below((upper - lower + 1) as usize) as u64;
+            for _ in 0..count {
+                let ok = match &tok.elem {
+                    ParseElement::Literal(c) => {
+                        out.push(*c);
+                        true
+                    }
+                    ParseElement::Wildcard => {
+                        out.push(*self.rng.pick(&self.config.alphabet));
+                        true
+                    }
+                    ParseElement::Bracket(chars) | ParseElement::BoundedPredicate(_, chars) => {
+                        out.push(*self.rng.pick(chars));
+                        true
+                    }
+                    ParseElement::Predicate(id) => {
+                        let holds: Vec<char> = self
+                            .config
+                            .alphabet
+                            .iter()
+                            .copied()
+                            .filter(|c| id.test(*c))
+                            .collect();
+                        if holds.is_empty() {
+                            false
+                        } else {
+                            out.push(*self.rng.pick(&holds));
+                            true
+                        }
+                    }
+                    ParseElement::NegatedBracket(chars) => {
+                        let outside: Vec<char> = self
+                            .config
+                            .alphabet
+                            .iter()
+                            .copied()
+                            .filter(|c| !chars.contains(c))
+                            .collect();
+                        if outside.is_empty() {
+                            false
+                        } else {
+                            out.push(*self.rng.pick(&outside));
+                            true
+                        }
+                    }
+                    ParseElement::StartAnchor => out.is_empty(),
+                    ParseElement::EndAnchor => {
+                        self.end_anchor.get_or_insert(out.len());
+                        true
+                    }
+                    ParseElement::Group(grp, _) => self.sample(grp, out, &mut scope.clone()),
+                    // doesn't capture, so its groups are numbered as if it weren't there
+                    ParseElement::CaseInsensitive(grp) => {
+                        self.sample(grp, out, &mut groups.clone())
+                    }
+                    // a backreference matches its group's pattern again
+                    ParseElement::BackReference(n) => match groups.get(*n as usize - 1) {
+                        Some(Some(closed)) => {
+                            self.sample(&closed.toks, out, &mut closed.scope.clone())
+                        }
+                        _ => false,
+                    },
+                    _ => false,
+                };
+
+                if !ok {
+                    return false;
+                }
+            }
+
+            // however often it repeated, the group and those inside it are closed now
+            close_groups(std::slice::from_ref(tok), groups);
+        }
+
+        true
+    }
+
+    // A string close to one the pattern matches that it doesn't match, found by mutating
+    // matching strings, or None if no such string turned up
+    pub fn non_matching(&mut self, pattern: &str) -> Option<String> {
+        let dfa = compile(pattern).ok()?.dfa;
+
+        for _ in 0..32 {
+            let mut chars: Vec<char> = self.matching(pattern).unwrap_or_default().chars().collect();
+
+            let c = *self.rng.pick(&self.config.alphabet);
+            let at = self.rng.below(chars.len() + 1);
+            match self.rng.below(3) {
+                0 => chars.insert(at, c),
+                1 if at < chars.len() => {
+                    chars.remove(at);
+                }
+                _ if at < chars.len() => chars[at] = c,
+                _ => chars.push(c),
+            }
+
+            let candidate: String = chars.into_iter().collect();
+            if test_string(&candidate, &dfa).is_err() {
+                return Some(candidate);
+            }
+        }
+
+        None
+    }
+}
+
+// Strings a pattern matches and near misses it rejects with the reason, each sorted shortest
+// first, e.g. for checking that a pattern means what its author meant
+#[derive(Debug, PartialEq)]
+pub struct Examples {
+    pub accepted: Vec<String>,
+    pub rejected: Vec<(String, SimError)>,
+}
+
+// Up to count distinct strings of each kind, sampled from the pattern and then mutated into near
+// misses with the characters its DFA mentions and one that it doesn't
+pub fn examples(pattern: &str, count: usize, seed: u64) -> Result<Examples, RegexError> {
+    let dfa = compile(pattern)?.dfa;
+
+    let mut alphabet = BTreeSet::new();
+    for id in dfa.states() {
+        for (transition, _) in dfa.transitions(id) {
+            if let Transition::Literal(c) = transition {
+                alphabet.insert(c);
+            }
+        }
+    }
+    if let Some(other) = ['x', '0', '!', ' ']
+        .into_iter()
+        .find(|c| !alphabet.contains(c))
+    {
+        alphabet.insert(other);
+    }
+    let config = GenConfig {
+        alphabet: alphabet.into_iter().collect(),
+        ..GenConfig::default()
+    };
+    let mut generator = Generator::new(config, seed);
+
+    let mut accepted = BTreeSet::new();
+    let mut rejected = BTreeSet::new();
+    for _ in 0..count * 16 {
+        if accepted.len() < count
+            && let Some(s) = generator.matching(pattern)
+        {
+            accepted.insert((s.chars().count(), s));
+        }
+        if rejected.len() < count
+            && let Some(s) = generator.non_matching(pattern)
+        {
+            rejected.insert((s.chars().count(), s));
+        }
+    }
+
+    Ok(Examples {
+        accepted: accepted.into_iter().map(|(_, s)| s).collect(),
+        rejected: rejected
+            .into_iter()
+            .map(|(_, s)| {
+                let e = test_string(&s, &dfa).expect_err("non_matching strings are rejected");
+                (s, e)
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile_regex, Regex};
+
+    #[test]
+    fn test_examples() {
+        let found = examples("ab+(c|d)", 3, 7).unwrap();
+        assert_eq!(found.accepted.len(), 3);
+        assert_eq!(found.rejected.len(), 3);
+
+        let dfa = compile_regex("ab+(c|d)");
+        for s in &found.accepted {
+            assert_eq!(test_string(s, &dfa), Ok(()), "{s}");
+        }
+        for (s, e) in &found.rejected {
+            assert_eq!(test_string(s, &dfa).as_ref(), Err(e), "{s}");
+        }
+        let lens: Vec<_> = found.accepted.iter().map(|s| s.len()).collect();
+        assert!(lens.is_sorted());
+
+        // a pattern with a finite language runs out of new strings
+        assert_eq!(examples("a|b", 5, 0).unwrap().accepted, ["a", "b"]);
+        assert!(examples("(", 1, 0).is_err());
+    }
+
+    #[test]
+    fn test_generated_patterns() {
+        // small enough that the reversed automata Regex builds stay cheap
+        let config = GenConfig {
+            max_atoms: 3,
+            max_alternatives: 2,
+            max_repeat: 2,
+            backreferences: true,
+            ..GenConfig::default()
+        };
+
+        for seed in 0..100 {
+            let mut generator = Generator::new(config.clone(), seed);
+            let pattern = generator.pattern();
+            let dfa = compile_regex(&pattern);
+            let regex = Regex::new(&pattern).unwrap();
+
+            for _ in 0..4 {
+                if let Some(s) = generator.matching(&pattern) {
+                    assert_eq!(test_string(&s, &dfa), Ok(()), "{pattern} should match {s}");
+                    assert!(regex.is_match(&s), "{pattern} should find {s}");
+                    assert!(regex.find(&s).is_some(), "{pattern} should find {s}");
+                }
+                if let Some(s) = generator.non_matching(&pattern) {
+                    assert!(test_string(&s, &dfa).is_err(), "{pattern} matched {s}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::error::RegexError;
+use crate::nfa::Nfa;
+use crate::parse::{
+    has_backreference, parse_alternative, try_lex, union_alternatives, ParseElement,
+};
+use crate::{Compilation, CompileStats};
+
+// Compiles successive versions of a pattern, reusing the NFA of every top-level alternative whose
+// text didn't change since the last version. Meant for editors and REPLs where a large
+// alternation grows one alternative at a time. The DFAs are still built from scratch.
+#[derive(Debug, Default)]
+pub struct IncrementalCompiler {
+    // the NFA of each alternative of the last version by its text, with its offset there
+    cache: HashMap<String, (Nfa, usize)>,
+    reused: usize,
+}
+
+impl IncrementalCompiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The same as crate::compile. Patterns with backreferences are compiled as a whole, since an
+    // alternative's groups can be referenced from the alternatives after it.
+    pub fn compile(&mut self, pattern: &str) -> Result<Compilation, RegexError> {
+        let timer = Instant::now();
+        let tokens = try_lex(pattern)?;
+        let lex = timer.elapsed();
+        self.reused = 0;
+        if has_backreference(&tokens) {
+            self.cache.clear();
+            return crate::compile(pattern);
+        }
+
+        let timer = Instant::now();
+        let mut cache = HashMap::new();
+        let mut alts = Vec::new();
+        for alt in tokens.split(|t| matches!(t.elem, ParseElement::Union)) {
+            let (start, end) = match alt {
+                [] => (0, 0),
+                [first, .., last] => (first.span.start, last.span.end),
+                [only] => (only.span.start, only.span.end),
+            };
+            let text = &pattern[start..end];
+
+            let nfa = match self.cache.get(text) {
+                Some((nfa, old_start)) => {
+                    self.reused += 1;
+                    let mut nfa = nfa.clone();
+                    nfa.move_spans(*old_start, start);
+                    nfa
+                }
+                None => parse_alternative(alt.to_vec())?,
+            };
+
+            cache.insert(text.to_string(), (nfa.clone(), start));
+            alts.push(nfa);
+        }
+        self.cache = cache;
+        let nfa = union_alternatives(alts);
+        let stats = CompileStats {
+            lex,
+            parse: timer.elapsed(),
+            ..Default::default()
+        };
+
+        Ok(Compilation::from_nfa(tokens, nfa, stats))
+    }
+
+    // how many alternatives the last compile took from the previous version
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, test_string};
+
+    #[test]
+    fn test_reuse() {
+        let mut compiler = IncrementalCompiler::new();
+        let inputs = ["foo", "bar", "baz", "abab", "c", "", "qux"];
+
+        let mut check = |pattern: &str, reused| {
+            let c = compiler.compile(pattern).unwrap();
+            assert_eq!(compiler.reused(), reused, "{pattern}");
+            let fresh = compile(pattern).unwrap();
+            for input in inputs {
+                assert_eq!(
+                    test_string(input, &c.dfa).is_ok(),
+                    test_string(input, &fresh.dfa).is_ok(),
+                    "{pattern} on {input}"
+                );
+            }
+        };
+
+        check("foo|(ab)*", 0);
+        check("foo|(ab)*|baz", 2);
+        check("(ab)*|foo|baz|", 3);
+        check("bar|foo|(ab)*|(ab)*", 3);
+        // backreferences make the whole pattern compile from scratch
+        check("(c)|foo|\\1", 0);
+        check("(c)|foo", 0);
+    }
+
+    #[test]
+    fn test_spans_follow_alternatives() {
+        let mut compiler = IncrementalCompiler::new();
+        compiler.compile("abc|xy").unwrap();
+        let c = compiler.compile("q|xy").unwrap();
+        assert_eq!(compiler.reused(), 1);
+
+        let mut spans: Vec<_> = c.nfa.spans().values().map(|s| (s.start, s.end)).collect();
+        spans.sort();
+        assert_eq!(spans, [(0, 1), (2, 3), (3, 4)]);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+
+use crate::backtrack;
+use crate::captures::{CaptureMode, Captures, Match};
+use crate::dfa::Dfa;
+use crate::id::StateId;
+use crate::meta::Cache;
+use crate::Regex;
+
+// Successive non-overlapping matches. After an empty match the search resumes one character
+// (not byte) later, and an empty match right where the previous match ended is skipped, so a*
+// over "baaac" yields 0..0, 1..4 and 5..5. With skip_empty, empty matches aren't reported at
+// all and a* yields only 1..4.
+pub struct Matches<'r, 'h> {
+    regex: &'r Regex,
+    haystack: &'h str,
+    at: usize,
+    last_end: Option<usize>,
+    skip_empty: bool,
+}
+
+impl<'r, 'h> Matches<'r, 'h> {
+    pub(crate) fn new(regex: &'r Regex, haystack: &'h str) -> Self {
+        Self {
+            regex,
+            haystack,
+            at: 0,
+            last_end: None,
+            skip_empty: false,
+        }
+    }
+
+    pub fn skip_empty(mut self) -> Self {
+        self.skip_empty = true;
+        self
+    }
+
+    // the offset of the character boundary after at, or past the end if there is none
+    fn next_boundary(&self, at: usize) -> usize {
+        self.haystack[at..]
+            .chars()
+            .next()
+            .map_or(self.haystack.len() + 1, |c| at + c.len_utf8())
+    }
+}
+
+impl<'h> Iterator for Matches<'_, 'h> {
+    type Item = Match<'h>;
+
+    fn next(&mut self) -> Option<Match<'h>> {
+        loop {
+            if self.at > self.haystack.len() {
+                return None;
+            }
+
+            let m = self.regex.find_at(self.haystack, self.at)?;
+
+            if m.is_empty() {
+                self.at = self.next_boundary(m.end());
+                if self.skip_empty || self.last_end == Some(m.end()) {
+                    continue;
+                }
+            } else {
+                self.at = m.end();
+            }
+
+            self.last_end = Some(m.end());
+            return Some(m);
+        }
+    }
+}
+
+// The offsets of the matches Matches gives, found with one cache kept across searches, see
+// Regex::find_positions_iter
+pub struct Positions<'r, 'h> {
+    regex: &'r Regex,
+    haystack: &'h str,
+    at: usize,
+    last_end: Option<usize>,
+    cache: Cache,
+}
+
+impl<'r, 'h> Positions<'r, 'h> {
+    pub(crate) fn new(regex: &'r Regex, haystack: &'h str) -> Self {
+        Self {
+            regex,
+            haystack,
+            at: 0,
+            last_end: None,
+            cache: Cache::new(),
+        }
+    }
+}
+
+impl Iterator for Positions<'_, '_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        loop {
+            if self.at > self.haystack.len() {
+                return None;
+            }
+
+            let (start, end) = self
+                .regex
+                .bounds_at(self.haystack, self.at, &mut self.cache)?;
+
+            if start == end {
+                self.at = self.haystack[end..]
+                    .chars()
+                    .next()
+                    .map_or(self.haystack.len() + 1, |c| end + c.len_utf8());
+                if self.last_end == Some(end) {
+                    continue;
+                }
+            } else {
+                self.at = end;
+            }
+
+            self.last_end = Some(end);
+            return Some((start, end));
+        }
+    }
+}
+
+// Successive non-overlapping leftmost-longest matches of a DFA (see Dfa::find), with the same
+// empty-match rules as Matches
+pub struct DfaMatches<'d, 'h> {
+    dfa: &'d Dfa,
+    haystack: &'h str,
+    at: usize,
+    last_end: Option<usize>,
+}
+
+impl<'d, 'h> DfaMatches<'d, 'h> {
+    pub(crate) fn new(dfa: &'d Dfa, haystack: &'h str) -> Self {
+        Self {
+            dfa,
+            haystack,
+            at: 0,
+            last_end: None,
+        }
+    }
+}
+
+impl<'h> Iterator for DfaMatches<'_, 'h> {
+    type Item = Match<'h>;
+
+    fn next(&mut self) -> Option<Match<'h>> {
+        loop {
+            if self.at > self.haystack.len() {
+                return None;
+            }
+
+            let m = self.dfa.find_at(self.haystack, self.at)?;
+
+            if m.is_empty() {
+                self.at = self.haystack[m.end()..]
+                    .chars()
+                    .next()
+                    .map_or(self.haystack.len() + 1, |c| m.end() + c.len_utf8());
+                if self.last_end == Some(m.end()) {
+                    continue;
+                }
+            } else {
+                self.at = m.end();
+            }
+
+            self.last_end = Some(m.end());
+            return Some(m);
+        }
+    }
+}
+
+// The strings a DFA accepts in full, shortest first and in lexicographic order among those of
+// the same length, at most cap of them (see Dfa::language_iter). A wildcard or predicate edge is
+// spelled with one character that takes it, so such a string stands for many alike.
+pub struct LanguageIter {
+    // the edges out of each state reachable from the start as (character, end), sorted
+    edges: Vec<Vec<(char, StateId)>>,
+    start: StateId,
+    // live[n] holds the reachable states that accept some string of length n
+    live: Vec<Vec<bool>>,
+    // the strings of the last length found, still to be yielded
+    pending: VecDeque<String>,
+    left: usize,
+}
+
+impl LanguageIter {
+    pub(crate) fn new(dfa: &Dfa, cap: usize) -> Self {
+        let mut edges = vec![Vec::new(); dfa.state_count()];
+        let mut reachable = vec![false; dfa.state_count()];
+        reachable[dfa.start().as_usize()] = true;
+        let mut stack = vec![dfa.start()];
+        while let Some(state) = stack.pop() {
+            let row: &mut Vec<(char, StateId)> = &mut edges[state.as_usize()];
+            for (transition, end) in dfa.transitions(state) {
+                let Some(c) = dfa.edge_char(state, transition, end) else {
+                    continue;
+                };
+                row.push((c, end));
+                if !reachable[end.as_usize()] {
+                    reachable[end.as_usize()] = true;
+                    stack.push(end);
+                }
+            }
+            // a wildcard can be spelled with the same character as a literal edge to its end
+            row.sort();
+            row.dedup();
+        }
+
+        let accepting = dfa
+            .states()
+            .map(|s| reachable[s.as_usize()] && dfa.accepts_at_end(s));
+        Self {
+            edges,
+            start: dfa.start(),
+            live: vec![accepting.collect()],
+            pending: VecDeque::new(),
+            left: cap,
+        }
+    }
+
+    // up to self.left strings of length len from state on, in order, after prefix
+    fn spell(&mut self, state: StateId, len: usize, prefix: &mut String) {
+        if len == 0 {
+            self.pending.push_back(prefix.clone());
+            return;
+        }
+        for i in 0..self.edges[state.as_usize()].len() {
+            let (c, end) = self.edges[state.as_usize()][i];
+            if self.pending.len() == self.left {
+                return;
+            }
+            if self.live[len - 1][end.as_usize()] {
+                prefix.push(c);
+                self.spell(end, len - 1, prefix);
+                prefix.pop();
+            }
+        }
+    }
+}
+
+impl Iterator for LanguageIter {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if self.left == 0 {
+                return None;
+            }
+            if let Some(s) = self.pending.pop_front() {
+                self.left -= 1;
+                return Some(s);
+            }
+
+            // once no state accepts a string of some length, none accepts a longer one
+            let len = self.live.len() - 1;
+            let live = &self.live[len];
+            if !live.contains(&true) {
+                return None;
+            }
+            let starts = live[self.start.as_usize()];
+            let longer = self
+                .edges
+                .iter()
+                .map(|row| row.iter().any(|(_, end)| live[end.as_usize()]))
+                .collect();
+            if starts {
+                self.spell(self.start, len, &mut String::new());
+            }
+            self.live.push(longer);
+        }
+    }
+}
+
+// The groups of each successive match, with the same empty-match rules as Matches
+pub struct CaptureMatches<'r, 'h> {
+    matches: Matches<'r, 'h>,
+}
+
+impl<'r, 'h> CaptureMatches<'r, 'h> {
+    pub(crate) fn new(regex: &'r Regex, haystack: &'h str) -> Self {
+        Self {
+            matches: Matches::new(regex, haystack),
+        }
+    }
+}
+
+impl<'h> Iterator for CaptureMatches<'_, 'h> {
+    type Item = Captures<'h>;
+
+    fn next(&mut self) -> Option<Captures<'h>> {
+        let m = self.matches.next()?;
+        let regex = self.matches.regex;
+
+        // leftmost-first from the match's own start finds the same match again, now with groups
+        let found = backtrack::search(&regex.prog, m.haystack(), m.start(), true, None, false)?;
+        Some(Captures::new(
+            m.haystack(),
+            found,
+            regex.names.clone(),
+            CaptureMode::LastIteration,
+        ))
+    }
+}
+
+// The text between matches. Optionally drops a trailing empty field (like str::split_terminator),
+// yields each delimiter between the fields around it, or stops splitting after a number of
+// fields (like str::splitn).
+pub struct Split<'r, 'h> {
+    matches: Matches<'r, 'h>,
+    haystack: &'h str,
+    last: usize,
+    done: bool,
+    drop_trailing_empty: bool,
+    keep_delimiters: bool,
+    delimiter: Option<&'h str>,
+    // how many more fields there can be, the last of them holding the rest of the haystack
+    remaining: usize,
+}
+
+impl<'r, 'h> Split<'r, 'h> {
+    pub(crate) fn new(
+        regex: &'r Regex,
+        haystack: &'h str,
+        drop_trailing_empty: bool,
+        keep_delimiters: bool,
+    ) -> Self {
+        Self {
+            matches: Matches::new(regex, haystack),
+            haystack,
+            last: 0,
+            done: false,
+            drop_trailing_empty,
+            keep_delimiters,
+            delimiter: None,
+            remaining: usize::MAX,
+        }
+    }
+
+    // at most limit fields, the last of which is the rest of the haystack unsplit
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.remaining = limit;
+        self
+    }
+
+    // don't split where the pattern only matches the empty string
+    pub fn skip_empty(mut self) -> Self {
+        self.matches.skip_empty = true;
+        self
+    }
+}
+
+impl<'h> Iterator for Split<'_, 'h> {
+    type Item = &'h str;
+
+    fn next(&mut self) -> Option<&'h str> {
+        if let Some(delimiter) = self.delimiter.take() {
+            return Some(delimiter);
+        }
+
+        if self.done {
+            return None;
+        }
+        match self.remaining {
+            0 => {
+                self.done = true;
+                return None;
+            }
+            1 => {
+                self.done = true;
+                return Some(&self.haystack[self.last..]);
+            }
+            _ => {}
+        }
+
+        match self.matches.next() {
+            Some(m) => {
+                self.remaining -= 1;
+                let field = &self.haystack[self.last..m.start()];
+                self.last = m.end();
+                if self.keep_delimiters {
+                    self.delimiter = Some(m.as_str());
+                }
+                Some(field)
+            }
+            None => {
+                self.done = true;
+                let field = &self.haystack[self.last..];
+                if self.drop_trailing_empty && field.is_empty() {
+                    None
+                } else {
+                    Some(field)
+                }
+            }
+        }
+    }
+}
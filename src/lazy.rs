@@ -0,0 +1,91 @@
+use crate::{Regex, RegexBuilder};
+
+// Support for lazy_regex! and static_regex!, which compile a constant pattern on first use and
+// share it from then on, so a pattern in a hot loop isn't compiled again on every iteration.
+
+// The pattern built with the options configure sets, panicking like Regex::new(..).unwrap()
+// with the pattern in the message if it is invalid
+pub fn compile(pattern: &str, configure: impl FnOnce(&mut RegexBuilder)) -> Regex {
+    let mut builder = RegexBuilder::new(pattern);
+    configure(&mut builder);
+    builder
+        .build()
+        .unwrap_or_else(|e| panic!("invalid pattern {pattern:?}: {e}"))
+}
+
+// A &'static Regex compiled the first time the expression runs, e.g.
+// lazy_regex!("[0-9]+").find(line), or with options lazy_regex!("abc", |b| {
+// b.case_insensitive(true); }). Each call site has its own cache, so the pattern has to be the
+// same every time it runs.
+#[macro_export]
+macro_rules! lazy_regex {
+    ($pattern:expr $(,)?) => {
+        $crate::lazy_regex!($pattern, |_| {})
+    };
+    ($pattern:expr, $configure:expr $(,)?) => {{
+        static REGEX: ::std::sync::OnceLock<$crate::Regex> = ::std::sync::OnceLock::new();
+        REGEX.get_or_init(|| $crate::lazy::compile($pattern, $configure))
+    }};
+}
+
+// Declare statics that compile their pattern the first time they are used, e.g.
+// static_regex!(pub static DATE = "\\d{4}-\\d{2}-\\d{2}";), which are LazyLock<Regex>.
+#[macro_export]
+macro_rules! static_regex {
+    ($($vis:vis static $name:ident = $pattern:expr;)+) => {
+        $(
+            $vis static $name: ::std::sync::LazyLock<$crate::Regex> =
+                ::std::sync::LazyLock::new(|| $crate::lazy::compile($pattern, |_| {}));
+        )+
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::ptr;
+    use std::thread;
+
+    use crate::Regex;
+
+    static_regex! {
+        static WORD = "[a-z]+";
+        pub(crate) static DATE = "\\d{4}-\\d{2}";
+    }
+
+    fn first_number(line: &str) -> Option<&str> {
+        lazy_regex!("[0-9]+").find(line).map(|m| m.as_str())
+    }
+
+    #[test]
+    fn test_lazy_regex() {
+        assert_eq!(first_number("ab 12 c3"), Some("12"));
+        assert_eq!(first_number("none"), None);
+        // every call at one site shares the Regex compiled the first time
+        let regexes: Vec<&'static Regex> = (0..3)
+            .map(|_| {
+                lazy_regex!("a|b", |b| {
+                    b.case_insensitive(true);
+                })
+            })
+            .collect();
+        assert!(regexes.windows(2).all(|w| ptr::eq(w[0], w[1])));
+        assert!(regexes[0].is_match("B"));
+
+        let found = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| WORD.find("12 ab").map(|m| m.range())))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(found, vec![Some(3..5); 4]);
+        assert!(DATE.is_match("2024-05"));
+
+        let err = panic::catch_unwind(|| lazy_regex!("(").is_match("")).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("invalid pattern \"(\": "));
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::captures::Match;
+use crate::error::RegexError;
+use crate::nfa::{Nfa, Numbered, AT_END, AT_START};
+use crate::parse::{parse, try_lex};
+use crate::sparse_set::SparseSet;
+use crate::transition_table::{Index, Transition};
+use crate::CharMap;
+
+// how many states a LazyCache holds by default before it starts over
+pub const DEFAULT_CACHE_STATES: usize = 4096;
+
+// the kinds of pass, as indexes into LazyDfa::starts: anchored at the start of the input,
+// unanchored, and unanchored over the input read backwards
+const FORWARD: usize = 0;
+const SEARCH: usize = 1;
+const REVERSE: usize = 2;
+
+// hands out LazyDfa::id, so a cache knows whose states it holds
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// A DFA built while it runs instead of up front. Each state is a set of NFA states, made the
+// first time a search reaches it and kept in a LazyCache for later searches, along with the
+// edges followed out of it. A pattern whose full DFA would be huge, like (a|b)*a(a|b){20},
+// starts matching at once and only ever builds the states its haystacks lead to. When the
+// cache is full it is cleared and the search carries on, so memory stays bounded at the cost
+// of building some states again.
+#[derive(Debug)]
+pub struct LazyDfa {
+    // The pattern's NFA followed by the reversed one, each with a search state that loops on
+    // any char before its start, so one set of states serves every kind of pass
+    nfa: Numbered,
+    // where the passes start: see FORWARD, SEARCH and REVERSE
+    starts: [usize; 3],
+    // the forward and reversed NFAs' accepting states
+    accepting: [usize; 2],
+    // applied to every character of the input, see Dfa::set_char_map
+    char_map: CharMap,
+    max_states: usize,
+    id: usize,
+}
+
+// A state of a LazyDfa. nfa_states is sorted, and empty for the dead state.
+#[derive(Debug)]
+struct State {
+    nfa_states: Vec<usize>,
+    accepting: bool,
+    // whether the NFA accepts if the input ends here, crossing $ edges
+    accepting_at_end: bool,
+    next: HashMap<char, usize>,
+}
+
+// The states a LazyDfa has built so far. Like meta::Cache, it is kept apart so the LazyDfa can
+// be shared between threads, each with a cache of its own. A cache used with another LazyDfa
+// forgets what it held.
+#[derive(Debug, Default)]
+pub struct LazyCache {
+    owner: usize,
+    states: Vec<State>,
+    index: HashMap<Vec<usize>, usize>,
+    // the start state of each kind of pass, where the input begins and elsewhere
+    starts: [[Option<usize>; 2]; 3],
+    clears: usize,
+    set: SparseSet,
+    stack: Vec<usize>,
+}
+
+impl LazyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the states built since the cache was last cleared
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // how many times the cache has filled up and been cleared
+    pub fn clear_count(&self) -> usize {
+        self.clears
+    }
+
+    fn clear(&mut self) {
+        self.states.clear();
+        self.index.clear();
+        self.starts = [[None; 2]; 3];
+    }
+}
+
+impl LazyDfa {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        Ok(Self::from_nfa(&parse(try_lex(pattern)?)?))
+    }
+
+    pub fn from_nfa(nfa: &Nfa) -> Self {
+        Self::with_cache_capacity(nfa, DEFAULT_CACHE_STATES)
+    }
+
+    // a LazyDfa whose caches hold at most max_states states (at least one)
+    pub fn with_cache_capacity(nfa: &Nfa, max_states: usize) -> Self {
+        let mut forward = nfa.clone();
+        let search = forward.add_unanchored_start();
+        let mut reverse = nfa.reverse();
+        let reverse_search = reverse.add_unanchored_start();
+
+        let mut numbered = Numbered::new(&forward);
+        let offset = numbered.edges.len();
+        let reverse = Numbered::new(&reverse);
+        numbered
+            .edges
+            .extend(reverse.edges.into_iter().map(|edges| {
+                edges
+                    .into_iter()
+                    .map(|(transition, end)| (transition, end + offset))
+                    .collect()
+            }));
+
+        Self {
+            starts: [0, search.index(), reverse_search.index() + offset],
+            accepting: [numbered.accepting, reverse.accepting + offset],
+            nfa: numbered,
+            char_map: std::convert::identity,
+            max_states: max_states.max(1),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn cache_capacity(&self) -> usize {
+        self.max_states
+    }
+
+    // map every character of the input before matching it, e.g. with the same map the pattern's
+    // characters went through
+    pub fn set_char_map(&mut self, char_map: CharMap) {
+        self.char_map = char_map;
+    }
+
+    // the state for the closure of the NFA states in cache.set, built if it is new
+    fn add_state(&self, cache: &mut LazyCache) -> usize {
+        let mut nfa_states: Vec<usize> = cache.set.iter().collect();
+        nfa_states.sort_unstable();
+        if let Some(&i) = cache.index.get(&nfa_states) {
+            return i;
+        }
+        if cache.states.len() >= self.max_states {
+            cache.clear();
+            cache.clears += 1;
+        }
+
+        cache.set.clear();
+        for &s in &nfa_states {
+            self.nfa.close(s, &AT_END, &mut cache.set, &mut cache.stack);
+        }
+        let [forward, reverse] = self.accepting;
+        let state = State {
+            accepting: nfa_states.binary_search(&forward).is_ok()
+                || nfa_states.binary_search(&reverse).is_ok(),
+            accepting_at_end: cache.set.contains(forward) || cache.set.contains(reverse),
+            nfa_states: nfa_states.clone(),
+            next: HashMap::new(),
+        };
+        cache.states.push(state);
+        cache.index.insert(nfa_states, cache.states.len() - 1);
+
+        cache.states.len() - 1
+    }
+
+    fn prepare(&self, cache: &mut LazyCache) {
+        if cache.owner != self.id {
+            *cache = LazyCache {
+                owner: self.id,
+                set: SparseSet::new(self.nfa.edges.len()),
+                ..LazyCache::default()
+            };
+        }
+    }
+
+    fn start(&self, kind: usize, at_start: bool, cache: &mut LazyCache) -> usize {
+        if let Some(start) = cache.starts[kind][at_start as usize] {
+            return start;
+        }
+        let through: &[Transition] = if at_start {
+            &AT_START
+        } else {
+            &[Transition::Epsilon]
+        };
+        cache.set.clear();
+        self.nfa
+            .close(self.starts[kind], through, &mut cache.set, &mut cache.stack);
+        let start = self.add_state(cache);
+        cache.starts[kind][at_start as usize] = Some(start);
+
+        start
+    }
+
+    // the state after reading c in state from, built if this edge hasn't been followed before
+    fn next(&self, from: usize, c: char, cache: &mut LazyCache) -> usize {
+        if let Some(&to) = cache.states[from].next.get(&c) {
+            return to;
+        }
+
+        let through = &[Transition::Epsilon];
+        let mapped = (self.char_map)(c);
+        cache.set.clear();
+        for &s in &cache.states[from].nfa_states {
+            for (transition, end) in &self.nfa.edges[s] {
+                if transition.accepts(mapped) {
+                    self.nfa
+                        .close(*end, through, &mut cache.set, &mut cache.stack);
+                }
+            }
+        }
+        let clears = cache.clears;
+        let to = self.add_state(cache);
+        // from is gone if adding to cleared the cache
+        if cache.clears == clears {
+            cache.states[from].next.insert(c, to);
+        }
+
+        to
+    }
+
+    // The length of the longest prefix of input the pattern accepts, like Nfa::longest_prefix.
+    // ^ holds where input begins only if at_start, and $ where it ends.
+    pub fn longest_prefix(
+        &self,
+        input: &str,
+        at_start: bool,
+        cache: &mut LazyCache,
+    ) -> Option<usize> {
+        self.prepare(cache);
+        let mut state = self.start(FORWARD, at_start, cache);
+        let mut longest = None;
+
+        for (i, c) in input.char_indices() {
+            if cache.states[state].accepting {
+                longest = Some(i);
+            }
+            state = self.next(state, c, cache);
+            if cache.states[state].nfa_states.is_empty() {
+                return longest;
+            }
+        }
+
+        if cache.states[state].accepting_at_end {
+            longest = Some(input.len());
+        }
+        longest
+    }
+
+    // Whether the pattern matches anywhere in haystack, in one pass from the unanchored start
+    // that stops at the first accepting state
+    pub fn contains_match(&self, haystack: &str, cache: &mut LazyCache) -> bool {
+        self.prepare(cache);
+        let mut state = self.start(SEARCH, true, cache);
+
+        for c in haystack.chars() {
+            if cache.states[state].accepting {
+                return true;
+            }
+            state = self.next(state, c, cache);
+        }
+
+        cache.states[state].accepting_at_end
+    }
+
+    // Where the leftmost match in haystack starts, reading it backwards once with the reversed
+    // pattern: its state accepts at every position some match starts from
+    fn leftmost_start(&self, haystack: &str, cache: &mut LazyCache) -> Option<usize> {
+        let mut state = self.start(REVERSE, true, cache);
+        let mut leftmost = None;
+
+        for (i, c) in haystack.char_indices().rev() {
+            if cache.states[state].accepting {
+                leftmost = Some(i + c.len_utf8());
+            }
+            state = self.next(state, c, cache);
+        }
+
+        if cache.states[state].accepting_at_end {
+            leftmost = Some(0);
+        }
+        leftmost
+    }
+
+    // The leftmost-longest match anywhere in haystack, like Nfa::find. A forward pass rules the
+    // haystack out or confirms a match, a backward one finds where the leftmost match starts and
+    // an anchored one how far it goes, so the search takes time linear in the haystack.
+    pub fn find<'h>(&self, haystack: &'h str, cache: &mut LazyCache) -> Option<Match<'h>> {
+        if !self.contains_match(haystack, cache) {
+            return None;
+        }
+        let start = self.leftmost_start(haystack, cache)?;
+        let len = self.longest_prefix(&haystack[start..], start == 0, cache)?;
+
+        Some(Match::new(haystack, start, start + len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn test_lazy_dfa() {
+        // the eager DFA for this has thousands of states
+        let lazy = LazyDfa::new("(a|b)*a(a|b){11}").unwrap();
+        let mut cache = LazyCache::new();
+        let hit = "ba".to_string() + &"b".repeat(11) + "x";
+        assert_eq!(
+            lazy.find(&hit, &mut cache).map(|m| m.range()),
+            Some(0..hit.len() - 1)
+        );
+        assert_eq!(lazy.find("ab", &mut cache), None);
+
+        // a small cache is cleared along the way and gives the same answers
+        let pattern = "^x|a(b|bc)d?|c$|(a|b)*a(a|b){3}";
+        let nfa = compile(pattern).unwrap().nfa;
+        let small = LazyDfa::with_cache_capacity(&nfa, 3);
+        let mut small_cache = LazyCache::new();
+        for input in ["xab", "zabcd", "abc", "zc", "zcz", "", "bbabbbab"] {
+            let expected = nfa.find(input).map(|m| m.range());
+            assert_eq!(
+                small.find(input, &mut small_cache).map(|m| m.range()),
+                expected
+            );
+            assert_eq!(lazy.find(input, &mut cache), None);
+        }
+        assert!(small_cache.state_count() <= 3);
+        assert!(small_cache.clear_count() > 0);
+
+        // the cache keeps its states between searches
+        let states = cache.state_count();
+        lazy.find(&hit, &mut cache);
+        assert_eq!(cache.state_count(), states);
+    }
+
+    #[test]
+    fn test_lazy_dfa_linear_find() {
+        // trying every position in turn would read this haystack 20000 times over
+        let lazy = LazyDfa::new("a*b").unwrap();
+        let mut cache = LazyCache::new();
+        let haystack = "a".repeat(20000);
+        assert_eq!(lazy.find(&haystack, &mut cache), None);
+        assert!(!lazy.contains_match(&haystack, &mut cache));
+
+        let haystack = haystack + "b";
+        assert_eq!(
+            lazy.find(&haystack, &mut cache).map(|m| m.range()),
+            Some(0..haystack.len())
+        );
+        // the leftmost match wins over one that ends sooner
+        let lazy = LazyDfa::new("abcd|c").unwrap();
+        assert_eq!(
+            lazy.find("xabcd", &mut cache).map(|m| m.range()),
+            Some(1..5)
+        );
+    }
+}
@@ -1,170 +1,3414 @@
+pub mod backtrack;
+pub mod captures;
+pub mod conformance;
+pub mod coverage;
+pub mod decision_tree;
+pub mod dense;
 pub mod dfa;
+pub mod diff;
+pub mod dot;
+pub mod error;
+pub mod explain;
+pub mod fold;
+pub mod generate;
+pub mod id;
+pub mod incremental;
+pub mod iter;
+pub mod lazy;
+pub mod lazy_dfa;
+pub mod meta;
+pub mod mutate;
 pub mod nfa;
 pub mod parse;
+pub mod predicate;
+pub mod prog;
+pub mod replace;
+pub mod session;
+pub mod set;
+pub mod simulator;
+pub mod sparse_set;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod transition_table;
+pub mod worst_case;
 
-use crate::dfa::{Dfa, SimError};
-use crate::parse::{lex, parse};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{ControlFlow, Range};
+use std::str::FromStr;
+use std::str::Utf8Error;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::captures::{CaptureMode, Captures, Match};
+use crate::decision_tree::DecisionTree;
+use crate::dfa::{CharSet, Dfa, MergeDiff, SimError};
+use crate::dot::DotOptions;
+use crate::error::RegexError;
+use crate::iter::{CaptureMatches, Matches, Positions, Split};
+use crate::lazy_dfa::{LazyCache, LazyDfa};
+use crate::meta::{Cache, Decoded, Engine, InvalidUtf8, QuickReject, SearchOptions};
+use crate::nfa::Nfa;
+use crate::parse::{
+    bound_wildcards, check, check_syntax, clamp_repeats, group_info, has_anchor, has_word_boundary,
+    lex_all, map_chars, parse, parse_with, try_lex, try_lex_with, GroupInfo, ParseElement,
+    Shorthands, Span, SyntaxConfig, Token,
+};
+use crate::predicate::Predicate;
+use crate::prog::Program;
+use crate::transition_table::{Alphabet, TableBackend};
+
+// a per-character mapping applied to the pattern and the haystack, e.g. char::to_ascii_lowercase
+pub type CharMap = fn(char) -> char;
+
+fn minimized(nfa: Nfa) -> Dfa {
+    let mut dfa = Dfa::from_nfa(nfa);
+    dfa.minimize();
+
+    dfa
+}
+
+fn compile_tokens(toks: Vec<Token>) -> Result<Dfa, RegexError> {
+    Ok(minimized(parse(toks)?))
+}
+
+// How long each phase of compiling a pattern took and how large its result was, to see where a
+// slow pattern spends its time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompileStats {
+    pub lex: Duration,
+    pub parse: Duration,
+    pub subset_construction: Duration,
+    pub minimization: Duration,
+    // every token at every depth, which are also the nodes of the syntax tree
+    pub tokens: usize,
+    pub nfa_states: usize,
+    pub raw_dfa_states: usize,
+    pub dfa_states: usize,
+}
+
+impl CompileStats {
+    pub fn total(&self) -> Duration {
+        self.lex + self.parse + self.subset_construction + self.minimization
+    }
+}
+
+impl fmt::Display for CompileStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "lex: {:?} ({} tokens)", self.lex, self.tokens)?;
+        writeln!(
+            f,
+            "parse: {:?} ({} NFA states)",
+            self.parse, self.nfa_states
+        )?;
+        writeln!(
+            f,
+            "subset construction: {:?} ({} DFA states)",
+            self.subset_construction, self.raw_dfa_states
+        )?;
+        writeln!(
+            f,
+            "minimization: {:?} ({} DFA states)",
+            self.minimization, self.dfa_states
+        )?;
+        writeln!(f, "total: {:?}", self.total())
+    }
+}
+
+fn count_tokens(toks: &[Token]) -> usize {
+    toks.iter()
+        .map(|tok| match &tok.elem {
+            ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                1 + count_tokens(grp)
+            }
+            _ => 1,
+        })
+        .sum()
+}
+
+// Every stage of compiling a pattern, for front ends that show or analyze more than the final
+// machine
+#[derive(Debug, Clone)]
+pub struct Compilation {
+    // the lexed pattern, which is also its syntax tree: each group holds its own tokens
+    pub tokens: Vec<Token>,
+    pub nfa: Nfa,
+    // the subset construction before minimization
+    pub raw_dfa: Dfa,
+    pub dfa: Dfa,
+    pub stats: CompileStats,
+}
+
+impl Compilation {
+    // stats has the lex and parse times filled in
+    fn from_nfa(tokens: Vec<Token>, nfa: Nfa, mut stats: CompileStats) -> Self {
+        let timer = Instant::now();
+        let raw_dfa = Dfa::from_nfa(nfa.clone());
+        stats.subset_construction = timer.elapsed();
+
+        let timer = Instant::now();
+        let mut dfa = raw_dfa.clone();
+        dfa.minimize();
+        stats.minimization = timer.elapsed();
+
+        stats.tokens = count_tokens(&tokens);
+        stats.nfa_states = nfa.state_count();
+        stats.raw_dfa_states = raw_dfa.state_count();
+        stats.dfa_states = dfa.state_count();
+
+        Self {
+            tokens,
+            nfa,
+            raw_dfa,
+            dfa,
+            stats,
+        }
+    }
+
+    // what minimizing raw_dfa into dfa changed, see Dfa::merge_diff
+    pub fn merge_diff(&self) -> MergeDiff {
+        self.dfa
+            .merge_diff(&self.raw_dfa)
+            .expect("dfa is raw_dfa minimized")
+    }
+
+    // every stage in one DOT graph, see render_pipeline_dot_with
+    pub fn render_pipeline_dot(&self) -> String {
+        self.render_pipeline_dot_with(DotOptions::default())
+    }
+
+    // The NFA, the unminimized DFA and the minimized DFA as three clusters of one DOT graph.
+    // Node names are prefixed per cluster, and the NFA states in the DFA labels are numbered as
+    // in the NFA cluster.
+    pub fn render_pipeline_dot_with(&self, options: DotOptions) -> String {
+        dot::render_pipeline(self, options)
+    }
+}
+
+pub fn compile(input: &str) -> Result<Compilation, RegexError> {
+    let mut stats = CompileStats::default();
+    let timer = Instant::now();
+    let tokens = try_lex(input)?;
+    stats.lex = timer.elapsed();
+
+    let timer = Instant::now();
+    let nfa = parse(tokens.clone())?;
+    stats.parse = timer.elapsed();
+
+    Ok(Compilation::from_nfa(tokens, nfa, stats))
+}
+
+// Panics if the pattern is malformed, see try_compile_regex
 pub fn compile_regex(input: &str) -> Dfa {
-    let nfa = parse(lex(input.to_string()));
+    try_compile_regex(input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+// The minimized DFA for input, or what is wrong with it and where, for patterns that come from
+// users. Lexing and parsing are the only steps that can fail; building the automata can't.
+pub fn try_compile_regex(input: &str) -> Result<Dfa, RegexError> {
+    try_lex(input).and_then(compile_tokens)
+}
+
+// every syntax error in the pattern at once rather than just the first, e.g. for an editor to
+// underline them all
+pub fn check_pattern(pattern: &str) -> Vec<RegexError> {
+    let (toks, mut errors) = lex_all(pattern);
+    errors.extend(check(&toks));
+    errors.sort_by_key(|e| e.span);
+
+    errors
+}
+
+// Whether dfa accepts the whole of input, and why not. For a match anywhere in a haystack use
+// Regex::is_match, and Regex::is_full_match for the same question on a Regex.
+pub fn test_string(input: &str, dfa: &Dfa) -> Result<(), SimError> {
+    dfa.simulate(input.to_string())
+}
+
+// A compiled pattern together with what is known about its source
+#[derive(Debug)]
+pub struct Regex {
+    // With an unanchored start as well, for finding matches anywhere. Built with the Regex
+    // unless it would have more than the builder's dfa_state_limit states, in which case lazy
+    // stands in for it and it is only built if dfa() asks for it.
+    dfa: OnceLock<Dfa>,
+    lazy: Option<LazyDfa>,
+    // what dfa and reverse are built from
+    nfa: Nfa,
+    // the reversed language, for matching backwards from a given end, built on first use
+    reverse: OnceLock<Dfa>,
+    // whether the automata recognize exactly the pattern, rather than a superset of it because
+    // of repetitions over the builder's max_expanded_repeat or of \b and \B
+    exact: bool,
+    // never Auto
+    engine: Engine,
+    // whether is_match means is_full_match
+    full_match: bool,
+    prog: Program,
+    groups: Vec<GroupInfo>,
+    names: Arc<HashMap<String, usize>>,
+    // The pattern and options it was built with, and the same pattern built case-insensitive
+    // once a search asks for it
+    options: RegexBuilder,
+    // the pattern lexed, kept so it can be built again with other options
+    tokens: Vec<Token>,
+    folded: OnceLock<Box<Regex>>,
+    quick: QuickReject,
+    // the byte length of every match, if they all have the same
+    fixed_len: Option<usize>,
+    // whether the pattern uses ^ or $, which the automata only check at the ends of what they
+    // are given, so searches that hand them part of the haystack can't rely on them alone
+    has_anchor: bool,
+}
+
+// Options for compiling a Regex
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    pattern: String,
+    char_map: Option<CharMap>,
+    max_expanded_repeat: u64,
+    dfa_state_limit: usize,
+    syntax: SyntaxConfig,
+    engine: Engine,
+    full_match: bool,
+    table_backend: TableBackend,
+    invalid_utf8: InvalidUtf8,
+    alphabet: Alphabet,
+    case_insensitive: bool,
+    predicates: Vec<(String, Predicate)>,
+    shorthands: Shorthands,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            char_map: None,
+            max_expanded_repeat: 100,
+            dfa_state_limit: 2000,
+            syntax: SyntaxConfig::default(),
+            engine: Engine::Auto,
+            full_match: false,
+            table_backend: TableBackend::Hash,
+            invalid_utf8: InvalidUtf8::Reject,
+            alphabet: Alphabet::Unicode,
+            case_insensitive: false,
+            predicates: Vec::new(),
+            shorthands: Shorthands::default(),
+        }
+    }
+
+    // Map every character of the pattern and of each haystack before matching, without copying
+    // the haystack. Reported offsets still refer to the original haystack. For instance
+    // char::to_ascii_lowercase makes matching ASCII case-insensitive.
+    pub fn char_map(&mut self, char_map: CharMap) -> &mut Self {
+        self.char_map = Some(char_map);
+        self
+    }
+
+    // Repetitions like \d{1,1000} are laid out copy by copy in the automata, which gets slow for
+    // large bounds. Beyond this bound (100 by default) the repetition is matched by a counter in
+    // the backtracker instead, and the automata only serve to rule out haystacks quickly.
+    pub fn max_expanded_repeat(&mut self, limit: u64) -> &mut Self {
+        self.max_expanded_repeat = limit;
+        self
+    }
+
+    // The most states the DFA built with the Regex may have (2000 by default). A pattern whose
+    // DFA would have more, like (a|b)*a(a|b){20} with its millions, is matched with a LazyDfa
+    // instead, which only builds the states each search reaches. Engine::Dfa always builds the
+    // DFA in full, and Engine::LazyDfa never does.
+    pub fn dfa_state_limit(&mut self, states: usize) -> &mut Self {
+        self.dfa_state_limit = states;
+        self
+    }
+
+    // Reject patterns that use a construct the config turns off, e.g. SyntaxConfig::linear() for
+    // a service that must only run patterns the DFA can match on its own
+    pub fn syntax(&mut self, syntax: SyntaxConfig) -> &mut Self {
+        self.syntax = syntax;
+        self
+    }
+
+    // which backend decides is_match, picked from the pattern by default (see Engine)
+    pub fn engine(&mut self, engine: Engine) -> &mut Self {
+        self.engine = engine;
+        self
+    }
+
+    // Make is_match require the whole haystack to match, as test_string does, instead of a
+    // match anywhere in it
+    pub fn full_match(&mut self, full_match: bool) -> &mut Self {
+        self.full_match = full_match;
+        self
+    }
+
+    // how the automata store their edges, see TransitionTable
+    pub fn table_backend(&mut self, backend: TableBackend) -> &mut Self {
+        self.table_backend = backend;
+        self
+    }
+
+    // what is_match_bytes and find_bytes do with invalid UTF-8, rejecting it by default
+    pub fn invalid_utf8(&mut self, policy: InvalidUtf8) -> &mut Self {
+        self.invalid_utf8 = policy;
+        self
+    }
+
+    // Bound what . matches to the characters the input is known to contain, all of Unicode by
+    // default. Characters outside the alphabet still match literals that name them.
+    pub fn alphabet(&mut self, alphabet: Alphabet) -> &mut Self {
+        self.alphabet = alphabet;
+        self
+    }
+
+    // Fold every literal and class while building the automata, so that abc also matches ABC
+    // as if the whole pattern were in a (?i:...) scope. The Regex's dfa() then answers
+    // test_string case-insensitively too.
+    pub fn case_insensitive(&mut self, case_insensitive: bool) -> &mut Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    // Register predicate under name when building, so the pattern can match a character it holds
+    // for with \p{name} (see predicate::register). Names are shared by the whole process.
+    pub fn predicate(&mut self, name: &str, predicate: Predicate) -> &mut Self {
+        self.predicates.push((name.to_string(), predicate));
+        self
+    }
+
+    // The characters \w and [[:word:]] match, inside classes too, instead of [A-Za-z0-9_], e.g.
+    // with - added so \w+ matches identifiers like max-width
+    pub fn word_chars(&mut self, chars: &[char]) -> &mut Self {
+        self.shorthands.word = chars.to_vec();
+        self
+    }
+
+    // the characters \s matches instead of space and tab
+    pub fn space_chars(&mut self, chars: &[char]) -> &mut Self {
+        self.shorthands.space = chars.to_vec();
+        self
+    }
+
+    pub fn get_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn get_char_map(&self) -> Option<CharMap> {
+        self.char_map
+    }
+
+    pub fn get_max_expanded_repeat(&self) -> u64 {
+        self.max_expanded_repeat
+    }
+
+    pub fn get_dfa_state_limit(&self) -> usize {
+        self.dfa_state_limit
+    }
+
+    pub fn get_syntax(&self) -> SyntaxConfig {
+        self.syntax
+    }
+
+    // as requested, so possibly Auto, unlike Regex::engine
+    pub fn get_engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn get_full_match(&self) -> bool {
+        self.full_match
+    }
+
+    pub fn get_table_backend(&self) -> TableBackend {
+        self.table_backend
+    }
+
+    pub fn get_invalid_utf8(&self) -> InvalidUtf8 {
+        self.invalid_utf8
+    }
+
+    pub fn get_alphabet(&self) -> Alphabet {
+        self.alphabet
+    }
+
+    pub fn get_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn get_word_chars(&self) -> &[char] {
+        &self.shorthands.word
+    }
+
+    pub fn get_space_chars(&self) -> &[char] {
+        &self.shorthands.space
+    }
+
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        // the lexer looks predicate names up
+        self.register_predicates()?;
+        self.build_from(try_lex_with(&self.pattern, &self.shorthands)?)
+    }
+
+    fn register_predicates(&self) -> Result<(), RegexError> {
+        // a registration error isn't about any part of the pattern
+        for (name, predicate) in &self.predicates {
+            predicate::register(name, *predicate)
+                .map_err(|kind| RegexError::new(kind, Span::new(0, 0)))?;
+        }
+        Ok(())
+    }
+
+    // the DFA minimized, with the char map and table backend asked for
+    fn finish(&self, mut dfa: Dfa) -> Dfa {
+        dfa.minimize();
+        if let Some(char_map) = self.char_map {
+            dfa.set_char_map(char_map);
+        }
+        if self.table_backend != TableBackend::Hash {
+            dfa.set_backend(self.table_backend);
+        }
+
+        dfa
+    }
+
+    // build with the pattern already lexed into tokens, which only depend on the shorthands
+    fn build_from(&self, tokens: Vec<Token>) -> Result<Regex, RegexError> {
+        let mut toks = tokens.clone();
+        let mut syntax = self.syntax;
+        if matches!(self.engine, Engine::Dfa | Engine::LazyDfa) {
+            syntax.large_repeats = false;
+            syntax.word_boundaries = false;
+        }
+        // the program is compiled before parse would find these
+        let mut errors = check(&toks);
+        errors.extend(check_syntax(&toks, &syntax, self.max_expanded_repeat));
+        if let Some(e) = errors.into_iter().next() {
+            return Err(e);
+        }
+        if let Some(char_map) = self.char_map {
+            map_chars(&mut toks, char_map);
+        }
+        bound_wildcards(&mut toks, self.alphabet, self.case_insensitive);
+
+        let groups = group_info(&toks);
+        let names = groups
+            .iter()
+            .filter_map(|g| Some((g.name.clone()?, g.index)))
+            .collect();
+        let mut prog =
+            prog::compile_with_limit(&toks, self.max_expanded_repeat, self.case_insensitive);
+
+        let has_anchor = has_anchor(&toks);
+        let clamped = clamp_repeats(&mut toks, self.max_expanded_repeat);
+        let exact = !clamped && !has_word_boundary(&toks);
+        let nfa = parse_with(toks, self.case_insensitive)?;
+        if let Some(char_map) = self.char_map {
+            prog.char_map = char_map;
+        }
+
+        let built = match self.engine {
+            Engine::Dfa => Some(Dfa::from_nfa_with_unanchored(nfa.clone())),
+            Engine::LazyDfa => None,
+            _ => Dfa::try_from_nfa_with_unanchored(nfa.clone(), self.dfa_state_limit),
+        };
+        let dfa = OnceLock::new();
+        let mut lazy = None;
+        let mut quick = QuickReject::unknown(self.char_map);
+        let mut fixed_len = None;
+        match built {
+            Some(built) => {
+                let dfa = dfa.get_or_init(|| self.finish(built));
+                quick = QuickReject::new(dfa, self.char_map);
+                fixed_len = match dfa.byte_len_bounds() {
+                    Some((min, Some(max)))
+                        if min == max && self.char_map.is_none() && !has_anchor =>
+                    {
+                        Some(min)
+                    }
+                    _ => None,
+                };
+
+                if !dfa.accepts_empty() {
+                    prog.first_chars = Some(dfa.first_chars());
+                }
+                // the prefix is in terms of mapped characters, which the haystack isn't
+                let prefix = dfa.longest_common_prefix();
+                if self.char_map.is_none() && !prefix.is_empty() {
+                    prog.prefix = Some(prefix);
+                }
+            }
+            None => {
+                let mut built = LazyDfa::from_nfa(&nfa);
+                if let Some(char_map) = self.char_map {
+                    built.set_char_map(char_map);
+                }
+                lazy = Some(built);
+            }
+        }
+
+        Ok(Regex {
+            engine: self.engine.resolve(exact, dfa.get().is_some()),
+            dfa,
+            lazy,
+            nfa,
+            reverse: OnceLock::new(),
+            exact,
+            full_match: self.full_match,
+            prog,
+            groups,
+            names: Arc::new(names),
+            options: self.clone(),
+            tokens,
+            folded: OnceLock::new(),
+            quick,
+            fixed_len,
+            has_anchor,
+        })
+    }
+}
+
+// the pattern, as written
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.pattern())
+    }
+}
+
+// so a pattern from a config value can be parsed with str::parse, like Regex::new
+impl FromStr for Regex {
+    type Err = RegexError;
+
+    fn from_str(pattern: &str) -> Result<Self, RegexError> {
+        Self::new(pattern)
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.options.pattern
+    }
+
+    // The options it was built with, so options().build() compiles the same regex again
+    pub fn options(&self) -> &RegexBuilder {
+        &self.options
+    }
+
+    // The same pattern built again with its options changed by configure, e.g. made
+    // case-insensitive or to match whole haystacks only, without lexing the pattern again unless
+    // word_chars or space_chars changed. Fails if the pattern doesn't suit the new options, like
+    // a large repetition under Engine::Dfa.
+    pub fn try_clone_with_options(
+        &self,
+        configure: impl FnOnce(&mut RegexBuilder),
+    ) -> Result<Regex, RegexError> {
+        let mut options = self.options.clone();
+        configure(&mut options);
+        // \w and \s were expanded when the pattern was lexed
+        if options.shorthands != self.options.shorthands {
+            return options.build();
+        }
+        options.register_predicates()?;
+        options.build_from(self.tokens.clone())
+    }
+
+    // The minimized automaton. If is_exact is false it accepts a superset of the pattern's
+    // language, for repetitions over RegexBuilder::max_expanded_repeat or for \b and \B. For a
+    // pattern over the builder's dfa_state_limit it is built on the first call, which can take
+    // long.
+    pub fn dfa(&self) -> &Dfa {
+        self.dfa.get_or_init(|| {
+            let dfa = Dfa::from_nfa_with_unanchored(self.nfa.clone());
+            self.options.finish(dfa)
+        })
+    }
+
+    // the pattern's NFA, which the automata are built from
+    pub(crate) fn nfa(&self) -> &Nfa {
+        &self.nfa
+    }
+
+    // whether searches run a LazyDfa, because the DFA would have been over the builder's
+    // dfa_state_limit
+    pub fn is_lazy(&self) -> bool {
+        self.lazy.is_some()
+    }
+
+    // whether the automaton alone decides if there is a match, without the backtracker
+    fn automaton_decides(&self) -> bool {
+        matches!(self.engine, Engine::Dfa | Engine::LazyDfa)
+    }
+
+    fn reverse(&self) -> &Dfa {
+        self.reverse
+            .get_or_init(|| self.options.finish(Dfa::from_nfa(self.nfa.reverse())))
+    }
+
+    // whether the automaton sees a match anywhere in haystack
+    fn automaton_matches(&self, haystack: &str, cache: &mut Cache) -> bool {
+        match &self.lazy {
+            Some(lazy) => lazy.contains_match(haystack, &mut cache.lazy),
+            None => self.dfa().contains_match(haystack),
+        }
+    }
+
+    // whether the automaton accepts haystack as a whole
+    fn automaton_accepts(&self, haystack: &str, cache: &mut Cache) -> bool {
+        let len = match &self.lazy {
+            Some(lazy) => lazy.longest_prefix(haystack, true, &mut cache.lazy),
+            None => self.dfa().longest_prefix(haystack),
+        };
+        len == Some(haystack.len())
+    }
+
+    // the program the backtracker runs, which finds leftmost-first matches and captures
+    pub fn prog(&self) -> &Program {
+        &self.prog
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    // The characters a non-empty match can start with, after the builder's char map. This and
+    // the other hints below don't build the DFA of a pattern over the builder's dfa_state_limit,
+    // and give the loosest answer for it instead (unless dfa() was called).
+    pub fn first_chars(&self) -> CharSet {
+        self.dfa
+            .get()
+            .map_or_else(CharSet::everything, Dfa::first_chars)
+    }
+
+    // The fewest and most characters a match can have, with no most if matches can be
+    // arbitrarily long, e.g. to check a field's length before matching it. For repetitions over
+    // max_expanded_repeat the bounds are looser than the pattern's.
+    pub fn len_hint(&self) -> (usize, Option<usize>) {
+        match self.dfa.get() {
+            Some(dfa) => dfa.char_len_bounds().unwrap_or((0, Some(0))),
+            None => (0, None),
+        }
+    }
+
+    // the number of characters in every match, if the pattern only matches strings of one length
+    pub fn fixed_len(&self) -> Option<usize> {
+        match self.len_hint() {
+            (min, Some(max)) if min == max && self.dfa.get()?.char_len_bounds().is_some() => {
+                Some(min)
+            }
+            _ => None,
+        }
+    }
+
+    // the characters a non-empty match can end with, after the builder's char map
+    pub fn last_chars(&self) -> CharSet {
+        self.dfa
+            .get()
+            .map_or_else(CharSet::everything, Dfa::last_chars)
+    }
+
+    // the number of groups, counting the implicit group 0 spanning the whole match
+    pub fn captures_len(&self) -> usize {
+        self.groups.len() + 1
+    }
+
+    // the name of every group, starting with the always unnamed group 0
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        std::iter::once(None).chain(self.groups.iter().map(|g| g.name.as_deref()))
+    }
+
+    // the explicit groups (1 and up) with their spans in the pattern
+    pub fn group_info(&self) -> &[GroupInfo] {
+        &self.groups
+    }
+
+    // Whether there is a match anywhere in haystack, returning as soon as one is seen. With the
+    // builder's full_match set this is is_full_match instead.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.is_match_cached(haystack, &mut Cache::new())
+    }
+
+    // is_match with the search buffers taken from cache, see Cache
+    pub fn is_match_cached(&self, haystack: &str, cache: &mut Cache) -> bool {
+        if self.full_match {
+            return self.is_full_match_cached(haystack, cache);
+        }
+
+        !self.quick.rejects(haystack)
+            && self.automaton_matches(haystack, cache)
+            && (self.automaton_decides()
+                || backtrack::search_with(
+                    &mut cache.scratch,
+                    &self.prog,
+                    haystack,
+                    0,
+                    false,
+                    None,
+                    false,
+                )
+                .is_some())
+    }
+
+    // whether the whole of haystack matches, so "abc" matches "abc" but not "xabcx"
+    pub fn is_full_match(&self, haystack: &str) -> bool {
+        self.is_full_match_cached(haystack, &mut Cache::new())
+    }
+
+    pub fn is_full_match_cached(&self, haystack: &str, cache: &mut Cache) -> bool {
+        !self.quick.rejects_full(haystack)
+            && self.automaton_accepts(haystack, cache)
+            && (self.automaton_decides()
+                || backtrack::search_with(
+                    &mut cache.scratch,
+                    &self.prog,
+                    haystack,
+                    0,
+                    true,
+                    Some(haystack.len()),
+                    false,
+                )
+                .is_some())
+    }
+
+    // is_match for each haystack, with one cache shared between them. Meant for
+    // scoring many short strings, where allocating those for every search dominates.
+    pub fn matches_many<'a>(&self, haystacks: impl IntoIterator<Item = &'a str>) -> Vec<bool> {
+        let mut cache = Cache::new();
+        haystacks
+            .into_iter()
+            .map(|h| self.is_match_cached(h, &mut cache))
+            .collect()
+    }
+
+    // matches_many with the haystacks split between threads, each with its own buffers
+    pub fn matches_many_parallel(&self, haystacks: &[&str]) -> Vec<bool> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = haystacks.len().div_ceil(threads).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = haystacks
+                .chunks(chunk)
+                .map(|chunk| scope.spawn(move || self.matches_many(chunk.iter().copied())))
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("haystack thread panicked"))
+                .collect()
+        })
+    }
+
+    // The match that ends furthest right, extended as far left as it can go (like .NET's
+    // RightToLeft). Each candidate end is tried from the back of haystack with the reversed
+    // automaton, so nothing before the match is read. Note that it can overlap an earlier match,
+    // e.g. aa in "aaa" is found at 1..3.
+    pub fn find_last<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        let ends = haystack
+            .char_indices()
+            .map(|(i, c)| i + c.len_utf8())
+            .rev()
+            .chain([0]);
+
+        for end in ends {
+            let Some(len) = self.reverse().longest_suffix(&haystack[..end]) else {
+                continue;
+            };
+            if self.automaton_decides() && !self.has_anchor {
+                return Some(Match::new(haystack, end - len, end));
+            }
+
+            // The automaton over-approximates, so the real match starts at end - len or later. It
+            // also takes the end of the slice for the end of the input, where $ would match.
+            let start = (end - len..=end)
+                .filter(|i| haystack.is_char_boundary(*i))
+                .find(|i| {
+                    backtrack::search(&self.prog, haystack, *i, true, Some(end), false).is_some()
+                });
+            if let Some(start) = start {
+                return Some(Match::new(haystack, start, end));
+            }
+        }
+
+        None
+    }
+
+    // the leftmost-first match anywhere in haystack
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        self.find_at(haystack, 0)
+    }
+
+    // The leftmost-first match starting at or after byte offset start. An offset inside a
+    // multi-byte character is moved up to the next character.
+    pub fn find_at<'h>(&self, haystack: &'h str, start: usize) -> Option<Match<'h>> {
+        self.find_at_cached(haystack, start, &mut Cache::new())
+    }
+
+    pub fn find_at_cached<'h>(
+        &self,
+        haystack: &'h str,
+        start: usize,
+        cache: &mut Cache,
+    ) -> Option<Match<'h>> {
+        let (start, end) = self.bounds_at(haystack, start, cache)?;
+        Some(Match::new(haystack, start, end))
+    }
+
+    // find_at_cached as offsets, which doesn't allocate once cache's buffers have grown to fit
+    pub(crate) fn bounds_at(
+        &self,
+        haystack: &str,
+        start: usize,
+        cache: &mut Cache,
+    ) -> Option<(usize, usize)> {
+        let start = (start..=haystack.len()).find(|i| haystack.is_char_boundary(*i))?;
+
+        // the DFA rules out haystacks without any match before the backtracker runs
+        if self.quick.rejects(&haystack[start..])
+            || !self.automaton_matches(&haystack[start..], cache)
+        {
+            return None;
+        }
+
+        // every match is one length in bytes, so the DFA alone can tell where the first one is
+        if let Some(len) = self.fixed_len
+            && self.automaton_decides()
+        {
+            return (start..=haystack.len())
+                .filter(|i| haystack.is_char_boundary(*i))
+                .take_while(|i| i + len <= haystack.len())
+                .find(|i| {
+                    haystack
+                        .get(*i..*i + len)
+                        .is_some_and(|window| self.dfa().longest_prefix(window) == Some(len))
+                })
+                .map(|i| (i, i + len));
+        }
+
+        backtrack::match_bounds(&mut cache.scratch, &self.prog, haystack, start)
+    }
+
+    // is_match on bytes, e.g. lines of a log that may not be valid UTF-8 (see
+    // RegexBuilder::invalid_utf8)
+    pub fn is_match_bytes(&self, haystack: &[u8]) -> Result<bool, Utf8Error> {
+        let decoded = Decoded::new(haystack, self.options.invalid_utf8)?;
+        Ok(self.is_match(&decoded.text))
+    }
+
+    // find on bytes, with the match's offsets in haystack
+    pub fn find_bytes(&self, haystack: &[u8]) -> Result<Option<Range<usize>>, Utf8Error> {
+        let decoded = Decoded::new(haystack, self.options.invalid_utf8)?;
+        let found = self.find(&decoded.text).map(|m| m.range());
+
+        Ok(found.map(|r| decoded.offset(r.start)..decoded.offset(r.end)))
+    }
+
+    // find with per-call options, see SearchOptions
+    pub fn find_with<'h>(&self, haystack: &'h str, options: SearchOptions) -> Option<Match<'h>> {
+        if options.case_insensitive && !self.options.case_insensitive {
+            let options = SearchOptions {
+                case_insensitive: false,
+                ..options
+            };
+            return self.case_folded().find_with(haystack, options);
+        }
+        if !options.anchored {
+            return self.find(haystack);
+        }
+
+        let matches_prefix = match &self.lazy {
+            Some(lazy) => lazy
+                .longest_prefix(haystack, true, &mut LazyCache::new())
+                .is_some(),
+            None => self.dfa().matches_prefix(haystack),
+        };
+        if !matches_prefix {
+            return None;
+        }
+        let found = backtrack::search(&self.prog, haystack, 0, true, None, false)?;
+
+        Some(Match::new(haystack, found.slots[0]?, found.slots[1]?))
+    }
+
+    pub fn is_match_with(&self, haystack: &str, options: SearchOptions) -> bool {
+        self.find_with(haystack, options).is_some()
+    }
+
+    // the same pattern and options but case-insensitive, built on first use
+    fn case_folded(&self) -> &Regex {
+        self.folded.get_or_init(|| {
+            let folded = self.try_clone_with_options(|options| {
+                options.case_insensitive(true);
+            });
+            Box::new(folded.expect("a valid pattern stays valid folded"))
+        })
+    }
+
+    pub fn find_iter<'r, 'h>(&'r self, haystack: &'h str) -> Matches<'r, 'h> {
+        Matches::new(self, haystack)
+    }
+
+    // The (start, end) offsets of the matches find_iter gives, e.g. to feed an index. The
+    // iterator keeps one Cache for all its searches and builds no Match, so once the cache has
+    // grown to fit the haystack, producing an item doesn't allocate.
+    pub fn find_positions_iter<'r, 'h>(&'r self, haystack: &'h str) -> Positions<'r, 'h> {
+        Positions::new(self, haystack)
+    }
+
+    // Call f with each match find_iter would give, until it returns Break, which is returned. The
+    // haystack is only searched as far as the last match f saw, so f can stop after a few matches
+    // or once a deadline has passed without the rest of a large input being scanned.
+    pub fn find_iter_with<'h, B>(
+        &self,
+        haystack: &'h str,
+        f: impl FnMut(Match<'h>) -> ControlFlow<B>,
+    ) -> ControlFlow<B> {
+        self.find_iter(haystack).try_for_each(f)
+    }
+
+    // the text between matches
+    pub fn split<'r, 'h>(&'r self, haystack: &'h str) -> Split<'r, 'h> {
+        Split::new(self, haystack, false, false)
+    }
+
+    // like split, but with at most limit fields, the last of which holds the rest of the haystack
+    // (see Split::limit), so splitn("a b c", 2) on spaces gives a and "b c"
+    pub fn splitn<'r, 'h>(&'r self, haystack: &'h str, limit: usize) -> Split<'r, 'h> {
+        self.split(haystack).limit(limit)
+    }
+
+    // like split, but without an empty field after a final delimiter, so "a,b," gives a and b
+    pub fn split_terminator<'r, 'h>(&'r self, haystack: &'h str) -> Split<'r, 'h> {
+        Split::new(self, haystack, true, false)
+    }
+
+    // like split, but each delimiter is yielded between the fields around it, so fields are at
+    // even positions and delimiters at odd ones
+    pub fn split_with_delimiters<'r, 'h>(&'r self, haystack: &'h str) -> Split<'r, 'h> {
+        Split::new(self, haystack, false, true)
+    }
+
+    // the leftmost-first match anywhere in haystack and what each group matched
+    pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+        self.captures_with(haystack, CaptureMode::LastIteration)
+    }
+
+    pub fn captures_with<'h>(&self, haystack: &'h str, mode: CaptureMode) -> Option<Captures<'h>> {
+        let record = mode == CaptureMode::AllIterations;
+        let found = backtrack::search(&self.prog, haystack, 0, false, None, record)?;
+
+        Some(Captures::new(haystack, found, self.names.clone(), mode))
+    }
+
+    // Every choice the backtracker made looking for the first match in haystack, and where it
+    // went back to, to see why a pattern is slow on an input (see DecisionTree). This runs the
+    // backtracker even where captures would be answered without it.
+    pub fn backtrack_tree(&self, haystack: &str) -> DecisionTree {
+        backtrack::search_traced(&self.prog, haystack, 0, false, None).1
+    }
+
+    pub fn captures_iter<'r, 'h>(&'r self, haystack: &'h str) -> CaptureMatches<'r, 'h> {
+        CaptureMatches::new(self, haystack)
+    }
+
+    // the whole match and the text of each of the N groups for every match, e.g.
+    // `for (_, [key, value]) in re.extract_iter::<2>(log) { ... }`
+    pub fn extract_iter<'r, 'h, const N: usize>(
+        &'r self,
+        haystack: &'h str,
+    ) -> impl Iterator<Item = (&'h str, [&'h str; N])> + 'r
+    where
+        'h: 'r,
+    {
+        self.captures_iter(haystack).map(|caps| caps.extract())
+    }
+
+    // The haystack with its first match replaced by replacement, in which $1, ${name} and so on
+    // are expanded as by Captures::expand. Borrowed if there was nothing to replace.
+    pub fn replace<'h>(&self, haystack: &'h str, replacement: &str) -> Cow<'h, str> {
+        self.replacen(haystack, 1, replacement)
+    }
+
+    // replace for every non-overlapping match, as find_iter finds them
+    pub fn replace_all<'h>(&self, haystack: &'h str, replacement: &str) -> Cow<'h, str> {
+        self.replacen(haystack, 0, replacement)
+    }
+
+    // replace for the first limit matches, or for all of them if limit is 0
+    pub fn replacen<'h>(&self, haystack: &'h str, limit: usize, replacement: &str) -> Cow<'h, str> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        // without a $ the replacement is the same every time, and captures aren't needed
+        let literal = !replacement.contains('$');
+
+        let mut out = String::new();
+        let mut last = 0;
+        let mut replaced = false;
+        if literal {
+            for m in self.find_iter(haystack).take(limit) {
+                out.push_str(&haystack[last..m.start()]);
+                out.push_str(replacement);
+                last = m.end();
+                replaced = true;
+            }
+        } else {
+            for caps in self.captures_iter(haystack).take(limit) {
+                let m = caps.get(0).unwrap();
+                out.push_str(&haystack[last..m.start()]);
+                caps.expand(replacement, &mut out);
+                last = m.end();
+                replaced = true;
+            }
+        }
+
+        if !replaced {
+            return Cow::Borrowed(haystack);
+        }
+        out.push_str(&haystack[last..]);
+        Cow::Owned(out)
+    }
+
+    // Replace every match with replacement, expanded as by Captures::expand and then cased like
+    // the text it replaces (see replace::Case), so with foo as the pattern and bar as the
+    // replacement, "foo Foo FOO" becomes "bar Bar BAR"
+    pub fn replace_all_preserving_case(&self, haystack: &str, replacement: &str) -> String {
+        let mut out = String::with_capacity(haystack.len());
+        let mut last = 0;
+        let mut expanded = String::new();
+        for caps in self.captures_iter(haystack) {
+            let m = caps.get(0).unwrap();
+            out.push_str(&haystack[last..m.start()]);
+            expanded.clear();
+            caps.expand(replacement, &mut expanded);
+            out.push_str(&replace::preserve_case(m.as_str(), &expanded));
+            last = m.end();
+        }
+        out.push_str(&haystack[last..]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dfa::Minimizer;
+    use crate::dot::{DotOptions, LabelEscape};
+    use crate::transition_table::{intern_class, Transition};
+
+    #[test]
+    fn test_brackets_char_classes() {
+        let r = compile_regex("\\w");
+
+        for c in 'a'..='z' {
+            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+        }
+
+        for c in 'A'..='Z' {
+            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+        }
+
+        for c in '0'..='9' {
+            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+        }
+
+        assert_eq!(test_string(String::from('_').as_str(), &r), Ok(()));
+
+        assert!(matches!(
+            test_string(" ", &r),
+            Err(SimError::NoMatch { found: ' ', .. })
+        ));
+    }
+
+    #[test]
+    fn test_wildcard_simple() {
+        let r = compile_regex("a.b");
+
+        assert_eq!(test_string("abb", &r), Ok(()));
+        assert_eq!(test_string("axb", &r), Ok(()));
+        assert!(matches!(
+            test_string("ab", &r),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert!(matches!(
+            test_string("axby", &r),
+            Err(SimError::Premature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_ranges() {
+        // normal range
+        let r1 = compile_regex("a{3, 5}");
+
+        assert!(matches!(
+            test_string("", &r1),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert!(matches!(
+            test_string("a", &r1),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert!(matches!(
+            test_string("aa", &r1),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("aaa", &r1), Ok(()));
+        assert_eq!(test_string("aaaa", &r1), Ok(()));
+        assert_eq!(test_string("aaaaa", &r1), Ok(()));
+        assert!(matches!(
+            test_string("aaaaaa", &r1),
+            Err(SimError::Premature { .. })
+        ));
+
+        // exact repetition
+        let r2 = compile_regex("a{3}");
+
+        assert!(matches!(
+            test_string("aa", &r2),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("aaa", &r2), Ok(()));
+        assert!(matches!(
+            test_string("aaaa", &r2),
+            Err(SimError::Premature { .. })
+        ));
+
+        // open range
+        let r3 = compile_regex("a{3,}");
+        assert!(matches!(
+            test_string("aa", &r3),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("aaa", &r3), Ok(()));
+        assert_eq!(test_string("aaaa", &r3), Ok(()));
+        assert_eq!(test_string("aaaaa", &r3), Ok(()));
+    }
+
+    #[test]
+    fn test_repetition() {
+        // *
+        let r1 = compile_regex("a*");
+
+        assert_eq!(test_string("", &r1), Ok(()));
+        assert_eq!(test_string("a", &r1), Ok(()));
+        assert_eq!(test_string("aa", &r1), Ok(()));
+        assert_eq!(test_string("aaa", &r1), Ok(()));
+        assert!(matches!(
+            test_string("aaab", &r1),
+            Err(SimError::Premature { .. })
+        ));
+
+        // +
+        let r2 = compile_regex("a+");
+
+        assert!(matches!(
+            test_string("", &r2),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("a", &r2), Ok(()));
+        assert_eq!(test_string("aa", &r2), Ok(()));
+
+        // ?
+        let r3 = compile_regex("a?");
+
+        assert_eq!(test_string("", &r3), Ok(()));
+        assert_eq!(test_string("a", &r3), Ok(()));
+        assert!(matches!(
+            test_string("aa", &r3),
+            Err(SimError::Premature { .. })
+        ));
+    }
+
+    #[test]
+    fn test_long_composition() {
+        // x+ keeps a single copy of x, with a loop back around it
+        let nfa = parse(try_lex("(ab)+").unwrap()).unwrap();
+        assert_eq!(nfa.state_count(), 5);
+        assert_eq!(nfa.find_first("abab").map(|m| m.range()), Some(0..4));
+        let nfa = parse(try_lex("(ab)+?").unwrap()).unwrap();
+        assert_eq!(nfa.find_first("abab").map(|m| m.range()), Some(0..2));
+
+        let pattern = "ab".repeat(2000);
+        let nfa = parse(try_lex(&pattern).unwrap()).unwrap();
+        assert_eq!(nfa.state_count(), 4001);
+        let dfa = compile_regex(&format!("{pattern}+"));
+        assert_eq!(test_string(&pattern, &dfa), Ok(()));
+        assert_eq!(test_string(&format!("{pattern}b"), &dfa), Ok(()));
+        assert!(test_string(&pattern[1..], &dfa).is_err());
+    }
+
+    #[test]
+    fn test_nfa_arena() {
+        use crate::nfa::Nfa;
+        use crate::transition_table::{Index, NfaState};
+
+        // states are numbered from 0 without gaps, START and ACCEPTING first
+        let nfa = parse(try_lex("(a|bc)*d").unwrap()).unwrap();
+        let states: Vec<_> = nfa.states().collect();
+        assert_eq!(states[..2], [NfaState::START, NfaState::ACCEPTING]);
+        assert!(nfa.edges(NfaState::ACCEPTING).is_empty());
+        assert!(states
+            .iter()
+            .flat_map(|s| nfa.edges(*s))
+            .all(|e| e.end.index() < nfa.state_count()));
+
+        // a union moves the other NFA's states in after its own
+        let mut a = Nfa::new(Transition::Literal('a'), None);
+        let mut b = parse(try_lex("bc").unwrap()).unwrap();
+        let marker = b.mark_accepting();
+        let moved = a.union(&mut b);
+        assert_eq!(moved[..2], [NfaState::START, NfaState::ACCEPTING]);
+        assert_eq!(a.state_count(), 4);
+        assert_eq!(a.edges(moved[marker.index()])[0].end, NfaState::ACCEPTING);
+        assert_eq!(b.state_count(), 2);
+    }
+
+    #[test]
+    fn test_union() {
+        let r1 = compile_regex("a*|b");
+
+        assert_eq!(test_string("", &r1), Ok(()));
+        assert_eq!(test_string("a", &r1), Ok(()));
+        assert_eq!(test_string("aa", &r1), Ok(()));
+        assert_eq!(test_string("b", &r1), Ok(()));
+        assert!(matches!(
+            test_string("bb", &r1),
+            Err(SimError::Premature { .. })
+        ));
+        assert!(matches!(
+            test_string("ab", &r1),
+            Err(SimError::Premature { .. })
+        ));
+
+        let r2 = compile_regex("ab|12");
+        assert_eq!(test_string("ab", &r2), Ok(()));
+        assert_eq!(test_string("12", &r2), Ok(()));
+        assert!(matches!(
+            test_string("a2", &r2),
+            Err(SimError::NoMatch { found: '2', .. })
+        ));
+    }
+
+    #[test]
+    fn test_group() {
+        let r1 = compile_regex("(abc)+");
+
+        assert!(matches!(
+            test_string("", &r1),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("abc", &r1), Ok(()));
+        assert_eq!(test_string("abcabc", &r1), Ok(()));
+        assert!(matches!(
+            test_string("abcabcab", &r1),
+            Err(SimError::EndOfString { .. })
+        ));
+
+        let r2 = compile_regex("((ab)+|(12)*)+");
+
+        assert_eq!(test_string("", &r2), Ok(()));
+        assert_eq!(test_string("ab", &r2), Ok(()));
+        assert_eq!(test_string("abab12ab12", &r2), Ok(()));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let ranges = |p: &str, h: &str| {
+            Regex::new(p)
+                .unwrap()
+                .find_iter(h)
+                .map(|m| (m.start(), m.end()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges("^ab", "abab"), [(0, 2)]);
+        assert_eq!(ranges("ab$", "abab"), [(2, 4)]);
+        assert_eq!(ranges("x*$", "ab"), [(2, 2)]);
+        assert_eq!(ranges("^|a", "aa"), [(0, 0), (1, 2)]);
+        assert_eq!(ranges("\\^\\$", "^$"), [(0, 2)]);
+        assert!(Regex::new("^$").unwrap().is_match(""));
+        assert!(!Regex::new("^$").unwrap().is_match("a"));
+        assert!(!Regex::new("a^b").unwrap().is_match("ab"));
+
+        // the searches that slice the haystack still only see the real ends
+        let last = |p: &str, h: &str| Regex::new(p).unwrap().find_last(h).map(|m| m.range());
+        assert_eq!(last("a$", "aab"), None);
+        assert_eq!(last("a$", "aba"), Some(2..3));
+        assert_eq!(last("^a", "aaa"), Some(0..1));
+        let backtrack = RegexBuilder::new("(a)$|^b\\1")
+            .engine(Engine::Backtrack)
+            .build()
+            .unwrap();
+        assert_eq!(backtrack.find_last("ba").map(|m| m.range()), Some(0..2));
+        assert_eq!(backtrack.find("xba").map(|m| m.range()), Some(2..3));
+
+        let dfa = compile_regex("(a|b$)c?$");
+        assert!(dfa.simulate("ac".to_string()).is_ok());
+        assert!(dfa.simulate("b".to_string()).is_ok());
+        assert_eq!(
+            dfa.simulate("bc".to_string()),
+            Err(SimError::Premature { offset: 1 })
+        );
+        assert_eq!(Regex::new("^ab$").unwrap().len_hint(), (2, Some(2)));
+        assert!(Regex::new("$").unwrap().dfa().accepts_empty());
+    }
+
+    #[test]
+    fn test_anchored_minimization() {
+        // minimizing keeps what each start accepts, whatever ^ and $ cut off
+        for pattern in ["^a|b", "(^|x)a$", "^$|a^b", "(a|^)+b"] {
+            let raw = Dfa::from_nfa_with_unanchored(parse(try_lex(pattern).unwrap()).unwrap());
+            let mut minimal = raw.clone();
+            minimal.minimize();
+            assert!(minimal.state_count() <= raw.state_count());
+            for input in ["", "a", "b", "xa", "xb", "ab", "xxa", "aab", "ba"] {
+                assert_eq!(
+                    minimal.matches_prefix(input),
+                    raw.matches_prefix(input),
+                    "{pattern} on {input}"
+                );
+                assert_eq!(
+                    minimal.contains_match(input),
+                    raw.contains_match(input),
+                    "{pattern} on {input}"
+                );
+            }
+        }
+
+        let dfa = Regex::new("^a|b").unwrap().dfa().clone();
+        let search = dfa.unanchored_start().unwrap();
+        assert_ne!(search, dfa.start());
+        assert!(dfa.contains_match("xb") && !dfa.contains_match("xa"));
+        let dot = dfa.to_dot("");
+        assert!(dot.contains("start [shape = point];\nstart -> d0;\n"));
+        assert!(dot.contains(&format!(
+            "search -> {search} [label = \"unanchored\"; style = dashed];"
+        )));
+        assert!(dfa
+            .to_string()
+            .contains(&format!("{search} (unanchored start)")));
+
+        // without ^ at the front both starts accept the same, and become one state
+        let dfa = Regex::new(".*a").unwrap().dfa().clone();
+        assert_eq!(dfa.unanchored_start(), Some(dfa.start()));
+    }
+
+    #[test]
+    fn test_negated_class() {
+        let dfa = compile_regex("a[^bc]+d");
+        for s in ["axd", "a\u{1F600}d", "add", "a^d"] {
+            assert_eq!(test_string(s, &dfa), Ok(()), "{s}");
+        }
+        for s in ["abd", "axcd", "ad"] {
+            assert!(test_string(s, &dfa).is_err(), "{s}");
+        }
+
+        for engine in [Engine::Dfa, Engine::LazyDfa, Engine::Backtrack] {
+            let build = |pattern: &str| RegexBuilder::new(pattern).engine(engine).build().unwrap();
+            let find = |pattern: &str, h: &str| build(pattern).find(h).map(|m| m.range());
+            assert_eq!(find("[^a-c]+", "abxyzc"), Some(2..5));
+            assert_eq!(find("(?i:[^a])", "Aab"), Some(2..3));
+            assert_eq!(find("[a^]", "x^"), Some(1..2));
+            assert!(!build("[^\\d\\s]").is_match("1 2"));
+        }
+        let ascii = RegexBuilder::new("[^a]")
+            .alphabet(Alphabet::Ascii)
+            .build()
+            .unwrap();
+        assert!(ascii.is_match("b") && !ascii.is_match("é"));
+
+        assert!(try_compile_regex("[^]").is_err());
+        let except = Transition::Except(intern_class(vec!['c', 'a', 'b', 'a']));
+        assert_eq!(except.dot_label(), "[^a-c]");
+        assert!(except.accepts('d') && !except.accepts('b'));
+    }
+
+    #[test]
+    fn test_predicates() {
+        use crate::error::ErrorKind;
+
+        fn cjk(c: char) -> bool {
+            ('\u{4e00}'..='\u{9fff}').contains(&c)
+        }
+        let build = |pattern: &str, engine| {
+            RegexBuilder::new(pattern)
+                .predicate("test_cjk", cjk)
+                .predicate("test_upper", char::is_uppercase)
+                .engine(engine)
+                .build()
+                .unwrap()
+        };
+        for engine in [Engine::Dfa, Engine::LazyDfa, Engine::Backtrack] {
+            let find = |pattern: &str, h: &str| build(pattern, engine).find(h).map(|m| m.range());
+            assert_eq!(find("\\p{test_cjk}+", "ab中文c"), Some(2..8));
+            assert_eq!(find("[^中]\\p{test_cjk}", "中文x文"), Some(6..10));
+            assert_eq!(
+                find("\\p{test_upper}\\p{test_cjk}|A.", "aB中Ax"),
+                Some(1..5)
+            );
+            assert_eq!(find("中|\\p{test_upper}", "x中"), Some(1..4));
+            assert!(!build("^\\p{test_cjk}$", engine).is_match("a"));
+
+            // case folding doesn't apply to a predicate, bounded to an alphabet or not
+            for alphabet in [Alphabet::Ascii, Alphabet::Latin1, Alphabet::Unicode] {
+                let upper = RegexBuilder::new("(?i:\\p{test_upper})")
+                    .predicate("test_upper", char::is_uppercase)
+                    .alphabet(alphabet)
+                    .engine(engine)
+                    .build()
+                    .unwrap();
+                assert!(upper.is_match("A") && !upper.is_match("a"), "{alphabet:?}");
+            }
+        }
+
+        // the DFA gives each combination of predicates its own edge, and minimization keeps it
+        let pattern = "\\p{test_cjk}a|\\p{test_upper}b|.c|文d";
+        let min = build(pattern, Engine::Dfa);
+        let raw = compile(pattern).unwrap().raw_dfa;
+        for s in ["中a", "Ab", "中b", "Ac", "文d", "文a", "xd", "Xa"] {
+            let expected = matches!(s, "中a" | "Ab" | "Ac" | "文d" | "文a");
+            assert_eq!(min.dfa().matches_prefix(s), expected, "{s}");
+            assert_eq!(raw.matches_prefix(s), expected, "{s}");
+        }
+        let labels: Vec<_> = raw
+            .transitions(raw.start())
+            .iter()
+            .map(|(t, _)| t.dot_label())
+            .collect();
+        assert!(
+            labels.contains(&"\\p{test_upper}".to_string()),
+            "{labels:?}"
+        );
+        assert!(labels.contains(&"'文'".to_string()), "{labels:?}");
+
+        assert_eq!(
+            try_compile_regex("\\p{nope}").unwrap_err().kind,
+            ErrorKind::UnknownPredicate("nope".to_string())
+        );
+        assert_eq!(
+            try_compile_regex("\\p{test_cjk").unwrap_err().kind,
+            ErrorKind::UnclosedPredicateName
+        );
+        let conflict = RegexBuilder::new("a")
+            .predicate("test_cjk", char::is_alphabetic)
+            .build();
+        assert_eq!(
+            conflict.unwrap_err().kind,
+            ErrorKind::PredicateConflict("test_cjk".to_string())
+        );
+    }
+
+    #[test]
+    fn test_lazy_quantifiers() {
+        use crate::error::ErrorKind;
+
+        let caps = Regex::new("(a+?)(a*)").unwrap().captures("aaa").unwrap();
+        assert_eq!(caps.extract(), ("aaa", ["a", "aa"]));
+        let caps = Regex::new("(a??)(a?)").unwrap().captures("a").unwrap();
+        assert_eq!(caps.extract(), ("a", ["", "a"]));
+
+        // repetitions over the limit are counted, and the counter honors laziness too
+        for max_expanded_repeat in [1000, 2] {
+            let build = |pattern: &str| {
+                RegexBuilder::new(pattern)
+                    .max_expanded_repeat(max_expanded_repeat)
+                    .build()
+                    .unwrap()
+            };
+            let find = |pattern: &str, h: &str| build(pattern).find(h).map(|m| m.range());
+            assert_eq!(find("a{2,5}?", "aaaaa"), Some(0..2));
+            assert_eq!(find("a{2,5}?b", "aaaab"), Some(0..5));
+            assert_eq!(find("x{3,}?", "xxxxx"), Some(0..3));
+        }
+
+        // the language doesn't change, so the automata are the same as the greedy ones
+        assert_eq!(
+            compile_regex("a*?b").adjacency(),
+            compile_regex("a*b").adjacency()
+        );
+        let errors = |pattern| -> Vec<_> {
+            check_pattern(pattern)
+                .into_iter()
+                .map(|e| (e.kind, e.span.start, e.span.end))
+                .collect()
+        };
+        assert_eq!(errors("a???"), [(ErrorKind::NothingToRepeat, 3, 4)]);
+        assert_eq!(errors("*?"), [(ErrorKind::NothingToRepeat, 0, 2)]);
+    }
+
+    #[cfg(feature = "petgraph")]
+    #[test]
+    fn test_petgraph() {
+        use petgraph::algo::{has_path_connecting, kosaraju_scc};
+        use petgraph::graph::NodeIndex;
+
+        use crate::id::StateId;
+        use crate::transition_table::NfaState;
+
+        let dfa = compile_regex("a(bc)*d");
+        let graph = dfa.to_petgraph();
+        assert_eq!(graph.node_count(), dfa.state_count());
+        // the (bc)* loop is the only cycle
+        let cycles: Vec<_> = kosaraju_scc(&graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1)
+            .collect();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].len(), 2);
+
+        let node = |id: StateId| NodeIndex::new(id.as_usize());
+        let accepting = dfa.accept_states()[0];
+        assert!(graph[node(accepting)].accepting);
+        assert!(has_path_connecting(
+            &graph,
+            node(dfa.start()),
+            node(accepting),
+            None
+        ));
+
+        let nfa = compile("a|b").unwrap().nfa.to_petgraph();
+        assert_eq!(nfa[NodeIndex::new(0)], NfaState::START);
+        assert!(nfa.edge_weights().any(|t| *t == Transition::Literal('b')));
+    }
+
+    #[test]
+    fn test_case_insensitive_builder() {
+        for engine in [Engine::Dfa, Engine::LazyDfa, Engine::Backtrack] {
+            let regex = RegexBuilder::new("ab[c-e]+")
+                .case_insensitive(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(regex.is_match("ABcDE"));
+            assert_eq!(regex.find("xxAbE").map(|m| m.range()), Some(2..5));
+            assert_eq!(test_string("aBCd", regex.dfa()), Ok(()));
+            assert!(test_string("abf", regex.dfa()).is_err());
+            // the pattern itself is left as written
+            assert_eq!(regex.pattern(), "ab[c-e]+");
+        }
+
+        // backreferences re-match the folded group
+        let regex = RegexBuilder::new("(k)\\1")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(regex.is_match("K\u{212A}"));
+        assert!(!Regex::new("abc").unwrap().is_match("ABC"));
+    }
+
+    #[test]
+    fn test_backreference() {
+        let r1 = compile_regex("(ab+)12\\1*");
+
+        assert_eq!(test_string("ab12ab", &r1), Ok(()));
+        assert_eq!(test_string("abbbbbbb12", &r1), Ok(()));
+        assert_eq!(test_string("abb12abbbbababb", &r1), Ok(()));
+
+        let r2 = compile_regex("(ab*)+(12?)*\\1?\\2+");
+        assert_eq!(test_string("aabbbbbaba121112", &r2), Ok(()));
+
+        let r3 = compile_regex("(1)(2)(3)(4)(5)(6)(7)(8)(9)(10)(11)\\11");
+        assert_eq!(test_string("123456789101111", &r3), Ok(()));
+    }
+
+    #[test]
+    fn test_hex_escape() {
+        let r1 = compile_regex("\\x4E");
+
+        assert_eq!(test_string("N", &r1), Ok(()));
+        assert!(matches!(
+            test_string("n", &r1),
+            Err(SimError::NoMatch { found: 'n', .. })
+        ));
+
+        let r2 = compile_regex("\\u006e");
+        assert_eq!(test_string("n", &r2), Ok(()));
+        assert!(matches!(
+            test_string("N", &r2),
+            Err(SimError::NoMatch { found: 'N', .. })
+        ));
+    }
+
+    #[test]
+    fn test_group_metadata() {
+        let r = Regex::new("(?<year>\\d{4})-((?P<month>\\d\\d)|x)").unwrap();
+
+        assert_eq!(r.captures_len(), 4);
+        assert_eq!(
+            r.capture_names().collect::<Vec<_>>(),
+            vec![None, Some("year"), None, Some("month")]
+        );
+
+        let info = r.group_info();
+        assert_eq!(info[0].span, parse::Span::new(0, 14));
+        assert_eq!(info[1].index, 2);
+        assert_eq!(info[2].span, parse::Span::new(16, 31));
+
+        assert_eq!(test_string("2024-07", r.dfa()), Ok(()));
+        assert_eq!(test_string("2024-x", r.dfa()), Ok(()));
+    }
+
+    #[test]
+    fn test_display() {
+        let r = compile_regex("ab|ac");
+        let out = r.to_string();
+        let lines: Vec<_> = out.lines().collect();
+
+        assert_eq!(lines[0], "DFA with 3 states");
+        assert!(lines[1].starts_with("  d0 (start) {start"));
+        assert_eq!(lines[2], "    'a' -> d1");
+        assert_eq!(lines[4], "    'b' -> d2");
+        assert_eq!(lines[5], "    'c' -> d2");
+        assert!(lines[6].starts_with("  d2 (accepting) {accepting"));
+
+        let nfa = parse(parse::lex("a*".to_string())).unwrap();
+        assert!(nfa.to_string().contains("  accepting (accepting)\n"));
+    }
+
+    #[test]
+    fn test_dot_is_stable() {
+        // states allocated in between don't change the text
+        let pattern = "(a|bc)*[x-z]?d$";
+        let first = compile(pattern).unwrap();
+        compile("(other|pattern)+").unwrap();
+        let second = compile(pattern).unwrap();
+        assert_eq!(first.nfa.to_dot(), second.nfa.to_dot());
+        assert_eq!(first.raw_dfa.to_dot("raw"), second.raw_dfa.to_dot("raw"));
+        assert_eq!(first.dfa.to_dot("min"), second.dfa.to_dot("min"));
+        assert_eq!(first.dfa.to_string(), second.dfa.to_string());
+    }
+
+    #[test]
+    fn test_dot_escaping() {
+        let dfa = compile_regex("\t\"\\\\");
+        let dot = dfa.to_dot("say \"hi\"");
+        assert!(dot.contains("graph [label=\"say \\\"hi\\\"\"];"));
+        assert!(dot.contains("[label = \"'\\\\t'\"];"));
+        assert!(dot.contains("[label = \"'\\\\\\\"'\"];"));
+        assert!(dot.contains("[label = \"'\\\\\\\\'\"];"));
+        assert!(!dot.contains('\t'));
+
+        let hex = DotOptions {
+            escape: LabelEscape::Hex,
+            ..DotOptions::default()
+        };
+        let dot = dfa.to_dot_with("", hex);
+        assert!(dot.contains("[label = \"'0x09'\"];"));
+        assert!(dot.contains("[label = \"'\\\\\\\"'\"];"));
+
+        let nfa = parse(parse::lex("\u{200b}".to_string())).unwrap();
+        assert!(nfa.to_dot().contains("'\\\\u{200b}'"));
+        assert!(nfa.to_dot_with(hex).contains("'0x200B'"));
+    }
+
+    #[test]
+    fn test_dot_classes() {
+        let dfa = compile_regex("[a-z0-9_]x|-|]");
+        let dot = dfa.to_dot("");
+        assert!(dot.contains("d0 -> d2 [label = \"[0-9_a-z]\"];"));
+        assert_eq!(dot.matches("d0 -> d1").count(), 1);
+        assert!(dot.contains("d0 -> d1 [label = \"[\\\\-\\\\]]\"];"));
+
+        let ungrouped = DotOptions {
+            group_classes: false,
+            ..DotOptions::default()
+        };
+        assert_eq!(
+            dfa.to_dot_with("", ungrouped).matches("d0 -> d2").count(),
+            37
+        );
+
+        let nfa = parse(parse::lex("[b-d]".to_string())).unwrap();
+        assert!(nfa.to_dot().contains(" [label = \"[b-d]\"];"));
+    }
+
+    #[test]
+    fn test_state_ids() {
+        let dfa = compile_regex("ab|ac");
+        let start = dfa.start();
+
+        assert_eq!(start.as_usize(), 0);
+        assert_eq!(dfa.states().count(), dfa.state_count());
+        let a = dfa.next_state(start, 'a').unwrap();
+        assert!(dfa.next_state(start, 'b').is_none());
+        assert!(!dfa.is_accepting(a));
+        let end = dfa.next_state(a, 'c').unwrap();
+        assert!(dfa.is_accepting(end));
+        assert_eq!(
+            dfa.transitions(a),
+            vec![
+                (Transition::Literal('b'), end),
+                (Transition::Literal('c'), end)
+            ]
+        );
+
+        // the same shape gets the same numbering however its states were built
+        let other = compile_regex("a(b|c)");
+        let edges = |d: &Dfa| d.states().map(|s| d.transitions(s)).collect::<Vec<_>>();
+        assert_eq!(edges(&other), edges(&dfa));
+        assert!(dfa.to_dot("").contains("d0 -> d1 [label = \"'a'\"];"));
+    }
+
+    #[test]
+    fn test_backreference_validation() {
+        use crate::error::ErrorKind;
+
+        let kind = |p: &str| Regex::new(p).unwrap_err().kind;
+
+        assert_eq!(kind("(a)(b)\\3"), ErrorKind::UndefinedGroup(3));
+        assert_eq!(
+            Regex::new("(a)\\2").unwrap_err().span,
+            parse::Span::new(3, 5)
+        );
+
+        // nested groups are numbered across the whole pattern
+        let r1 = compile_regex("(a(b))\\2");
+        assert_eq!(test_string("abb", &r1), Ok(()));
+
+        // with fewer than 12 groups, \12 is octal 012 (newline)
+        let r2 = compile_regex("(a)(b)\\12");
+        assert_eq!(test_string("ab\n", &r2), Ok(()));
+
+        // \0 is always octal, \8 with too few groups is a literal
+        let r3 = compile_regex("\\101\\0\\81");
+        assert_eq!(test_string("A\081", &r3), Ok(()));
+    }
+
+    #[test]
+    fn test_unclosed_backreference() {
+        // references to groups that haven't closed yet never match
+        let r1 = compile_regex("(\\1a)");
+        assert_eq!(
+            test_string("a", &r1),
+            Err(SimError::DeadState { offset: 0 })
+        );
+
+        let r2 = compile_regex("(a|b\\1)");
+        assert_eq!(test_string("a", &r2), Ok(()));
+        assert_eq!(
+            test_string("ba", &r2),
+            Err(SimError::DeadState { offset: 1 })
+        );
+
+        let r3 = compile_regex("\\2?(a)(b)");
+        assert_eq!(test_string("ab", &r3), Ok(()));
+        assert!(matches!(
+            test_string("bab", &r3),
+            Err(SimError::NoMatch { found: 'b', .. })
+        ));
+
+        let r4 = compile_regex("(\\1*a)+");
+        assert_eq!(test_string("aaa", &r4), Ok(()));
+    }
+
+    #[test]
+    fn test_captures() {
+        let r = Regex::new("(?<key>\\w+)=(\\d+|(x))").unwrap();
+        let caps = r.captures("set width=80;").unwrap();
+
+        assert_eq!(caps.get(0).unwrap().range(), 4..12);
+        assert_eq!(&caps["key"], "width");
+        assert_eq!(&caps[2], "80");
+        assert_eq!(caps.get(3), None);
+        assert!(r.captures("width=").is_none());
+
+        // backreferences re-match the group's pattern, not its text
+        let r2 = Regex::new("(a|b)\\1").unwrap();
+        assert_eq!(&r2.captures("ab").unwrap()[1], "a");
+    }
+
+    #[test]
+    fn test_capture_iterations() {
+        let r = Regex::new("((a|b)c)+").unwrap();
+
+        // by default the last iteration wins
+        let caps = r.captures("acbcac").unwrap();
+        assert_eq!(&caps[1], "ac");
+        assert_eq!(caps.get(2).unwrap().start(), 4);
+        assert_eq!(caps.iterations(2).len(), 1);
+
+        let caps = r
+            .captures_with("acbcac", CaptureMode::AllIterations)
+            .unwrap();
+        let text = |i| {
+            caps.iterations(i)
+                .iter()
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(text(1), vec!["ac", "bc", "ac"]);
+        assert_eq!(text(2), vec!["a", "b", "a"]);
+        assert_eq!(text(0), vec!["acbcac"]);
+
+        // iterations from abandoned attempts are not reported
+        let r2 = Regex::new("(a)*ab").unwrap();
+        let caps = r2
+            .captures_with("aaab", CaptureMode::AllIterations)
+            .unwrap();
+        assert_eq!(caps.iterations(1).len(), 2);
+    }
+
+    #[test]
+    fn test_is_match() {
+        let r = Regex::new("ab+c|d").unwrap();
+
+        assert!(r.is_match("xxabbbcxx"));
+        assert!(r.is_match("d"));
+        assert!(r.is_match("ab_abc"));
+        assert!(!r.is_match("abbb"));
+        assert!(!r.is_match(""));
+
+        // a match ends as soon as the accepting state is seen, whatever follows
+        let r2 = Regex::new("a.?b").unwrap();
+        assert!(r2.is_match("zzaab\u{1F600}"));
+
+        // empty alternatives match the empty string
+        assert!(Regex::new("").unwrap().is_match("anything"));
+        assert!(Regex::new("x|").unwrap().is_match("y"));
+        assert!(matches!(
+            test_string("", &compile_regex("a(|b)")),
+            Err(SimError::EndOfString { .. })
+        ));
+        assert_eq!(test_string("a", &compile_regex("a(|b)")), Ok(()));
+    }
+
+    #[test]
+    fn test_find_last() {
+        let r = Regex::new("a+b").unwrap();
+        assert_eq!(r.find_last("ab xaab zab").unwrap().range(), 9..11);
+        assert_eq!(r.find_last("aab aaab").unwrap().as_str(), "aaab");
+        assert_eq!(r.find_last("ba"), None);
+
+        let r2 = Regex::new("aa").unwrap();
+        assert_eq!(r2.find_last("aaa").unwrap().range(), 1..3);
+
+        let r3 = Regex::new("x*").unwrap();
+        assert_eq!(r3.find_last("abc").unwrap().range(), 3..3);
+
+        let r4 = Regex::new("\\u00e9+").unwrap();
+        assert_eq!(r4.find_last("a\u{e9}\u{e9}b").unwrap().range(), 1..5);
+    }
+
+    #[test]
+    fn test_split_variants() {
+        let r = Regex::new(",\\s*").unwrap();
+
+        let fields: Vec<_> = r.split("a, b,c,").collect();
+        assert_eq!(fields, vec!["a", "b", "c", ""]);
+
+        let fields: Vec<_> = r.split_terminator("a, b,c,").collect();
+        assert_eq!(fields, vec!["a", "b", "c"]);
+        assert_eq!(r.split_terminator("").count(), 0);
+        assert_eq!(r.split_terminator(",").collect::<Vec<_>>(), vec![""]);
+
+        let parts: Vec<_> = r.split_with_delimiters("a, b,c").collect();
+        assert_eq!(parts, vec!["a", ", ", "b", ",", "c"]);
+
+        let ws = Regex::new("\\s+").unwrap();
+        assert_eq!(
+            ws.splitn("a  b\tc d", 3).collect::<Vec<_>>(),
+            ["a", "b", "c d"]
+        );
+        assert_eq!(ws.splitn("a b", 5).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(ws.splitn("a b", 1).collect::<Vec<_>>(), ["a b"]);
+        assert_eq!(ws.splitn("a b", 0).count(), 0);
+        let parts: Vec<_> = r.split_with_delimiters("a,b,c").limit(2).collect();
+        assert_eq!(parts, vec!["a", ",", "b,c"]);
+
+        let r2 = Regex::new("a*").unwrap();
+        let spans: Vec<_> = r2.find_iter("baaac").map(|m| m.range()).collect();
+        assert_eq!(spans, vec![0..0, 1..4, 5..5]);
+    }
+
+    #[test]
+    fn test_extract_iter() {
+        let r = Regex::new("([a-z]+)=([0-9]*)").unwrap();
+        let pairs: Vec<_> = r
+            .extract_iter::<2>("a=1 bc=23 d=")
+            .map(|(_, pair)| pair)
+            .collect();
+        assert_eq!(pairs, vec![["a", "1"], ["bc", "23"], ["d", ""]]);
+
+        let r2 = Regex::new("x(y)?").unwrap();
+        let (whole, [y]) = r2.captures("x").unwrap().extract();
+        assert_eq!((whole, y), ("x", ""));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_extract_wrong_arity() {
+        let r = Regex::new("(a)(b)").unwrap();
+        r.captures("ab").unwrap().extract::<1>();
+    }
+
+    #[test]
+    fn test_char_map() {
+        let r = RegexBuilder::new("Hello [A-C]+")
+            .char_map(|c| c.to_ascii_lowercase())
+            .build()
+            .unwrap();
+
+        assert!(r.is_match("say HELLO abc"));
+        assert_eq!(r.find("say HELLO aBcd").map(|m| m.range()), Some(4..13));
+        assert_eq!(
+            r.find_last("hello a, hello B").map(|m| m.as_str()),
+            Some("hello B")
+        );
+        assert!(r.dfa().simulate("hello CAB".to_string()).is_ok());
+
+        // strip diacritics, keeping byte offsets into the original text
+        let fold = |c| match c {
+            'é' | 'è' => 'e',
+            c => c,
+        };
+        let r2 = RegexBuilder::new("cafe").char_map(fold).build().unwrap();
+        assert_eq!(r2.find("un café").map(|m| m.range()), Some(3..8));
+    }
+
+    #[test]
+    fn test_anchored_and_unanchored_starts() {
+        let r = Regex::new("ab+").unwrap();
+
+        assert!(r.dfa().matches_prefix("abbc"));
+        assert!(!r.dfa().matches_prefix("cab"));
+        assert!(r.dfa().contains_match("cab"));
+        assert!(!r.dfa().contains_match("cba"));
+        assert_eq!(test_string("abb", r.dfa()), Ok(()));
+        assert_eq!(r.find_at("ab cab", 1).map(|m| m.range()), Some(4..6));
+        assert!(r.dfa().to_string().contains("(unanchored start)"));
+    }
+
+    #[test]
+    fn test_dfa_find() {
+        let find = |pattern: &str, h: &str| {
+            let r = Regex::new(pattern).unwrap();
+            r.dfa().find(h).map(|m| (m.range(), m.as_str().to_string()))
+        };
+        assert_eq!(find("a+", "xaab"), Some((1..3, "aa".to_string())));
+        // leftmost-longest, where Regex::find prefers the first alternative
+        assert_eq!(find("a|ab", "cab"), Some((1..3, "ab".to_string())));
+        assert_eq!(find("x*", "ab"), Some((0..0, String::new())));
+        assert_eq!(find("b+", "aaa"), None);
+        // ^ only holds at the start of the haystack, $ at its end
+        assert_eq!(find("^a|b", "xab").map(|(r, _)| r), Some(2..3));
+        assert_eq!(find("^a|b", "ab").map(|(r, _)| r), Some(0..1));
+        assert_eq!(find("b$", "bb").map(|(r, _)| r), Some(1..2));
+        assert_eq!(find("é+", "café").map(|(r, _)| r), Some(3..5));
+
+        // without an unanchored start every position is tried from the start state
+        let dfa = compile_regex("[0-9]+");
+        assert_eq!(dfa.find("ab 123 4").map(|m| m.range()), Some(3..6));
+    }
+
+    #[test]
+    fn test_dfa_find_iter() {
+        let ranges = |pattern: &str, h: &str| {
+            let r = Regex::new(pattern).unwrap();
+            r.dfa().find_iter(h).map(|m| m.range()).collect::<Vec<_>>()
+        };
+        assert_eq!(ranges("a*", "baaac"), [0..0, 1..4, 5..5]);
+        assert_eq!(ranges("é*", "éxé"), [0..2, 3..5]);
+        assert_eq!(ranges("^a|b", "abab"), [0..1, 1..2, 3..4]);
+        assert_eq!(ranges("x+", "abc"), []);
+
+        let log = "GET /a 200\nGET /b 404\nPOST /c 500";
+        let dfa = compile_regex("[0-9]+");
+        let codes: Vec<_> = dfa.find_iter(log).map(|m| m.as_str()).collect();
+        assert_eq!(codes, ["200", "404", "500"]);
+    }
+
+    #[test]
+    fn test_nfa_priorities() {
+        use crate::transition_table::NfaState;
+
+        // alternatives keep the order they were written in
+        for (pattern, first) in [("a|b", 'a'), ("b|a", 'b')] {
+            let nfa = compile(pattern).unwrap().nfa;
+            let edges = nfa.prioritized_edges(NfaState::START);
+            assert_eq!(edges[0].0, Transition::Literal(first), "{pattern}");
+        }
+
+        // so a search that follows them agrees with the backtracker
+        let cases = [
+            ("a|ab", "ab"),
+            ("ab|a", "ab"),
+            ("(a|ab)(c|bcd)", "abcd"),
+            ("a*", "aaa"),
+            ("a*?", "aaa"),
+            ("a+?", "baaa"),
+            ("a*?b", "aab"),
+            ("(a|b)*?b", "abab"),
+            ("a{2,4}", "aaaaa"),
+            ("a{2,4}?", "aaaaa"),
+            ("x??y|x", "xy"),
+            ("(ab)+?|a", "ababab"),
+            ("^b|a", "ab"),
+            ("a$|ab", "aab"),
+            ("(a*)*b", "aac"),
+            ("[^a]+?c|b", "bbc"),
+            ("(x|xy){1,3}?y", "xyxyy"),
+            ("(a|b){2,}?", "abab"),
+        ];
+        for (pattern, haystack) in cases {
+            let nfa = compile(pattern).unwrap().nfa;
+            let expected = Regex::new(pattern)
+                .unwrap()
+                .find(haystack)
+                .map(|m| m.range());
+            let found = nfa.find_first(haystack).map(|m| m.range());
+            assert_eq!(found, expected, "{pattern} on {haystack}");
+        }
+
+        // ranks are counted per NFA, so building a pattern twice gives the same edges
+        let pattern = "(a|bc)*?d|e{2,3}?|f+";
+        let (first, second) = (compile(pattern).unwrap().nfa, compile(pattern).unwrap().nfa);
+        assert_eq!(first.state_count(), second.state_count());
+        assert!(first.states().all(|s| first.edges(s) == second.edges(s)));
+    }
+
+    #[test]
+    fn test_replace() {
+        let r = Regex::new("(?<word>[a-z]+)=(\\d+)").unwrap();
+        let h = "x=1, y=22, z=3";
+        assert_eq!(r.replace(h, "$2:${word}"), "1:x, y=22, z=3");
+        assert_eq!(r.replace_all(h, "${2}${word}"), "1x, 22y, 3z");
+        assert_eq!(r.replacen(h, 2, "_"), "_, _, z=3");
+        assert_eq!(r.replace_all(h, "$$"), "$, $, $");
+        assert!(matches!(
+            r.replace_all("nothing", "_"),
+            Cow::Borrowed("nothing")
+        ));
+        assert!(matches!(r.replace("a=1", "b"), Cow::Owned(_)));
+
+        // empty matches are replaced like find_iter finds them
+        let r = Regex::new("a*").unwrap();
+        assert_eq!(r.replace_all("baaac", "-"), "-b-c-");
+    }
+
+    #[test]
+    fn test_find_positions_iter() {
+        for (pattern, h) in [("a*", "baaac"), ("\\d+|x", "x12 y 345x"), ("é|b", "éébé")] {
+            let r = Regex::new(pattern).unwrap();
+            let ranges: Vec<_> = r.find_iter(h).map(|m| (m.start(), m.end())).collect();
+            assert_eq!(r.find_positions_iter(h).collect::<Vec<_>>(), ranges);
+        }
+        let r = Regex::new("a*").unwrap();
+        let positions: Vec<_> = r.find_positions_iter("baaac").collect();
+        assert_eq!(positions, [(0, 0), (1, 4), (5, 5)]);
+    }
+
+    #[test]
+    fn test_find_iter_with() {
+        let r = Regex::new("\\d+").unwrap();
+        let h = "1 22 333 4444";
+        let mut seen = Vec::new();
+        let flow = r.find_iter_with(h, |m| {
+            seen.push(m.as_str());
+            if seen.len() == 2 {
+                ControlFlow::Break(m.end())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(flow, ControlFlow::Break(4));
+        assert_eq!(seen, ["1", "22"]);
+
+        // f sees every match if it never breaks
+        let mut count = 0;
+        let flow: ControlFlow<()> = r.find_iter_with(h, |_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert!(flow.is_continue());
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_try_clone_with_options() {
+        let r = Regex::new("ab+").unwrap();
+        let folded = r
+            .try_clone_with_options(|b| {
+                b.case_insensitive(true);
+            })
+            .unwrap();
+        assert_eq!(folded.pattern(), "ab+");
+        assert!(folded.options().get_case_insensitive());
+        assert!(folded.is_match("xABB") && !r.is_match("xABB"));
+
+        let whole = folded
+            .try_clone_with_options(|b| {
+                b.full_match(true);
+            })
+            .unwrap();
+        assert!(whole.is_match("aBb") && !whole.is_match("xaBb"));
+
+        let large = Regex::new("a{1,500}").unwrap();
+        assert!(large
+            .try_clone_with_options(|b| {
+                b.engine(Engine::Dfa);
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_custom_shorthands() {
+        let mut builder = RegexBuilder::new("\\w+:\\s*[\\w.]+");
+        builder.word_chars(&['a', 'b', '-']).space_chars(&['~']);
+        let r = builder.build().unwrap();
+        assert_eq!(r.find("x a-b:~~b.a").unwrap().as_str(), "a-b:~~b.a");
+        assert!(!r.is_match("a: b"));
+        assert!(Regex::new("[[:word:]]").unwrap().is_match("_"));
+        let r = RegexBuilder::new("^[[:word:]]$")
+            .word_chars(&['-'])
+            .build()
+            .unwrap();
+        assert!(r.is_match("-") && !r.is_match("_"));
+
+        // a clone with other shorthands lexes the pattern again
+        let r = Regex::new("\\w+").unwrap();
+        let dashed = r.try_clone_with_options(|b| {
+            b.word_chars(&['-']);
+        });
+        assert_eq!(dashed.unwrap().find("ab--").unwrap().range(), 2..4);
+    }
+
+    #[test]
+    fn test_word_boundary() {
+        use crate::error::{Construct, ErrorKind};
+
+        let r = Regex::new("\\bcat\\b").unwrap();
+        assert_eq!(r.find("a cat.").unwrap().range(), 2..5);
+        assert!(!r.is_match("concat") && !r.is_match("cats"));
+        assert!(r.is_full_match("cat"));
+        assert_eq!(
+            Regex::new("\\Bcat")
+                .unwrap()
+                .find("cat concat")
+                .unwrap()
+                .range(),
+            7..10
+        );
+        // the automata skip over the assertion, so the backtracker decides
+        assert!(!r.is_exact());
+        assert_eq!(r.engine(), Engine::Backtrack);
+
+        // the word set is the one \w uses
+        let dashed = |pattern: &str| {
+            RegexBuilder::new(pattern)
+                .word_chars(&['a', 'd', 'h', 'i', 't', 'w', 'x', '-'])
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            Regex::new("\\bwidth")
+                .unwrap()
+                .find("max-width")
+                .unwrap()
+                .range(),
+            4..9
+        );
+        assert!(!dashed("\\bwidth").is_match("max-width"));
+        assert!(dashed("\\bwidth").is_match("max width"));
+        assert!(dashed("x-\\Bh").is_match("x-h") && !Regex::new("x-\\Bh").unwrap().is_match("x-h"));
+        assert!(dashed("\\w+\\b").is_full_match("wax-"));
+        let clone = Regex::new("\\bwidth").unwrap().try_clone_with_options(|b| {
+            b.word_chars(&['-']);
+        });
+        assert!(clone.unwrap().is_match("max-width") && !dashed("\\bwidth").is_match("a-width"));
+        // and folded inside (?i:...)
+        let folded = RegexBuilder::new("(?i:\\bb)")
+            .word_chars(&['a', 'b'])
+            .build()
+            .unwrap();
+        assert!(!folded.is_match("Ab") && folded.is_match("-B"));
+
+        for engine in [Engine::Dfa, Engine::LazyDfa] {
+            let err = RegexBuilder::new("a\\B")
+                .engine(engine)
+                .build()
+                .unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Disabled(Construct::WordBoundary));
+            assert_eq!(err.span, parse::Span::new(1, 3));
+        }
+        assert!(RegexBuilder::new("\\b")
+            .syntax(SyntaxConfig::linear())
+            .build()
+            .is_err());
+    }
 
-    let mut dfa = Dfa::from_nfa(nfa);
-    dfa.minimize();
+    #[test]
+    fn test_regex_from_str() {
+        let r: Regex = "(\\d+)-(\\d+)".parse().unwrap();
+        assert_eq!(r.pattern(), "(\\d+)-(\\d+)");
+        assert_eq!(r.to_string(), r.pattern());
+        assert!(r.is_match("pages 3-7"));
+        assert_eq!(r.find("pages 3-7").map(|m| m.range()), Some(6..9));
+        assert_eq!(r.captures("3-7").unwrap().get(2).unwrap().as_str(), "7");
 
-    dfa
-}
+        let err = "a(".parse::<Regex>().unwrap_err();
+        assert_eq!(err, Regex::new("a(").unwrap_err());
+    }
 
-pub fn test_string(input: &str, dfa: &Dfa) -> Result<(), SimError> {
-    dfa.simulate(input.to_string())
-}
+    #[test]
+    fn test_compilation_artifacts() {
+        let c = compile("(ab)|c").unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(c.tokens.len(), 3);
+        assert!(matches!(c.tokens[0].elem, parse::ParseElement::Group(..)));
+        assert_eq!(c.raw_dfa.simulate("ab".to_string()), Ok(()));
+        assert_eq!(c.dfa.simulate("c".to_string()), Ok(()));
+        assert!(c.dfa.to_string().lines().count() <= c.raw_dfa.to_string().lines().count());
+
+        assert!(compile("\\2(a)").is_err());
+    }
 
     #[test]
-    fn test_brackets_char_classes() {
-        let r = compile_regex("\\w");
+    fn test_sim_verdicts() {
+        let r = compile_regex("ab[cd]");
 
-        for c in 'a'..='z' {
-            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+        assert_eq!(
+            test_string("ax", &r),
+            Err(SimError::NoMatch {
+                found: 'x',
+                offset: 1,
+                expected: vec![Transition::Literal('b')],
+            })
+        );
+        let err = test_string("ab", &r).unwrap_err();
+        assert_eq!(err.to_string(), "input ended early, expected 'c' or 'd'");
+        assert_eq!(
+            test_string("abcd", &r),
+            Err(SimError::Premature { offset: 3 })
+        );
+
+        // (a\1) can never match, so after an a nothing else can either
+        let r2 = compile_regex("x|(a\\1)");
+        assert_eq!(
+            test_string("ab", &r2),
+            Err(SimError::DeadState { offset: 1 })
+        );
+        assert_eq!(
+            test_string("a", &r2),
+            Err(SimError::DeadState { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn test_map_symbols() {
+        let dfa = compile_regex("Ab|ab|[^B]x");
+        let mut lower = dfa.map_symbols(|c| c.to_ascii_lowercase());
+        lower.minimize();
+        for (input, accepted) in [("ab", true), ("Ab", false), ("bx", true), ("Ax", true)] {
+            assert_eq!(test_string(input, &lower).is_ok(), accepted, "{input:?}");
         }
+        assert!(lower
+            .transitions(lower.start())
+            .iter()
+            .all(|(t, _)| { !matches!(t, Transition::Literal(c) if c.is_ascii_uppercase()) }));
 
-        for c in 'A'..='Z' {
-            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+        // every digit read as 0
+        let digits = compile_regex("1[2-4]|9$");
+        let zeros = digits.map_symbols(|c| if c.is_ascii_digit() { '0' } else { c });
+        assert!(test_string("00", &zeros).is_ok() && test_string("0", &zeros).is_ok());
+        assert!(test_string("12", &zeros).is_err());
+        assert!(zeros.first_chars().chars.iter().eq([&'0']));
+    }
+
+    #[test]
+    fn test_is_equivalent() {
+        let equivalent = [
+            ("(a|b)*", "(a*b*)*"),
+            ("a+", "aa*"),
+            ("[0-9]{2,3}", "\\d\\d\\d?"),
+            ("x|y$", "[xy]"),
+        ];
+        for (a, b) in equivalent {
+            assert!(compile_regex(a).is_equivalent(&compile_regex(b)), "{a} {b}");
         }
+        for (a, b) in [
+            ("a+", "a*"),
+            ("(ab)*", "(a|b)*"),
+            (".", "[^x]"),
+            ("a.b", "a[^c]b"),
+        ] {
+            let (a, b) = (compile_regex(a), compile_regex(b));
+            assert!(!a.is_equivalent(&b));
+            assert!(!b.is_equivalent(&a));
+            assert!(a.distinguishing_string(&b).is_some());
+        }
+        // the unminimized DFA is equivalent to the minimized one
+        let compilation = compile("(a|ab)(c|bcd)").unwrap();
+        assert!(compilation.raw_dfa.is_equivalent(&compilation.dfa));
+    }
 
-        for c in '0'..='9' {
-            assert_eq!(test_string(String::from(c).as_str(), &r), Ok(()));
+    #[test]
+    fn test_shortest_match_example() {
+        let example = |p: &str| compile_regex(p).shortest_match_example();
+        assert_eq!(example("[0-9]{3}-[a-z]+").as_deref(), Some("000-a"));
+        assert_eq!(example("abc|x").as_deref(), Some("x"));
+        assert_eq!(example("a*").as_deref(), Some(""));
+        assert_eq!(example("(ab)+$").as_deref(), Some("ab"));
+        assert_eq!(example(".[^a-z]").as_deref(), Some("a0"));
+        let never = compile_regex("a").intersect(&compile_regex("b"));
+        assert_eq!(never.shortest_match_example(), None);
+    }
+
+    #[test]
+    fn test_language_iter() {
+        let language = |p: &str, cap| compile_regex(p).language_iter(cap).collect::<Vec<_>>();
+        assert_eq!(language("(ab|a)c?", 10), ["a", "ab", "ac", "abc"]);
+        assert_eq!(language("[a-c]*", 6), ["", "a", "b", "c", "aa", "ab"]);
+        assert_eq!(language("x(ab)*$", 3), ["x", "xab", "xabab"]);
+        assert_eq!(language("a{3}|b.", 3), ["ba", "aaa"]);
+        assert_eq!(language("a*", 0), Vec::<String>::new());
+        let never = compile_regex("a").intersect(&compile_regex("b"));
+        assert_eq!(never.language_iter(5).next(), None);
+    }
+
+    #[test]
+    fn test_is_subset_of() {
+        let paths = compile_regex("/api/v[12]/[a-z]+");
+        let narrowed = compile_regex("/api/v1/(users|orders)");
+        assert_eq!(narrowed.is_subset_of(&paths), Ok(()));
+        assert_eq!(paths.is_subset_of(&narrowed), Err("/api/v1/a".to_string()));
+        assert_eq!(
+            compile_regex("/api/v[1-3]/x").is_subset_of(&paths),
+            Err("/api/v3/x".to_string())
+        );
+        // every DFA contains itself and the empty string takes part too
+        assert_eq!(paths.is_subset_of(&paths), Ok(()));
+        assert_eq!(
+            compile_regex("a*").is_subset_of(&compile_regex("a+")),
+            Err(String::new())
+        );
+    }
+
+    #[test]
+    fn test_intersect_union() {
+        let digits = compile_regex("[0-9]+");
+        let three = compile_regex("[0-9]{3}|x");
+        let both = digits.intersect(&three);
+        let either = digits.union(&three);
+        for (input, in_both, in_either) in [
+            ("123", true, true),
+            ("12", false, true),
+            ("x", false, true),
+            ("", false, false),
+            ("12a", false, false),
+        ] {
+            assert_eq!(test_string(input, &both).is_ok(), in_both, "{input:?}");
+            assert_eq!(test_string(input, &either).is_ok(), in_either, "{input:?}");
         }
+        assert_eq!(both.distinguishing_string(&compile_regex("[0-9]{3}")), None);
+        assert_eq!(
+            either.distinguishing_string(&compile_regex("[0-9]+|x")),
+            None
+        );
 
-        assert_eq!(test_string(String::from('_').as_str(), &r), Ok(()));
+        // no string matches both, and $ and other characters carry over
+        assert!(compile_regex("a+")
+            .intersect(&compile_regex("b+"))
+            .accept_states()
+            .is_empty());
+        let ends = compile_regex("a.*").intersect(&compile_regex(".*b$"));
+        assert!(test_string("azzb", &ends).is_ok());
+        assert!(test_string("azz", &ends).is_err());
+        assert_eq!(ends.find("xab").map(|m| m.range()), Some(1..3));
+    }
 
-        assert_eq!(test_string(" ", &r), Err(SimError::NoMatch(' ')));
+    #[test]
+    fn test_simulate_bytes() {
+        use crate::dfa::ByteMode;
+
+        // raw bytes are Latin-1 characters, so \xnn matches the byte nn
+        let png = compile_regex("\\x89PNG.*");
+        assert_eq!(
+            png.simulate_bytes(b"\x89PNG\r\n\xff", ByteMode::Raw),
+            Ok(())
+        );
+        let utf8 = ByteMode::Utf8(InvalidUtf8::Reject);
+        assert_eq!(
+            png.simulate_bytes(b"\x89PNG", utf8),
+            Err(SimError::InvalidUtf8 { offset: 0 })
+        );
+
+        let r = compile_regex("é.b");
+        assert_eq!(r.simulate_bytes("éxb".as_bytes(), utf8), Ok(()));
+        let replace = ByteMode::Utf8(InvalidUtf8::Replace);
+        assert_eq!(r.simulate_bytes(b"\xc3\xa9\xffb", replace), Ok(()));
+        assert!(matches!(
+            r.simulate_bytes("éxb".as_bytes(), ByteMode::Raw),
+            Err(SimError::NoMatch {
+                found: 'Ã',
+                offset: 0,
+                ..
+            })
+        ));
+        assert_eq!(
+            r.simulate_bytes(b"\xc3\xa9x\xff", replace),
+            Err(SimError::NoMatch {
+                found: char::REPLACEMENT_CHARACTER,
+                offset: 3,
+                expected: vec![Transition::Literal('b')]
+            })
+        );
     }
 
     #[test]
-    fn test_wildcard_simple() {
-        let r = compile_regex("a.b");
+    fn test_check_pattern() {
+        use crate::error::ErrorKind::*;
 
-        assert_eq!(test_string("abb", &r), Ok(()));
-        assert_eq!(test_string("axb", &r), Ok(()));
-        assert_eq!(test_string("ab", &r), Err(SimError::EndOfString));
-        assert_eq!(test_string("axby", &r), Err(SimError::Premature));
+        let kinds = |p: &str| {
+            check_pattern(p)
+                .into_iter()
+                .map(|e| (e.kind, e.span.start, e.span.end))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kinds("a(b|c)*\\d{2,3}"), vec![]);
+        assert_eq!(
+            kinds("(a[z-b]|*)\\q{3,1}\\2)x{"),
+            vec![
+                (ReversedClassRange('z', 'b'), 3, 6),
+                (NothingToRepeat, 8, 9),
+                (UnknownEscape('q'), 10, 12),
+                (InvalidRange(3, 1), 12, 17),
+                (UndefinedGroup(2), 17, 19),
+                (UnopenedGroup, 19, 20),
+                (InvalidRepetition, 21, 22),
+            ]
+        );
+        assert_eq!(
+            kinds("((?<>a)[x"),
+            vec![
+                (UnclosedGroup, 0, 1),
+                (EmptyGroupName, 1, 5),
+                (UnclosedBracket, 7, 9)
+            ]
+        );
+
+        // the first error is what compiling reports
+        let err = Regex::new("a)b(").unwrap_err();
+        assert_eq!(err.to_string(), "unopened group at 1..2");
     }
 
     #[test]
-    fn test_ranges() {
-        // normal range
-        let r1 = compile_regex("a{3, 5}");
+    fn test_try_compile_regex() {
+        use crate::error::ErrorKind;
 
-        assert_eq!(test_string("", &r1), Err(SimError::EndOfString));
-        assert_eq!(test_string("a", &r1), Err(SimError::EndOfString));
-        assert_eq!(test_string("aa", &r1), Err(SimError::EndOfString));
-        assert_eq!(test_string("aaa", &r1), Ok(()));
-        assert_eq!(test_string("aaaa", &r1), Ok(()));
-        assert_eq!(test_string("aaaaa", &r1), Ok(()));
-        assert_eq!(test_string("aaaaaa", &r1), Err(SimError::Premature));
+        let kind = |p: &str| try_compile_regex(p).unwrap_err().kind;
+        assert_eq!(kind("(ab"), ErrorKind::UnclosedGroup);
+        assert_eq!(kind("a\\q"), ErrorKind::UnknownEscape('q'));
+        assert_eq!(kind("a{5,2}"), ErrorKind::InvalidRange(5, 2));
+        assert_eq!(kind("a{x}"), ErrorKind::InvalidRepetition);
 
-        // exact repetition
-        let r2 = compile_regex("a{3}");
+        let dfa = try_compile_regex("a(b|c)+").unwrap();
+        assert!(dfa.simulate("abcb".to_string()).is_ok());
+        assert_eq!(dfa.state_count(), compile_regex("a(b|c)+").state_count());
+    }
 
-        assert_eq!(test_string("aa", &r2), Err(SimError::EndOfString));
-        assert_eq!(test_string("aaa", &r2), Ok(()));
-        assert_eq!(test_string("aaaa", &r2), Err(SimError::Premature));
+    #[test]
+    fn test_tokenize_spans() {
+        use crate::parse::{tokenize_spans, TokenKind::*};
 
-        // open range
-        let r3 = compile_regex("a{3,}");
-        assert_eq!(test_string("aa", &r3), Err(SimError::EndOfString));
-        assert_eq!(test_string("aaa", &r3), Ok(()));
-        assert_eq!(test_string("aaaa", &r3), Ok(()));
-        assert_eq!(test_string("aaaaa", &r3), Ok(()));
+        let pattern = "(?<x>a\\.)+[b-c]|\\1()$";
+        let regions: Vec<_> = tokenize_spans(pattern)
+            .into_iter()
+            .map(|(span, kind)| (&pattern[span.start..span.end], kind))
+            .collect();
+
+        assert_eq!(
+            regions,
+            vec![
+                ("(?<x>", GroupOpen),
+                ("a", Literal),
+                ("\\.", Escape),
+                (")", GroupClose),
+                ("+", Quantifier),
+                ("[b-c]", Class),
+                ("|", Alternation),
+                ("\\1", BackReference),
+                ("(", GroupOpen),
+                (")", GroupClose),
+                ("$", Anchor),
+            ]
+        );
+
+        let spans = tokenize_spans("(a\\q");
+        assert_eq!(spans[0], (parse::Span::new(0, 1), GroupOpen));
+        assert_eq!(spans[2], (parse::Span::new(2, 4), Error));
     }
 
     #[test]
-    fn test_repetition() {
-        // *
-        let r1 = compile_regex("a*");
+    fn test_empty_matches() {
+        let r = Regex::new("x*").unwrap();
+        let ranges = |it: Matches| it.map(|m| m.range()).collect::<Vec<_>>();
 
-        assert_eq!(test_string("", &r1), Ok(()));
-        assert_eq!(test_string("a", &r1), Ok(()));
-        assert_eq!(test_string("aa", &r1), Ok(()));
-        assert_eq!(test_string("aaa", &r1), Ok(()));
-        assert_eq!(test_string("aaab", &r1), Err(SimError::Premature));
+        // each position is reported once, stepping over whole characters
+        assert_eq!(ranges(r.find_iter("é")), vec![0..0, 2..2]);
+        assert_eq!(ranges(r.find_iter("axxb")), vec![0..0, 1..3, 4..4]);
+        assert_eq!(ranges(r.find_iter("axxb").skip_empty()), vec![1..3]);
+        assert_eq!(r.find_iter("").count(), 1);
+        assert_eq!(r.find_iter("").skip_empty().count(), 0);
 
-        // +
-        let r2 = compile_regex("a+");
+        let fields: Vec<_> = r.split("aé").collect();
+        assert_eq!(fields, vec!["", "a", "é", ""]);
+        let fields: Vec<_> = r.split("axxé").skip_empty().collect();
+        assert_eq!(fields, vec!["a", "é"]);
+    }
 
-        assert_eq!(test_string("", &r2), Err(SimError::EndOfString));
-        assert_eq!(test_string("a", &r2), Ok(()));
-        assert_eq!(test_string("aa", &r2), Ok(()));
+    #[test]
+    fn test_multibyte_offsets() {
+        let hay = "ñaé😀b";
 
-        // ?
-        let r3 = compile_regex("a?");
+        let r = Regex::new("[é😀]+").unwrap();
+        let m = r.find(hay).unwrap();
+        assert_eq!((m.range(), m.char_range()), (3..9, 2..4));
+        assert_eq!(r.find_last(hay).map(|m| m.range()), Some(3..9));
+        assert_eq!(r.captures(hay).unwrap().get(0), Some(m));
+        assert_eq!(r.find_at(hay, 4).map(|m| m.range()), Some(5..9));
+        assert!(r.is_match(hay));
 
-        assert_eq!(test_string("", &r3), Ok(()));
-        assert_eq!(test_string("a", &r3), Ok(()));
-        assert_eq!(test_string("aa", &r3), Err(SimError::Premature));
+        // every engine steps over whole characters
+        let dot = Regex::new(".").unwrap();
+        let starts: Vec<_> = dot.find_iter(hay).map(|m| m.char_start()).collect();
+        assert_eq!(starts, vec![0, 1, 2, 3, 4]);
+        assert_eq!(dot.find_last(hay).map(|m| m.range()), Some(9..10));
+        assert_eq!(test_string("😀", dot.dfa()), Ok(()));
     }
 
     #[test]
-    fn test_union() {
-        let r1 = compile_regex("a*|b");
+    fn test_syntax_config() {
+        use crate::error::{Construct, ErrorKind};
 
-        assert_eq!(test_string("", &r1), Ok(()));
-        assert_eq!(test_string("a", &r1), Ok(()));
-        assert_eq!(test_string("aa", &r1), Ok(()));
-        assert_eq!(test_string("b", &r1), Ok(()));
-        assert_eq!(test_string("bb", &r1), Err(SimError::Premature));
-        assert_eq!(test_string("ab", &r1), Err(SimError::Premature));
+        let build = |pattern: &str, syntax| RegexBuilder::new(pattern).syntax(syntax).build();
+        let linear = SyntaxConfig::linear();
 
-        let r2 = compile_regex("ab|12");
-        assert_eq!(test_string("ab", &r2), Ok(()));
-        assert_eq!(test_string("12", &r2), Ok(()));
-        assert_eq!(test_string("a2", &r2), Err(SimError::NoMatch('2')));
+        assert!(build("(a|b)*c{2,50}", linear).is_ok());
+        let err = build("(a(b))\\2", linear).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Disabled(Construct::BackReference));
+        assert_eq!(err.span, parse::Span::new(6, 8));
+        assert_eq!(err.to_string(), "backreferences are disabled at 6..8");
+        let err = build("x{3,500}", linear).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Disabled(Construct::LargeRepeat));
+
+        let bounded = SyntaxConfig {
+            unbounded_repeats: false,
+            ..SyntaxConfig::default()
+        };
+        assert!(build("a{1,9}(\\d?)\\1", bounded).is_ok());
+        for pattern in ["a*", "(a+)", "a{2,}"] {
+            let err = build(pattern, bounded).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Disabled(Construct::UnboundedRepeat));
+        }
     }
 
     #[test]
-    fn test_group() {
-        let r1 = compile_regex("(abc)+");
+    fn test_engine_selection() {
+        use crate::error::{Construct, ErrorKind};
 
-        assert_eq!(test_string("", &r1), Err(SimError::EndOfString));
-        assert_eq!(test_string("abc", &r1), Ok(()));
-        assert_eq!(test_string("abcabc", &r1), Ok(()));
-        assert_eq!(test_string("abcabcab", &r1), Err(SimError::EndOfString));
+        assert_eq!(Regex::new("(a|b)\\1c*").unwrap().engine(), Engine::Dfa);
+        assert_eq!(Regex::new("a{2,300}").unwrap().engine(), Engine::Backtrack);
+        assert_eq!(
+            Regex::new("(a|b)*a(a|b){14}").unwrap().engine(),
+            Engine::LazyDfa
+        );
+        let big = RegexBuilder::new("(a|b)*a(a|b){3}a{2,300}")
+            .dfa_state_limit(4)
+            .build()
+            .unwrap();
+        assert_eq!(big.engine(), Engine::Backtrack);
+        assert!(big.is_lazy() && big.is_match("abbbaa") && !big.is_match("abbba"));
 
-        let r2 = compile_regex("((ab)+|(12)*)+");
+        let build = |pattern: &str, engine| RegexBuilder::new(pattern).engine(engine).build();
+        let err = build("a{2,300}", Engine::Dfa).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Disabled(Construct::LargeRepeat));
+        let err = build("a{2,300}", Engine::LazyDfa).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Disabled(Construct::LargeRepeat));
 
-        assert_eq!(test_string("", &r2), Ok(()));
-        assert_eq!(test_string("ab", &r2), Ok(()));
-        assert_eq!(test_string("abab12ab12", &r2), Ok(()));
+        let lazy = build("(a|b)\\1c*", Engine::LazyDfa).unwrap();
+        assert!(lazy.is_lazy() && lazy.engine() == Engine::LazyDfa);
+
+        let forced = build("(a|b)\\1c*", Engine::Backtrack).unwrap();
+        assert_eq!(forced.engine(), Engine::Backtrack);
+        for hay in ["xaac", "ab", "bbccc", ""] {
+            assert_eq!(
+                forced.is_match(hay),
+                Regex::new("(a|b)\\1c*").unwrap().is_match(hay)
+            );
+            assert_eq!(lazy.is_match(hay), forced.is_match(hay));
+        }
     }
 
     #[test]
-    fn test_backreference() {
-        let r1 = compile_regex("(ab+)12\\1*");
+    fn test_full_match() {
+        let r = Regex::new("abc").unwrap();
+        assert!(r.is_match("xabcx"));
+        assert!(!r.is_full_match("xabcx"));
+        assert!(r.is_full_match("abc"));
 
-        assert_eq!(test_string("ab12ab", &r1), Ok(()));
-        assert_eq!(test_string("abbbbbbb12", &r1), Ok(()));
-        assert_eq!(test_string("abb12abbbbababb", &r1), Ok(()));
+        let full = RegexBuilder::new("a{2,300}")
+            .full_match(true)
+            .build()
+            .unwrap();
+        assert!(full.is_match("aaa"));
+        assert!(!full.is_match("aaab"));
+        assert!(!full.is_match("a"));
+    }
 
-        let r2 = compile_regex("(ab*)+(12?)*\\1?\\2+");
-        assert_eq!(test_string("aabbbbbaba121112", &r2), Ok(()));
+    #[test]
+    fn test_case_insensitive_scope() {
+        let r = Regex::new("a(?i:b[c-e]+)f").unwrap();
+        assert!(r.is_full_match("aBcDef"));
+        assert!(!r.is_full_match("ABcdef"));
+        assert!(!r.is_full_match("abcdeF"));
 
-        let r3 = compile_regex("(1)(2)(3)(4)(5)(6)(7)(8)(9)(10)(11)\\11");
-        assert_eq!(test_string("123456789101111", &r3), Ok(()));
+        // the scope doesn't capture, so (x) is group 1
+        let r = Regex::new("(?i:(x)y)\\1").unwrap();
+        assert_eq!(r.captures_len(), 2);
+        assert!(r.is_full_match("XYx"));
+        assert_eq!(r.captures("XYX").unwrap().get(1).unwrap().as_str(), "X");
+
+        // simple folding: K matches the Kelvin sign, but i and I never match the Turkish
+        // dotted and dotless i
+        let r = Regex::new("(?i:k)").unwrap();
+        assert!(r.is_full_match("K") && r.is_full_match("\u{212A}"));
+        let r = Regex::new("(?i:i)").unwrap();
+        assert!(r.is_full_match("I"));
+        assert!(!r.is_full_match("ı") && !r.is_full_match("İ"));
+        assert!(!Regex::new("(?i:ı)").unwrap().is_full_match("I"));
+        assert!(Regex::new("(?i:σ+)").unwrap().is_full_match("Σς"));
+
+        assert!(Regex::new("(?x:a)").is_err());
     }
 
     #[test]
-    fn test_hex_escape() {
-        let r1 = compile_regex("\\x4E");
+    fn test_longest_common_prefix() {
+        let lcp = |pattern| compile_regex(pattern).longest_common_prefix();
+        assert_eq!(lcp("abc(d|e)f"), "abc");
+        assert_eq!(lcp("ab(cd|ce)"), "abc");
+        assert_eq!(lcp("foo(bar)?"), "foo");
+        assert_eq!(lcp("(ab)+c"), "ab");
+        assert_eq!(lcp("a|b"), "");
+        assert_eq!(lcp("x?y"), "");
 
-        assert_eq!(test_string("N", &r1), Ok(()));
-        assert_eq!(test_string("n", &r1), Err(SimError::NoMatch('n')));
+        // used to skip ahead in searches
+        let r = Regex::new("needle\\d+").unwrap();
+        let hay = "needles and needle7 and needle42";
+        let found: Vec<_> = r.find_iter(hay).map(|m| m.as_str()).collect();
+        assert_eq!(found, ["needle7", "needle42"]);
+        assert!(r.find("needle").is_none());
+    }
 
-        let r2 = compile_regex("\\u006e");
-        assert_eq!(test_string("n", &r2), Ok(()));
-        assert_eq!(test_string("N", &r2), Err(SimError::NoMatch('N')));
+    #[test]
+    fn test_random_walk_trace() {
+        use crate::generate::Rng;
+
+        let dfa = compile_regex("ab(c|.d)*e");
+        let mut rng = Rng::new(7);
+        for len in [0, 3, 10] {
+            let (input, trace) = dfa.random_walk_trace(&mut rng, len).unwrap();
+            assert!(input.chars().count() >= len.max(3));
+            assert_eq!(test_string(&input, &dfa), Ok(()), "{input}");
+            assert!(trace.accepted);
+            assert_eq!(trace.steps.len(), input.chars().count());
+
+            let dot = dfa.to_dot_trace("", &trace);
+            assert!(dot.contains("d0 -> d1 [label = \"'a'\"; color = red; penwidth = 2];"));
+        }
+
+        assert!(compile_regex("(a\\1)")
+            .random_walk_trace(&mut rng, 4)
+            .is_none());
+    }
+
+    #[test]
+    fn test_state_accessors() {
+        use crate::transition_table::NfaState;
+
+        let c = compile("ab*").unwrap();
+        assert!(!c.nfa.is_empty());
+        let start_edges = c.nfa.edges(NfaState::START);
+        assert!(start_edges
+            .iter()
+            .any(|e| e.transition == Transition::Literal('a')));
+        assert_eq!(c.nfa.spans().len(), 2);
+
+        for id in c.dfa.states() {
+            let state = c.dfa.state(id);
+            assert_eq!(state.is_accepting(), c.dfa.is_accepting(id));
+            assert_eq!(
+                state.is_accepting(),
+                state.nfa_states().contains(&NfaState::ACCEPTING)
+            );
+        }
+    }
+
+    #[test]
+    fn test_adjacency() {
+        use crate::dfa::SymbolRange::{Chars, End};
+
+        let dfa = compile_regex("[a-cx]y|.z|q$");
+        let adjacency = dfa.adjacency();
+        assert_eq!(adjacency.len(), dfa.state_count());
+        let (start, edges) = &adjacency[dfa.start().as_usize()];
+        assert_eq!(*start, dfa.start());
+        let ranges: Vec<_> = edges.iter().map(|(r, _)| *r).collect();
+        assert_eq!(
+            ranges,
+            [
+                Chars('\0', '`'),
+                Chars('a', 'c'),
+                Chars('d', 'p'),
+                Chars('q', 'q'),
+                Chars('r', 'w'),
+                Chars('x', 'x'),
+                Chars('y', char::MAX)
+            ]
+        );
+        let to: Vec<_> = edges.iter().map(|(_, to)| *to).collect();
+        assert!([to[2], to[4], to[6]].iter().all(|s| *s == to[0]));
+        assert_eq!(to[1], to[5]);
+        assert_ne!(to[1], to[0]);
+
+        // every character from the start leads where next_state says
+        for c in ['\0', 'b', 'q', 'x', '\u{10FFFF}', '\u{E000}'] {
+            let to = edges
+                .iter()
+                .find(|(r, _)| matches!(r, Chars(a, b) if (*a..=*b).contains(&c)))
+                .map(|(_, to)| *to);
+            assert_eq!(to, dfa.next_state(dfa.start(), c));
+        }
+        let (_, after_q) = &adjacency[to[3].as_usize()];
+        assert!(after_q
+            .iter()
+            .any(|(r, to)| *r == End && dfa.is_accepting(*to)));
+    }
+
+    #[test]
+    fn test_class_items() {
+        use crate::error::ErrorKind;
+
+        let r = Regex::new("[\\d\\s[xy-z][:upper:]_-]+").unwrap();
+        assert!(r.is_full_match("0 9\tzXQ_-"));
+        assert!(!r.is_full_match("a"));
+        assert!(Regex::new("[\\x41-\\x43]").unwrap().is_full_match("B"));
+
+        let kinds =
+            |pattern| -> Vec<_> { check_pattern(pattern).into_iter().map(|e| e.kind).collect() };
+        assert_eq!(kinds("[\\d-a]"), [ErrorKind::ShorthandInRange]);
+        assert_eq!(kinds("[a-\\w]"), [ErrorKind::ShorthandInRange]);
+        assert_eq!(
+            kinds("[[:nope:]a]"),
+            [ErrorKind::UnknownPosixClass("nope".to_string())]
+        );
+        assert_eq!(kinds("[a[b]"), [ErrorKind::UnclosedBracket]);
+        assert_eq!(check_pattern("x[c-a]")[0].span, parse::Span::new(2, 5));
+    }
+
+    #[test]
+    fn test_search_options() {
+        let r = Regex::new("(b)c+").unwrap();
+        let anchored = SearchOptions {
+            anchored: true,
+            ..SearchOptions::default()
+        };
+        let folded = SearchOptions {
+            case_insensitive: true,
+            ..SearchOptions::default()
+        };
+
+        assert_eq!(
+            r.find_with("abcc", SearchOptions::default())
+                .unwrap()
+                .range(),
+            1..4
+        );
+        assert!(r.find_with("abcc", anchored).is_none());
+        assert_eq!(r.find_with("bccd", anchored).unwrap().range(), 0..3);
+        assert!(!r.is_match("xBCc"));
+        assert!(r.is_match_with("xBCc", folded));
+        assert!(!r.is_match_with(
+            "xBCc",
+            SearchOptions {
+                anchored: true,
+                ..folded
+            }
+        ));
+        assert!(r.is_match_with(
+            "BCc",
+            SearchOptions {
+                anchored: true,
+                ..folded
+            }
+        ));
+    }
+
+    #[test]
+    fn test_matches_many() {
+        // the backreference keeps the backtracker in the loop
+        let r = Regex::new("(a+)b\\1").unwrap();
+        let haystacks = ["aba", "xaabaa", "ab", "", "aab"];
+        let expect = [true, true, false, false, false];
+
+        assert_eq!(r.matches_many(haystacks), expect);
+        assert_eq!(r.matches_many_parallel(&haystacks), expect);
+        let many: Vec<_> = (0..100)
+            .map(|i| if i % 3 == 0 { "aba" } else { "b" })
+            .collect();
+        let parallel = r.matches_many_parallel(&many);
+        assert_eq!(
+            parallel,
+            many.iter().map(|h| r.is_match(h)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_cache_per_thread() {
+        let r = Regex::new("(x+)y\\1").unwrap();
+        thread::scope(|scope| {
+            for n in 1..4 {
+                let r = &r;
+                scope.spawn(move || {
+                    let mut cache = Cache::new();
+                    let hay = format!("..{}y{}", "x".repeat(n), "x".repeat(n));
+                    let m = r.find_at_cached(&hay, 0, &mut cache).unwrap();
+                    assert_eq!(m.range(), 2..hay.len());
+                    assert!(r.is_match_cached(&hay, &mut cache));
+                    assert!(!r.is_full_match_cached(&hay, &mut cache));
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_options_round_trip() {
+        let r = RegexBuilder::new("(A|b)+")
+            .char_map(|c| c.to_ascii_lowercase())
+            .full_match(true)
+            .build()
+            .unwrap();
+        let options = r.options();
+        assert_eq!(r.pattern(), "(A|b)+");
+        assert_eq!(r.to_string(), "(A|b)+");
+        assert_eq!(options.get_pattern(), "(A|b)+");
+        assert_eq!(options.get_max_expanded_repeat(), 100);
+        assert_eq!(options.get_engine(), Engine::Auto);
+        assert!(options.get_full_match());
+        assert_eq!(options.get_char_map().unwrap()('Q'), 'q');
+
+        let again = options.build().unwrap();
+        assert_eq!(again.pattern(), r.pattern());
+        for hay in ["ab", "Bab", "abc", ""] {
+            assert_eq!(again.is_match(hay), r.is_match(hay), "{hay}");
+        }
+    }
+
+    #[test]
+    fn test_minimization_steps() {
+        let tokens = try_lex("(a|b)*abb").unwrap();
+        let dfa = Dfa::from_nfa(parse(tokens).unwrap());
+        let steps = dfa.minimization_steps();
+
+        let first = &steps[0];
+        assert_eq!(first.len(), 2);
+        for (before, after) in steps.iter().zip(&steps[1..]) {
+            assert_eq!(after.len(), before.len() + 1);
+        }
+        let mut minimal = dfa.clone();
+        minimal.minimize();
+        assert_eq!(steps.last().unwrap().len(), minimal.state_count());
+
+        // Moore's algorithm can split several blocks per round, and finds the same states
+        let rounds = dfa.minimization_steps_with(Minimizer::Moore);
+        assert_eq!(rounds[0], *first);
+        assert!(rounds.len() <= steps.len());
+        assert_eq!(rounds.last(), steps.last());
+        let mut moore = dfa.clone();
+        moore.minimize_with(Minimizer::Moore);
+        assert!(moore.is_equivalent(&minimal));
+        assert_eq!(moore.state_count(), minimal.state_count());
+
+        // a minimal DFA ends up with one state per block
+        let again = minimal.minimization_steps();
+        assert!(again.last().unwrap().iter().all(|block| block.len() == 1));
+
+        let dot = dfa.to_dot_partition("", first);
+        let accepting = first.iter().find(|b| dfa.is_accepting(b[0])).unwrap();
+        let fill = if accepting == &first[0] { 1 } else { 2 };
+        let node = format!("{} [label", accepting[0]);
+        let line = dot.lines().find(|l| l.starts_with(&node)).unwrap();
+        assert!(
+            line.ends_with(&format!("fillcolor = \"/set312/{fill}\"];")),
+            "{line}"
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        use crate::transition_table::NfaState;
+
+        let nfa = parse(try_lex("(a|b)*abb").unwrap()).unwrap();
+        let dfa = Dfa::from_nfa(nfa.clone());
+        let mut minimal = dfa.clone();
+        minimal.minimize();
+
+        // before minimizing, every state is only the NFA states it was built from
+        for id in dfa.states() {
+            assert_eq!(dfa.merged_states(id), [dfa.state(id).nfa_states()]);
+        }
+
+        let mut merged: Vec<_> = minimal
+            .states()
+            .flat_map(|id| minimal.merged_states(id))
+            .collect();
+        let mut original: Vec<_> = dfa.states().map(|id| dfa.state(id).nfa_states()).collect();
+        merged.sort();
+        original.sort();
+        assert_eq!(merged, original);
+        assert!(minimal
+            .states()
+            .any(|id| minimal.merged_states(id).len() > 1));
+
+        // minimizing again keeps what the first pass merged
+        let mut twice = minimal.clone();
+        twice.minimize();
+        let start = twice.merged_states(twice.start());
+        assert_eq!(start, minimal.merged_states(minimal.start()));
+
+        // the states after each edge of the pattern
+        for &(_, _, state) in nfa.spans().keys() {
+            let ids = minimal.states_with(state);
+            assert!(!ids.is_empty());
+            for id in ids {
+                assert!(minimal.state(id).nfa_states().contains(&state));
+            }
+        }
+        assert_eq!(minimal.states_with(NfaState::START), [minimal.start()]);
+    }
+
+    #[test]
+    fn test_minimize_properties() {
+        use crate::generate::{GenConfig, Generator};
+
+        // every string over the patterns' alphabet and one character they don't use
+        let mut inputs = vec![String::new()];
+        let mut last = inputs.clone();
+        for _ in 0..5 {
+            last = last
+                .iter()
+                .flat_map(|s| ['a', 'b', 'c'].map(|c| format!("{s}{c}")))
+                .collect();
+            inputs.extend(last.clone());
+        }
+        let walk = |dfa: &Dfa, from, input: &str| {
+            let end = input.chars().try_fold(from, |id, c| dfa.next_state(id, c));
+            end.is_some_and(|id| dfa.is_accepting(id))
+        };
+
+        let config = GenConfig {
+            alphabet: vec!['a', 'b'],
+            max_depth: 2,
+            max_atoms: 3,
+            max_repeat: 2,
+            ..GenConfig::default()
+        };
+        let mut generator = Generator::new(config, 1985);
+        for _ in 0..200 {
+            let pattern = generator.pattern();
+            let c = compile(&pattern).unwrap();
+            let (raw, dfa) = (&c.raw_dfa, &c.dfa);
+
+            for input in &inputs {
+                let expect = walk(raw, raw.start(), input);
+                assert_eq!(
+                    walk(dfa, dfa.start(), input),
+                    expect,
+                    "{pattern} on {input}"
+                );
+            }
+
+            // no two states accept the same strings, which with at most 6 states would differ on
+            // one of length 5 or less
+            if dfa.state_count() <= 6 {
+                let mut seen = HashMap::new();
+                for id in dfa.states() {
+                    let accepts: Vec<_> = inputs.iter().map(|i| walk(dfa, id, i)).collect();
+                    if let Some(other) = seen.insert(accepts, id) {
+                        panic!("{pattern}: {other} and {id} are equivalent\n{dfa}");
+                    }
+                }
+            }
+
+            let mut again = dfa.clone();
+            again.minimize();
+            assert_eq!(again.state_count(), dfa.state_count(), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_table_backends() {
+        use crate::id::StateId;
+        use crate::transition_table::TransitionTable;
+
+        let pattern = "(x|y)+[0-3]?\\w|z.";
+        let hash = Regex::new(pattern).unwrap();
+        for backend in [TableBackend::BTree, TableBackend::Dense] {
+            let r = RegexBuilder::new(pattern)
+                .table_backend(backend)
+                .build()
+                .unwrap();
+            assert_eq!(r.dfa().backend(), backend);
+            for id in hash.dfa().states() {
+                assert_eq!(r.dfa().transitions(id), hash.dfa().transitions(id));
+            }
+            for hay in ["xy2a", "zq", "--xx_", "y", ""] {
+                assert_eq!(r.find(hay), hash.find(hay), "{hay}");
+                assert_eq!(r.find_last(hay), hash.find_last(hay), "{hay}");
+            }
+        }
+
+        // the trait works as an object over any backend
+        let mut tables: Vec<Box<dyn TransitionTable<StateId>>> =
+            [TableBackend::Hash, TableBackend::BTree, TableBackend::Dense]
+                .map(|b| b.table::<StateId, StateId>())
+                .into();
+        let s = StateId::new;
+        for table in &mut tables {
+            table.add_transition(s(0), Transition::Literal('b'), s(1));
+            table.add_transition(s(0), Transition::Wildcard, s(2));
+            table.add_transition(s(2), Transition::Literal('a'), s(2));
+            table.rename(s(2), s(3));
+            assert_eq!(
+                table.edges(&s(0)),
+                [
+                    (Transition::Literal('b'), s(1)),
+                    (Transition::Wildcard, s(3))
+                ]
+            );
+            assert_eq!(table.target(&s(3), &Transition::Literal('a')), Some(s(3)));
+            assert!(table.targets(&s(2), &Transition::Literal('a')).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let hay = b"ok \xff\xfe err=\xe2\x82 x";
+        let r = Regex::new("err=.").unwrap();
+        let e = r.find_bytes(hay).unwrap_err();
+        assert_eq!(e.valid_up_to(), 3);
+        assert!(r.is_match_bytes(hay).is_err());
+        assert_eq!(r.find_bytes(b"err=x"), Ok(Some(0..5)));
+
+        let lossy = RegexBuilder::new("err=.")
+            .invalid_utf8(InvalidUtf8::Replace)
+            .build()
+            .unwrap();
+        // the truncated sequence is one U+FFFD, reported with its two original bytes
+        assert_eq!(lossy.find_bytes(hay), Ok(Some(6..12)));
+        let spaced = RegexBuilder::new("\\s.+\\s")
+            .invalid_utf8(InvalidUtf8::Replace)
+            .build()
+            .unwrap();
+        assert_eq!(spaced.find_bytes(hay), Ok(Some(2..13)));
+        assert_eq!(lossy.is_match_bytes(b"\xff"), Ok(false));
+    }
+
+    #[test]
+    fn test_replace_preserving_case() {
+        let r = Regex::new("(?i:colou?r)").unwrap();
+        assert_eq!(
+            r.replace_all_preserving_case("Colour, COLOR or color?", "hue"),
+            "Hue, HUE or hue?"
+        );
+
+        let r = Regex::new("(?<first>\\w+) (\\w+)").unwrap();
+        let caps = r.captures("Ada LOVELACE").unwrap();
+        let mut dst = String::new();
+        caps.expand("$2, ${first} $$5 $3${x}$", &mut dst);
+        assert_eq!(dst, "LOVELACE, Ada $5 $");
+        // mixed case in the match leaves the replacement as it is
+        assert_eq!(
+            r.replace_all_preserving_case("Ada LOVELACE", "$2 $1"),
+            "LOVELACE Ada"
+        );
+        assert_eq!(
+            r.replace_all_preserving_case("ada lovelace", "$2 X"),
+            "lovelace x"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_dot() {
+        let c = compile("a(b|c)*").unwrap();
+        let dot = c.render_pipeline_dot();
+        assert!(dot.starts_with("digraph pipeline {\nsubgraph cluster_nfa {\nlabel = \"NFA\";\n"));
+        assert!(dot.contains("subgraph cluster_raw {\nlabel = \"Unminimized DFA\";\n"));
+        assert!(dot.contains("min_d0 [label = \"d0 {start}\"; shape = circle];\n"));
+        assert_eq!(dot.matches("subgraph").count(), 3);
+
+        // every NFA state a DFA state is labeled with is a node of the NFA cluster
+        let nfa_nodes = c.nfa.state_count();
+        assert!(dot.contains(&format!("nfa_s{} ", nfa_nodes - 3)));
+        assert!(!dot.contains(&format!("s{} ", nfa_nodes - 2)));
+        for id in c.raw_dfa.states() {
+            let line = dot
+                .lines()
+                .find(|l| l.starts_with(&format!("raw_{id} ")))
+                .unwrap();
+            let inner = &line[line.find('{').unwrap() + 1..line.find('}').unwrap()];
+            for name in inner.split(", ") {
+                assert!(dot.contains(&format!("nfa_{name} [label")), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_diff() {
+        let diff = compile("ab|cb").unwrap().merge_diff();
+        assert_eq!(
+            diff.to_string(),
+            "4 states before minimization, 3 after\nmerged d1, d2 into d1\nredirected d0 -'a'-> \
+             d1, now to d1\nredirected d0 -'c'-> d2, now to d1\n"
+        );
+
+        let minimal = compile("abc").unwrap();
+        let diff = minimal.merge_diff();
+        assert!(diff.merges.is_empty() && diff.redirects.is_empty());
+        assert!(minimal.dfa.merge_diff(&compile_regex("x")).is_none());
+    }
+
+    #[test]
+    fn test_alphabet() {
+        for engine in [Engine::Dfa, Engine::LazyDfa, Engine::Backtrack] {
+            let build = |pattern: &str, alphabet| {
+                RegexBuilder::new(pattern)
+                    .alphabet(alphabet)
+                    .engine(engine)
+                    .build()
+                    .unwrap()
+            };
+
+            let ascii = build("a.c", Alphabet::Ascii);
+            assert!(ascii.is_match("abc") && ascii.is_match("a\x7fc"));
+            assert!(!ascii.is_match("aéc"));
+            let latin1 = build("a.c", Alphabet::Latin1);
+            assert!(latin1.is_match("aéc") && !latin1.is_match("a€c"));
+            assert!(build("a.c", Alphabet::Unicode).is_match("a€c"));
+
+            // literals outside the alphabet still match
+            let literal = build("é.", Alphabet::Ascii);
+            assert!(literal.is_match("éx") && !literal.is_match("éé"));
+            assert_eq!(literal.find("ééx").map(|m| m.range()), Some(2..5));
+
+            // a case-insensitive negated class leaves out both cases of what it excludes
+            for alphabet in [Alphabet::Ascii, Alphabet::Latin1] {
+                for pattern in ["(?i:[^a])", "x|(?i:[^aé])"] {
+                    let negated = build(pattern, alphabet);
+                    assert!(
+                        !negated.is_match("a") && !negated.is_match("A"),
+                        "{pattern}"
+                    );
+                    assert!(negated.is_match("b"));
+                }
+                let folded = RegexBuilder::new("[^é]")
+                    .alphabet(alphabet)
+                    .case_insensitive(true)
+                    .engine(engine)
+                    .build()
+                    .unwrap();
+                assert!(!folded.is_match("é") && !folded.is_match("É") && folded.is_match("e"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_quick_reject() {
+        let bounds = |pattern| compile_regex(pattern).byte_len_bounds();
+        assert_eq!(bounds("\\d{5}(-\\d{4})?"), Some((5, Some(10))));
+        assert_eq!(bounds("é."), Some((3, Some(6))));
+        assert_eq!(bounds("ab+|c"), Some((1, None)));
+        assert_eq!(bounds("x*"), Some((0, None)));
+        assert_eq!(bounds("(a\\2)(b)"), None);
+
+        let zip = RegexBuilder::new("\\d{5}(-\\d{4})?")
+            .full_match(true)
+            .build()
+            .unwrap();
+        for (hay, expect) in [
+            ("12345", true),
+            ("12345-6789", true),
+            ("1234", false),
+            ("12345-67890", false),
+            ("x2345", false),
+            ("12345-", false),
+            ("", false),
+        ] {
+            assert_eq!(zip.is_match(hay), expect, "{hay}");
+        }
+        let lower = RegexBuilder::new("ab")
+            .char_map(|c| c.to_ascii_lowercase())
+            .full_match(true)
+            .build()
+            .unwrap();
+        assert!(lower.is_match("AB"));
+        assert!(Regex::new("abc").unwrap().find_at("xxabc", 2).is_some());
+        assert!(Regex::new("abc").unwrap().find_at("xxxab", 2).is_none());
+    }
+
+    #[test]
+    fn test_len_hint() {
+        let phone = Regex::new("\\d{3}-\\d{4}").unwrap();
+        assert_eq!(phone.len_hint(), (8, Some(8)));
+        assert_eq!(phone.fixed_len(), Some(8));
+        assert_eq!(
+            phone.find("call 555-1234 now").map(|m| m.range()),
+            Some(5..13)
+        );
+        let found: Vec<_> = phone
+            .find_iter("1-2 555-0000/555-1111")
+            .map(|m| m.start())
+            .collect();
+        assert_eq!(found, [4, 13]);
+
+        assert_eq!(Regex::new("ab+").unwrap().len_hint(), (2, None));
+        assert_eq!(Regex::new("ab+").unwrap().fixed_len(), None);
+        // fixed in characters but not in bytes
+        let accent = Regex::new("é.").unwrap();
+        assert_eq!(accent.fixed_len(), Some(2));
+        assert_eq!(accent.find("xéé").map(|m| m.range()), Some(1..5));
+        assert_eq!(Regex::new("").unwrap().fixed_len(), Some(0));
+        assert_eq!(
+            Regex::new("a{0}").unwrap().find("").map(|m| m.range()),
+            Some(0..0)
+        );
+        assert_eq!(Regex::new("(a\\2)(b)").unwrap().fixed_len(), None);
+    }
+
+    #[test]
+    fn test_compile_stats() {
+        let c = compile("(?i:a)(b|cd)*").unwrap();
+        let stats = c.stats;
+        assert_eq!(stats.tokens, 8);
+        assert_eq!(stats.raw_dfa_states, c.raw_dfa.state_count());
+        assert_eq!(stats.dfa_states, c.dfa.state_count());
+        assert!(stats.dfa_states <= stats.raw_dfa_states);
+        assert!(stats.nfa_states > stats.raw_dfa_states);
+        assert_eq!(
+            stats.total(),
+            stats.lex + stats.parse + stats.subset_construction + stats.minimization
+        );
+        assert!(stats.to_string().contains(&format!(
+            "minimization: {:?} ({} DFA states)\n",
+            stats.minimization, stats.dfa_states
+        )));
+    }
+
+    #[test]
+    fn test_max_expanded_repeat() {
+        let r = RegexBuilder::new("x\\d{2,150}y|(ab){120}")
+            .max_expanded_repeat(10)
+            .build()
+            .unwrap();
+        assert!(!r.is_exact());
+
+        let digits = |n| "7".repeat(n);
+        assert!(r.is_match(&format!("x{}y", digits(150))));
+        assert!(!r.is_match(&format!("x{}y", digits(151))));
+        assert!(!r.is_match("x1y"));
+        assert_eq!(
+            r.find(&format!("-x{}y-", digits(2))).map(|m| m.range()),
+            Some(1..5)
+        );
+        assert!(r.is_match(&"ab".repeat(120)));
+        assert!(!r.is_match(&"ab".repeat(119)));
+        assert_eq!(
+            r.find_last(&format!("x{}y", digits(160)))
+                .map(|m| m.range()),
+            None
+        );
+        assert_eq!(
+            r.find_last(&format!("x{}y x33y", digits(9)))
+                .map(|m| m.range()),
+            Some(12..16)
+        );
+
+        // small bounds are still expanded, including zero
+        let r2 = Regex::new("a{0}b{0,2}c{0,}").unwrap();
+        assert!(r2.is_exact());
+        assert_eq!(test_string("", r2.dfa()), Ok(()));
+        assert_eq!(test_string("bbccc", r2.dfa()), Ok(()));
+        assert!(test_string("a", r2.dfa()).is_err());
+    }
+
+    #[test]
+    fn test_lazy_fallback() {
+        // the DFA for this has tens of thousands of states, too many to build up front
+        let start = Instant::now();
+        let r = Regex::new("(a|b)*a(a|b){14}").unwrap();
+        assert!(r.is_lazy());
+        // the hints don't build the DFA either
+        assert!(r.first_chars().any && r.last_chars().any);
+        assert_eq!((r.len_hint(), r.fixed_len()), ((0, None), None));
+        let hit = "ba".to_string() + &"b".repeat(14);
+        assert!(r.is_match(&hit));
+        assert!(!r.is_match(&"b".repeat(30)));
+        assert!(r.is_full_match(&hit));
+        assert_eq!(r.find(&(hit.clone() + "x")).map(|m| m.range()), Some(0..16));
+        assert_eq!(r.find_last(&hit).map(|m| m.range()), Some(0..16));
+        assert!(r.dfa.get().is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // a small pattern still gets its DFA with the Regex
+        assert!(!Regex::new("(a|b)*a(a|b){3}").unwrap().is_lazy());
+        let r = RegexBuilder::new("(a|b)*a(a|b){3}")
+            .dfa_state_limit(4)
+            .build()
+            .unwrap();
+        assert!(r.is_lazy());
+        assert_eq!(r.find("xxabbb").map(|m| m.range()), Some(2..6));
+    }
+
+    #[test]
+    fn test_first_last_chars() {
+        let r = Regex::new("(ab|c)x*[yz]|d").unwrap();
+
+        let first = r.first_chars();
+        assert_eq!(first.chars, "acd".chars().collect());
+        assert!(!first.any);
+        assert_eq!(r.last_chars().chars, "dyz".chars().collect());
+        assert_eq!(r.find("qqqcxxz").map(|m| m.range()), Some(3..7));
+
+        let r2 = Regex::new("a.").unwrap();
+        assert!(r2.last_chars().any && r2.last_chars().contains('#'));
+        assert!(!r2.first_chars().contains('#'));
+    }
+
+    #[test]
+    fn test_batch_verification() {
+        let zip = compile_regex("\\d{5}(-\\d{4})?");
+
+        let report = zip.accepts_all_of(&["12345", "12345-6789", "1234", "12345-67x9"]);
+        assert!(!report.passed());
+        assert_eq!(report.failures.len(), 2);
+        assert_eq!(report.failures[1].index, 3);
+        assert_eq!(
+            report.to_string(),
+            "2 of 4 strings failed:\n  [2] \"1234\": input ended early, expected '0' or '1' or '2' \
+             or '3' or '4' or '5' or '6' or '7' or '8' or '9'\n  [3] \"12345-67x9\": unexpected 'x' \
+             at 8, expected '0' or '1' or '2' or '3' or '4' or '5' or '6' or '7' or '8' or '9'\n"
+        );
+
+        let report = zip.rejects_all_of(&["", "00000", "123456"]);
+        assert_eq!(
+            report.to_string(),
+            "1 of 3 strings failed:\n  [1] \"00000\": accepted\n"
+        );
+        assert!(zip.accepts_all_of(&["00000"]).passed());
     }
 }
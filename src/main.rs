@@ -1,16 +1,15 @@
-mod dfa;
-mod nfa;
-mod parse;
-mod transition_table;
-
+use std::fs;
 use std::io::Write;
 use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
 
 use colored::Colorize;
 use text_io::read;
 
-use dfa::Dfa;
-use parse::{lex, parse};
+use regex_rs::dfa::{Minimizer, Partition};
+use regex_rs::session::Session;
+use regex_rs::{compile, conformance, diff, explain, generate, Compilation, Regex, RegexBuilder};
 
 fn show_dot(dot_file: String) -> Child {
     let mut dot_cmd = Command::new("dot")
@@ -27,10 +26,11 @@ fn show_dot(dot_file: String) -> Child {
     dot_cmd
 }
 
+// render to filename, in the format its extension names (png or svg)
 fn write_dot(filename: &str, dot_file: String) {
-    #[allow(clippy::zombie_processes)]
+    let format = filename.rsplit('.').next().unwrap_or("png");
     let mut dot_cmd = Command::new("dot")
-        .args(["-T", "png", "-o", filename])
+        .args(["-T", format, "-o", filename])
         .stdin(Stdio::piped())
         .spawn()
         .expect("Failed to spawn dot process");
@@ -39,64 +39,287 @@ fn write_dot(filename: &str, dot_file: String) {
     stdin
         .write_all(dot_file.as_bytes())
         .expect("Failed to write to stdin");
+    drop(stdin);
+    dot_cmd.wait().expect("dot child command wasn't running");
+}
+
+fn write_pngs(compilation: &Compilation) {
+    write_dot(
+        "./dfa_nonmin.png",
+        compilation.raw_dfa.to_dot("Unminimized DFA"),
+    );
+    write_dot("./nfa.png", compilation.nfa.to_dot());
+    write_dot(
+        "./dfa_min.png",
+        compilation
+            .dfa
+            .to_dot("DFA minimized with Hopcroft's algorithm"),
+    );
+}
+
+// the stages of compilation in one window, side by side, and as PNGs if asked
+fn show(compilation: &Compilation, should_write: bool) -> Child {
+    let child = show_dot(compilation.render_pipeline_dot());
+    if should_write {
+        write_pngs(compilation);
+    }
+    child
+}
+
+// Recompile the pattern in path whenever the file changes, rewriting the PNGs and pipeline.svg
+// and printing the compile statistics, until interrupted
+fn watch(path: &str) -> ! {
+    let mut last = None;
+    loop {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified != last {
+            last = modified;
+            match fs::read_to_string(path) {
+                Ok(text) => {
+                    // the pattern is the file's first line
+                    let pattern = text.lines().next().unwrap_or("");
+                    println!("{}{pattern}", "Pattern: ".green().bold());
+                    match compile(pattern) {
+                        Ok(compilation) => {
+                            write_pngs(&compilation);
+                            write_dot("./pipeline.svg", compilation.render_pipeline_dot());
+                            print!("{}", compilation.stats);
+                        }
+                        Err(e) => println!("{}{e}", "Error: ".red()),
+                    }
+                }
+                Err(e) => println!("{}{e}", "Error: ".red()),
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+// a partition as its blocks of states, e.g. {d0 d2} {d1}
+fn partition_text(partition: &Partition) -> String {
+    let blocks: Vec<String> = partition
+        .iter()
+        .map(|block| {
+            let states: Vec<String> = block.iter().map(|s| s.to_string()).collect();
+            format!("{{{}}}", states.join(" "))
+        })
+        .collect();
+    blocks.join(" ")
+}
+
+// Minimize the DFA for pattern with the algorithm args name, writing it before and after to
+// dfa_before.dot and dfa_after.dot, and print each step of the refinement if asked
+fn minimize(args: &[String]) {
+    let Some(pattern) = args.get(2) else {
+        eprintln!(
+            "{}usage: regex-rs minimize PATTERN [--algorithm hopcroft|moore] [--show-steps]",
+            "Error: ".red()
+        );
+        std::process::exit(1);
+    };
+    let minimizer = match args.iter().position(|a| a == "--algorithm") {
+        None => Minimizer::Hopcroft,
+        Some(i) => match args.get(i + 1).map(String::as_str) {
+            Some("hopcroft") => Minimizer::Hopcroft,
+            Some("moore") => Minimizer::Moore,
+            _ => {
+                eprintln!("{}--algorithm is hopcroft or moore", "Error: ".red());
+                std::process::exit(1);
+            }
+        },
+    };
+    let compilation = compile(pattern).unwrap_or_else(|e| {
+        eprintln!("{}{e}", "Error: ".red());
+        std::process::exit(1);
+    });
+
+    let before = compilation.raw_dfa;
+    if args.contains(&String::from("--show-steps")) {
+        for (i, step) in before.minimization_steps_with(minimizer).iter().enumerate() {
+            println!("{}{}", format!("step {i}: ").bold(), partition_text(step));
+        }
+    }
+    let mut after = before.clone();
+    after.minimize_with(minimizer);
+    println!(
+        "{} states before, {} after ({minimizer:?})",
+        before.state_count(),
+        after.state_count()
+    );
+
+    let label = format!("DFA minimized with {minimizer:?}'s algorithm");
+    for (path, dot) in [
+        ("./dfa_before.dot", before.to_dot("Unminimized DFA")),
+        ("./dfa_after.dot", after.to_dot(&label)),
+    ] {
+        if let Err(e) = fs::write(path, dot) {
+            eprintln!("{}{path}: {e}", "Error: ".red());
+            std::process::exit(1);
+        }
+    }
+}
+
+// whether the whole of input matches, with the session's options, and if not why not
+fn check(regex: &Regex, input: &str) {
+    if regex.is_full_match(input) {
+        println!("{}accepted", "Output: ".green());
+        return;
+    }
+    match regex.dfa().simulate(input.to_string()) {
+        Err(e) => println!("{}{e}", "Output: ".red()),
+        // only the backtracker tells backreferences, large repetitions and \b apart
+        Ok(_) => println!("{}rejected", "Output: ".red()),
+    }
 }
 
 fn main() {
-    let args = std::env::args();
-    let should_write = args
-        .collect::<Vec<_>>()
-        .contains(&String::from("--output-png"));
+    let args = std::env::args().collect::<Vec<_>>();
+    let should_write = args.contains(&String::from("--output-png"));
 
-    // parse regex
-    let toks = lex(read!("{}\n"));
+    // check the engine against the bundled corpus instead of reading a pattern
+    if args.contains(&String::from("--selftest")) {
+        let report = conformance::run();
+        print!("{report}");
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
 
-    let nfa = parse(toks);
+    // compare two patterns, exiting with 1 if they differ like diff(1)
+    if args.get(1).is_some_and(|a| a == "diff") {
+        let (Some(old), Some(new)) = (args.get(2), args.get(3)) else {
+            eprintln!("{}usage: regex-rs diff OLD NEW", "Error: ".red());
+            std::process::exit(2);
+        };
+        match diff::diff(old, new) {
+            Ok(d) => {
+                print!("{d}");
+                if should_write {
+                    write_dot("./diff.png", d.to_dot());
+                }
+                std::process::exit(if d.is_equivalent() { 0 } else { 1 });
+            }
+            Err(e) => {
+                eprintln!("{}{e}", "Error: ".red());
+                std::process::exit(2);
+            }
+        }
+    }
 
-    let mut dfa = Dfa::from_nfa(nfa.clone());
-    let mut dfa_non_min_child = show_dot(dfa.to_dot("Unminimized DFA"));
-    if should_write {
-        write_dot("./dfa_nonmin.png", dfa.to_dot("Unminimized DFA"));
+    if args.get(1).is_some_and(|a| a == "minimize") {
+        minimize(&args);
+        return;
     }
 
-    dfa.minimize();
+    if let Some(i) = args.iter().position(|a| a == "--watch") {
+        let Some(path) = args.get(i + 1) else {
+            eprintln!("{}--watch needs a pattern file", "Error: ".red());
+            std::process::exit(1);
+        };
+        watch(path);
+    }
 
-    let mut nfa_child = show_dot(nfa.to_dot());
-    if should_write {
-        write_dot("./nfa.png", nfa.to_dot());
+    // compile regex
+    let pattern: String = read!("{}\n");
+
+    // describe each element of the pattern instead of drawing its automata
+    if args.contains(&String::from("--explain")) {
+        match explain::explain(&pattern) {
+            Ok(explanations) => explanations.iter().for_each(|e| println!("{e}")),
+            Err(e) => {
+                eprintln!("{}{e}", "Error: ".red());
+                std::process::exit(1);
+            }
+        }
+        return;
     }
 
-    let mut dfa_child = show_dot(dfa.to_dot("DFA minimized with Hopcroft's algorithm"));
-    if should_write {
-        write_dot(
-            "./dfa_min.png",
-            dfa.to_dot("DFA minimized with Hopcroft's algorithm"),
-        );
+    let compilation = compile(&pattern).unwrap_or_else(|e| {
+        eprintln!("{}{e}", "Error: ".red());
+        std::process::exit(1);
+    });
+    if args.contains(&String::from("--stats")) {
+        print!("{}", compilation.stats);
     }
+    if args.contains(&String::from("--merges")) {
+        print!("{}", compilation.merge_diff());
+    }
+    let mut child = show(&compilation, should_write);
+    let mut session = Session::new(RegexBuilder::new(&pattern));
+    let mut regex = session.options.build().unwrap_or_else(|e| {
+        eprintln!("{}{e}", "Error: ".red());
+        std::process::exit(1);
+    });
 
     // TUI
     print!("{}", "> ".green().bold());
     let mut input: String = read!("{}\n");
 
+    let mut seed = 0;
     while input != "exit" {
-        let sim = dfa.simulate(input);
-        match sim {
-            Ok(_) => println!("{}{:?}", "Output: ".green(), sim),
-            Err(_) => println!("{}{:?}", "Output: ".red(), sim),
+        match input.as_str() {
+            // strings to sanity-check the pattern with, different ones each time
+            ":examples" => {
+                let pattern = session.options.get_pattern();
+                let examples = generate::examples(pattern, 5, seed).expect("the pattern compiled");
+                seed += 1;
+                for s in examples.accepted {
+                    println!("{}{s:?}", "accepted: ".green());
+                }
+                for (s, e) in examples.rejected {
+                    println!("{}{s:?}: {e}", "rejected: ".red());
+                }
+            }
+            // change an option the way a session file writes it, e.g. :set case_insensitive = true
+            _ if input.starts_with(":set ") => {
+                let mut changed = session.clone();
+                let built = changed
+                    .set(&input[5..])
+                    .map_err(|e| e.to_string())
+                    .and_then(|()| changed.options.build().map_err(|e| e.to_string()));
+                match built {
+                    Ok(built) => {
+                        regex = built;
+                        session = changed;
+                    }
+                    Err(e) => println!("{}{e}", "Error: ".red()),
+                }
+            }
+            _ if input.starts_with(":save ") => match session.save(&input[6..]) {
+                Ok(()) => println!("saved to {}", &input[6..]),
+                Err(e) => println!("{}{e}", "Error: ".red()),
+            },
+            // switch to a saved pattern and its options, and check its strings again
+            _ if input.starts_with(":load ") => match Session::load(&input[6..]) {
+                Ok(loaded) => match loaded.options.build() {
+                    Ok(loaded_regex) => {
+                        // the drawing shows the pattern's automata without the options
+                        if let Ok(loaded_compilation) = compile(loaded.options.get_pattern()) {
+                            child.kill().expect("Failed to kill dot child");
+                            child.wait().expect("dot child command wasn't running");
+                            child = show(&loaded_compilation, should_write);
+                        }
+                        regex = loaded_regex;
+                        session = loaded;
+
+                        println!("pattern: {}", session.options.get_pattern());
+                        for s in &session.history {
+                            print!("{s:?} ");
+                            check(&regex, s);
+                        }
+                    }
+                    Err(e) => println!("{}{e}", "Error: ".red()),
+                },
+                Err(e) => println!("{}{e}", "Error: ".red()),
+            },
+            _ => {
+                check(&regex, &input);
+                session.push(&input);
+            }
         }
         print!("{}", "> ".green().bold());
         input = read!("{}\n");
     }
 
     // subprocess cleanup
-    nfa_child.kill().expect("Failed to kill nfa child");
-    dfa_child.kill().expect("Failed to kill dfa child");
-    dfa_non_min_child
-        .kill()
-        .expect("Failed to kill dfa non-minimized child");
-
-    nfa_child.wait().expect("nfa_child command wasn't running");
-    dfa_child.wait().expect("dfa_child command wasn't running");
-    dfa_non_min_child
-        .wait()
-        .expect("dfa_non_min_child command wasn't running");
+    child.kill().expect("Failed to kill dot child");
+    child.wait().expect("dot child command wasn't running");
 }
@@ -0,0 +1,190 @@
+use std::borrow::Cow;
+use std::str::Utf8Error;
+
+use crate::backtrack::Scratch;
+use crate::dfa::{CharSet, Dfa};
+use crate::lazy_dfa::LazyCache;
+use crate::CharMap;
+
+// Which backend decides whether a Regex matches. Captures and match bounds always come from the
+// backtracker, since the DFA only knows whether a match ends somewhere, not where leftmost-first
+// matching would put it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    // The DFA when it recognizes the pattern exactly, or the LazyDfa if the DFA would be over
+    // the builder's dfa_state_limit, and the backtracker otherwise
+    #[default]
+    Auto,
+    // the DFA alone, in time linear in the haystack. Patterns with repetitions over the
+    // builder's max_expanded_repeat or with \b or \B are rejected, since their DFA is only a
+    // superset.
+    Dfa,
+    // A LazyDfa alone, which builds the states each search reaches instead of the whole DFA up
+    // front. Also linear in the haystack, and rejects the same patterns as Dfa.
+    LazyDfa,
+    // the backtracker confirms every DFA hit, with the DFA only ruling out haystacks early
+    Backtrack,
+}
+
+impl Engine {
+    // the engine to use for a pattern whose automata are exact or not, and whose DFA fits in
+    // the state limit or not
+    pub(crate) fn resolve(self, exact: bool, fits: bool) -> Self {
+        match self {
+            Self::Auto if exact && fits => Self::Dfa,
+            Self::Auto if exact => Self::LazyDfa,
+            Self::Auto => Self::Backtrack,
+            engine => engine,
+        }
+    }
+}
+
+// Per-call overrides for a search, so one compiled pattern can serve callers with different needs.
+// There is no multiline mode: ^ and $ only ever match at the start and end of the haystack, not
+// around line breaks inside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SearchOptions {
+    // the match has to start at the beginning of the haystack
+    pub anchored: bool,
+    // Match regardless of case, as RegexBuilder::case_insensitive does. The folded pattern is
+    // compiled the first time a search asks for it and kept for later ones.
+    pub case_insensitive: bool,
+}
+
+// Mutable buffers for searches, kept apart from the Regex so that it stays immutable and can be
+// shared between threads without locks. Each thread makes its own cache and passes it to the
+// *_cached methods, which then don't allocate them again for every search. The other methods
+// make a fresh cache per call. They keep the backtracker's buffers, and the states of the
+// LazyDfa that a Regex whose DFA would have been too big matches with (as does a LazyDfa
+// searched through Simulator).
+#[derive(Debug, Default)]
+pub struct Cache {
+    pub(crate) scratch: Scratch,
+    pub(crate) lazy: LazyCache,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// What the *_bytes searches do with a haystack that isn't valid UTF-8. The automata work on
+// chars, so there is no mode that matches the invalid bytes themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InvalidUtf8 {
+    // fail with the position of the first invalid sequence
+    #[default]
+    Reject,
+    // Match each invalid sequence as U+FFFD, like String::from_utf8_lossy, so e.g. . matches it.
+    // Reported offsets are still those of the original bytes.
+    Replace,
+}
+
+// a byte haystack as text, with the offset in the bytes of every offset in the text
+pub(crate) struct Decoded<'h> {
+    pub text: Cow<'h, str>,
+    offsets: Option<Vec<usize>>,
+}
+
+impl<'h> Decoded<'h> {
+    pub fn new(bytes: &'h [u8], policy: InvalidUtf8) -> Result<Self, Utf8Error> {
+        let e = match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                return Ok(Self {
+                    text: Cow::Borrowed(text),
+                    offsets: None,
+                });
+            }
+            Err(e) => e,
+        };
+        if policy == InvalidUtf8::Reject {
+            return Err(e);
+        }
+
+        let mut text = String::new();
+        let mut offsets = Vec::new();
+        let mut at = 0;
+        for chunk in bytes.utf8_chunks() {
+            text.push_str(chunk.valid());
+            offsets.extend(at..at + chunk.valid().len());
+            at += chunk.valid().len();
+
+            if !chunk.invalid().is_empty() {
+                text.push(char::REPLACEMENT_CHARACTER);
+                offsets.extend([at; 3]);
+                at += chunk.invalid().len();
+            }
+        }
+        offsets.push(at);
+
+        Ok(Self {
+            text: Cow::Owned(text),
+            offsets: Some(offsets),
+        })
+    }
+
+    // the offset in the bytes of an offset in text
+    pub fn offset(&self, i: usize) -> usize {
+        self.offsets.as_ref().map_or(i, |offsets| offsets[i])
+    }
+}
+
+// What every match looks like from the outside, checked in constant time before a search so
+// that haystacks which can't match, like a postal code of the wrong length, are turned down
+// without running an automaton
+#[derive(Debug, Clone)]
+pub(crate) struct QuickReject {
+    // byte lengths, unknown under a char map that may change them
+    min_len: usize,
+    max_len: Option<usize>,
+    // the characters a non-empty match starts and ends with
+    first: CharSet,
+    last: CharSet,
+    char_map: CharMap,
+}
+
+impl QuickReject {
+    pub fn new(dfa: &Dfa, char_map: Option<CharMap>) -> Self {
+        let (min_len, max_len) = match char_map {
+            Some(_) => (0, None),
+            None => dfa.byte_len_bounds().unwrap_or((usize::MAX, Some(0))),
+        };
+
+        Self {
+            min_len,
+            max_len,
+            first: dfa.first_chars(),
+            last: dfa.last_chars(),
+            char_map: char_map.unwrap_or(std::convert::identity),
+        }
+    }
+
+    // one that turns nothing down, for a pattern whose DFA wasn't built
+    pub fn unknown(char_map: Option<CharMap>) -> Self {
+        Self {
+            min_len: 0,
+            max_len: None,
+            first: CharSet::everything(),
+            last: CharSet::everything(),
+            char_map: char_map.unwrap_or(std::convert::identity),
+        }
+    }
+
+    // whether haystack is too short to contain a match
+    pub fn rejects(&self, haystack: &str) -> bool {
+        haystack.len() < self.min_len
+    }
+
+    // whether haystack as a whole can't be a match, going by its length and its first and last
+    // characters
+    pub fn rejects_full(&self, haystack: &str) -> bool {
+        let fits =
+            |c: Option<char>, set: &CharSet| c.is_none_or(|c| set.contains((self.char_map)(c)));
+
+        self.rejects(haystack)
+            || self.max_len.is_some_and(|max| haystack.len() > max)
+            || !fits(haystack.chars().next(), &self.first)
+            || !fits(haystack.chars().next_back(), &self.last)
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use crate::error::RegexError;
+use crate::parse::{try_lex, ParseElement, Span, Token};
+use crate::try_compile_regex;
+
+// How a Mutant differs from the pattern it was made from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MutationKind {
+    // a bound of a repetition moved by one, e.g. {3,5} to {2,5}
+    Bound,
+    // *, + or ? replaced by another of them
+    Quantifier,
+    // one alternative of a | left out
+    DroppedAlternative,
+}
+
+// A pattern a typo away from another one, and a string that tells them apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutant {
+    pub pattern: String,
+    pub kind: MutationKind,
+    // the part of the original pattern that was changed
+    pub span: Span,
+    // A shortest string exactly one of the two patterns matches in full. None if they match the
+    // same strings, so no test can tell them apart.
+    pub witness: Option<String>,
+}
+
+impl fmt::Display for Mutant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.witness {
+            Some(witness) => write!(f, "{}: told apart by {witness:?}", self.pattern),
+            None => write!(f, "{}: equivalent", self.pattern),
+        }
+    }
+}
+
+// the quantifier written as text, lazy or not
+fn quantifier(elem: &ParseElement, lazy: bool) -> String {
+    let text = match elem {
+        ParseElement::Star => "*".to_string(),
+        ParseElement::Plus => "+".to_string(),
+        ParseElement::Question => "?".to_string(),
+        ParseElement::Range(min, max) if min == max => format!("{{{min}}}"),
+        ParseElement::Range(min, max) => format!("{{{min},{max}}}"),
+        ParseElement::OpenRange(min) => format!("{{{min},}}"),
+        _ => unreachable!("not a quantifier"),
+    };
+    if lazy {
+        text + "?"
+    } else {
+        text
+    }
+}
+
+// the quantifiers a typo could turn elem into
+fn mutated_quantifiers(elem: &ParseElement) -> Vec<(MutationKind, ParseElement)> {
+    use MutationKind::*;
+    use ParseElement::*;
+
+    match *elem {
+        Star => vec![(Quantifier, Plus), (Quantifier, Question)],
+        Plus => vec![(Quantifier, Star), (Quantifier, Question)],
+        Question => vec![(Quantifier, Star), (Quantifier, Plus)],
+        Range(min, max) => {
+            let mut bounds = vec![(min + 1, max.max(min + 1)), (min, max + 1)];
+            if min > 0 {
+                bounds.push((min - 1, if min == max { min - 1 } else { max }));
+            }
+            if max > min {
+                bounds.push((min, max - 1));
+            }
+            bounds.sort();
+            bounds.dedup();
+            bounds
+                .into_iter()
+                .map(|(min, max)| (Bound, Range(min, max)))
+                .collect()
+        }
+        OpenRange(min) => {
+            let mut mins = vec![min + 1];
+            mins.extend(min.checked_sub(1));
+            mins.into_iter()
+                .map(|min| (Bound, OpenRange(min)))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// every mutation of the tokens, and of the tokens inside their groups, as (kind, span, text)
+fn mutations(toks: &[Token], out: &mut Vec<(MutationKind, Span, String)>) {
+    for tok in toks {
+        match &tok.elem {
+            ParseElement::Group(inner, _) | ParseElement::CaseInsensitive(inner) => {
+                mutations(inner, out)
+            }
+            elem if elem.is_modifier() => {
+                let lazy = matches!(elem, ParseElement::Lazy(_));
+                for (kind, mutated) in mutated_quantifiers(elem.greedy()) {
+                    out.push((kind, tok.span, quantifier(&mutated, lazy)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // leave out each alternative with the | that separates it from its neighbor
+    let unions: Vec<Span> = toks
+        .iter()
+        .filter(|t| matches!(t.elem, ParseElement::Union))
+        .map(|t| t.span)
+        .collect();
+    for i in 0..unions.len() + 1 {
+        if unions.is_empty() {
+            break;
+        }
+        let span = if i == 0 {
+            Span::new(toks[0].span.start, unions[0].end)
+        } else if i < unions.len() {
+            Span::new(unions[i - 1].start, unions[i].start)
+        } else {
+            let end = toks.last().map_or(0, |t| t.span.end);
+            Span::new(unions[i - 1].start, end.max(unions[i - 1].end))
+        };
+        out.push((MutationKind::DroppedAlternative, span, String::new()));
+    }
+}
+
+// Patterns close to pattern, each with a typo a test suite should catch: a repetition bound off
+// by one, a quantifier swapped for another, or an alternative left out. Each comes with a
+// shortest string that the pattern and the mutant disagree on (whether it matches in full), to
+// add to the tests if they don't catch the mutant yet. Mutants that don't compile are left out.
+pub fn mutate(pattern: &str) -> Result<Vec<Mutant>, RegexError> {
+    let toks = try_lex(pattern)?;
+    let original = try_compile_regex(pattern)?;
+
+    let mut found = Vec::new();
+    mutations(&toks, &mut found);
+    found.sort_by_key(|(kind, span, _)| (*span, *kind));
+
+    let mut mutants: Vec<Mutant> = Vec::new();
+    for (kind, span, text) in found {
+        let mutated = format!("{}{text}{}", &pattern[..span.start], &pattern[span.end..]);
+        if mutated == pattern || mutants.iter().any(|m| m.pattern == mutated) {
+            continue;
+        }
+        let Ok(dfa) = try_compile_regex(&mutated) else {
+            continue;
+        };
+        mutants.push(Mutant {
+            witness: original.distinguishing_string(&dfa),
+            pattern: mutated,
+            kind,
+            span,
+        });
+    }
+
+    Ok(mutants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_regex;
+    use crate::test_string;
+
+    #[test]
+    fn test_mutate() {
+        let mutants = mutate("a{2,3}(b+|c)").unwrap();
+        let patterns: Vec<_> = mutants.iter().map(|m| m.pattern.as_str()).collect();
+        assert_eq!(
+            patterns,
+            [
+                "a{1,3}(b+|c)",
+                "a{2}(b+|c)",
+                "a{2,4}(b+|c)",
+                "a{3}(b+|c)",
+                "a{2,3}(c)",
+                "a{2,3}(b*|c)",
+                "a{2,3}(b?|c)",
+                "a{2,3}(b+)",
+            ]
+        );
+        assert!(mutants[4..].iter().all(|m| m.span.start >= 6));
+        assert_eq!(mutants[0].kind, MutationKind::Bound);
+        assert_eq!(mutants[4].kind, MutationKind::DroppedAlternative);
+        assert_eq!(mutants[5].kind, MutationKind::Quantifier);
+
+        // each witness is matched by exactly one of the two patterns
+        let original = compile_regex("a{2,3}(b+|c)");
+        for m in &mutants {
+            let witness = m.witness.as_deref().unwrap();
+            let mutant = compile_regex(&m.pattern);
+            assert_ne!(
+                test_string(witness, &original).is_ok(),
+                test_string(witness, &mutant).is_ok(),
+                "{m}"
+            );
+        }
+        assert_eq!(mutants[0].witness.as_deref(), Some("ab"));
+
+        // a lazy quantifier stays lazy, and equivalent mutants have no witness
+        let mutants = mutate("(a*)*?").unwrap();
+        assert!(mutants
+            .iter()
+            .any(|m| m.pattern == "(a*)+?" && m.witness.is_none()));
+        assert!(mutate("(").is_err());
+    }
+}
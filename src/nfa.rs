@@ -1,87 +1,233 @@
-use std::collections::{hash_map::Entry, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 
 use crate::{
-    parse::ParseElement,
-    transition_table::{NfaState, Transition, TransitionTable},
+    captures::Match,
+    dot::{edge_labels, nfa_names, quote, DotOptions},
+    parse::{ParseElement, Span},
+    sparse_set::SparseSet,
+    transition_table::{Index, NfaState, Transition},
 };
 
+// the empty edges that can be crossed at the start and at the end of the input, see closure
+pub const AT_START: [Transition; 2] = [Transition::Epsilon, Transition::StartAnchor];
+pub const AT_END: [Transition; 2] = [Transition::Epsilon, Transition::EndAnchor];
+
+// An edge out of an NFA state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub transition: Transition,
+    pub end: NfaState,
+    // When the edge was built, which decides the order a leftmost-first search tries the edges
+    // out of a state in (see prioritized_edges). The automaton forgets the order alternatives
+    // and repetitions were written in, so it is kept here: an edge built earlier ranks lower,
+    // which puts an alternative before the ones after it and a greedy repetition's way back into
+    // its body before its exit.
+    pub rank: i64,
+    // whether a lazy repetition added the edge, which ranks it before every edge that isn't,
+    // and before the lazy edges built earlier than it
+    pub lazy: bool,
+    // the pattern region a consuming edge was built from
+    pub span: Option<Span>,
+}
+
+// A Thompson NFA kept as an arena: the edges out of each state in a Vec indexed by NfaState, with
+// START and ACCEPTING first. Composing two NFAs moves one's states in after the other's, so
+// states never need renaming.
 #[derive(Debug, Clone)]
 pub struct Nfa {
-    pub transitions: HashMap<NfaState, HashMap<Transition, Vec<NfaState>>>,
-    pub empty: bool,
+    states: Vec<Vec<Edge>>,
+    // the edges into ACCEPTING, as (state, position among its edges), so that concatenating onto
+    // it doesn't look through every state. ACCEPTING has no edges out of it, and START none in.
+    into_accepting: Vec<(NfaState, usize)>,
+    // nothing has been added yet, see empty()
+    empty: bool,
+    // the ranks of the edges are first_rank..next_rank, so that composing NFAs can shift one's
+    // ranks past the other's
+    first_rank: i64,
+    next_rank: i64,
+    // whether edges added now belong to a lazy repetition, see add_modifier
+    lazy: bool,
 }
 
 impl Nfa {
+    // every state, START and ACCEPTING first
+    pub fn states(&self) -> impl Iterator<Item = NfaState> + '_ {
+        (0..self.states.len()).map(NfaState::from_index)
+    }
+
+    // the edges out of state, in the order they were added
+    pub fn edges(&self, state: NfaState) -> &[Edge] {
+        &self.states[state.index()]
+    }
+
+    // the pattern region of each consuming edge, by (start, transition, end)
+    pub fn spans(&self) -> HashMap<(NfaState, Transition, NfaState), Span> {
+        let mut spans = HashMap::new();
+        for start in self.states() {
+            for edge in self.edges(start) {
+                if let Some(span) = edge.span {
+                    spans.insert((start, edge.transition, edge.end), span);
+                }
+            }
+        }
+        spans
+    }
+
+    pub fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    // whether this is still the identity from empty(), with nothing concatenated to it
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
     pub fn empty() -> Self {
         Self {
-            transitions: HashMap::new(),
             empty: true,
+            ..Self::never()
         }
     }
 
-    pub fn new(edge: Transition, modifier: Option<ParseElement>) -> Self {
-        let mut ret = Self {
-            transitions: HashMap::from([(
-                NfaState::Start,
-                HashMap::from([(edge, vec![NfaState::Accepting])]),
-            )]),
+    // accepts only the empty string, unlike empty() which is just the identity for concat
+    pub fn epsilon() -> Self {
+        Self::new(Transition::Epsilon, None)
+    }
+
+    // an empty alternative, as in a| or (), has to match the empty string on its own
+    pub fn or_epsilon(self) -> Self {
+        if self.empty {
+            Self::epsilon()
+        } else {
+            self
+        }
+    }
+
+    // accepts nothing
+    pub fn never() -> Self {
+        Self {
+            states: vec![Vec::new(), Vec::new()],
+            into_accepting: Vec::new(),
             empty: false,
-        };
+            first_rank: 1,
+            next_rank: 1,
+            lazy: false,
+        }
+    }
 
+    pub fn new(edge: Transition, modifier: Option<ParseElement>) -> Self {
+        let mut ret = Self::never();
+        ret.add_edge(NfaState::START, edge, NfaState::ACCEPTING);
         ret.add_modifier(modifier);
 
         ret
     }
 
+    // a new state without any edges
+    fn add_state(&mut self) -> NfaState {
+        self.states.push(Vec::new());
+        NfaState::from_index(self.states.len() - 1)
+    }
+
+    // add an edge, ranked after every edge built before it
+    fn add_edge(&mut self, start: NfaState, transition: Transition, end: NfaState) {
+        let rank = self.next_rank;
+        self.next_rank += 1;
+        self.push_edge(
+            start,
+            Edge {
+                transition,
+                end,
+                rank,
+                lazy: self.lazy,
+                span: None,
+            },
+        );
+    }
+
+    fn push_edge(&mut self, start: NfaState, edge: Edge) {
+        let edges = &mut self.states[start.index()];
+        if edge.end == NfaState::ACCEPTING {
+            self.into_accepting.push((start, edges.len()));
+        }
+        edges.push(edge);
+    }
+
+    // point every edge into ACCEPTING at state instead
+    fn redirect_accepting(&mut self, state: NfaState) {
+        for (start, i) in std::mem::take(&mut self.into_accepting) {
+            self.states[start.index()][i].end = state;
+        }
+    }
+
+    // Move START's edges to a new state and point the edges into ACCEPTING at another new one,
+    // which are returned, leaving START and ACCEPTING free to wrap the NFA in a repetition
+    fn detach(&mut self) -> (NfaState, NfaState) {
+        let start_state = self.add_state();
+        let final_state = self.add_state();
+        self.states
+            .swap(NfaState::START.index(), start_state.index());
+        for (start, _) in &mut self.into_accepting {
+            if *start == NfaState::START {
+                *start = start_state;
+            }
+        }
+        self.redirect_accepting(final_state);
+
+        (start_state, final_state)
+    }
+
     pub fn add_modifier(&mut self, modifier: Option<ParseElement>) {
         match modifier {
-            Some(ParseElement::Star) => {
-                let final_state = NfaState::new();
-                let start_state = NfaState::new();
+            // the same repetition, with the edges it adds ranked first, so that leaving comes
+            // before going round again
+            Some(ParseElement::Lazy(greedy)) => {
+                let outer = std::mem::replace(&mut self.lazy, true);
+                self.add_modifier(Some(*greedy));
+                self.lazy = outer;
+            }
 
+            Some(ParseElement::Star) => {
                 // we create new start and final states to avoid issues with unions
-                self.transitions.rename(NfaState::Accepting, final_state);
-                self.transitions.rename(NfaState::Start, start_state);
+                let (start_state, final_state) = self.detach();
 
                 // add epsilon transition from start to finish for 0 instances
-                self.transitions.add_transition(
-                    start_state,
-                    Transition::Epsilon,
-                    NfaState::Accepting,
-                );
+                self.add_edge(start_state, Transition::Epsilon, NfaState::ACCEPTING);
 
                 // add epsilon transition from finish to start for repeated instances
-                self.transitions
-                    .add_transition(final_state, Transition::Epsilon, start_state);
+                self.add_edge(final_state, Transition::Epsilon, start_state);
 
                 // add epsilon transition from true start to the new start
-                self.transitions
-                    .add_transition(NfaState::Start, Transition::Epsilon, start_state);
+                self.add_edge(NfaState::START, Transition::Epsilon, start_state);
             }
 
             Some(ParseElement::Plus) => {
-                // treat x+ as x concatenated with x*
-                let mut new_nfa = self.clone();
-                new_nfa.reassign_states();
-                new_nfa.add_modifier(Some(ParseElement::Star));
-                self.concat(&mut new_nfa);
+                // like x*, but without the way around x, so no copy of x is needed
+                let (start_state, final_state) = self.detach();
+
+                self.add_edge(NfaState::START, Transition::Epsilon, start_state);
+                // going round again is added before leaving, so that it ranks first
+                self.add_edge(final_state, Transition::Epsilon, start_state);
+                self.add_edge(final_state, Transition::Epsilon, NfaState::ACCEPTING);
             }
 
             Some(ParseElement::Question) => {
                 // add epsilon transition from start to finish
-                self.transitions.add_transition(
-                    NfaState::Start,
-                    Transition::Epsilon,
-                    NfaState::Accepting,
-                );
+                self.add_edge(NfaState::START, Transition::Epsilon, NfaState::ACCEPTING);
             }
 
+            // x{0} only matches the empty string
+            Some(ParseElement::Range(_, 0)) => *self = Self::epsilon(),
+
             Some(ParseElement::Range(lower, upper)) => {
                 // repeated concatenation up to lower, then concatenate with ? metacharacter through upper
                 let template = self.clone();
+                if lower == 0 {
+                    self.add_modifier(Some(ParseElement::Question));
+                }
                 for i in 1..upper {
                     let mut new_nfa = template.clone();
-                    new_nfa.reassign_states();
                     if i >= lower {
                         new_nfa.add_modifier(Some(ParseElement::Question));
                     }
@@ -89,12 +235,13 @@ impl Nfa {
                 }
             }
 
+            Some(ParseElement::OpenRange(0)) => self.add_modifier(Some(ParseElement::Star)),
+
             Some(ParseElement::OpenRange(start)) => {
                 // concatenate start times, with the last getting a *
                 let template = self.clone();
                 for i in 0..start {
                     let mut new_nfa = template.clone();
-                    new_nfa.reassign_states();
                     if i == start - 1 {
                         new_nfa.add_modifier(Some(ParseElement::Star));
                     }
@@ -106,58 +253,151 @@ impl Nfa {
         }
     }
 
+    // Add a second entry point that skips any prefix of the input before entering START, i.e.
+    // starting there recognizes .*P while START still recognizes P
+    pub fn add_unanchored_start(&mut self) -> NfaState {
+        let search = self.add_state();
+        self.add_edge(search, Transition::Wildcard, search);
+        self.add_edge(search, Transition::Epsilon, NfaState::START);
+
+        search
+    }
+
+    // Route every accepting path through a new state, which is returned, so that a DFA state
+    // containing it is known to accept this NFA's language even after a union with others
+    // (which moves it, see union)
+    pub fn mark_accepting(&mut self) -> NfaState {
+        let marker = self.add_state();
+        self.redirect_accepting(marker);
+        self.add_edge(marker, Transition::Epsilon, NfaState::ACCEPTING);
+
+        marker
+    }
+
+    // The machine for the reversed language: every edge flipped and START/ACCEPTING swapped. The
+    // anchors swap too, since the reversed input starts where the original ends.
+    pub fn reverse(&self) -> Self {
+        let flip = |state: NfaState| match state {
+            NfaState::START => NfaState::ACCEPTING,
+            NfaState::ACCEPTING => NfaState::START,
+            s => s,
+        };
+        let flip_anchor = |transition: Transition| match transition {
+            Transition::StartAnchor => Transition::EndAnchor,
+            Transition::EndAnchor => Transition::StartAnchor,
+            t => t,
+        };
+
+        let mut ret = Self {
+            first_rank: self.first_rank,
+            next_rank: self.next_rank,
+            ..Self::never()
+        };
+        ret.states.resize(self.states.len(), Vec::new());
+        for start in self.states() {
+            for edge in self.edges(start) {
+                let flipped = Edge {
+                    transition: flip_anchor(edge.transition),
+                    end: flip(start),
+                    ..*edge
+                };
+                ret.push_edge(flip(edge.end), flipped);
+            }
+        }
+
+        ret
+    }
+
+    // Move other's states into self's arena after self's own, with other's START becoming start
+    // and its ACCEPTING becoming ACCEPTING, and its ranks shifted by shift. Returns where each of
+    // other's states went.
+    fn append(&mut self, other: Self, start: NfaState, shift: i64) -> Vec<NfaState> {
+        let offset = self.states.len() - 2;
+        let moved: Vec<NfaState> = (0..other.states.len())
+            .map(|i| match i {
+                0 => start,
+                1 => NfaState::ACCEPTING,
+                i => NfaState::from_index(offset + i),
+            })
+            .collect();
+
+        self.states.resize(offset + other.states.len(), Vec::new());
+        for (from, edges) in moved.iter().zip(other.states) {
+            for edge in edges {
+                let end = moved[edge.end.index()];
+                let rank = edge.rank + shift;
+                self.push_edge(*from, Edge { end, rank, ..edge });
+            }
+        }
+
+        moved
+    }
+
+    // Append other, which is left accepting nothing. Only the edges into self's accepting state
+    // are redirected, to other's start, and other's states are moved rather than cloned, so
+    // building a pattern one element at a time takes time linear in its length.
     pub fn concat(&mut self, other: &mut Self) {
+        let other = std::mem::replace(other, Self::never());
         if self.empty {
-            *self = other.clone();
+            *self = other;
             return;
         }
 
-        let new_state = NfaState::new();
-
-        // set old accepting state to other's start state
-        self.transitions.rename(NfaState::Accepting, new_state);
-
-        let mut other = other.to_owned();
+        // set old accepting state to other's start state, with other's edges ranked after self's
+        let new_state = self.add_state();
+        self.redirect_accepting(new_state);
+        let shift = self.next_rank - other.first_rank;
+        self.next_rank = other.next_rank + shift;
+        self.append(other, new_state, shift);
+    }
 
-        // set other's start to new state
-        other.transitions.rename(NfaState::Start, new_state);
+    // Add other's alternatives to self's, leaving other accepting nothing. Other's rank before
+    // self's, as the alternatives written before them. Returns the state each of other's states
+    // became, e.g. for the marker from mark_accepting.
+    pub fn union(&mut self, other: &mut Self) -> Vec<NfaState> {
+        // the union is just the two sharing START and ACCEPTING
+        let other = std::mem::replace(other, Self::never());
+        let shift = self.first_rank - other.next_rank;
+        self.first_rank = other.first_rank + shift;
+        self.append(other, NfaState::START, shift)
+    }
 
-        // copy other's transition table over to self
-        for (start, map) in &other.transitions {
-            self.transitions.insert(*start, map.clone());
+    // attribute every consuming edge to the given pattern span
+    pub fn set_span(&mut self, span: Span) {
+        for edge in self.states.iter_mut().flatten() {
+            if edge.transition.consumes() {
+                edge.span = Some(span);
+            }
         }
     }
 
-    pub fn union(&mut self, other: &mut Self) {
-        // since states are unique, the union is just the two transition tables merging
-        for (start, map) in other.transitions.iter() {
-            for (transition, states) in map.iter() {
-                for state in states {
-                    self.transitions.add_transition(*start, *transition, *state);
-                }
-            }
+    // move the spans of a sub-pattern that was at from in an earlier version of the pattern to to
+    pub fn move_spans(&mut self, from: usize, to: usize) {
+        for span in self
+            .states
+            .iter_mut()
+            .flatten()
+            .filter_map(|e| e.span.as_mut())
+        {
+            *span = Span::new(span.start - from + to, span.end - from + to);
         }
     }
 
     // find all states reachable from the set states through epsilon-transitions alone
     pub fn epsilon_closure(&self, states: Vec<NfaState>) -> BTreeSet<NfaState> {
-        let mut stack = Vec::new();
-        let mut ret = BTreeSet::new();
+        self.closure(states, &[Transition::Epsilon])
+    }
 
-        for state in states.iter() {
-            ret.insert(*state);
-            stack.push(*state);
-        }
+    // find all states reachable from the set states through the given empty edges, e.g. AT_START
+    // where the input begins
+    pub fn closure(&self, states: Vec<NfaState>, through: &[Transition]) -> BTreeSet<NfaState> {
+        let mut ret: BTreeSet<NfaState> = states.iter().copied().collect();
+        let mut stack = states;
 
         while let Some(t) = stack.pop() {
-            if let Some(trans) = self.transitions.get(&t)
-                && let Some(epsilon_trans) = trans.get(&Transition::Epsilon)
-            {
-                for eps in epsilon_trans {
-                    if !ret.contains(eps) {
-                        ret.insert(*eps);
-                        stack.push(*eps);
-                    }
+            for edge in self.edges(t) {
+                if through.contains(&edge.transition) && ret.insert(edge.end) {
+                    stack.push(edge.end);
                 }
             }
         }
@@ -165,38 +405,123 @@ impl Nfa {
         ret
     }
 
-    // Reassign all states (except Start and Accepting) in the NFA to new state numbers. Useful for concatenating copies of NFAs
-    pub fn reassign_states(&mut self) {
-        let mut lookup: HashMap<NfaState, NfaState> = HashMap::new();
+    // The length of the longest prefix of input the NFA accepts, following every path at once
+    // on sets of states. ^ holds where input begins only if at_start, and $ where it ends.
+    pub fn longest_prefix(&self, input: &str, at_start: bool) -> Option<usize> {
+        Numbered::new(self).longest_prefix(input, at_start, &mut Scratch::default())
+    }
 
-        for state in self.transitions.keys() {
-            if *state == NfaState::Start {
-                continue;
-            }
-            if let Entry::Vacant(e) = lookup.entry(*state) {
-                e.insert(NfaState::new());
+    // the edges out of state in the order a leftmost-first search tries them, see Edge::rank
+    pub fn prioritized_edges(&self, state: NfaState) -> Vec<(Transition, NfaState)> {
+        let mut edges = self.edges(state).to_vec();
+        edges.sort_by_key(|edge| {
+            let rank = if edge.lazy { -edge.rank } else { edge.rank };
+            (!edge.lazy, rank, edge.transition, edge.end)
+        });
+
+        edges
+            .iter()
+            .map(|edge| (edge.transition, edge.end))
+            .collect()
+    }
+
+    // The leftmost-first match anywhere in haystack, which is what Perl and the backtracker find:
+    // at the first position a match starts at, the first one found by following edges in
+    // priority order. A (state, offset) pair reached a second time can't lead anywhere new, so
+    // each position takes time linear in the NFA's size times the haystack's length.
+    pub fn find_first<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        let starts = haystack.char_indices().map(|(i, _)| i);
+        for start in starts.chain([haystack.len()]) {
+            let mut visited = HashSet::new();
+            let mut stack = vec![(NfaState::START, start)];
+
+            while let Some((state, pos)) = stack.pop() {
+                if !visited.insert((state, pos)) {
+                    continue;
+                }
+                if state == NfaState::ACCEPTING {
+                    return Some(Match::new(haystack, start, pos));
+                }
+
+                let next = haystack[pos..].chars().next();
+                // pushed last to first, so the first edge is explored first
+                for (transition, end) in self.prioritized_edges(state).into_iter().rev() {
+                    let to = match transition {
+                        Transition::Epsilon => Some(pos),
+                        Transition::StartAnchor => (pos == 0).then_some(pos),
+                        Transition::EndAnchor => (pos == haystack.len()).then_some(pos),
+                        _ => next
+                            .filter(|c| transition.accepts(*c))
+                            .map(|c| pos + c.len_utf8()),
+                    };
+                    stack.extend(to.map(|to| (end, to)));
+                }
             }
         }
 
-        for (old, new) in lookup {
-            self.transitions.rename(old, new);
-        }
+        None
+    }
+
+    // The leftmost-longest match anywhere in haystack, trying every position in turn like
+    // Dfa::find
+    pub fn find<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+        let numbered = Numbered::new(self);
+        let mut scratch = Scratch::default();
+        haystack
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([haystack.len()])
+            .find_map(|i| {
+                let len = numbered.longest_prefix(&haystack[i..], i == 0, &mut scratch)?;
+                Some(Match::new(haystack, i, i + len))
+            })
     }
 
     pub fn to_dot(&self) -> String {
+        self.to_dot_with(DotOptions::default())
+    }
+
+    // The NFA as a petgraph Graph with a node per state, in NfaState order so that the start
+    // state is node 0, and each edge carrying its transition
+    #[cfg(feature = "petgraph")]
+    pub fn to_petgraph(&self) -> petgraph::Graph<NfaState, Transition> {
+        let mut graph = petgraph::Graph::new();
+        let nodes: Vec<_> = self.states().map(|s| graph.add_node(s)).collect();
+        for start in self.states() {
+            let mut edges: Vec<_> = self
+                .edges(start)
+                .iter()
+                .map(|e| (e.transition, e.end))
+                .collect();
+            edges.sort();
+            for (transition, end) in edges {
+                graph.add_edge(nodes[start.index()], nodes[end.index()], transition);
+            }
+        }
+
+        graph
+    }
+
+    // The NFA in DOT, with its states in order like the pipeline's and the edges sorted, so the
+    // same NFA always gives the same text
+    pub fn to_dot_with(&self, options: DotOptions) -> String {
+        let names = nfa_names(self);
         let mut out = String::new();
-        for (start, map) in &self.transitions {
-            for (transition, states) in map {
-                for end in states {
-                    out.push_str(
-                        format!(
-                            "{} -> {} [label = \"{}\"];\n",
-                            start.dot_node(),
-                            end.dot_node(),
-                            transition.dot_label()
-                        )
-                        .as_str(),
-                    );
+        for start in self.states() {
+            let mut by_end: BTreeMap<NfaState, Vec<Transition>> = BTreeMap::new();
+            for edge in self.edges(start) {
+                by_end.entry(edge.end).or_default().push(edge.transition);
+            }
+
+            for (end, mut transitions) in by_end {
+                transitions.sort();
+                for label in edge_labels(&transitions, options) {
+                    out.push_str(&format!(
+                        "{} -> {} [label = \"{}\"];\n",
+                        names[&start],
+                        names[&end],
+                        quote(&label)
+                    ));
                 }
             }
         }
@@ -204,3 +529,131 @@ impl Nfa {
         format!("digraph nfa {{\ngraph [label=\"NFA\"];\n{out}}}")
     }
 }
+
+// The NFA's edges in vectors by state number, with START as 0, so that a simulation can keep the
+// states it is in as SparseSets
+#[derive(Debug)]
+pub(crate) struct Numbered {
+    pub edges: Vec<Vec<(Transition, usize)>>,
+    pub accepting: usize,
+}
+
+// the current and next states of a simulation and the stack for closures, reused between runs
+#[derive(Default)]
+struct Scratch {
+    curr: SparseSet,
+    next: SparseSet,
+    stack: Vec<usize>,
+}
+
+impl Numbered {
+    pub fn new(nfa: &Nfa) -> Self {
+        let edges = nfa
+            .states
+            .iter()
+            .map(|edges| {
+                edges
+                    .iter()
+                    .map(|e| (e.transition, e.end.index()))
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            edges,
+            accepting: NfaState::ACCEPTING.index(),
+        }
+    }
+
+    // add state to set along with everything reachable from it through the given empty edges
+    pub fn close(
+        &self,
+        state: usize,
+        through: &[Transition],
+        set: &mut SparseSet,
+        stack: &mut Vec<usize>,
+    ) {
+        if !set.insert(state) {
+            return;
+        }
+        stack.push(state);
+        while let Some(s) = stack.pop() {
+            for (transition, end) in &self.edges[s] {
+                if through.contains(transition) && set.insert(*end) {
+                    stack.push(*end);
+                }
+            }
+        }
+    }
+
+    fn longest_prefix(&self, input: &str, at_start: bool, scratch: &mut Scratch) -> Option<usize> {
+        let Scratch { curr, next, stack } = scratch;
+        if curr.capacity() != self.edges.len() {
+            *curr = SparseSet::new(self.edges.len());
+            *next = SparseSet::new(self.edges.len());
+        }
+        let through: &[Transition] = if at_start {
+            &AT_START
+        } else {
+            &[Transition::Epsilon]
+        };
+        curr.clear();
+        self.close(0, through, curr, stack);
+        let mut longest = None;
+
+        for (i, c) in input.char_indices() {
+            if curr.contains(self.accepting) {
+                longest = Some(i);
+            }
+            next.clear();
+            for s in curr.iter() {
+                for (transition, end) in &self.edges[s] {
+                    if transition.accepts(c) {
+                        self.close(*end, &[Transition::Epsilon], next, stack);
+                    }
+                }
+            }
+            if next.is_empty() {
+                return longest;
+            }
+            std::mem::swap(curr, next);
+        }
+
+        // $ holds once the input has ended
+        next.clear();
+        for s in curr.iter() {
+            self.close(s, &AT_END, next, stack);
+        }
+        if next.contains(self.accepting) {
+            longest = Some(input.len());
+        }
+        longest
+    }
+}
+
+impl fmt::Display for Nfa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "NFA with {} states", self.states.len())?;
+        for state in self.states() {
+            let mut edges: Vec<_> = self
+                .edges(state)
+                .iter()
+                .map(|e| (e.transition, e.end))
+                .collect();
+            edges.sort();
+
+            let flag = if state == NfaState::ACCEPTING {
+                " (accepting)"
+            } else {
+                ""
+            };
+            writeln!(f, "  {}{flag}", state.dot_node())?;
+
+            for (transition, end) in edges {
+                writeln!(f, "    {} -> {}", transition.dot_label(), end.dot_node())?;
+            }
+        }
+
+        Ok(())
+    }
+}
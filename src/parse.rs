@@ -1,5 +1,40 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use crate::error::{Construct, ErrorKind, RegexError};
+use crate::fold::{fold_all, variants};
+use crate::id::PredicateId;
 use crate::nfa::Nfa;
-use crate::transition_table::Transition;
+use crate::predicate;
+use crate::transition_table::{intern_class, Alphabet, Transition};
+use crate::CharMap;
+
+// byte offsets into the pattern, end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub elem: ParseElement,
+    pub span: Span,
+}
+
+// a capture group as written in the pattern, numbered by its opening parenthesis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupInfo {
+    pub index: usize,
+    pub name: Option<String>,
+    pub span: Span,
+}
 
 #[derive(Debug, Clone)]
 pub enum ParseElement {
@@ -11,278 +46,1134 @@ pub enum ParseElement {
     Question,        // ? matches 0 or 1 times
     Range(u64, u64), // a{3,5} matches aaa, aaaa, aaaaa
     OpenRange(u64),  // a{n,} matches a n or more times
+    // *?, +?, ??, {n,m}? and {n,}? repeat as few times as the rest of the pattern allows. Only
+    // match bounds differ from the greedy quantifier, the language it accepts is the same.
+    Lazy(Box<ParseElement>),
 
     Union, // |
 
-    Group(Vec<ParseElement>), // (...)
-    Bracket(Vec<char>),       // [A-Za-z]
+    Group(Vec<Token>, Option<String>), // (...) or (?<name>...)
+    CaseInsensitive(Vec<Token>),       // (?i:...), which doesn't capture
+    Bracket(Vec<char>),                // [A-Za-z]
+    NegatedBracket(Vec<char>),         // [^A-Za-z] matches any character outside the class
+    Predicate(PredicateId),            // \p{name} matches a character the predicate holds for
+    // A predicate bounded to the alphabet (see bound_wildcards) as the characters in it that the
+    // predicate holds for. Unlike a Bracket it isn't case-folded, as the predicate isn't.
+    BoundedPredicate(PredicateId, Vec<char>),
 
     BackReference(u64), //\n where n>=1, POSIX regex only mandates 1-9
+
+    StartAnchor, // ^ matches only at the start of the input
+    EndAnchor,   // $ matches only at the end of the input
+    // \b matches between a character of the word set (that of \w when the pattern was lexed)
+    // and one outside it or an end of the input, \B everywhere else
+    WordBoundary(Vec<char>),
+    NotWordBoundary(Vec<char>),
 }
 
 impl ParseElement {
-    fn is_modifier(&self) -> bool {
+    pub fn is_modifier(&self) -> bool {
         matches!(
             self,
-            Self::Star | Self::Plus | Self::Question | Self::Range(_, _) | Self::OpenRange(_)
+            Self::Star
+                | Self::Plus
+                | Self::Question
+                | Self::Range(_, _)
+                | Self::OpenRange(_)
+                | Self::Lazy(_)
         )
     }
-}
 
-fn get_character_class(c: char) -> Vec<char> {
-    match c {
-        'w' => {
-            /* [A-Za-z0-9_]  */
-            let mut values: Vec<char> = ('A'..='Z').collect();
-            values.append(&mut ('a'..='z').collect());
-            values.append(&mut ('0'..='9').collect());
-            values.push('_');
-            values
-        }
-        'd' => {
-            /* [0-9] */
-            ('0'..='9').collect()
+    // a lazy quantifier's greedy counterpart, or the element itself
+    pub fn greedy(&self) -> &Self {
+        match self {
+            Self::Lazy(modifier) => modifier,
+            elem => elem,
         }
-        's' => {
-            /* [ \t] */
-            vec![' ', '\t']
-        }
-        _ => {
-            panic!("{c} is not a supported character class!")
+    }
+}
+
+// a peekable char iterator that also knows its byte offset in the pattern
+#[derive(Clone)]
+struct Cursor<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    len: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.char_indices().peekable(),
+            len: input.len(),
         }
     }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek().map(|(_, c)| c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.len, |(i, _)| *i)
+    }
 }
 
-fn get_escaped(iter: &mut impl Iterator<Item = char>) -> char {
-    let next = iter.next().unwrap();
+impl Iterator for Cursor<'_> {
+    type Item = char;
 
-    match next {
-        '.' | '*' | '+' | '?' | '{' | '}' | '|' | '^' | '$' | '(' | ')' | '[' | ']' | '-'
-        | '\\' => next,
-        't' => '\t',
-        'x' => {
-            let mut n = iter.next().unwrap().to_digit(16).unwrap();
-            n *= 16;
-            n += iter.next().unwrap().to_digit(16).unwrap();
-            char::from_u32(n).unwrap()
+    fn next(&mut self) -> Option<char> {
+        self.chars.next().map(|(_, c)| c)
+    }
+}
+
+// The characters the shorthands \w and \s (and [[:word:]]) stand for, which
+// RegexBuilder::word_chars and space_chars can change, e.g. to count - as part of a word when
+// matching identifiers. \d is always [0-9].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shorthands {
+    pub word: Vec<char>,
+    pub space: Vec<char>,
+}
+
+impl Default for Shorthands {
+    fn default() -> Self {
+        /* [A-Za-z0-9_]  */
+        let mut word: Vec<char> = ('A'..='Z').collect();
+        word.append(&mut ('a'..='z').collect());
+        word.append(&mut ('0'..='9').collect());
+        word.push('_');
+
+        Self {
+            word,
+            /* [ \t] */
+            space: vec![' ', '\t'],
         }
-        'u' => {
-            let mut n = 0u32;
-            for _ in 0..4 {
-                n *= 16;
-                n += iter.next().unwrap().to_digit(16).unwrap();
+    }
+}
+
+impl Shorthands {
+    fn class(&self, c: char) -> Vec<char> {
+        match c {
+            'w' => self.word.clone(),
+            /* [0-9] */
+            'd' => ('0'..='9').collect(),
+            's' => self.space.clone(),
+            _ => {
+                panic!("{c} is not a supported character class!")
             }
-            char::from_u32(n).unwrap()
         }
-        _ => panic!("Unknown escape character {next}!"),
     }
 }
 
-pub fn lex(input: String) -> Vec<ParseElement> {
-    let mut iter = input.chars().peekable();
+// the ASCII members of a POSIX class like [:alpha:], or None if there is no such class
+fn get_posix_class(name: &str) -> Option<Vec<char>> {
+    let ascii = (0u8..128).map(char::from);
+    let members = match name {
+        "alpha" => ascii.filter(char::is_ascii_alphabetic).collect(),
+        "digit" => ascii.filter(char::is_ascii_digit).collect(),
+        "alnum" => ascii.filter(char::is_ascii_alphanumeric).collect(),
+        "upper" => ascii.filter(char::is_ascii_uppercase).collect(),
+        "lower" => ascii.filter(char::is_ascii_lowercase).collect(),
+        "space" => ascii
+            .filter(char::is_ascii_whitespace)
+            .chain(['\x0b'])
+            .collect(),
+        "punct" => ascii.filter(char::is_ascii_punctuation).collect(),
+        "xdigit" => ascii.filter(char::is_ascii_hexdigit).collect(),
+        _ => return None,
+    };
 
-    let mut stack = Vec::new();
-    let mut curr = Vec::new();
+    Some(members)
+}
 
-    while iter.peek().is_some() {
-        match iter.next().unwrap() {
-            '.' => curr.push(ParseElement::Wildcard),
-            '*' => curr.push(ParseElement::Star),
-            '+' => curr.push(ParseElement::Plus),
-            '?' => curr.push(ParseElement::Question),
-            '|' => curr.push(ParseElement::Union),
+// one member of a bracketed class
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClassItem {
+    Literal(char),
+    Range(char, char),
+    Shorthand(char),        // \d, \w or \s, and [:word:] as \w
+    Posix(Vec<char>),       // [:alpha:] and the like
+    Nested(Vec<ClassItem>), // [...] inside a class adds its members
+}
 
-            // repetition ranges
-            '{' => {
-                // consume until digit
-                while !iter.peek().unwrap().is_ascii_digit() {
-                    let _ = iter.next();
+impl ClassItem {
+    fn chars(&self, shorthands: &Shorthands) -> Vec<char> {
+        match self {
+            Self::Literal(c) => vec![*c],
+            Self::Range(start, end) => (*start..=*end).collect(),
+            Self::Shorthand(c) => shorthands.class(*c),
+            Self::Posix(members) => members.clone(),
+            Self::Nested(items) => items.iter().flat_map(|i| i.chars(shorthands)).collect(),
+        }
+    }
+}
+
+// A character or shorthand inside a class, or None if it was a bad escape (already reported)
+fn get_class_atom(iter: &mut Cursor, errors: &mut Vec<RegexError>) -> Option<ClassItem> {
+    let start = iter.offset();
+
+    match iter.next()? {
+        '\\' => match iter.peek() {
+            Some('w' | 'd' | 's') => Some(ClassItem::Shorthand(iter.next().unwrap())),
+            _ => match get_escaped(iter) {
+                Ok(c) => Some(ClassItem::Literal(c)),
+                Err(kind) => {
+                    errors.push(RegexError::new(kind, Span::new(start, iter.offset())));
+                    None
                 }
+            },
+        },
+        c => Some(ClassItem::Literal(c)),
+    }
+}
+
+// The items of a class after its opening bracket, up to and including the closing one. Errors
+// in single items are collected in errors, and the class is only rejected if it doesn't close.
+fn get_class(iter: &mut Cursor, errors: &mut Vec<RegexError>) -> Result<Vec<ClassItem>, ErrorKind> {
+    let mut items = Vec::new();
 
-                // range
-                let (mut min, mut max) = (0u64, 0u64);
-                let mut done = false;
+    loop {
+        let start = iter.offset();
 
-                while iter.peek().unwrap().is_ascii_digit() {
-                    min *= 10;
-                    min += iter.next().unwrap().to_digit(10).unwrap() as u64;
+        match iter.peek() {
+            None => return Err(ErrorKind::UnclosedBracket),
+            Some(']') => {
+                let _ = iter.next();
+                return Ok(items);
+            }
+            Some('[') => {
+                let _ = iter.next();
+                if iter.peek() != Some(&':') {
+                    items.push(ClassItem::Nested(get_class(iter, errors)?));
+                    continue;
                 }
 
-                // consume until comma or close curly
-                while !matches!(iter.peek().unwrap(), ',') && !matches!(iter.peek().unwrap(), '}') {
-                    let _ = iter.next();
+                let _ = iter.next();
+                let mut name = String::new();
+                loop {
+                    match iter.next() {
+                        Some(':') if iter.peek() == Some(&']') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(ErrorKind::UnclosedBracket),
+                    }
                 }
+                let _ = iter.next();
 
-                // exact range, i.e. a{3}
-                if iter.next().unwrap() == '}' {
-                    curr.push(ParseElement::Range(min, min));
-                    done = true;
+                if name == "word" {
+                    items.push(ClassItem::Shorthand('w'));
+                    continue;
+                }
+                match get_posix_class(&name) {
+                    Some(members) => items.push(ClassItem::Posix(members)),
+                    None => errors.push(RegexError::new(
+                        ErrorKind::UnknownPosixClass(name),
+                        Span::new(start, iter.offset()),
+                    )),
                 }
+            }
+            Some(_) => {
+                let first = get_class_atom(iter, errors);
 
-                // consume until next digit
-                while !done && !iter.peek().unwrap().is_ascii_digit() {
-                    // open range, i.e. a{3,}
-                    if iter.next().unwrap() == '}' {
-                        curr.push(ParseElement::OpenRange(min));
-                        done = true;
-                    }
+                // a hyphen is literal as the first or last character
+                let mut ahead = iter.clone();
+                let is_range =
+                    ahead.next() == Some('-') && !matches!(ahead.peek(), Some(']') | None);
+                if !is_range {
+                    items.extend(first);
+                    continue;
                 }
 
-                if !done {
-                    while iter.peek().unwrap().is_ascii_digit() {
-                        max *= 10;
-                        max += iter.next().unwrap().to_digit(10).unwrap() as u64;
+                let _ = iter.next();
+                let last = get_class_atom(iter, errors);
+                let span = Span::new(start, iter.offset());
+                match (first, last) {
+                    (Some(ClassItem::Literal(a)), Some(ClassItem::Literal(b))) if a > b => {
+                        errors.push(RegexError::new(ErrorKind::ReversedClassRange(a, b), span));
+                    }
+                    (Some(ClassItem::Literal(a)), Some(ClassItem::Literal(b))) => {
+                        items.push(ClassItem::Range(a, b));
+                    }
+                    (Some(_), Some(_)) => {
+                        errors.push(RegexError::new(ErrorKind::ShorthandInRange, span));
                     }
+                    // a bad escape at either end was already reported
+                    _ => {}
+                }
+            }
+        }
+    }
+}
 
-                    // consume until close curly
-                    while !matches!(iter.next().unwrap(), '}') {}
+fn get_escaped(iter: &mut Cursor) -> Result<char, ErrorKind> {
+    let Some(next) = iter.next() else {
+        return Err(ErrorKind::TrailingBackslash);
+    };
 
-                    curr.push(ParseElement::Range(min, max));
-                }
+    match next {
+        '.' | '*' | '+' | '?' | '{' | '}' | '|' | '^' | '$' | '(' | ')' | '[' | ']' | '-'
+        | '\\' => Ok(next),
+        't' => Ok('\t'),
+        'x' => get_hex(iter, 2),
+        'u' => get_hex(iter, 4),
+        _ => Err(ErrorKind::UnknownEscape(next)),
+    }
+}
+
+// exactly len hex digits naming a character, as in \x41 or \u00e9
+fn get_hex(iter: &mut Cursor, len: usize) -> Result<char, ErrorKind> {
+    let mut n = 0u32;
+    for _ in 0..len {
+        match iter.peek().and_then(|c| c.to_digit(16)) {
+            Some(d) => {
+                n = n * 16 + d;
+                let _ = iter.next();
             }
+            None => return Err(ErrorKind::InvalidHexEscape),
+        }
+    }
+
+    char::from_u32(n).ok_or(ErrorKind::InvalidHexEscape)
+}
+
+// a decimal number with optional spaces around it
+fn get_number(iter: &mut Cursor) -> Option<u64> {
+    while iter.peek() == Some(&' ') {
+        let _ = iter.next();
+    }
+
+    let mut n = None;
+    while let Some(d) = iter.peek().and_then(|c| c.to_digit(10)) {
+        n = Some(
+            n.unwrap_or(0u64)
+                .saturating_mul(10)
+                .saturating_add(d as u64),
+        );
+        let _ = iter.next();
+    }
+
+    while iter.peek() == Some(&' ') {
+        let _ = iter.next();
+    }
+    n
+}
+
+// the rest of {n}, {n,} or {n,m} after the opening brace
+fn get_repetition(iter: &mut Cursor) -> Result<ParseElement, ErrorKind> {
+    let min = get_number(iter).ok_or(ErrorKind::InvalidRepetition)?;
+
+    match iter.next() {
+        // exact range, i.e. a{3}
+        Some('}') => return Ok(ParseElement::Range(min, min)),
+        Some(',') => {}
+        _ => return Err(ErrorKind::InvalidRepetition),
+    }
+
+    // open range, i.e. a{3,}
+    let Some(max) = get_number(iter) else {
+        return match iter.next() {
+            Some('}') => Ok(ParseElement::OpenRange(min)),
+            _ => Err(ErrorKind::InvalidRepetition),
+        };
+    };
+
+    if iter.next() != Some('}') {
+        return Err(ErrorKind::InvalidRepetition);
+    }
+    if min > max {
+        return Err(ErrorKind::InvalidRange(min, max));
+    }
+
+    Ok(ParseElement::Range(min, max))
+}
+
+// what an opening parenthesis starts
+enum Opening {
+    Capture(Option<String>), // (...), (?<name>...) or (?P<name>...)
+    CaseInsensitive,         // (?i:...)
+}
+
+impl Opening {
+    fn close(self, toks: Vec<Token>) -> ParseElement {
+        match self {
+            Self::Capture(name) => ParseElement::Group(toks, name),
+            Self::CaseInsensitive => ParseElement::CaseInsensitive(toks),
+        }
+    }
+}
+
+// the kind of group after its opening parenthesis, with its name if it has one
+fn get_group_opening(iter: &mut Cursor) -> Result<Opening, ErrorKind> {
+    if iter.peek() != Some(&'?') {
+        return Ok(Opening::Capture(None));
+    }
+
+    let _ = iter.next();
+    if iter.peek() == Some(&'i') {
+        let _ = iter.next();
+        if iter.next() != Some(':') {
+            return Err(ErrorKind::UnsupportedGroupSyntax);
+        }
+        return Ok(Opening::CaseInsensitive);
+    }
+    if iter.peek() == Some(&'P') {
+        let _ = iter.next();
+    }
+    if iter.next() != Some('<') {
+        return Err(ErrorKind::UnsupportedGroupSyntax);
+    }
+
+    let mut name = String::new();
+    loop {
+        match iter.next() {
+            Some('>') => break,
+            Some(c) => name.push(c),
+            None => return Err(ErrorKind::UnclosedGroupName),
+        }
+    }
+
+    if name.is_empty() {
+        return Err(ErrorKind::EmptyGroupName);
+    }
+    Ok(Opening::Capture(Some(name)))
+}
+
+// the registered predicate named by \p{name}
+fn get_predicate(iter: &mut Cursor) -> Result<PredicateId, ErrorKind> {
+    let _ = iter.next();
+    if iter.next() != Some('{') {
+        return Err(ErrorKind::UnknownEscape('p'));
+    }
+
+    let mut name = String::new();
+    loop {
+        match iter.next() {
+            Some('}') => break,
+            Some(c) => name.push(c),
+            None => return Err(ErrorKind::UnclosedPredicateName),
+        }
+    }
+
+    predicate::lookup(&name).ok_or(ErrorKind::UnknownPredicate(name))
+}
+
+// \n is a backreference if n < 10 or at least n groups have been opened so far,
+// otherwise it is an octal escape (\0, \101) or a literal digit (\8)
+fn get_numbered_escape(iter: &mut Cursor, groups_opened: u64) -> ParseElement {
+    let digits: String = iter.clone().take_while(|c| c.is_ascii_digit()).collect();
+
+    let n = digits.chars().fold(0u64, |acc, c| {
+        acc.saturating_mul(10)
+            .saturating_add(c.to_digit(10).unwrap() as u64)
+    });
+
+    if !digits.starts_with('0') && (n < 10 || n <= groups_opened) {
+        for _ in 0..digits.len() {
+            let _ = iter.next();
+        }
+        return ParseElement::BackReference(n);
+    }
+
+    let octal: String = digits
+        .chars()
+        .take_while(|c| c.is_digit(8))
+        .take(3)
+        .collect();
+
+    if octal.is_empty() {
+        return ParseElement::Literal(iter.next().unwrap());
+    }
+
+    for _ in 0..octal.len() {
+        let _ = iter.next();
+    }
+    ParseElement::Literal(char::from_u32(u32::from_str_radix(&octal, 8).unwrap()).unwrap())
+}
+
+// Lex the whole pattern, collecting every syntax error instead of stopping at the first one.
+// Whatever could be lexed is still returned, with unclosed groups closed at the end.
+pub fn lex_all(input: &str) -> (Vec<Token>, Vec<RegexError>) {
+    lex_all_with(input, &Shorthands::default())
+}
+
+// lex_all, with \w and \s standing for the characters in shorthands
+pub fn lex_all_with(input: &str, shorthands: &Shorthands) -> (Vec<Token>, Vec<RegexError>) {
+    let mut iter = Cursor::new(input);
+    let mut errors = Vec::new();
+
+    let mut stack = Vec::new();
+    let mut curr = Vec::new();
+    let mut groups_opened = 0u64;
+
+    while iter.peek().is_some() {
+        let start = iter.offset();
+        let mut elem = None;
+        let mut error = None;
+
+        match iter.next().unwrap() {
+            '.' => elem = Some(ParseElement::Wildcard),
+            '*' => elem = Some(ParseElement::Star),
+            '+' => elem = Some(ParseElement::Plus),
+            // a ? right after another quantifier makes it lazy
+            '?' if curr.last().is_some_and(|t: &Token| {
+                t.elem.is_modifier() && !matches!(t.elem, ParseElement::Lazy(_))
+            }) =>
+            {
+                let last = curr.last_mut().unwrap();
+                last.elem = ParseElement::Lazy(Box::new(last.elem.clone()));
+                last.span.end = iter.offset();
+            }
+            '?' => elem = Some(ParseElement::Question),
+            '|' => elem = Some(ParseElement::Union),
+            '^' => elem = Some(ParseElement::StartAnchor),
+            '$' => elem = Some(ParseElement::EndAnchor),
+
+            // repetition ranges
+            '{' => match get_repetition(&mut iter) {
+                Ok(range) => elem = Some(range),
+                Err(kind) => {
+                    // skip whatever is left of the braces
+                    if kind == ErrorKind::InvalidRepetition {
+                        while iter.next().is_some_and(|c| c != '}') {}
+                    }
+                    error = Some(kind);
+                }
+            },
 
             '(' => {
+                // a group with a bad name is still opened so that its ) lines up
+                let opening = get_group_opening(&mut iter).unwrap_or_else(|kind| {
+                    error = Some(kind);
+                    Opening::Capture(None)
+                });
+
                 // new group
-                stack.push(curr.clone());
+                if matches!(opening, Opening::Capture(_)) {
+                    groups_opened += 1;
+                }
+                stack.push((curr.clone(), start, opening));
                 curr.clear();
             }
-            ')' => {
+            ')' => match stack.pop() {
                 // close group
-                let group = ParseElement::Group(curr.clone());
-                curr.clear();
-                curr = stack.pop().unwrap();
-                curr.push(group);
-            }
+                Some((prev, group_start, opening)) => {
+                    let group = opening.close(curr.clone());
+                    curr = prev;
+                    curr.push(Token {
+                        elem: group,
+                        span: Span::new(group_start, iter.offset()),
+                    });
+                }
+                None => error = Some(ErrorKind::UnopenedGroup),
+            },
 
-            // character ranges
+            // character classes
             '[' => {
-                let mut values = Vec::new();
-
-                while !matches!(iter.peek().unwrap(), ']') {
-                    match iter.next().unwrap() {
-                        '\\' => match iter.peek().unwrap() {
-                            'w' | 'd' | 's' => {
-                                values.extend(get_character_class(iter.next().unwrap()));
-                            }
-                            _ => values.push(get_escaped(&mut iter)),
-                        },
-                        '-' => {
-                            // plain hyphen is valid if it is the first or last character
-                            if values.is_empty() || *iter.peek().unwrap() == ']' {
-                                values.push('-');
+                let reported = errors.len();
+                // a ^ anywhere else in the class is literal
+                let negated = iter.peek() == Some(&'^');
+                if negated {
+                    let _ = iter.next();
+                }
+                match get_class(&mut iter, &mut errors) {
+                    Ok(items) => {
+                        let values: Vec<char> =
+                            items.iter().flat_map(|i| i.chars(shorthands)).collect();
+                        if !values.is_empty() {
+                            elem = Some(if negated {
+                                ParseElement::NegatedBracket(values)
                             } else {
-                                let prev = values.pop().unwrap();
-                                let end = iter.next().unwrap();
-                                for c in prev..=end {
-                                    values.push(c);
-                                }
-                            }
-                        }
-                        c => {
-                            values.push(c);
+                                ParseElement::Bracket(values)
+                            });
+                        } else if errors.len() == reported {
+                            // an invalid item already explains why nothing is left
+                            error = Some(ErrorKind::EmptyClass);
                         }
                     }
+                    Err(kind) => error = Some(kind),
                 }
-
-                // consume closing bracket
-                let _ = iter.next();
-
-                curr.push(ParseElement::Bracket(values));
             }
 
             // Escaped character
             '\\' => {
-                match iter.peek().unwrap() {
-                    'w' | 'd' | 's' => {
+                match iter.peek() {
+                    Some('w' | 'd' | 's') => {
                         // character classes are treated like brackets
-                        curr.push(ParseElement::Bracket(get_character_class(
-                            iter.next().unwrap(),
-                        )));
+                        elem = Some(ParseElement::Bracket(
+                            shorthands.class(iter.next().unwrap()),
+                        ));
                     }
 
-                    '0'..='9' => {
-                        // digits
-                        let mut n: u64 = iter.next().unwrap().to_digit(10).unwrap() as u64;
-                        while iter.peek().is_some() && iter.peek().unwrap().is_ascii_digit() {
-                            n *= 10;
-                            n += iter.next().unwrap().to_digit(10).unwrap() as u64;
-                        }
-                        curr.push(ParseElement::BackReference(n));
+                    Some('0'..='9') => {
+                        elem = Some(get_numbered_escape(&mut iter, groups_opened));
                     }
 
-                    _ => {
-                        curr.push(ParseElement::Literal(get_escaped(&mut iter)));
+                    Some('p') => match get_predicate(&mut iter) {
+                        Ok(id) => elem = Some(ParseElement::Predicate(id)),
+                        Err(kind) => error = Some(kind),
+                    },
+
+                    Some('b') => {
+                        let _ = iter.next();
+                        elem = Some(ParseElement::WordBoundary(shorthands.word.clone()));
                     }
+
+                    Some('B') => {
+                        let _ = iter.next();
+                        elem = Some(ParseElement::NotWordBoundary(shorthands.word.clone()));
+                    }
+
+                    _ => match get_escaped(&mut iter) {
+                        Ok(c) => elem = Some(ParseElement::Literal(c)),
+                        Err(kind) => error = Some(kind),
+                    },
                 }
             }
 
             // anything else is a literal
-            c => curr.push(ParseElement::Literal(c)),
+            c => elem = Some(ParseElement::Literal(c)),
+        }
+
+        if let Some(elem) = elem {
+            curr.push(Token {
+                elem,
+                span: Span::new(start, iter.offset()),
+            });
+        }
+        if let Some(kind) = error {
+            errors.push(RegexError::new(kind, Span::new(start, iter.offset())));
+        }
+    }
+
+    // close whatever is still open, reporting each opening parenthesis
+    while let Some((prev, group_start, opening)) = stack.pop() {
+        errors.push(RegexError::new(
+            ErrorKind::UnclosedGroup,
+            Span::new(group_start, group_start + 1),
+        ));
+
+        let group = opening.close(curr);
+        curr = prev;
+        curr.push(Token {
+            elem: group,
+            span: Span::new(group_start, input.len()),
+        });
+    }
+
+    errors.sort_by_key(|e| e.span);
+    (curr, errors)
+}
+
+// lex the pattern, or report its first syntax error
+pub fn try_lex(input: &str) -> Result<Vec<Token>, RegexError> {
+    try_lex_with(input, &Shorthands::default())
+}
+
+// try_lex, with \w and \s standing for the characters in shorthands
+pub fn try_lex_with(input: &str, shorthands: &Shorthands) -> Result<Vec<Token>, RegexError> {
+    let (toks, errors) = lex_all_with(input, shorthands);
+
+    match errors.into_iter().next() {
+        Some(e) => Err(e),
+        None => Ok(toks),
+    }
+}
+
+pub fn lex(input: String) -> Vec<Token> {
+    try_lex(&input).unwrap_or_else(|e| panic!("{e}"))
+}
+
+// Every error parse() could stop at, e.g. a modifier with nothing before it or a reference to a
+// group that doesn't exist, in pattern order
+pub fn check(toks: &[Token]) -> Vec<RegexError> {
+    fn walk(toks: &[Token], total: u64, out: &mut Vec<RegexError>) {
+        let mut repeatable = false;
+
+        for tok in toks {
+            match &tok.elem {
+                elem if elem.is_modifier() => {
+                    if !repeatable {
+                        out.push(RegexError::new(ErrorKind::NothingToRepeat, tok.span));
+                    }
+                    repeatable = false;
+                    continue;
+                }
+                ParseElement::Union => {
+                    repeatable = false;
+                    continue;
+                }
+                ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                    walk(grp, total, out)
+                }
+                ParseElement::BackReference(n) if *n > total => {
+                    out.push(RegexError::new(ErrorKind::UndefinedGroup(*n), tok.span));
+                }
+                _ => {}
+            }
+            repeatable = true;
+        }
+    }
+
+    let mut errors = Vec::new();
+    walk(toks, group_info(toks).len() as u64, &mut errors);
+    errors
+}
+
+// Which constructs a pattern may use. Without backreferences, large repetitions and word
+// boundaries every match is decided by the DFA alone, in time linear in the haystack. (Lookaround
+// isn't supported at all, so there is nothing to turn off for it.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxConfig {
+    pub backreferences: bool,
+    pub unbounded_repeats: bool,
+    pub large_repeats: bool,
+    pub word_boundaries: bool,
+}
+
+impl Default for SyntaxConfig {
+    fn default() -> Self {
+        Self {
+            backreferences: true,
+            unbounded_repeats: true,
+            large_repeats: true,
+            word_boundaries: true,
+        }
+    }
+}
+
+impl SyntaxConfig {
+    // only what the DFA can match on its own
+    pub fn linear() -> Self {
+        Self {
+            backreferences: false,
+            large_repeats: false,
+            word_boundaries: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn allows(&self, construct: Construct) -> bool {
+        match construct {
+            Construct::BackReference => self.backreferences,
+            Construct::UnboundedRepeat => self.unbounded_repeats,
+            Construct::LargeRepeat => self.large_repeats,
+            Construct::WordBoundary => self.word_boundaries,
+        }
+    }
+}
+
+// every use of a construct config turns off, where large means a repetition bound over limit
+pub fn check_syntax(toks: &[Token], config: &SyntaxConfig, limit: u64) -> Vec<RegexError> {
+    let mut errors = Vec::new();
+
+    for tok in toks {
+        let used: &[Construct] = match tok.elem.greedy() {
+            ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                errors.extend(check_syntax(grp, config, limit));
+                continue;
+            }
+            ParseElement::BackReference(_) => &[Construct::BackReference],
+            ParseElement::WordBoundary(_) | ParseElement::NotWordBoundary(_) => {
+                &[Construct::WordBoundary]
+            }
+            ParseElement::Star | ParseElement::Plus => &[Construct::UnboundedRepeat],
+            ParseElement::OpenRange(n) if *n > limit => {
+                &[Construct::UnboundedRepeat, Construct::LargeRepeat]
+            }
+            ParseElement::OpenRange(_) => &[Construct::UnboundedRepeat],
+            ParseElement::Range(_, n) if *n > limit => &[Construct::LargeRepeat],
+            _ => continue,
+        };
+
+        if let Some(c) = used.iter().find(|c| !config.allows(**c)) {
+            errors.push(RegexError::new(ErrorKind::Disabled(*c), tok.span));
+        }
+    }
+
+    errors
+}
+
+// Replace every repetition with a bound above limit by *, so that the automata built from the
+// tokens stay small. They then accept a superset of the pattern's language. Returns whether
+// anything was replaced.
+pub fn clamp_repeats(toks: &mut [Token], limit: u64) -> bool {
+    let mut clamped = false;
+
+    for tok in toks {
+        // the automata don't tell lazy repetitions from greedy ones
+        match &mut tok.elem {
+            ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                clamped |= clamp_repeats(grp, limit)
+            }
+            elem => {
+                let large = match elem.greedy() {
+                    ParseElement::Range(_, upper) => *upper > limit,
+                    ParseElement::OpenRange(lower) => *lower > limit,
+                    _ => false,
+                };
+                if large {
+                    tok.elem = ParseElement::Star;
+                    clamped = true;
+                }
+            }
+        }
+    }
+
+    clamped
+}
+
+// What a region of the pattern is, for highlighting it the way the parser reads it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Literal,
+    Escape, // an escaped literal, i.e. \. or \x41
+    Wildcard,
+    Class, // [...] or \w, \d, \s
+    Quantifier,
+    Alternation,
+    GroupOpen, // (, (?<name> or (?i:
+    GroupClose,
+    BackReference,
+    Anchor, // ^, $, \b or \B
+    Error,  // a region the lexer rejected
+}
+
+// classify every region of the pattern, in order
+pub fn tokenize_spans(pattern: &str) -> Vec<(Span, TokenKind)> {
+    fn walk(toks: &[Token], unclosed: &[usize], out: &mut Vec<(Span, TokenKind)>) {
+        for tok in toks {
+            let kind = match &tok.elem {
+                ParseElement::Literal(c) if tok.span.end - tok.span.start > c.len_utf8() => {
+                    TokenKind::Escape
+                }
+                ParseElement::Literal(_) => TokenKind::Literal,
+                ParseElement::Wildcard => TokenKind::Wildcard,
+                ParseElement::Bracket(_)
+                | ParseElement::NegatedBracket(_)
+                | ParseElement::Predicate(_)
+                | ParseElement::BoundedPredicate(..) => TokenKind::Class,
+                ParseElement::Union => TokenKind::Alternation,
+                ParseElement::BackReference(_) => TokenKind::BackReference,
+                ParseElement::StartAnchor
+                | ParseElement::EndAnchor
+                | ParseElement::WordBoundary(_)
+                | ParseElement::NotWordBoundary(_) => TokenKind::Anchor,
+                elem if elem.is_modifier() => TokenKind::Quantifier,
+                ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                    // the delimiters are whatever the group's contents don't cover
+                    let closed = !unclosed.contains(&tok.span.start);
+                    let end = if closed {
+                        tok.span.end - 1
+                    } else {
+                        tok.span.end
+                    };
+                    let open_end = grp.first().map_or(end, |t| t.span.start);
+                    out.push((Span::new(tok.span.start, open_end), TokenKind::GroupOpen));
+
+                    walk(grp, unclosed, out);
+
+                    if closed {
+                        out.push((Span::new(end, tok.span.end), TokenKind::GroupClose));
+                    }
+                    continue;
+                }
+                _ => unreachable!(),
+            };
+            out.push((tok.span, kind));
+        }
+    }
+
+    let (toks, errors) = lex_all(pattern);
+    let unclosed: Vec<_> = errors
+        .iter()
+        .filter(|e| e.kind == ErrorKind::UnclosedGroup)
+        .map(|e| e.span.start)
+        .collect();
+
+    let mut ret = Vec::new();
+    walk(&toks, &unclosed, &mut ret);
+
+    // an unclosed group is already shown by its opening parenthesis
+    ret.extend(
+        errors
+            .iter()
+            .filter(|e| e.kind != ErrorKind::UnclosedGroup)
+            .map(|e| (e.span, TokenKind::Error)),
+    );
+    ret.sort_by_key(|(span, _)| *span);
+
+    ret
+}
+
+// list the capture groups in the order their opening parentheses appear
+pub fn group_info(toks: &[Token]) -> Vec<GroupInfo> {
+    fn walk(toks: &[Token], out: &mut Vec<GroupInfo>) {
+        for tok in toks {
+            match &tok.elem {
+                ParseElement::Group(grp, name) => {
+                    out.push(GroupInfo {
+                        index: out.len() + 1,
+                        name: name.clone(),
+                        span: tok.span,
+                    });
+                    walk(grp, out);
+                }
+                ParseElement::CaseInsensitive(grp) => walk(grp, out),
+                _ => {}
+            }
+        }
+    }
+
+    let mut ret = Vec::new();
+    walk(toks, &mut ret);
+    ret
+}
+
+// apply a character mapping to every literal and class member, so the pattern lines up with
+// input that is mapped the same way while matching
+pub fn map_chars(toks: &mut [Token], char_map: CharMap) {
+    for tok in toks {
+        match &mut tok.elem {
+            ParseElement::Literal(c) => *c = char_map(*c),
+            ParseElement::Bracket(chars)
+            | ParseElement::NegatedBracket(chars)
+            | ParseElement::WordBoundary(chars)
+            | ParseElement::NotWordBoundary(chars) => {
+                chars.iter_mut().for_each(|c| *c = char_map(*c))
+            }
+            ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => {
+                map_chars(grp, char_map)
+            }
+            _ => {}
+        }
+    }
+}
+
+// Turn every wildcard into a class of the alphabet's characters, so that . only matches those,
+// every negated class into the rest of the alphabet and every predicate into the characters of
+// the alphabet it holds for. Wildcard and predicate edges stay for the unbounded alphabet, which
+// is too large to list. fold says whether the tokens are case-insensitive, as they are inside
+// (?i:...), so that a negated class leaves out the other case of what it excludes too.
+pub fn bound_wildcards(toks: &mut [Token], alphabet: Alphabet, fold: bool) {
+    if alphabet == Alphabet::Unicode {
+        return;
+    }
+    for tok in toks {
+        match &mut tok.elem {
+            ParseElement::Wildcard => {
+                tok.elem = ParseElement::Bracket(('\0'..=alphabet.max()).collect())
+            }
+            ParseElement::NegatedBracket(chars) => {
+                // folding the rest doesn't bring back anything excluded once both cases are
+                let excluded = if fold { fold_all(chars) } else { chars.clone() };
+                let rest: Vec<char> = ('\0'..=alphabet.max())
+                    .filter(|c| !excluded.contains(c))
+                    .collect();
+                // a class of the whole alphabet still excludes everything in it
+                if !rest.is_empty() {
+                    tok.elem = ParseElement::Bracket(rest);
+                }
+            }
+            ParseElement::Predicate(id) => {
+                let holds: Vec<char> = ('\0'..=alphabet.max()).filter(|c| id.test(*c)).collect();
+                if !holds.is_empty() {
+                    tok.elem = ParseElement::BoundedPredicate(*id, holds);
+                }
+            }
+            ParseElement::Group(grp, _) => bound_wildcards(grp, alphabet, fold),
+            ParseElement::CaseInsensitive(grp) => bound_wildcards(grp, alphabet, true),
+            _ => {}
         }
     }
+}
+
+pub fn parse(toks: Vec<Token>) -> Result<Nfa, RegexError> {
+    parse_with(toks, false)
+}
+
+// parse, with fold making every literal and class match regardless of case, as if the whole
+// pattern were in a (?i:...) scope
+pub fn parse_with(toks: Vec<Token>, fold: bool) -> Result<Nfa, RegexError> {
+    let total = group_info(&toks).len() as u64;
+    parse_scope(toks, &mut Vec::new(), total, fold)
+}
 
-    if !stack.is_empty() {
-        panic!("Unfinished stack!");
+// The NFA of one top-level alternative on its own. Only for alternatives without
+// backreferences, which would need the groups of the alternatives before them.
+pub(crate) fn parse_alternative(toks: Vec<Token>) -> Result<Nfa, RegexError> {
+    let total = group_info(&toks).len() as u64;
+    parse_scope(toks, &mut Vec::new(), total, false)
+}
+
+// join the NFAs of a pattern's top-level alternatives the way parse does
+pub(crate) fn union_alternatives(mut alts: Vec<Nfa>) -> Nfa {
+    let mut nfa = alts.pop().unwrap_or_else(Nfa::epsilon);
+    while let Some(mut alt) = alts.pop() {
+        nfa.union(&mut alt);
     }
 
-    curr
+    nfa
 }
 
-pub fn parse(toks: Vec<ParseElement>) -> Nfa {
+// whether the pattern uses ^ or $ anywhere
+pub fn has_anchor(toks: &[Token]) -> bool {
+    toks.iter().any(|tok| match &tok.elem {
+        ParseElement::StartAnchor | ParseElement::EndAnchor => true,
+        ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => has_anchor(grp),
+        _ => false,
+    })
+}
+
+// whether the pattern uses \b or \B, which only the backtracker checks
+pub fn has_word_boundary(toks: &[Token]) -> bool {
+    toks.iter().any(|tok| match &tok.elem {
+        ParseElement::WordBoundary(_) | ParseElement::NotWordBoundary(_) => true,
+        ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => has_word_boundary(grp),
+        _ => false,
+    })
+}
+
+pub fn has_backreference(toks: &[Token]) -> bool {
+    toks.iter().any(|tok| match &tok.elem {
+        ParseElement::BackReference(_) => true,
+        ParseElement::Group(grp, _) | ParseElement::CaseInsensitive(grp) => has_backreference(grp),
+        _ => false,
+    })
+}
+
+// one edge for each of chars between a new start and accepting state
+fn class_nfa(mut chars: Vec<char>) -> Nfa {
+    let mut nfa = Nfa::new(Transition::Literal(chars.pop().unwrap()), None);
+    while let Some(c) = chars.pop() {
+        nfa.union(&mut Nfa::new(Transition::Literal(c), None));
+    }
+
+    nfa
+}
+
+// Groups holds every group opened so far by number, and is None until the group is closed. Inside
+// a (?i:...) scope fold is set, and every literal also matches its other cases (see fold.rs).
+fn parse_scope(
+    toks: Vec<Token>,
+    groups: &mut Vec<Option<Nfa>>,
+    total: u64,
+    fold: bool,
+) -> Result<Nfa, RegexError> {
     let mut curr_nfa = Nfa::empty();
 
     let mut union_stack = Vec::new();
-    let mut groups = Vec::new();
 
     let mut tok_iter = toks.iter().peekable();
 
     while let Some(tok) = tok_iter.next() {
         // check repetition metacharacter
         let modifier = match tok_iter.peek() {
-            Some(m) if m.is_modifier() => Some(tok_iter.next().unwrap().clone()),
+            // laziness only changes where a match ends, not what the automata accept, so only
+            // the NFA's priorities differ (see Nfa::add_modifier)
+            Some(m) if m.elem.is_modifier() => Some(tok_iter.next().unwrap().elem.clone()),
             _ => None,
         };
 
-        match tok {
+        match &tok.elem {
+            ParseElement::Literal(c) if fold && variants(*c).len() > 1 => {
+                let mut new_nfa = class_nfa(variants(*c));
+                new_nfa.set_span(tok.span);
+                new_nfa.add_modifier(modifier);
+                curr_nfa.concat(&mut new_nfa);
+            }
+
             ParseElement::Literal(c) => {
-                curr_nfa.concat(&mut Nfa::new(Transition::Literal(*c), modifier));
+                let mut new_nfa = Nfa::new(Transition::Literal(*c), modifier);
+                new_nfa.set_span(tok.span);
+                curr_nfa.concat(&mut new_nfa);
             }
 
             ParseElement::Union => {
-                union_stack.push(curr_nfa);
+                union_stack.push(curr_nfa.or_epsilon());
                 curr_nfa = Nfa::empty();
             }
 
             ParseElement::Wildcard => {
-                curr_nfa.concat(&mut Nfa::new(Transition::Wildcard, modifier));
+                let mut new_nfa = Nfa::new(Transition::Wildcard, modifier);
+                new_nfa.set_span(tok.span);
+                curr_nfa.concat(&mut new_nfa);
+            }
+
+            ParseElement::StartAnchor => {
+                curr_nfa.concat(&mut Nfa::new(Transition::StartAnchor, modifier));
+            }
+
+            ParseElement::EndAnchor => {
+                curr_nfa.concat(&mut Nfa::new(Transition::EndAnchor, modifier));
+            }
+
+            // the automata don't look at the characters around a position, so they accept a
+            // superset of the pattern and leave the check to the backtracker
+            ParseElement::WordBoundary(_) | ParseElement::NotWordBoundary(_) => {
+                curr_nfa.concat(&mut Nfa::new(Transition::Epsilon, modifier));
             }
 
             ParseElement::Bracket(chars) => {
-                let mut chars = chars.clone();
-                let mut new_nfa = Nfa::new(Transition::Literal(chars.pop().unwrap()), None);
-                while !chars.is_empty() {
-                    new_nfa.union(&mut Nfa::new(
-                        Transition::Literal(chars.pop().unwrap()),
-                        None,
-                    ));
-                }
+                let chars = if fold { fold_all(chars) } else { chars.clone() };
+                let mut new_nfa = class_nfa(chars);
+                new_nfa.set_span(tok.span);
+                new_nfa.add_modifier(modifier);
 
+                curr_nfa.concat(&mut new_nfa);
+            }
+
+            ParseElement::NegatedBracket(chars) => {
+                let chars = if fold { fold_all(chars) } else { chars.clone() };
+                let mut new_nfa = Nfa::new(Transition::Except(intern_class(chars)), None);
+                new_nfa.set_span(tok.span);
                 new_nfa.add_modifier(modifier);
 
                 curr_nfa.concat(&mut new_nfa);
             }
 
-            ParseElement::Group(grp) => {
-                let mut new_nfa = parse(grp.clone());
-                groups.push(new_nfa.clone());
+            // the predicate sees the character as it is, case folding doesn't apply to it
+            ParseElement::Predicate(id) => {
+                let mut new_nfa = Nfa::new(Transition::Pred(*id), modifier);
+                new_nfa.set_span(tok.span);
+                curr_nfa.concat(&mut new_nfa);
+            }
+
+            ParseElement::BoundedPredicate(_, chars) => {
+                let mut new_nfa = class_nfa(chars.clone());
+                new_nfa.set_span(tok.span);
+                new_nfa.add_modifier(modifier);
+
+                curr_nfa.concat(&mut new_nfa);
+            }
+
+            ParseElement::Group(grp, _) => {
+                let index = groups.len();
+                groups.push(None);
+
+                let mut new_nfa = parse_scope(grp.clone(), groups, total, fold)?;
+                groups[index] = Some(new_nfa.clone());
+                new_nfa.add_modifier(modifier);
+                curr_nfa.concat(&mut new_nfa);
+            }
+
+            ParseElement::CaseInsensitive(grp) => {
+                let mut new_nfa = parse_scope(grp.clone(), groups, total, true)?;
                 new_nfa.add_modifier(modifier);
                 curr_nfa.concat(&mut new_nfa);
             }
 
             ParseElement::BackReference(n) => {
-                let mut new_nfa = groups[(*n as usize) - 1].clone();
+                let error = |kind| Err(RegexError::new(kind, tok.span));
+                if *n > total {
+                    return error(ErrorKind::UndefinedGroup(*n));
+                }
+
+                // like PCRE, a reference to a group that hasn't closed yet (either a forward
+                // reference or one from inside the group itself) can never match
+                let mut new_nfa = match groups.get((*n - 1) as usize) {
+                    Some(Some(nfa)) => nfa.clone(),
+                    _ => Nfa::never(),
+                };
+                new_nfa.set_span(tok.span);
                 new_nfa.add_modifier(modifier);
-                new_nfa.reassign_states();
                 curr_nfa.concat(&mut new_nfa);
             }
 
@@ -290,15 +1181,17 @@ pub fn parse(toks: Vec<ParseElement>) -> Nfa {
             | ParseElement::Plus
             | ParseElement::Question
             | ParseElement::Range(_, _)
-            | ParseElement::OpenRange(_) => {
-                panic!("Unexpected modifier!");
+            | ParseElement::OpenRange(_)
+            | ParseElement::Lazy(_) => {
+                return Err(RegexError::new(ErrorKind::NothingToRepeat, tok.span));
             }
         }
     }
 
+    curr_nfa = curr_nfa.or_epsilon();
     while !union_stack.is_empty() {
         curr_nfa.union(&mut union_stack.pop().unwrap());
     }
 
-    curr_nfa
+    Ok(curr_nfa)
 }
@@ -0,0 +1,80 @@
+use std::ptr;
+use std::sync::{Mutex, OnceLock};
+
+use crate::error::ErrorKind;
+use crate::id::PredicateId;
+
+// A test of single characters, e.g. char::is_alphabetic or whether a character is a CJK
+// ideograph, for classes too large to list. Once registered under a name, patterns match one
+// character it holds for with \p{name}, and the DFA treats each combination of predicates as a
+// class of the alphabet (see Transition::PredClass) instead of enumerating characters.
+pub type Predicate = fn(char) -> bool;
+
+// Each predicate is a bit of a PredClass mask, so there can only be this many. Registrations
+// last for the rest of the process.
+pub const MAX_PREDICATES: usize = 64;
+
+static PREDICATES: [OnceLock<(String, Predicate)>; MAX_PREDICATES] =
+    [const { OnceLock::new() }; MAX_PREDICATES];
+
+// Make name refer to predicate in every pattern compiled from now on. Registering the same
+// predicate under the same name again gives the same id, a different one is an error.
+pub fn register(name: &str, predicate: Predicate) -> Result<PredicateId, ErrorKind> {
+    // only registering takes the lock, looking predicates up doesn't
+    static REGISTERING: Mutex<()> = Mutex::new(());
+    let _guard = REGISTERING.lock().unwrap();
+
+    if let Some(id) = lookup(name) {
+        return if ptr::fn_addr_eq(PREDICATES[id.as_usize()].get().unwrap().1, predicate) {
+            Ok(id)
+        } else {
+            Err(ErrorKind::PredicateConflict(name.to_string()))
+        };
+    }
+    let free = PREDICATES
+        .iter()
+        .position(|slot| slot.get().is_none())
+        .ok_or(ErrorKind::TooManyPredicates)?;
+    let _ = PREDICATES[free].set((name.to_string(), predicate));
+
+    Ok(PredicateId::new(free))
+}
+
+// the predicate registered under name, if any
+pub fn lookup(name: &str) -> Option<PredicateId> {
+    PREDICATES
+        .iter()
+        .map_while(OnceLock::get)
+        .position(|(n, _)| n == name)
+        .map(PredicateId::new)
+}
+
+impl PredicateId {
+    pub fn name(self) -> &'static str {
+        &PREDICATES[self.as_usize()].get().expect("registered").0
+    }
+
+    pub fn test(self, c: char) -> bool {
+        (PREDICATES[self.as_usize()].get().expect("registered").1)(c)
+    }
+
+    // its bit in a PredClass mask
+    pub fn bit(self) -> u64 {
+        1 << self.as_usize()
+    }
+}
+
+// The predicates of preds that hold for c, as a PredClass mask
+pub fn mask(preds: &[PredicateId], c: char) -> u64 {
+    preds
+        .iter()
+        .filter(|p| p.test(c))
+        .fold(0, |mask, p| mask | p.bit())
+}
+
+// the predicates in a PredClass mask, in order
+pub fn in_mask(mask: u64) -> impl Iterator<Item = PredicateId> {
+    (0..MAX_PREDICATES)
+        .filter(move |i| mask & (1 << i) != 0)
+        .map(PredicateId::new)
+}
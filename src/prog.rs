@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use crate::dfa::CharSet;
+use crate::fold::{fold_all, variants};
+use crate::id::PredicateId;
+use crate::parse::{group_info, ParseElement, Span, Token};
+use crate::CharMap;
+
+// Instructions for the capture engines. Unlike the NFA, alternatives are ordered: Split tries
+// its first target before its second, which gives leftmost-first (Perl-like) matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    Char(char),
+    Any,
+    Class(Vec<char>),    // sorted
+    NotClass(Vec<char>), // sorted, matches any character outside it
+    Pred(PredicateId),   // any character the predicate holds for
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize), // record the current position in a capture slot
+    CounterInit(usize),
+    // Loop back into the body that follows while the counter is below max, and leave for exit
+    // once it is at least min, trying to leave first if lazy. Used for repetitions too large to
+    // lay out copy by copy.
+    CounterLoop {
+        counter: usize,
+        min: u64,
+        max: Option<u64>,
+        exit: usize,
+        lazy: bool,
+    },
+    // only continue at the start or the end of the whole input
+    StartAnchor,
+    EndAnchor,
+    // only continue where one of the characters around the position is in the (sorted) word
+    // set and the other isn't or is past an end of the input, or for NotWordBoundary where not
+    WordBoundary(Vec<char>),
+    NotWordBoundary(Vec<char>),
+    Match,
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub insts: Vec<Inst>,
+    // two slots (start, end) per group, including group 0
+    pub slots: usize,
+    pub counters: usize,
+    // applied to every input character before it is matched
+    pub char_map: CharMap,
+    // if set, a match can only start at one of these (mapped) characters
+    pub first_chars: Option<CharSet>,
+    // if set, every match starts with this text, so searches jump from one occurrence to the next
+    pub prefix: Option<String>,
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+    // group number by the group's span in the pattern
+    indices: HashMap<Span, usize>,
+    // the compiled body of each group once it is closed, as (instructions, original offset)
+    bodies: Vec<Option<(Vec<Inst>, usize)>>,
+    // repetitions with a bound above this use a counter instead of copies
+    limit: u64,
+    counters: usize,
+    // inside a (?i:...) scope
+    fold: bool,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    // emit a copy of instructions originally compiled at base, optionally dropping captures
+    fn place(&mut self, body: &[Inst], base: usize, saves: bool) {
+        let offset = self.insts.len();
+        let relocate = |t: &usize| t - base + offset;
+
+        for (i, inst) in body.iter().enumerate() {
+            let inst = match inst {
+                Inst::Split(a, b) => Inst::Split(relocate(a), relocate(b)),
+                Inst::Jmp(t) => Inst::Jmp(relocate(t)),
+                Inst::CounterLoop {
+                    counter,
+                    min,
+                    max,
+                    exit,
+                    lazy,
+                } => Inst::CounterLoop {
+                    counter: *counter,
+                    min: *min,
+                    max: *max,
+                    exit: relocate(exit),
+                    lazy: *lazy,
+                },
+                Inst::Save(_) if !saves => Inst::Jmp(offset + i + 1),
+                inst => inst.clone(),
+            };
+            self.insts.push(inst);
+        }
+    }
+
+    // alternatives separated by unions at this level
+    fn alternation(&mut self, toks: &[Token]) {
+        let alts: Vec<&[Token]> = toks
+            .split(|t| matches!(t.elem, ParseElement::Union))
+            .collect();
+
+        let mut jumps = Vec::new();
+        for (i, alt) in alts.iter().enumerate() {
+            if i + 1 == alts.len() {
+                self.sequence(alt);
+                break;
+            }
+
+            let split = self.emit(Inst::Split(0, 0));
+            self.sequence(alt);
+            jumps.push(self.emit(Inst::Jmp(0)));
+            self.insts[split] = Inst::Split(split + 1, self.insts.len());
+        }
+
+        let end = self.insts.len();
+        for jump in jumps {
+            self.insts[jump] = Inst::Jmp(end);
+        }
+    }
+
+    fn sequence(&mut self, toks: &[Token]) {
+        let mut iter = toks.iter().peekable();
+
+        while let Some(tok) = iter.next() {
+            let modifier = match iter.peek() {
+                Some(m) if m.elem.is_modifier() => Some(&iter.next().unwrap().elem),
+                _ => None,
+            };
+
+            // compile the atom once, then lay out as many copies as the modifier needs
+            let base = self.insts.len();
+            self.atom(tok);
+            let body = self.insts.split_off(base);
+            let lazy = matches!(modifier, Some(ParseElement::Lazy(_)));
+
+            match modifier.map(ParseElement::greedy) {
+                None => self.place(&body, base, true),
+                Some(ParseElement::Star) => self.star(&body, base, lazy),
+                Some(ParseElement::Plus) => {
+                    self.place(&body, base, true);
+                    self.star(&body, base, lazy);
+                }
+                Some(ParseElement::Question) => self.question(&body, base, lazy),
+                Some(ParseElement::Range(lower, upper)) if *upper > self.limit => {
+                    self.counted(&body, base, *lower, Some(*upper), lazy)
+                }
+                Some(ParseElement::OpenRange(lower)) if *lower > self.limit => {
+                    self.counted(&body, base, *lower, None, lazy)
+                }
+                Some(ParseElement::Range(lower, upper)) => {
+                    for _ in 0..*lower {
+                        self.place(&body, base, true);
+                    }
+                    for _ in *lower..*upper {
+                        self.question(&body, base, lazy);
+                    }
+                }
+                Some(ParseElement::OpenRange(lower)) => {
+                    for _ in 0..*lower {
+                        self.place(&body, base, true);
+                    }
+                    self.star(&body, base, lazy);
+                }
+                Some(_) => unreachable!(),
+            }
+        }
+    }
+
+    // a split that prefers the body at split + 1 over skipping to end, or the other way if lazy
+    fn split(&mut self, split: usize, end: usize, lazy: bool) {
+        self.insts[split] = if lazy {
+            Inst::Split(end, split + 1)
+        } else {
+            Inst::Split(split + 1, end)
+        };
+    }
+
+    // the word set of \b and \B, folded like \w is inside (?i:...)
+    fn word_set(&self, word: &[char]) -> Vec<char> {
+        if self.fold {
+            return fold_all(word);
+        }
+        let mut word = word.to_vec();
+        word.sort();
+        word.dedup();
+        word
+    }
+
+    fn star(&mut self, body: &[Inst], base: usize, lazy: bool) {
+        let split = self.emit(Inst::Split(0, 0));
+        self.place(body, base, true);
+        self.emit(Inst::Jmp(split));
+        self.split(split, self.insts.len(), lazy);
+    }
+
+    fn counted(&mut self, body: &[Inst], base: usize, min: u64, max: Option<u64>, lazy: bool) {
+        let counter = self.counters;
+        self.counters += 1;
+
+        self.emit(Inst::CounterInit(counter));
+        let head = self.emit(Inst::Fail);
+        self.place(body, base, true);
+        self.emit(Inst::Jmp(head));
+        self.insts[head] = Inst::CounterLoop {
+            counter,
+            min,
+            max,
+            exit: self.insts.len(),
+            lazy,
+        };
+    }
+
+    fn question(&mut self, body: &[Inst], base: usize, lazy: bool) {
+        let split = self.emit(Inst::Split(0, 0));
+        self.place(body, base, true);
+        self.split(split, self.insts.len(), lazy);
+    }
+
+    fn atom(&mut self, tok: &Token) {
+        match &tok.elem {
+            ParseElement::Literal(c) if self.fold && variants(*c).len() > 1 => {
+                self.emit(Inst::Class(variants(*c)));
+            }
+            ParseElement::Literal(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            ParseElement::Wildcard => {
+                self.emit(Inst::Any);
+            }
+            ParseElement::StartAnchor => {
+                self.emit(Inst::StartAnchor);
+            }
+            ParseElement::EndAnchor => {
+                self.emit(Inst::EndAnchor);
+            }
+            ParseElement::WordBoundary(word) => {
+                let word = self.word_set(word);
+                self.emit(Inst::WordBoundary(word));
+            }
+            ParseElement::NotWordBoundary(word) => {
+                let word = self.word_set(word);
+                self.emit(Inst::NotWordBoundary(word));
+            }
+            ParseElement::Bracket(chars) if self.fold => {
+                self.emit(Inst::Class(fold_all(chars)));
+            }
+            ParseElement::Bracket(chars) => {
+                let mut chars = chars.clone();
+                chars.sort();
+                chars.dedup();
+                self.emit(Inst::Class(chars));
+            }
+            ParseElement::NegatedBracket(chars) if self.fold => {
+                self.emit(Inst::NotClass(fold_all(chars)));
+            }
+            ParseElement::NegatedBracket(chars) => {
+                let mut chars = chars.clone();
+                chars.sort();
+                chars.dedup();
+                self.emit(Inst::NotClass(chars));
+            }
+            ParseElement::Predicate(id) => {
+                self.emit(Inst::Pred(*id));
+            }
+            // sorted by bound_wildcards, and not folded
+            ParseElement::BoundedPredicate(_, chars) => {
+                self.emit(Inst::Class(chars.clone()));
+            }
+            ParseElement::Group(grp, _) => {
+                let index = self.indices[&tok.span];
+
+                let start = self.emit(Inst::Save(index * 2));
+                self.alternation(grp);
+                self.emit(Inst::Save(index * 2 + 1));
+
+                self.bodies[index] = Some((self.insts[start..].to_vec(), start));
+            }
+            ParseElement::CaseInsensitive(grp) => {
+                let outer = std::mem::replace(&mut self.fold, true);
+                self.alternation(grp);
+                self.fold = outer;
+            }
+            ParseElement::BackReference(n) => {
+                // re-match the group's pattern without touching its captures
+                match self.bodies.get(*n as usize).cloned().flatten() {
+                    Some((body, base)) => self.place(&body, base, false),
+                    None => {
+                        self.emit(Inst::Fail);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// expects tokens that parse() has already accepted
+pub fn compile(toks: &[Token]) -> Program {
+    compile_with_limit(toks, u64::MAX, false)
+}
+
+// Like compile, but repetitions with a bound above limit loop on a counter, and with fold the
+// whole pattern matches regardless of case as if it were in a (?i:...) scope
+pub fn compile_with_limit(toks: &[Token], limit: u64, fold: bool) -> Program {
+    let groups = group_info(toks);
+
+    let mut compiler = Compiler {
+        insts: Vec::new(),
+        indices: groups.iter().map(|g| (g.span, g.index)).collect(),
+        bodies: vec![None; groups.len() + 1],
+        limit,
+        counters: 0,
+        fold,
+    };
+
+    compiler.emit(Inst::Save(0));
+    compiler.alternation(toks);
+    compiler.emit(Inst::Save(1));
+    compiler.emit(Inst::Match);
+
+    Program {
+        insts: compiler.insts,
+        slots: (groups.len() + 1) * 2,
+        counters: compiler.counters,
+        char_map: std::convert::identity,
+        first_chars: None,
+        prefix: None,
+    }
+}
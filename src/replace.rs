@@ -0,0 +1,96 @@
+// Casing a replacement like the text it replaces, as editors do for "preserve case" replacements:
+// foo -> bar, Foo -> Bar, FOO -> BAR
+
+// how a piece of text is cased, going by its cased letters only
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    // foo
+    Lower,
+    // FOO
+    Upper,
+    // Foo, and also a single capital like F
+    Title,
+    // fOo, FoO, or no cased letters at all; such a replacement is left as it is
+    Mixed,
+}
+
+impl Case {
+    pub fn of(text: &str) -> Self {
+        let mut letters = text
+            .chars()
+            .filter(|c| c.is_lowercase() || c.is_uppercase());
+        let Some(first) = letters.next() else {
+            return Self::Mixed;
+        };
+        let rest: Vec<_> = letters.collect();
+
+        if first.is_lowercase() {
+            if rest.iter().all(|c| c.is_lowercase()) {
+                Self::Lower
+            } else {
+                Self::Mixed
+            }
+        } else if rest.is_empty() {
+            Self::Title
+        } else if rest.iter().all(|c| c.is_uppercase()) {
+            Self::Upper
+        } else if rest.iter().all(|c| c.is_lowercase()) {
+            Self::Title
+        } else {
+            Self::Mixed
+        }
+    }
+
+    // text in this case. Title only capitalizes the first letter and keeps the rest, so that
+    // a replacement like McDonald stays as it is.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Self::Lower => text.to_lowercase(),
+            Self::Upper => text.to_uppercase(),
+            Self::Title => {
+                let mut out = String::with_capacity(text.len());
+                let mut done = false;
+                for c in text.chars() {
+                    if !done && (c.is_lowercase() || c.is_uppercase()) {
+                        out.extend(c.to_uppercase());
+                        done = true;
+                    } else {
+                        out.push(c);
+                    }
+                }
+                out
+            }
+            Self::Mixed => text.to_string(),
+        }
+    }
+}
+
+// replacement cased like matched
+pub fn preserve_case(matched: &str, replacement: &str) -> String {
+    Case::of(matched).apply(replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preserve_case() {
+        let cases = [
+            ("foo", "bar", "bar"),
+            ("Foo", "bar", "Bar"),
+            ("FOO", "bar", "BAR"),
+            ("F", "bar", "Bar"),
+            ("fOo", "bar", "bar"),
+            ("foo", "McDonald", "mcdonald"),
+            ("Foo", "mcDonald", "McDonald"),
+            ("FOO_1", "baz-2", "BAZ-2"),
+            ("42", "Bar", "Bar"),
+            ("ÉTÉ", "hiver", "HIVER"),
+            ("Straße", "weg", "Weg"),
+        ];
+        for (matched, replacement, expect) in cases {
+            assert_eq!(preserve_case(matched, replacement), expect, "{matched}");
+        }
+    }
+}
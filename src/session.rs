@@ -0,0 +1,440 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::meta::{Engine, InvalidUtf8};
+use crate::parse::SyntaxConfig;
+use crate::transition_table::{Alphabet, TableBackend};
+use crate::RegexBuilder;
+
+// how many tested strings a session keeps
+pub const MAX_HISTORY: usize = 50;
+
+// What an interactive session was working on: the pattern with its options and the strings tested
+// against it, saved as a small TOML file so a demo can be prepared ahead or work resumed later.
+// A char map is a function and isn't saved.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub options: RegexBuilder,
+    // oldest first
+    pub history: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    // what is wrong with the file, and on which line (from 1)
+    Syntax { line: usize, message: String },
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Syntax { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// the TOML values a session uses
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// parse the value at the start of s, returning it and the rest of s
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    let s = s.trim_start();
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::Str(out), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let c = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or(format!("invalid escape \\u{hex}"))?;
+                        out.push(c);
+                    }
+                    _ => return Err("invalid escape".to_string()),
+                },
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    } else if let Some(mut rest) = s.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_value(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected , or ] in array".to_string());
+            }
+        }
+    } else {
+        let end = s
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(s.len());
+        let (word, rest) = s.split_at(end);
+        let value = match word {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => Value::Int(
+                word.replace('_', "")
+                    .parse()
+                    .map_err(|_| format!("unexpected value {word:?}"))?,
+            ),
+        };
+        Ok((value, rest))
+    }
+}
+
+impl Session {
+    pub fn new(options: RegexBuilder) -> Self {
+        Self {
+            options,
+            history: Vec::new(),
+        }
+    }
+
+    // Change one option with a line as it would appear under [options], e.g. `alphabet =
+    // "ascii"`. The session is left as it was if the line is wrong. Like saving and loading, this
+    // drops a char map.
+    pub fn set(&mut self, assignment: &str) -> Result<(), SessionError> {
+        // to_toml ends in the [options] table, so the line overrides what is there
+        let text = format!("{}{}\n", self.to_toml(), assignment.trim());
+        *self = Self::from_toml(&text).map_err(|e| match e {
+            SessionError::Syntax { message, .. } => SessionError::Syntax { line: 1, message },
+            e => e,
+        })?;
+
+        Ok(())
+    }
+
+    // remember a tested string, forgetting the oldest past MAX_HISTORY
+    pub fn push(&mut self, input: &str) {
+        self.history.push(input.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        let o = &self.options;
+        let history: Vec<_> = self.history.iter().map(|s| quote(s)).collect();
+        let engine = match o.get_engine() {
+            Engine::Auto => "auto",
+            Engine::Dfa => "dfa",
+            Engine::LazyDfa => "lazy_dfa",
+            Engine::Backtrack => "backtrack",
+        };
+        let backend = match o.get_table_backend() {
+            TableBackend::Hash => "hash",
+            TableBackend::BTree => "btree",
+            TableBackend::Dense => "dense",
+        };
+        let invalid_utf8 = match o.get_invalid_utf8() {
+            InvalidUtf8::Reject => "reject",
+            InvalidUtf8::Replace => "replace",
+        };
+        let alphabet = match o.get_alphabet() {
+            Alphabet::Ascii => "ascii",
+            Alphabet::Latin1 => "latin1",
+            Alphabet::Unicode => "unicode",
+        };
+        let syntax = o.get_syntax();
+
+        format!(
+            "pattern = {}\nhistory = [{}]\n\n[options]\nmax_expanded_repeat = {}\ndfa_state_limit = {}\n\
+             engine = \"{engine}\"\nfull_match = {}\ntable_backend = \"{backend}\"\ninvalid_utf8 = \
+             \"{invalid_utf8}\"\nalphabet = \"{alphabet}\"\nbackreferences = {}\n\
+             unbounded_repeats = {}\nlarge_repeats = {}\nword_boundaries = {}\n\
+             case_insensitive = {}\nword_chars = {}\nspace_chars = {}\n",
+            quote(o.get_pattern()),
+            history.join(", "),
+            o.get_max_expanded_repeat(),
+            o.get_dfa_state_limit(),
+            o.get_full_match(),
+            syntax.backreferences,
+            syntax.unbounded_repeats,
+            syntax.large_repeats,
+            syntax.word_boundaries,
+            o.get_case_insensitive(),
+            quote(&o.get_word_chars().iter().collect::<String>()),
+            quote(&o.get_space_chars().iter().collect::<String>()),
+        )
+    }
+
+    // Read a session written by to_toml. Options that are left out keep their defaults.
+    pub fn from_toml(text: &str) -> Result<Self, SessionError> {
+        let mut pattern = None;
+        let mut history = Vec::new();
+        let mut options = RegexBuilder::new("");
+        let mut syntax = SyntaxConfig::default();
+        let mut table = "";
+
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| SessionError::Syntax {
+                line: i + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                table = match name.trim() {
+                    "options" => "options",
+                    name => return Err(error(format!("unknown table [{name}]"))),
+                };
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected key = value".to_string()))?;
+            let (value, rest) = parse_value(value).map_err(error)?;
+            let rest = rest.trim();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error(format!("unexpected {rest:?} after the value")));
+            }
+
+            let word = |value: &Value| match value {
+                Value::Str(s) => Ok(s.clone()),
+                _ => Err(error("expected a string".to_string())),
+            };
+            let flag = |value: &Value| match value {
+                Value::Bool(b) => Ok(*b),
+                _ => Err(error("expected true or false".to_string())),
+            };
+            let unknown = |what: &str| Err(error(format!("unknown {what}")));
+            match (table, key.trim()) {
+                ("", "pattern") => pattern = Some(word(&value)?),
+                ("", "history") => match value {
+                    Value::Array(items) => {
+                        history = items.iter().map(word).collect::<Result<_, _>>()?;
+                    }
+                    _ => return Err(error("expected an array of strings".to_string())),
+                },
+                ("options", "max_expanded_repeat") => match value {
+                    Value::Int(n) => {
+                        options.max_expanded_repeat(n);
+                    }
+                    _ => return Err(error("expected a number".to_string())),
+                },
+                ("options", "dfa_state_limit") => match value {
+                    Value::Int(n) => {
+                        options.dfa_state_limit(n as usize);
+                    }
+                    _ => return Err(error("expected a number".to_string())),
+                },
+                ("options", "engine") => {
+                    options.engine(match word(&value)?.as_str() {
+                        "auto" => Engine::Auto,
+                        "dfa" => Engine::Dfa,
+                        "lazy_dfa" => Engine::LazyDfa,
+                        "backtrack" => Engine::Backtrack,
+                        _ => return unknown("engine"),
+                    });
+                }
+                ("options", "full_match") => {
+                    options.full_match(flag(&value)?);
+                }
+                ("options", "case_insensitive") => {
+                    options.case_insensitive(flag(&value)?);
+                }
+                ("options", "table_backend") => {
+                    options.table_backend(match word(&value)?.as_str() {
+                        "hash" => TableBackend::Hash,
+                        "btree" => TableBackend::BTree,
+                        "dense" => TableBackend::Dense,
+                        _ => return unknown("table backend"),
+                    });
+                }
+                ("options", "invalid_utf8") => {
+                    options.invalid_utf8(match word(&value)?.as_str() {
+                        "reject" => InvalidUtf8::Reject,
+                        "replace" => InvalidUtf8::Replace,
+                        _ => return unknown("invalid_utf8 policy"),
+                    });
+                }
+                ("options", "alphabet") => {
+                    options.alphabet(match word(&value)?.as_str() {
+                        "ascii" => Alphabet::Ascii,
+                        "latin1" => Alphabet::Latin1,
+                        "unicode" => Alphabet::Unicode,
+                        _ => return unknown("alphabet"),
+                    });
+                }
+                ("options", "word_chars") => {
+                    options.word_chars(&word(&value)?.chars().collect::<Vec<_>>());
+                }
+                ("options", "space_chars") => {
+                    options.space_chars(&word(&value)?.chars().collect::<Vec<_>>());
+                }
+                ("options", "backreferences") => syntax.backreferences = flag(&value)?,
+                ("options", "unbounded_repeats") => syntax.unbounded_repeats = flag(&value)?,
+                ("options", "large_repeats") => syntax.large_repeats = flag(&value)?,
+                ("options", "word_boundaries") => syntax.word_boundaries = flag(&value)?,
+                (_, key) => return unknown(&format!("key {key:?}")),
+            }
+        }
+
+        let pattern = pattern.ok_or(SessionError::Syntax {
+            line: text.lines().count(),
+            message: "missing pattern".to_string(),
+        })?;
+        let mut builder = RegexBuilder::new(&pattern);
+        builder
+            .max_expanded_repeat(options.get_max_expanded_repeat())
+            .dfa_state_limit(options.get_dfa_state_limit())
+            .syntax(syntax)
+            .engine(options.get_engine())
+            .full_match(options.get_full_match())
+            .table_backend(options.get_table_backend())
+            .invalid_utf8(options.get_invalid_utf8())
+            .alphabet(options.get_alphabet())
+            .case_insensitive(options.get_case_insensitive())
+            .word_chars(options.get_word_chars())
+            .space_chars(options.get_space_chars());
+
+        Ok(Self {
+            options: builder,
+            history,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_toml())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SessionError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut options = RegexBuilder::new("\"a\\\\d+\"\t(?<x>é)");
+        options
+            .engine(Engine::Backtrack)
+            .max_expanded_repeat(7)
+            .dfa_state_limit(5)
+            .alphabet(Alphabet::Latin1)
+            .case_insensitive(true)
+            .space_chars(&[' ', '\t', '\n'])
+            .syntax(SyntaxConfig::linear());
+        let mut session = Session::new(options);
+        for input in ["plain", "quote\" and \\", "tab\tnewline\n\u{1}", ""] {
+            session.push(input);
+        }
+
+        let text = session.to_toml();
+        assert!(
+            text.starts_with("pattern = \"\\\"a\\\\\\\\d+\\\"\\t(?<x>é)\"\nhistory = [\"plain\", ")
+        );
+        let loaded = Session::from_toml(&text).unwrap();
+        assert_eq!(loaded.history, session.history);
+        assert_eq!(loaded.to_toml(), text);
+        assert_eq!(loaded.options.get_engine(), Engine::Backtrack);
+        assert_eq!(loaded.options.get_dfa_state_limit(), 5);
+        assert!(loaded.options.get_case_insensitive());
+        assert_eq!(loaded.options.get_syntax(), SyntaxConfig::linear());
+        assert_eq!(loaded.options.get_space_chars(), [' ', '\t', '\n']);
+
+        for _ in 0..MAX_HISTORY {
+            session.push("x");
+        }
+        assert_eq!(session.history.len(), MAX_HISTORY);
+    }
+
+    #[test]
+    fn test_errors() {
+        let minimal = Session::from_toml("# comment\npattern = \"ab\" # trailing\n").unwrap();
+        assert_eq!(minimal.options.get_pattern(), "ab");
+        assert!(minimal.history.is_empty());
+
+        let error = |text: &str| Session::from_toml(text).unwrap_err().to_string();
+        assert_eq!(error("history = []\n"), "line 1: missing pattern");
+        assert_eq!(
+            error("pattern = \"a\"\n[options]\nengine = \"pike\"\n"),
+            "line 3: unknown engine"
+        );
+        assert_eq!(error("pattern = \"a\nb\"\n"), "line 1: unterminated string");
+        assert_eq!(
+            error("pattern = \"a\" x\n"),
+            "line 1: unexpected \"x\" after the value"
+        );
+        assert_eq!(error("colour = 3\n"), "line 1: unknown key \"colour\"");
+    }
+
+    #[test]
+    fn test_set() {
+        let mut session = Session::new(RegexBuilder::new("ab"));
+        session.push("AB");
+        session.set("case_insensitive = true").unwrap();
+        session.set(" alphabet = \"ascii\" ").unwrap();
+        assert!(session.options.get_case_insensitive());
+        assert_eq!(session.options.get_alphabet(), Alphabet::Ascii);
+        assert_eq!(session.history, ["AB"]);
+        assert!(session.options.build().unwrap().is_match("AB"));
+
+        let error = session.set("engine = \"pike\"").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: unknown engine");
+        assert_eq!(
+            session.set("pattern = \"x\"").unwrap_err().to_string(),
+            "line 1: unknown key \"pattern\""
+        );
+        assert_eq!(session.options.get_engine(), Engine::Auto);
+        assert_eq!(session.options.get_pattern(), "ab");
+    }
+}
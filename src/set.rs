@@ -0,0 +1,313 @@
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::dfa::Dfa;
+use crate::error::RegexError;
+use crate::id::PatternId;
+use crate::nfa::Nfa;
+use crate::{CharMap, Regex};
+
+// Several patterns checked against the same haystack, reporting which of them match. Patterns
+// can be added and removed while the set is in use: each keeps the id it was given, and adding
+// one only compiles that pattern, while the combined DFA is rebuilt the next time it is asked
+// for.
+#[derive(Debug, Default)]
+pub struct RegexSet {
+    // by id, None where a pattern was removed. Ids aren't reused.
+    regexes: Vec<Option<Regex>>,
+    dfa: OnceLock<Dfa>,
+}
+
+impl RegexSet {
+    pub fn new<I, S>(patterns: I) -> Result<Self, RegexError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut set = Self::default();
+        for p in patterns {
+            set.add(p.as_ref())?;
+        }
+
+        Ok(set)
+    }
+
+    // compile pattern and add it, with the next id
+    pub fn add(&mut self, pattern: &str) -> Result<PatternId, RegexError> {
+        Ok(self.add_regex(Regex::new(pattern)?))
+    }
+
+    pub fn add_regex(&mut self, regex: Regex) -> PatternId {
+        self.dfa.take();
+        self.regexes.push(Some(regex));
+        PatternId::new(self.regexes.len() - 1)
+    }
+
+    // The pattern with this id, if it is still in the set. The other patterns keep their ids.
+    pub fn remove(&mut self, id: PatternId) -> Option<Regex> {
+        let regex = self.regexes.get_mut(id.as_usize())?.take()?;
+        self.dfa.take();
+        Some(regex)
+    }
+
+    // the number of patterns in the set, not counting removed ones
+    pub fn len(&self) -> usize {
+        self.regexes.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn live(&self) -> impl Iterator<Item = (PatternId, &Regex)> {
+        self.regexes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| Some((PatternId::new(i), r.as_ref()?)))
+    }
+
+    // the patterns in the set by id
+    pub fn patterns(&self) -> impl Iterator<Item = (PatternId, &str)> {
+        self.live().map(|(id, r)| (id, r.pattern()))
+    }
+
+    pub fn pattern(&self, id: PatternId) -> Option<&str> {
+        Some(self.regexes.get(id.as_usize())?.as_ref()?.pattern())
+    }
+
+    // One minimized DFA for all the patterns, anchored at the start of the input, whose accepting
+    // states tell which patterns match there (see Dfa::accepting_patterns). Like each pattern's
+    // own DFA it accepts a superset for repetitions over max_expanded_repeat. Built on first use
+    // and kept until the set changes. Each pattern keeps the options it was built with, except
+    // that the DFA maps the input with one char map only: the one the patterns share, or none
+    // if they don't all have the same.
+    pub fn dfa(&self) -> &Dfa {
+        self.dfa.get_or_init(|| {
+            let nfas = self
+                .regexes
+                .iter()
+                // removed patterns stay as placeholders so the rest keep their ids
+                .map(|r| r.as_ref().map_or_else(Nfa::never, |r| r.nfa().clone()))
+                .collect();
+
+            let mut dfa = Dfa::from_patterns(nfas);
+            dfa.minimize();
+            if let Some(char_map) = self.shared_char_map() {
+                dfa.set_char_map(char_map);
+            }
+
+            dfa
+        })
+    }
+
+    // the char map every pattern was built with, if they agree on one
+    fn shared_char_map(&self) -> Option<CharMap> {
+        let mut maps = self.live().map(|(_, r)| r.options().get_char_map());
+        let first = maps.next()??;
+        maps.all(|map| map.is_some_and(|map| std::ptr::fn_addr_eq(map, first)))
+            .then_some(first)
+    }
+
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.live().any(|(_, r)| r.is_match(haystack))
+    }
+
+    // the ids of the patterns that match somewhere in haystack, in order
+    pub fn matches(&self, haystack: &str) -> Vec<PatternId> {
+        self.live()
+            .filter(|(_, r)| r.is_match(haystack))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    // The same as matches, but the patterns are split across scoped threads, one chunk per
+    // available core. Worth it when there are many patterns or a long haystack.
+    pub fn matches_parallel(&self, haystack: &str) -> Vec<PatternId> {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = self.regexes.len().div_ceil(threads).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .regexes
+                .chunks(chunk)
+                .enumerate()
+                .map(|(n, regexes)| {
+                    scope.spawn(move || {
+                        (0..regexes.len())
+                            .filter(|i| regexes[*i].as_ref().is_some_and(|r| r.is_match(haystack)))
+                            .map(|i| PatternId::new(n * chunk + i))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().expect("pattern thread panicked"))
+                .collect()
+        })
+    }
+}
+
+impl FromIterator<Regex> for RegexSet {
+    fn from_iter<I: IntoIterator<Item = Regex>>(iter: I) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<Regex> for RegexSet {
+    fn extend<I: IntoIterator<Item = Regex>>(&mut self, iter: I) {
+        for regex in iter {
+            self.add_regex(regex);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_parallel() {
+        let patterns: Vec<String> = (0..40).map(|i| format!("(x{i})\\1|y{i}")).collect();
+        let set = RegexSet::new(&patterns).unwrap();
+
+        let hay = "y3 x17x17 y39";
+        let ids: Vec<_> = set.matches(hay).iter().map(|id| id.as_usize()).collect();
+        assert_eq!(ids, vec![3, 17, 39]);
+        assert_eq!(set.matches_parallel(hay), set.matches(hay));
+        assert!(set.matches_parallel("nothing").is_empty());
+
+        assert!(RegexSet::new(["a", "(b"]).is_err());
+    }
+
+    #[test]
+    fn test_add_remove() {
+        let mut set: RegexSet = ["foo", "ba+r"]
+            .map(|p| Regex::new(p).unwrap())
+            .into_iter()
+            .collect();
+        assert_eq!(set.add("baz").unwrap(), PatternId::new(2));
+        assert!(set.add("(").is_err());
+        let id = PatternId::new;
+        let patterns = |set: &RegexSet, input: &str| {
+            let dfa = set.dfa();
+            let end = input
+                .chars()
+                .try_fold(dfa.start(), |s, c| dfa.next_state(s, c));
+            end.map_or(Vec::new(), |s| dfa.accepting_patterns(s))
+        };
+
+        assert_eq!(set.matches("foo baaar baz"), [id(0), id(1), id(2)]);
+        assert_eq!(patterns(&set, "foo"), [id(0)]);
+
+        assert_eq!(set.remove(id(0)).unwrap().pattern(), "foo");
+        assert!(set.remove(id(0)).is_none());
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.pattern(id(0)), None);
+        assert_eq!(set.matches("foo baaar baz"), [id(1), id(2)]);
+        assert_eq!(set.matches_parallel("foo baaar baz"), [id(1), id(2)]);
+        assert_eq!(patterns(&set, "foo"), []);
+
+        // the ids after a removed pattern don't shift, in the DFA either
+        set.extend([Regex::new("fo+").unwrap()]);
+        let listed: Vec<_> = set.patterns().collect();
+        assert_eq!(listed, [(id(1), "ba+r"), (id(2), "baz"), (id(3), "fo+")]);
+        assert_eq!(patterns(&set, "baz"), [id(2)]);
+        assert_eq!(patterns(&set, "foo"), [id(3)]);
+    }
+
+    #[test]
+    fn test_accepting_patterns() {
+        let set = RegexSet::new(["ab", "a+", "b|ab", "c"]).unwrap();
+        let dfa = set.dfa();
+        let patterns = |input: &str| {
+            let end = input
+                .chars()
+                .try_fold(dfa.start(), |s, c| dfa.next_state(s, c));
+            end.map_or(Vec::new(), |s| dfa.accepting_patterns(s))
+        };
+        let ids = |ids: &[usize]| ids.iter().map(|i| PatternId::new(*i)).collect::<Vec<_>>();
+
+        assert_eq!(patterns("ab"), ids(&[0, 2]));
+        assert_eq!(patterns("aa"), ids(&[1]));
+        assert_eq!(patterns("b"), ids(&[2]));
+        assert_eq!(patterns(""), ids(&[]));
+        // "b" and "c" lead to the same language but to different patterns
+        assert_ne!(
+            dfa.next_state(dfa.start(), 'b'),
+            dfa.next_state(dfa.start(), 'c')
+        );
+
+        let accepting = dfa.accept_states();
+        assert!(accepting
+            .iter()
+            .all(|s| !dfa.accepting_patterns(*s).is_empty()));
+        let single = crate::compile_regex("ab");
+        assert_eq!(
+            single.accepting_patterns(single.accept_states()[0]),
+            ids(&[0])
+        );
+    }
+
+    #[test]
+    fn test_dfa_keeps_options() {
+        use crate::transition_table::Alphabet;
+        use crate::RegexBuilder;
+
+        let build = |pattern: &str, configure: fn(&mut RegexBuilder)| {
+            let mut builder = RegexBuilder::new(pattern);
+            configure(&mut builder);
+            builder.build().unwrap()
+        };
+        let set: RegexSet = [
+            build("abc", |b| {
+                b.case_insensitive(true);
+            }),
+            build("\\w+!", |b| {
+                b.word_chars(&['-']);
+            }),
+            build("x[^y]", |b| {
+                b.alphabet(Alphabet::Ascii);
+            }),
+            build("d", |_| {}),
+        ]
+        .into_iter()
+        .collect();
+        let dfa = set.dfa();
+        let patterns = |input: &str| {
+            let end = input
+                .chars()
+                .try_fold(dfa.start(), |s, c| dfa.next_state(s, c));
+            end.map_or(Vec::new(), |s| dfa.accepting_patterns(s))
+        };
+
+        for input in ["ABC", "--!", "ab!", "xé", "xz", "D"] {
+            let full: Vec<_> = set
+                .live()
+                .filter(|(_, r)| r.is_full_match(input))
+                .map(|(id, _)| id)
+                .collect();
+            assert_eq!(patterns(input), full, "{input}");
+        }
+        assert_eq!(patterns("ABC"), [PatternId::new(0)]);
+
+        // a char map shared by every pattern applies to the input too
+        let lower: RegexSet = ["ab", "c+"]
+            .map(|p| {
+                RegexBuilder::new(p)
+                    .char_map(|c| c.to_ascii_lowercase())
+                    .build()
+                    .unwrap()
+            })
+            .into_iter()
+            .collect();
+        let dfa = lower.dfa();
+        let end = "CC"
+            .chars()
+            .try_fold(dfa.start(), |s, c| dfa.next_state(s, c));
+        assert_eq!(dfa.accepting_patterns(end.unwrap()), [PatternId::new(1)]);
+    }
+}
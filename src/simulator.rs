@@ -0,0 +1,200 @@
+use std::fmt;
+
+use crate::backtrack;
+use crate::captures::Match;
+use crate::dense::{DenseDfa, DenseDfaRef};
+use crate::dfa::Dfa;
+use crate::lazy_dfa::LazyDfa;
+use crate::meta::Cache;
+use crate::nfa::Nfa;
+use crate::prog::Program;
+use crate::Regex;
+
+// An engine that finds the first match in a haystack, so code can be written once for every
+// engine, e.g. to compare them or to benchmark them, and other crates can plug in engines of
+// their own. Engines can disagree on which match comes first: the automata give the leftmost-
+// longest one, the backtracker (Program) and Regex the leftmost-first one. The trait is object
+// safe, so engines can be chosen at runtime as &dyn Simulator.
+pub trait Simulator {
+    // The first match in input, or None if there is none. cache holds buffers a thread can reuse
+    // between searches, which engines that don't need any ignore. An engine that stops without
+    // an answer, e.g. because a search takes too long, returns a MatchError.
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        cache: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError>;
+
+    // try_search with a fresh cache, panicking if the engine gives up
+    fn search<'h>(&self, input: &'h str) -> Option<Match<'h>> {
+        self.try_search(input, &mut Cache::new())
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+// Why an engine couldn't answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchError {
+    // the engine stopped at offset, e.g. because it ran out of time or memory
+    GaveUp { offset: usize },
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GaveUp { offset } => write!(f, "the search gave up at {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for MatchError {}
+
+// Dfa::find, leftmost-longest
+impl Simulator for Dfa {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        _: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find(input))
+    }
+}
+
+// DenseDfa::find, the same matches as the Dfa it was built from
+impl Simulator for DenseDfa {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        _: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find(input))
+    }
+}
+
+// DenseDfaRef::find, the same matches as the DenseDfa it was saved from
+impl Simulator for DenseDfaRef<'_> {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        _: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find(input))
+    }
+}
+
+// Nfa::find, leftmost-longest by simulating the NFA on sets of states
+impl Simulator for Nfa {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        _: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find(input))
+    }
+}
+
+// LazyDfa::find, leftmost-longest, with the states it builds kept in cache
+impl Simulator for LazyDfa {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        cache: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find(input, &mut cache.lazy))
+    }
+}
+
+// the backtracker on its own, without the DFA ruling out haystacks first
+impl Simulator for Program {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        cache: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        let found = backtrack::search_with(&mut cache.scratch, self, input, 0, false, None, false);
+        Ok(found.and_then(|found| Some(Match::new(input, found.slots[0]?, found.slots[1]?))))
+    }
+}
+
+// Regex::find, with whichever engine the Regex was built for
+impl Simulator for Regex {
+    fn try_search<'h>(
+        &self,
+        input: &'h str,
+        cache: &mut Cache,
+    ) -> Result<Option<Match<'h>>, MatchError> {
+        Ok(self.find_at_cached(input, 0, cache))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::compile;
+
+    fn ranges(engine: &dyn Simulator, inputs: &[&str]) -> Vec<Option<Range<usize>>> {
+        let mut cache = Cache::new();
+        inputs
+            .iter()
+            .map(|input| {
+                let found = engine.try_search(input, &mut cache).unwrap();
+                found.map(|m| m.range())
+            })
+            .collect()
+    }
+
+    // a backend from outside the crate: a fixed string, found with str::find
+    struct Substring(&'static str);
+
+    impl Simulator for Substring {
+        fn try_search<'h>(
+            &self,
+            input: &'h str,
+            _: &mut Cache,
+        ) -> Result<Option<Match<'h>>, MatchError> {
+            match input.len() {
+                len if len > 8 => Err(MatchError::GaveUp { offset: 8 }),
+                _ => Ok(input
+                    .find(self.0)
+                    .map(|i| Match::new(input, i, i + self.0.len()))),
+            }
+        }
+    }
+
+    #[test]
+    fn test_engines_agree() {
+        let pattern = "^x|a(b|bc)d?|c$";
+        let inputs = ["xab", "zabcd", "abc", "zc", "zcz", "", "yy"];
+        let compilation = compile(pattern).unwrap();
+        let regex = Regex::new(pattern).unwrap();
+        let program = crate::prog::compile(&compilation.tokens);
+
+        let longest = ranges(&compilation.nfa, &inputs);
+        assert_eq!(longest, ranges(&compilation.dfa, &inputs));
+        assert_eq!(longest, ranges(regex.dfa(), &inputs));
+        assert_eq!(longest, ranges(&DenseDfa::new(regex.dfa()), &inputs));
+        assert_eq!(
+            longest,
+            ranges(&LazyDfa::from_nfa(&compilation.nfa), &inputs)
+        );
+        assert_eq!(longest[1], Some(1..5));
+
+        // leftmost-first stops at the first alternative that matches
+        let first = ranges(&regex, &inputs);
+        assert_eq!(first, ranges(&program, &inputs));
+        assert_eq!(first[1], Some(1..3));
+        assert_eq!(first[0], Some(0..1));
+        assert_eq!(first[3..], [Some(1..2), None, None, None]);
+
+        let engines: Vec<Box<dyn Simulator>> = vec![Box::new(regex), Box::new(Substring("ab"))];
+        for engine in &engines {
+            assert_eq!(engine.search("zzab").map(|m| m.range()), Some(2..4));
+        }
+        assert_eq!(
+            engines[1].try_search("a long haystack", &mut Cache::new()),
+            Err(MatchError::GaveUp { offset: 8 })
+        );
+    }
+}
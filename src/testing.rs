@@ -0,0 +1,76 @@
+use std::sync::OnceLock;
+
+use crate::Regex;
+
+// Support for assert_matches! and assert_rejects!, which downstream crates use through the
+// testing feature.
+
+// Whether regex matches all of input, panicking with the simulation trace if that isn't expect.
+// The regex is compiled once per call site: cell holds it, unless the call site's pattern varies.
+pub fn check(cell: &'static OnceLock<Regex>, pattern: &str, input: &str, expect: bool) {
+    let compile =
+        || Regex::new(pattern).unwrap_or_else(|e| panic!("invalid pattern {pattern:?}: {e}"));
+    let local;
+    let cached = cell.get_or_init(compile);
+    let regex = if cached.pattern() == pattern {
+        cached
+    } else {
+        local = compile();
+        &local
+    };
+
+    if regex.is_full_match(input) == expect {
+        return;
+    }
+
+    let dfa = regex.dfa();
+    let (verb, why) = match dfa.simulate(input.to_string()) {
+        Err(e) if expect => ("match", format!("{e}\n")),
+        _ if expect => ("match", String::new()),
+        _ => ("reject", String::new()),
+    };
+    panic!(
+        "assertion failed: {pattern:?} should {verb} {input:?}\n{why}trace:\n{}",
+        dfa.trace(input)
+    );
+}
+
+// Assert that a pattern matches the whole of each input, e.g. assert_matches!("a+b", "aab").
+// On failure the message shows why the DFA rejected the input and the path it took.
+#[macro_export]
+macro_rules! assert_matches {
+    ($pattern:expr, $($input:expr),+ $(,)?) => {{
+        static REGEX: ::std::sync::OnceLock<$crate::Regex> = ::std::sync::OnceLock::new();
+        $($crate::testing::check(&REGEX, $pattern, $input, true);)+
+    }};
+}
+
+// assert that a pattern doesn't match the whole of any input, e.g. assert_rejects!("a+b", "ab!")
+#[macro_export]
+macro_rules! assert_rejects {
+    ($pattern:expr, $($input:expr),+ $(,)?) => {{
+        static REGEX: ::std::sync::OnceLock<$crate::Regex> = ::std::sync::OnceLock::new();
+        $($crate::testing::check(&REGEX, $pattern, $input, false);)+
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    #[test]
+    fn test_assert_macros() {
+        assert_matches!("a(b|c)+", "ab", "acbc");
+        assert_rejects!("a(b|c)+", "a", "abd", "");
+        // a different pattern at the same call site isn't served from the cache
+        for pattern in ["x", "y"] {
+            assert_matches!(pattern, pattern);
+        }
+
+        let err = panic::catch_unwind(|| assert_matches!("a(b|c)+", "abx")).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("assertion failed: \"a(b|c)+\" should match \"abx\"\n"));
+        assert!(msg.contains("trace:\n  0: d0 -'a'-> d"));
+        assert!(msg.ends_with("-'x'-> no edge\n  stuck"));
+    }
+}
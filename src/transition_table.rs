@@ -1,20 +1,70 @@
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 use std::hash::Hash;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
-pub trait TransitionTable<T> {
+use crate::dot::{class_label, escape_char, LabelEscape};
+use crate::id::{PredicateId, StateId};
+use crate::predicate;
+
+// Where an automaton keeps its edges. The trait is object safe, so an automaton can hold any
+// backend as a Box<dyn TransitionTable<S>>: HashMap is the fastest to build, BTreeMap iterates in
+// a deterministic order, and DenseTable (for states numbered from 0) is a Vec of sorted rows,
+// which takes the least memory and needs no hashing to look an edge up.
+pub trait TransitionTable<S>: fmt::Debug + Send + Sync {
     // add a new transition to the table
-    fn add_transition(&mut self, start: T, transition: Transition, end: T);
+    fn add_transition(&mut self, start: S, transition: Transition, end: S);
 
     // replace all instances of state old with state new
-    fn rename(&mut self, old: T, new: T);
+    fn rename(&mut self, old: S, new: S);
+
+    // the states the edge for transition out of start leads to
+    fn targets(&self, start: &S, transition: &Transition) -> Vec<S>;
+
+    // the first of targets, the only one in a deterministic automaton
+    fn target(&self, start: &S, transition: &Transition) -> Option<S>;
+
+    // every edge out of start, sorted by transition and then by end state
+    fn edges(&self, start: &S) -> Vec<(Transition, S)>;
+
+    fn boxed_clone(&self) -> Box<dyn TransitionTable<S>>;
+}
+
+impl<S: Clone + 'static> Clone for Box<dyn TransitionTable<S>> {
+    fn clone(&self) -> Self {
+        self.boxed_clone()
+    }
+}
+
+// the backends of TransitionTable, see there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableBackend {
+    #[default]
+    Hash,
+    BTree,
+    Dense,
+}
+
+impl TableBackend {
+    // an empty table of this kind
+    pub fn table<S, T>(self) -> Box<dyn TransitionTable<S>>
+    where
+        S: Ord + Hash + Clone + fmt::Debug + Send + Sync + Index + 'static,
+        T: StateContainer<S> + Clone + fmt::Debug + Send + Sync + 'static,
+    {
+        match self {
+            Self::Hash => Box::new(HashMap::<S, HashMap<Transition, T>>::new()),
+            Self::BTree => Box::new(BTreeMap::<S, BTreeMap<Transition, T>>::new()),
+            Self::Dense => Box::new(DenseTable::<T>::default()),
+        }
+    }
 }
 
 // Transition table with BTreeSet/Vec end states
 impl<S, T> TransitionTable<S> for HashMap<S, HashMap<Transition, T>>
 where
-    S: PartialEq + Eq + Hash + Clone,
-    T: StateContainer<S>,
+    S: PartialEq + Eq + Hash + Clone + Ord + fmt::Debug + Send + Sync + 'static,
+    T: StateContainer<S> + Clone + fmt::Debug + Send + Sync + 'static,
 {
     fn add_transition(&mut self, start: S, transition: Transition, end: S) {
         self.entry(start)
@@ -38,6 +88,183 @@ where
             }
         }
     }
+
+    fn targets(&self, start: &S, transition: &Transition) -> Vec<S> {
+        self.get(start)
+            .and_then(|row| row.get(transition))
+            .map_or_else(Vec::new, |ends| ends.states())
+    }
+
+    fn target(&self, start: &S, transition: &Transition) -> Option<S> {
+        self.get(start)?.get(transition)?.first_state().cloned()
+    }
+
+    fn edges(&self, start: &S) -> Vec<(Transition, S)> {
+        let mut edges = row_edges(self.get(start).into_iter().flatten());
+        edges.sort();
+        edges
+    }
+
+    fn boxed_clone(&self) -> Box<dyn TransitionTable<S>> {
+        Box::new(self.clone())
+    }
+}
+
+impl<S, T> TransitionTable<S> for BTreeMap<S, BTreeMap<Transition, T>>
+where
+    S: Ord + Clone + fmt::Debug + Send + Sync + 'static,
+    T: StateContainer<S> + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    fn add_transition(&mut self, start: S, transition: Transition, end: S) {
+        self.entry(start)
+            .or_default()
+            .entry(transition)
+            .or_insert(T::new_container())
+            .insert_state(end);
+    }
+
+    fn rename(&mut self, old: S, new: S) {
+        if let Some(row) = self.remove(&old) {
+            self.insert(new.clone(), row);
+        }
+
+        for map in self.values_mut() {
+            for end in map.values_mut() {
+                if end.contains_state(&old) {
+                    end.replace_state(&old, new.clone());
+                }
+            }
+        }
+    }
+
+    fn targets(&self, start: &S, transition: &Transition) -> Vec<S> {
+        self.get(start)
+            .and_then(|row| row.get(transition))
+            .map_or_else(Vec::new, |ends| ends.states())
+    }
+
+    fn target(&self, start: &S, transition: &Transition) -> Option<S> {
+        self.get(start)?.get(transition)?.first_state().cloned()
+    }
+
+    fn edges(&self, start: &S) -> Vec<(Transition, S)> {
+        let mut edges = row_edges(self.get(start).into_iter().flatten());
+        edges.sort();
+        edges
+    }
+
+    fn boxed_clone(&self) -> Box<dyn TransitionTable<S>> {
+        Box::new(self.clone())
+    }
+}
+
+fn row_edges<'a, S: Clone, T: StateContainer<S> + 'a>(
+    row: impl Iterator<Item = (&'a Transition, &'a T)>,
+) -> Vec<(Transition, S)> {
+    row.flat_map(|(t, ends)| ends.states().into_iter().map(|end| (*t, end)))
+        .collect()
+}
+
+// states numbered from 0, which DenseTable uses to index its rows
+pub trait Index: Sized {
+    fn index(&self) -> usize;
+    fn from_index(index: usize) -> Self;
+}
+
+impl Index for StateId {
+    fn index(&self) -> usize {
+        self.as_usize()
+    }
+
+    fn from_index(index: usize) -> Self {
+        StateId::new(index)
+    }
+}
+
+// A row of edges per state, indexed by the state's number and sorted by transition
+#[derive(Debug, Clone)]
+pub struct DenseTable<T> {
+    rows: Vec<Vec<(Transition, T)>>,
+}
+
+impl<T> Default for DenseTable<T> {
+    fn default() -> Self {
+        Self { rows: Vec::new() }
+    }
+}
+
+impl<T> DenseTable<T> {
+    fn row(&self, start: usize) -> &[(Transition, T)] {
+        self.rows.get(start).map_or(&[], |row| row)
+    }
+
+    fn find(&self, start: usize, transition: &Transition) -> Option<&T> {
+        let row = self.row(start);
+        let i = row.binary_search_by(|(t, _)| t.cmp(transition)).ok()?;
+        Some(&row[i].1)
+    }
+}
+
+impl<S, T> TransitionTable<S> for DenseTable<T>
+where
+    S: Index + Ord + Clone + fmt::Debug + Send + Sync + 'static,
+    T: StateContainer<S> + Clone + fmt::Debug + Send + Sync + 'static,
+{
+    fn add_transition(&mut self, start: S, transition: Transition, end: S) {
+        let start = start.index();
+        if self.rows.len() <= start {
+            self.rows.resize_with(start + 1, Vec::new);
+        }
+
+        let row = &mut self.rows[start];
+        match row.binary_search_by(|(t, _)| t.cmp(&transition)) {
+            Ok(i) => row[i].1.insert_state(end),
+            Err(i) => {
+                let mut ends = T::new_container();
+                ends.insert_state(end);
+                row.insert(i, (transition, ends));
+            }
+        }
+    }
+
+    fn rename(&mut self, old: S, new: S) {
+        let (from, to) = (old.index(), new.index());
+        if from != to && from < self.rows.len() {
+            let row = std::mem::take(&mut self.rows[from]);
+            for (transition, ends) in row {
+                for end in ends.states() {
+                    self.add_transition(S::from_index(to), transition, end);
+                }
+            }
+        }
+
+        for row in &mut self.rows {
+            for (_, ends) in row {
+                if ends.contains_state(&old) {
+                    ends.replace_state(&old, new.clone());
+                }
+            }
+        }
+    }
+
+    fn targets(&self, start: &S, transition: &Transition) -> Vec<S> {
+        self.find(start.index(), transition)
+            .map_or_else(Vec::new, |ends| ends.states())
+    }
+
+    fn target(&self, start: &S, transition: &Transition) -> Option<S> {
+        self.find(start.index(), transition)?.first_state().cloned()
+    }
+
+    fn edges(&self, start: &S) -> Vec<(Transition, S)> {
+        let mut edges = row_edges(self.row(start.index()).iter().map(|(t, ends)| (t, ends)));
+        edges.sort();
+        edges
+    }
+
+    fn boxed_clone(&self) -> Box<dyn TransitionTable<S>> {
+        Box::new(self.clone())
+    }
 }
 
 pub trait StateContainer<T> {
@@ -45,6 +272,8 @@ pub trait StateContainer<T> {
     fn insert_state(&mut self, v: T);
     fn contains_state(&self, v: &T) -> bool;
     fn replace_state(&mut self, old: &T, new: T);
+    fn first_state(&self) -> Option<&T>;
+    fn states(&self) -> Vec<T>;
 }
 
 impl<T: PartialEq + Clone> StateContainer<T> for Vec<T> {
@@ -67,11 +296,48 @@ impl<T: PartialEq + Clone> StateContainer<T> for Vec<T> {
             }
         });
     }
+
+    fn first_state(&self) -> Option<&T> {
+        self.first()
+    }
+
+    fn states(&self) -> Vec<T> {
+        self.clone()
+    }
+}
+
+// a DFA edge has a single end state, which is its own container like DfaState
+impl StateContainer<StateId> for StateId {
+    fn new_container() -> Self {
+        StateId::new(0)
+    }
+
+    fn insert_state(&mut self, v: StateId) {
+        *self = v;
+    }
+
+    fn contains_state(&self, v: &StateId) -> bool {
+        self == v
+    }
+
+    fn replace_state(&mut self, old: &StateId, new: StateId) {
+        if self == old {
+            *self = new;
+        }
+    }
+
+    fn first_state(&self) -> Option<&StateId> {
+        Some(self)
+    }
+
+    fn states(&self) -> Vec<StateId> {
+        vec![*self]
+    }
 }
 
 impl<T> StateContainer<T> for BTreeSet<T>
 where
-    T: Ord,
+    T: Ord + Clone,
 {
     fn new_container() -> Self {
         Self::new()
@@ -90,51 +356,154 @@ where
             self.insert(new);
         }
     }
-}
 
-// just keep a global state counter to prevent name collisions
-static STATE_COUNTER: AtomicU64 = AtomicU64::new(0);
+    fn first_state(&self) -> Option<&T> {
+        self.first()
+    }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub enum NfaState {
-    Start,
-    Accepting,
-    S(u64),
+    fn states(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
 }
 
+// A state of an Nfa, its index in the NFA's arena of states. Every NFA starts at START and
+// accepts at ACCEPTING, the first two, so states from different NFAs can share an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NfaState(u32);
+
 impl NfaState {
-    pub fn new() -> Self {
-        Self::S(STATE_COUNTER.fetch_add(1, Ordering::Relaxed))
-    }
+    pub const START: Self = Self(0);
+    pub const ACCEPTING: Self = Self(1);
 
     pub fn dot_node(&self) -> String {
-        match self {
-            Self::Start => "start".to_string(),
-            Self::Accepting => "accepting".to_string(),
-            Self::S(n) => format!("s{n}"),
+        match *self {
+            Self::START => "start".to_string(),
+            Self::ACCEPTING => "accepting".to_string(),
+            Self(n) => format!("s{}", n - 2),
         }
     }
 }
 
-impl Default for NfaState {
-    fn default() -> Self {
-        Self::new()
+impl Index for NfaState {
+    fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_index(index: usize) -> Self {
+        Self(index as u32)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+// The characters . stands for. When the input is known to be e.g. ASCII, bounding the alphabet
+// to it keeps anything built by complementing over it small. Characters outside it still match
+// literals that name them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alphabet {
+    Ascii,
+    Latin1,
+    // every Unicode scalar value
+    #[default]
+    Unicode,
+}
+
+impl Alphabet {
+    // the largest character in the alphabet, which starts at '\0'
+    pub fn max(self) -> char {
+        match self {
+            Self::Ascii => '\x7f',
+            Self::Latin1 => '\u{ff}',
+            Self::Unicode => char::MAX,
+        }
+    }
+
+    pub fn contains(self, c: char) -> bool {
+        c <= self.max()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Transition {
     Literal(char),
     Wildcard,
     Epsilon, // Empty String
+    // Empty, but only crossed at the start or end of the input. A DFA has no start anchor
+    // edges, and its end anchor edges are followed once the input has ended.
+    StartAnchor,
+    EndAnchor,
+    // Any character outside the sorted set, from a negated class. Only NFAs have these, a DFA
+    // gives the set's characters edges of their own and everything else the wildcard edge.
+    Except(&'static [char]),
+    // A character the registered predicate holds for (\p{name}). Only NFAs have these, see
+    // PredClass.
+    Pred(PredicateId),
+    // A character without a literal edge for which exactly the DFA's predicates in the mask hold
+    // (see predicate::mask), so that each combination of predicates is one symbol. Subset
+    // construction only gives a state these edges for combinations some character has.
+    PredClass(u64),
+}
+
+// The sorted, deduplicated set of chars, shared with every other class that has the same
+// members so that Transition stays Copy. Each distinct set is allocated once and kept.
+pub fn intern_class(mut chars: Vec<char>) -> &'static [char] {
+    static CLASSES: Mutex<BTreeSet<&'static [char]>> = Mutex::new(BTreeSet::new());
+
+    chars.sort();
+    chars.dedup();
+    let mut classes = CLASSES.lock().unwrap();
+    if let Some(class) = classes.get(chars.as_slice()) {
+        return class;
+    }
+    let class: &'static [char] = Box::leak(chars.into_boxed_slice());
+    classes.insert(class);
+    class
 }
 
 impl Transition {
+    // whether crossing the edge reads a character
+    pub fn consumes(&self) -> bool {
+        matches!(
+            self,
+            Self::Literal(_)
+                | Self::Wildcard
+                | Self::Except(_)
+                | Self::Pred(_)
+                | Self::PredClass(_)
+        )
+    }
+
+    // whether the edge can be crossed by reading c
+    pub fn accepts(&self, c: char) -> bool {
+        match self {
+            Self::Literal(l) => *l == c,
+            Self::Wildcard => true,
+            Self::Except(chars) => chars.binary_search(&c).is_err(),
+            Self::Pred(id) => id.test(c),
+            // only the DFA knows which other predicates must not hold
+            Self::PredClass(mask) => predicate::in_mask(*mask).all(|id| id.test(c)),
+            Self::Epsilon | Self::StartAnchor | Self::EndAnchor => false,
+        }
+    }
+
     pub fn dot_label(&self) -> String {
+        self.label(LabelEscape::Mnemonic)
+    }
+
+    pub fn label(&self, escape: LabelEscape) -> String {
         match self {
-            Self::Literal(c) => format!("'{c}'"),
+            Self::Literal(c) => format!("'{}'", escape_char(*c, escape)),
             Self::Wildcard => ".".to_string(),
             Self::Epsilon => "ε".to_string(),
+            Self::StartAnchor => "^".to_string(),
+            Self::EndAnchor => "$".to_string(),
+            Self::Except(chars) => {
+                let class = class_label(&chars.iter().copied().collect(), escape);
+                format!("[^{}", &class[1..])
+            }
+            Self::Pred(id) => format!("\\p{{{}}}", id.name()),
+            Self::PredClass(mask) => predicate::in_mask(*mask)
+                .map(|id| format!("\\p{{{}}}", id.name()))
+                .collect::<Vec<_>>()
+                .join("&"),
         }
     }
 }
@@ -0,0 +1,147 @@
+use std::collections::BTreeSet;
+
+use crate::backtrack::{self, Scratch};
+use crate::dfa::Dfa;
+use crate::prog::Inst;
+use crate::Regex;
+
+// Inputs that make a pattern's engines do as much work as possible, for benchmarking it or for
+// showing concretely how exposed it is to ReDoS
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorstCase {
+    pub input: String,
+    // the backtracker's steps (see Scratch::steps) on input
+    pub steps: usize,
+}
+
+// The longest input of at most len characters that the DFA reads in full without reaching an
+// accepting state, from its unanchored start if it has one. The DFA gives up on anything else
+// sooner, at the first accepting state or a character it has no edge for.
+pub fn longest_rejected_prefix(dfa: &Dfa, len: usize) -> String {
+    let start = dfa.unanchored_start().unwrap_or(dfa.start());
+
+    // walks[k] holds the states a rejecting walk of k more characters can start from
+    let mut walks = vec![dfa
+        .states()
+        .filter(|s| !dfa.is_accepting(*s))
+        .collect::<BTreeSet<_>>()];
+    while walks.len() <= len {
+        let prev = walks.last().unwrap();
+        let next: BTreeSet<_> = prev
+            .iter()
+            .copied()
+            .filter(|s| {
+                dfa.transitions(*s).into_iter().any(|(transition, end)| {
+                    prev.contains(&end) && dfa.edge_char(*s, transition, end).is_some()
+                })
+            })
+            .collect();
+        if next.is_empty() {
+            break;
+        }
+        walks.push(next);
+    }
+
+    let Some(k) = (0..walks.len()).rev().find(|k| walks[*k].contains(&start)) else {
+        return String::new();
+    };
+
+    let mut input = String::new();
+    let mut curr = start;
+    for remaining in (0..k).rev() {
+        let (c, end) = dfa
+            .transitions(curr)
+            .into_iter()
+            .filter(|(_, end)| walks[remaining].contains(end))
+            .find_map(|(transition, end)| Some((dfa.edge_char(curr, transition, end)?, end)))
+            .unwrap();
+        input.push(c);
+        curr = end;
+    }
+
+    input
+}
+
+// the backtracker's steps for an is_match search of input
+pub fn backtrack_steps(regex: &Regex, input: &str) -> usize {
+    let mut scratch = Scratch::default();
+    backtrack::search_with(
+        &mut scratch,
+        &regex.prog,
+        input,
+        0,
+        false,
+        regex.full_match.then_some(input.len()),
+        false,
+    );
+    scratch.steps()
+}
+
+// Build an input of len characters one at a time, each time appending the character that makes
+// the backtracker work hardest. The candidates are the characters the pattern mentions and one
+// it doesn't, so inputs like aaaa...! against (a+)+b come out on their own. Greedy, so a bound
+// on the worst case rather than the worst case itself.
+pub fn backtrack_worst_case(regex: &Regex, len: usize) -> WorstCase {
+    let mut alphabet = BTreeSet::new();
+    for inst in &regex.prog.insts {
+        match inst {
+            Inst::Char(c) => {
+                alphabet.insert(*c);
+            }
+            Inst::Class(chars) | Inst::NotClass(chars) => alphabet.extend(chars),
+            _ => {}
+        }
+    }
+    if let Some(other) = ['!', '#', '~', '\u{FFFD}']
+        .into_iter()
+        .find(|c| !alphabet.contains(c))
+    {
+        alphabet.insert(other);
+    }
+
+    let mut worst = WorstCase {
+        input: String::new(),
+        steps: backtrack_steps(regex, ""),
+    };
+    for _ in 0..len {
+        let mut best: Option<WorstCase> = None;
+        for c in &alphabet {
+            let mut input = worst.input.clone();
+            input.push(*c);
+            let steps = backtrack_steps(regex, &input);
+            if best.as_ref().is_none_or(|b| steps > b.steps) {
+                best = Some(WorstCase { input, steps });
+            }
+        }
+        worst = best.unwrap();
+    }
+
+    worst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_regex;
+
+    #[test]
+    fn test_longest_rejected_prefix() {
+        assert_eq!(longest_rejected_prefix(&compile_regex("ab*c"), 5), "abbbb");
+        assert_eq!(longest_rejected_prefix(&compile_regex("x{3}y"), 10), "xxx");
+        assert_eq!(longest_rejected_prefix(&compile_regex("a*"), 10), "");
+        assert_eq!(longest_rejected_prefix(&compile_regex("a.c"), 2), "aa");
+    }
+
+    #[test]
+    fn test_backtrack_worst_case() {
+        let regex = Regex::new("(a|aa)+b").unwrap();
+        let worst = backtrack_worst_case(&regex, 16);
+        assert_eq!(worst.input.chars().count(), 16);
+        assert!(!regex.is_match(&worst.input));
+        assert_eq!(worst.steps, backtrack_steps(&regex, &worst.input));
+        for other in ["b".repeat(16), "ab".repeat(8), "!".repeat(16)] {
+            assert!(backtrack_steps(&regex, &other) < worst.steps, "{other}");
+        }
+    }
+}